@@ -1,9 +1,16 @@
 use tokio::sync::oneshot;
 
-use pipeweaver_ipc::commands::{APICommand, APICommandResponse, DaemonCommand, DaemonResponse, DaemonStatus};
+use pipeweaver_ipc::commands::{
+    APICommand, APICommandResponse, ApiError, BatchRequest, DaemonCommand, DaemonResponse,
+    DaemonStatus, PipewireGraph,
+};
+use ulid::Ulid;
 
 pub enum DaemonMessage {
     GetStatus(oneshot::Sender<DaemonStatus>),
+    GetPatchesSince(Ulid, u64, oneshot::Sender<DaemonResponse>),
     RunDaemon(DaemonCommand, oneshot::Sender<DaemonResponse>),
     RunPipewire(APICommand, oneshot::Sender<APICommandResponse>),
+    RunBatch(BatchRequest, oneshot::Sender<Vec<APICommandResponse>>),
+    GetGraph(oneshot::Sender<Result<PipewireGraph, ApiError>>),
 }
@@ -0,0 +1,76 @@
+use crate::handler::messaging::DaemonMessage;
+use crate::handler::packet::Messenger;
+use pipeweaver_ipc::commands::{APICommand, APICommandResponse, DaemonCommand, DaemonResponse};
+use pipeweaver_ipc::commands::{DaemonStatus, PipewireGraph};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc;
+
+/// A stand-in for the Primary Worker, it answers with whatever status the test has set, and
+/// records the commands it's sent so servers can be tested without a Pipewire Manager.
+#[derive(Clone, Default)]
+pub(crate) struct MockDaemon {
+    status: Arc<Mutex<DaemonStatus>>,
+    commands: Arc<Mutex<Vec<APICommand>>>,
+    daemon_commands: Arc<Mutex<Vec<DaemonCommand>>>,
+    status_requests: Arc<AtomicUsize>,
+}
+
+impl MockDaemon {
+    pub(crate) fn status(&self) -> MutexGuard<'_, DaemonStatus> {
+        self.status.lock().unwrap()
+    }
+
+    pub(crate) fn commands(&self) -> MutexGuard<'_, Vec<APICommand>> {
+        self.commands.lock().unwrap()
+    }
+
+    pub(crate) fn daemon_commands(&self) -> MutexGuard<'_, Vec<DaemonCommand>> {
+        self.daemon_commands.lock().unwrap()
+    }
+
+    /// How many times the status has been fetched
+    pub(crate) fn status_requests(&self) -> usize {
+        self.status_requests.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) fn mock_daemon(status: DaemonStatus) -> (Messenger, MockDaemon) {
+    let (tx, mut rx) = mpsc::channel(32);
+    let daemon = MockDaemon::default();
+    *daemon.status() = status;
+
+    let mock = daemon.clone();
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            match message {
+                DaemonMessage::GetStatus(tx) => {
+                    mock.status_requests.fetch_add(1, Ordering::Relaxed);
+                    let _ = tx.send(mock.status().clone());
+                }
+                DaemonMessage::GetPatchesSince(_, _, tx) => {
+                    let _ = tx.send(DaemonResponse::Status(mock.status().clone()));
+                }
+                DaemonMessage::RunDaemon(command, tx) => {
+                    mock.daemon_commands().push(command);
+                    let _ = tx.send(DaemonResponse::Ok);
+                }
+                DaemonMessage::RunPipewire(command, tx) => {
+                    mock.commands().push(command);
+                    let _ = tx.send(APICommandResponse::Ok);
+                }
+                DaemonMessage::RunBatch(batch, tx) => {
+                    let responses = batch.commands.iter().map(|_| APICommandResponse::Ok);
+                    let responses = responses.collect();
+                    mock.commands().extend(batch.commands);
+                    let _ = tx.send(responses);
+                }
+                DaemonMessage::GetGraph(tx) => {
+                    let _ = tx.send(Ok(PipewireGraph::default()));
+                }
+            }
+        }
+    });
+
+    (tx, daemon)
+}
@@ -1,4 +1,7 @@
 pub(crate) mod packet;
 mod messaging;
+#[cfg(test)]
+pub(crate) mod mock_daemon;
+mod mpris;
 pub(crate) mod primary_worker;
 mod pipewire;
\ No newline at end of file
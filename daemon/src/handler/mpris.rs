@@ -0,0 +1,201 @@
+use crate::servers::dbus_server::dbus_builder;
+use anyhow::Result;
+use log::{debug, info, warn};
+use tokio::sync::mpsc;
+use zbus::{connection, fdo, Connection, Proxy};
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+#[derive(Debug)]
+pub(crate) enum MprisMessage {
+    /// Pause any playing players whose bus name contains this value
+    Pause(String),
+
+    /// Resume any players we previously paused which match this value
+    Resume(String),
+}
+
+/// D-Bus calls can take a while, so we handle these in their own task rather than blocking
+/// the Pipewire Manager while players respond.
+pub(crate) fn spawn_mpris_handler() -> mpsc::Sender<MprisMessage> {
+    let (tx, rx) = mpsc::channel(8);
+    tokio::spawn(run_mpris_handler(rx, dbus_builder));
+    tx
+}
+
+async fn run_mpris_handler<F>(mut receiver: mpsc::Receiver<MprisMessage>, builder: F)
+where
+    F: Fn() -> Result<connection::Builder<'static>>,
+{
+    let mut connection: Option<Connection> = None;
+
+    // Players which we've paused, and should resume
+    let mut paused: Vec<String> = vec![];
+
+    while let Some(message) = receiver.recv().await {
+        if connection.is_none() {
+            match create_connection(&builder).await {
+                Ok(conn) => connection = Some(conn),
+                Err(e) => {
+                    warn!("[MPRIS] Unable to connect to D-Bus: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        let Some(conn) = &connection else {
+            continue;
+        };
+
+        let result = match message {
+            MprisMessage::Pause(player) => pause(conn, &player, &mut paused).await,
+            MprisMessage::Resume(player) => resume(conn, &player, &mut paused).await,
+        };
+        if let Err(e) = result {
+            warn!("[MPRIS] Error: {}", e);
+        }
+    }
+    debug!("[MPRIS] Handler Stopped");
+}
+
+async fn create_connection<F>(builder: &F) -> Result<Connection>
+where
+    F: Fn() -> Result<connection::Builder<'static>>,
+{
+    Ok(builder()?.build().await?)
+}
+
+async fn pause(connection: &Connection, player: &str, paused: &mut Vec<String>) -> Result<()> {
+    for name in find_players(connection, player).await? {
+        let proxy = Proxy::new(connection, name.clone(), MPRIS_PATH, MPRIS_PLAYER).await?;
+        let status: String = proxy.get_property("PlaybackStatus").await?;
+        if status != "Playing" {
+            continue;
+        }
+
+        info!("[MPRIS] Pausing {}", name);
+        proxy.call_method("Pause", &()).await?;
+        if !paused.contains(&name) {
+            paused.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Players are matched by a case-insensitive substring, of either the MPRIS bus name or (when
+/// ducking) the application name Pipewire reports for its stream
+pub(crate) fn player_matches(name: &str, player: &str) -> bool {
+    name.to_lowercase().contains(&player.to_lowercase())
+}
+
+async fn resume(connection: &Connection, player: &str, paused: &mut Vec<String>) -> Result<()> {
+    let matches = |name: &String| player_matches(name, player);
+
+    // Only resume players that we paused ourselves
+    for name in paused.iter().filter(|name| matches(name)) {
+        info!("[MPRIS] Resuming {}", name);
+        let proxy = match Proxy::new(connection, name.clone(), MPRIS_PATH, MPRIS_PLAYER).await {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                warn!("[MPRIS] Unable to Resume {}: {}", name, e);
+                continue;
+            }
+        };
+        if let Err(e) = proxy.call_method("Play", &()).await {
+            // The player may have gone away since we paused it
+            debug!("[MPRIS] Unable to Resume {}: {}", name, e);
+        }
+    }
+    paused.retain(|name| !matches(name));
+    Ok(())
+}
+
+async fn find_players(connection: &Connection, player: &str) -> Result<Vec<String>> {
+    let dbus = fdo::DBusProxy::new(connection).await?;
+
+    Ok(dbus
+        .list_names()
+        .await?
+        .into_iter()
+        .map(|name| name.to_string())
+        .filter(|name| name.starts_with(MPRIS_PREFIX))
+        .filter(|name| player_matches(&name[MPRIS_PREFIX.len()..], player))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::servers::dbus_server::tests::start_bus;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::time;
+    use zbus::interface;
+
+    #[derive(Clone)]
+    struct FakePlayer {
+        status: Arc<Mutex<String>>,
+    }
+
+    #[interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl FakePlayer {
+        fn pause(&self) {
+            *self.status.lock().unwrap() = String::from("Paused");
+        }
+
+        fn play(&self) {
+            *self.status.lock().unwrap() = String::from("Playing");
+        }
+
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            self.status.lock().unwrap().clone()
+        }
+    }
+
+    async fn fake_player(address: &str, name: &str, status: &str) -> (Connection, FakePlayer) {
+        let player = FakePlayer { status: Arc::new(Mutex::new(status.to_string())) };
+        let builder = connection::Builder::address(address).unwrap();
+        let builder = builder.name(format!("{}{}", MPRIS_PREFIX, name)).unwrap();
+        let connection = builder.serve_at(MPRIS_PATH, player.clone()).unwrap();
+        (connection.build().await.unwrap(), player)
+    }
+
+    async fn wait_for(player: &FakePlayer, status: &str) {
+        let waited = time::timeout(Duration::from_secs(2), async {
+            while *player.status.lock().unwrap() != status {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        waited.await.expect("Player didn't change state");
+    }
+
+    // This needs `dbus-daemon`, run it with `cargo test -p pipeweaver-daemon -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs dbus-daemon"]
+    async fn pauses_and_resumes_players() {
+        let bus = start_bus().expect("dbus-daemon should be available");
+        let (_music, music) = fake_player(&bus.address, "FakeMusic", "Playing").await;
+        let (_video, video) = fake_player(&bus.address, "FakeMusic.Video", "Stopped").await;
+        let (_other, other) = fake_player(&bus.address, "Other", "Playing").await;
+
+        let (tx, rx) = mpsc::channel(8);
+        let address = bus.address.clone();
+        let builder = move || Ok(connection::Builder::address(address.as_str())?);
+        let handler = tokio::spawn(run_mpris_handler(rx, builder));
+
+        tx.send(MprisMessage::Pause(String::from("fakemusic"))).await.unwrap();
+        wait_for(&music, "Paused").await;
+
+        // Only the player we paused is resumed, one which wasn't playing is left alone
+        tx.send(MprisMessage::Resume(String::from("fakemusic"))).await.unwrap();
+        wait_for(&music, "Playing").await;
+        assert_eq!(*video.status.lock().unwrap(), "Stopped");
+        assert_eq!(*other.status.lock().unwrap(), "Playing");
+
+        drop(tx);
+        handler.await.unwrap();
+    }
+}
@@ -1,9 +1,9 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
 
 use crate::handler::messaging::DaemonMessage;
-use pipeweaver_ipc::commands::{DaemonRequest, DaemonResponse};
+use pipeweaver_ipc::commands::{DaemonRequest, DaemonResponse, GraphFormat};
 
 pub type Messenger = Sender<DaemonMessage>;
 type Response = Result<DaemonResponse>;
@@ -25,6 +25,34 @@ pub async fn handle_packet(request: DaemonRequest, sender: Messenger) -> Respons
             let result = rx.await.context("Error from device manager")?;
             Ok(DaemonResponse::Status(result))
         }
+        DaemonRequest::GetPatchesSince(epoch, revision) => {
+            let (tx, rx) = oneshot::channel();
+
+            sender
+                .send(DaemonMessage::GetPatchesSince(epoch, revision, tx))
+                .await
+                .map_err(|e| anyhow!(e.to_string()))
+                .context("Failed to send message to device manager")?;
+
+            rx.await.context("Error from device manager")
+        }
+        DaemonRequest::GetGraph(format) => {
+            let (tx, rx) = oneshot::channel();
+
+            sender
+                .send(DaemonMessage::GetGraph(tx))
+                .await
+                .map_err(|e| anyhow!(e.to_string()))
+                .context("Failed to send message to device manager")?;
+
+            match rx.await.context("Error from device manager")? {
+                Ok(graph) => match format {
+                    GraphFormat::Json => Ok(DaemonResponse::Graph(graph)),
+                    GraphFormat::Dot => Ok(DaemonResponse::GraphDot(graph.to_dot())),
+                },
+                Err(error) => Ok(DaemonResponse::Err(error)),
+            }
+        }
         DaemonRequest::Daemon(daemon_command) => {
             let (tx, rx) = oneshot::channel();
             sender
@@ -47,6 +75,24 @@ pub async fn handle_packet(request: DaemonRequest, sender: Messenger) -> Respons
             let result = rx.await.context("Error from Device Manager")?;
             Ok(DaemonResponse::Pipewire(result))
         }
+        DaemonRequest::Batch(batch) => {
+            let (tx, rx) = oneshot::channel();
+            sender
+                .send(DaemonMessage::RunBatch(batch, tx))
+                .await
+                .map_err(|e| anyhow!(e.to_string()))
+                .context("Failed to send message to device manager")?;
+
+            let result = rx.await.context("Error from Device Manager")?;
+            Ok(DaemonResponse::Batch(result))
+        }
+        DaemonRequest::Auth(_) => {
+            // The IPC server handles these itself, anything reaching here came from elsewhere
+            bail!("Token management is only available over IPC");
+        }
+        DaemonRequest::Subscribe(_) => {
+            bail!("Subscriptions are only available over IPC");
+        }
     }
 }
 
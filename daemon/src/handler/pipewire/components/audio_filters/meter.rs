@@ -1,4 +1,4 @@
-use pipeweaver_pipewire::{FilterHandler, FilterProperty, FilterValue};
+use pipeweaver_pipewire::{FilterHandler, FilterProperty, FilterValue, PipewireError};
 use tokio::sync::mpsc;
 use ulid::Ulid;
 
@@ -48,27 +48,28 @@ impl FilterHandler for MeterFilter {
         }]
     }
 
-    fn get_property(&self, id: u32) -> FilterProperty {
+    fn get_property(&self, id: u32) -> Result<FilterProperty, PipewireError> {
         match id {
-            0 => FilterProperty {
+            0 => Ok(FilterProperty {
                 id: 0,
                 name: "Volume".into(),
                 value: FilterValue::Bool(self.enabled),
-            },
-            _ => panic!("Attempted to lookup non-existent property!")
+            }),
+            _ => Err(PipewireError::UnknownProperty(id)),
         }
     }
 
-    fn set_property(&mut self, id: u32, value: FilterValue) {
+    fn set_property(&mut self, id: u32, value: FilterValue) -> Result<(), PipewireError> {
         match id {
             0 => {
                 if let FilterValue::Bool(value) = value {
                     self.enabled = value;
+                    Ok(())
                 } else {
-                    panic!("Attempted to Toggle Meter without Bool type");
+                    Err(PipewireError::InvalidPropertyValue(id))
                 }
             }
-            _ => panic!("Attempted to set non-existent property!")
+            _ => Err(PipewireError::UnknownProperty(id)),
         }
     }
 
@@ -1,4 +1,4 @@
-use pipeweaver_pipewire::{FilterHandler, FilterProperty, FilterValue};
+use pipeweaver_pipewire::{FilterHandler, FilterProperty, FilterValue, PipewireError};
 
 pub struct PassThroughFilter {}
 
@@ -13,12 +13,12 @@ impl FilterHandler for PassThroughFilter {
         vec![]
     }
 
-    fn get_property(&self, _: u32) -> FilterProperty {
-        panic!("Attempted to get non-existent property");
+    fn get_property(&self, id: u32) -> Result<FilterProperty, PipewireError> {
+        Err(PipewireError::UnknownProperty(id))
     }
 
-    fn set_property(&mut self, _: u32, _: FilterValue) {
-        panic!("Attempted to set non-existent property");
+    fn set_property(&mut self, id: u32, _: FilterValue) -> Result<(), PipewireError> {
+        Err(PipewireError::UnknownProperty(id))
     }
 
     fn process_samples(&mut self, inputs: Vec<&mut [f32]>, mut outputs: Vec<&mut [f32]>) {
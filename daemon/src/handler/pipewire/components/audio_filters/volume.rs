@@ -1,4 +1,4 @@
-use pipeweaver_pipewire::{FilterHandler, FilterProperty, FilterValue};
+use pipeweaver_pipewire::{FilterHandler, FilterProperty, FilterValue, PipewireError};
 
 const POWER_FACTOR: f32 = 3.8;
 
@@ -74,18 +74,18 @@ impl FilterHandler for VolumeFilter {
         }]
     }
 
-    fn get_property(&self, id: u32) -> FilterProperty {
+    fn get_property(&self, id: u32) -> Result<FilterProperty, PipewireError> {
         match id {
-            0 => FilterProperty {
+            0 => Ok(FilterProperty {
                 id: 0,
                 name: "Volume".into(),
                 value: FilterValue::UInt8(self.volume),
-            },
-            _ => panic!("Attempted to lookup non-existent property!"),
+            }),
+            _ => Err(PipewireError::UnknownProperty(id)),
         }
     }
 
-    fn set_property(&mut self, id: u32, value: FilterValue) {
+    fn set_property(&mut self, id: u32, value: FilterValue) -> Result<(), PipewireError> {
         match id {
             0 => {
                 if let FilterValue::UInt8(value) = value {
@@ -93,11 +93,12 @@ impl FilterHandler for VolumeFilter {
                     let (volume, volume_inner) = Self::calculate_volume(value);
                     self.volume = volume;
                     self.volume_inner = volume_inner;
+                    Ok(())
                 } else {
-                    panic!("Attempted to Set Volume as non-percentage");
+                    Err(PipewireError::InvalidPropertyValue(id))
                 }
             }
-            _ => panic!("Attempted to set non-existent property!"),
+            _ => Err(PipewireError::UnknownProperty(id)),
         }
     }
 
@@ -0,0 +1,168 @@
+use crate::handler::pipewire::components::node::NodeManagement;
+use crate::handler::pipewire::components::policy::PolicyManagement;
+use crate::handler::pipewire::manager::PipewireManager;
+use anyhow::{bail, Result};
+use log::debug;
+use pipeweaver_ipc::commands::ApiError;
+use pipeweaver_pipewire::{DefaultDevice, PipewireMessage};
+use pipeweaver_shared::NodeType;
+use ulid::Ulid;
+
+pub(crate) trait DefaultManagement {
+    /// Makes a Virtual Source the device applications play into by default
+    async fn default_set_sink(&mut self, id: Ulid) -> Result<()>;
+
+    /// Makes a Virtual Target the device applications record from by default
+    async fn default_set_source(&mut self, id: Ulid) -> Result<()>;
+
+    /// Hands the choice of default back to the session manager
+    async fn default_clear(&mut self, device: DefaultDevice) -> Result<()>;
+
+    /// Reasserts the defaults stored in the profile, something else may have changed them
+    /// while we weren't running
+    async fn default_apply(&self) -> Result<()>;
+
+    /// Called when Pipewire reports a new default
+    fn default_changed(&mut self, device: DefaultDevice, id: Option<Ulid>);
+}
+
+impl DefaultManagement for PipewireManager {
+    async fn default_set_sink(&mut self, id: Ulid) -> Result<()> {
+        let err = ApiError::not_found(id, "Unable to find Node");
+        if self.get_node_type(id).ok_or(err)? != NodeType::VirtualSource {
+            bail!(ApiError::invalid(id, "Only Virtual Sources can be the default sink"));
+        }
+        if self.profile.defaults.sink == Some(id) && self.current_defaults.sink == Some(id) {
+            bail!(ApiError::no_change(id, "Node is already the default sink"));
+        }
+
+        self.profile.defaults.sink = Some(id);
+        self.default_send(DefaultDevice::Sink, id)
+    }
+
+    async fn default_set_source(&mut self, id: Ulid) -> Result<()> {
+        let err = ApiError::not_found(id, "Unable to find Node");
+        if self.get_node_type(id).ok_or(err)? != NodeType::VirtualTarget {
+            bail!(ApiError::invalid(id, "Only Virtual Targets can be the default source"));
+        }
+        if !self.get_node_metadata(id).is_some_and(|m| m.virtual_microphone) {
+            bail!(ApiError::invalid(id, "Only Virtual Microphones can be the default source"));
+        }
+        if self.profile.defaults.source == Some(id) && self.current_defaults.source == Some(id) {
+            bail!(ApiError::no_change(id, "Node is already the default source"));
+        }
+
+        self.profile.defaults.source = Some(id);
+        self.default_send(DefaultDevice::Source, id)
+    }
+
+    async fn default_clear(&mut self, device: DefaultDevice) -> Result<()> {
+        match device {
+            DefaultDevice::Sink => self.profile.defaults.sink = None,
+            DefaultDevice::Source => self.profile.defaults.source = None,
+        }
+        let message = PipewireMessage::ClearDefaultDevice(device);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
+    }
+
+    async fn default_apply(&self) -> Result<()> {
+        let defaults = self.profile.defaults;
+        if let Some(id) = defaults.sink {
+            self.default_send(DefaultDevice::Sink, id)?;
+        }
+        if let Some(id) = defaults.source {
+            self.default_send(DefaultDevice::Source, id)?;
+        }
+        Ok(())
+    }
+
+    fn default_changed(&mut self, device: DefaultDevice, id: Option<Ulid>) {
+        debug!("Default {:?} is now {:?}", device, id);
+        match device {
+            DefaultDevice::Sink => self.current_defaults.sink = id,
+            DefaultDevice::Source => self.current_defaults.source = id,
+        }
+    }
+}
+
+trait DefaultManagementLocal {
+    fn default_send(&self, device: DefaultDevice, id: Ulid) -> Result<()>;
+}
+
+impl DefaultManagementLocal for PipewireManager {
+    fn default_send(&self, device: DefaultDevice, id: Ulid) -> Result<()> {
+        let message = PipewireMessage::SetDefaultDevice(device, id);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_ipc::commands::ErrorCode;
+    use pipeweaver_profile::{NodeMetadata, NodePolicy, Profile};
+
+    #[tokio::test]
+    async fn defaults_are_stored_and_applied() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let chat_mic = manager.profile.find_node("Chat Mic").unwrap();
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+
+        manager.default_set_sink(system).await.unwrap();
+        manager.default_set_source(chat_mic).await.unwrap();
+        assert_eq!(manager.profile.defaults.sink, Some(system));
+        assert_eq!(manager.profile.defaults.source, Some(chat_mic));
+        assert_eq!(pipewire.graph().defaults[&DefaultDevice::Sink], system);
+        assert_eq!(pipewire.graph().defaults[&DefaultDevice::Source], chat_mic);
+
+        // Sinks have to be something applications can play into
+        assert!(manager.default_set_sink(chat_mic).await.is_err());
+        assert!(manager.default_set_source(headphones).await.is_err());
+
+        // Once Pipewire agrees, setting it again changes nothing
+        manager.default_changed(DefaultDevice::Sink, Some(system));
+        assert!(manager.default_set_sink(system).await.is_err());
+
+        // Something else takes over the default, loading the profile puts it back
+        pipewire.graph().defaults.clear();
+        manager.default_apply().await.unwrap();
+        assert_eq!(pipewire.graph().defaults[&DefaultDevice::Sink], system);
+
+        // Rebuilding a node puts it back as the default once it's been recreated
+        let policy = NodePolicy { priority_session: Some(2000), ..Default::default() };
+        manager.node_set_policy(system, policy).await.unwrap();
+        let icon_name = Some("audio-input-microphone".into());
+        let metadata = NodeMetadata { icon_name, ..Default::default() };
+        manager.node_set_metadata(chat_mic, metadata).await.unwrap();
+        assert_eq!(pipewire.graph().defaults[&DefaultDevice::Sink], system);
+        assert_eq!(pipewire.graph().defaults[&DefaultDevice::Source], chat_mic);
+
+        // Removing the node forgets it as a default
+        manager.node_remove(system).await.unwrap();
+        assert_eq!(manager.profile.defaults.sink, None);
+    }
+
+    #[tokio::test]
+    async fn default_sources_are_microphones() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let chat_mic = manager.profile.find_node("Chat Mic").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let speaker = NodeMetadata { virtual_microphone: false, ..Default::default() };
+
+        // Something which isn't a microphone can't be recorded from by default
+        manager.node_set_metadata(stream, speaker.clone()).await.unwrap();
+        let error = ApiError::from(manager.default_set_source(stream).await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert!(!pipewire.graph().defaults.contains_key(&DefaultDevice::Source));
+
+        // And the default source can't stop being one
+        manager.default_set_source(chat_mic).await.unwrap();
+        let error = ApiError::from(manager.node_set_metadata(chat_mic, speaker).await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert!(manager.get_node_metadata(chat_mic).unwrap().virtual_microphone);
+    }
+}
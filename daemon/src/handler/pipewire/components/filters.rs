@@ -4,8 +4,9 @@ use crate::handler::pipewire::components::audio_filters::volume::VolumeFilter;
 use crate::handler::pipewire::manager::PipewireManager;
 use crate::{APP_ID, APP_NAME, APP_NAME_ID};
 use anyhow::{bail, Result};
+use pipeweaver_ipc::commands::ApiError;
 use pipeweaver_pipewire::oneshot;
-use pipeweaver_pipewire::{FilterProperties, FilterValue, MediaClass, PipewireMessage};
+use pipeweaver_pipewire::{FilterProperties, FilterValue, MediaClass, NodeLabels, PipewireMessage};
 use ulid::Ulid;
 
 pub(crate) trait FilterManagement {
@@ -20,6 +21,9 @@ pub(crate) trait FilterManagement {
 
     async fn filter_volume_set(&self, id: Ulid, volume: u8) -> Result<()>;
 
+    /// Renames a filter in place, without disturbing anything linked to it
+    async fn filter_set_name(&self, id: Ulid, name: String) -> Result<()>;
+
     async fn filter_remove(&mut self, id: Ulid) -> Result<()>;
 }
 
@@ -60,16 +64,23 @@ impl FilterManagement for PipewireManager {
 
     async fn filter_volume_set(&self, id: Ulid, volume: u8) -> Result<()> {
         if !(0..=100).contains(&volume) {
-            bail!("Volume must be between 0 and 100");
+            bail!(ApiError::invalid(None, "Volume must be between 0 and 100"));
         }
 
         let value = FilterValue::UInt8(volume);
         let message = PipewireMessage::SetFilterValue(id, 0, value);
-        let _ = self.pipewire().send_message(message);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
 
         Ok(())
     }
 
+    async fn filter_set_name(&self, id: Ulid, name: String) -> Result<()> {
+        let labels = NodeLabels { description: filter_description(&name), nick: name };
+        let message = PipewireMessage::UpdateNodeProperties(id, labels, None);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
+    }
+
     async fn filter_remove(&mut self, id: Ulid) -> Result<()> {
         self.filter_pw_remove(id).await
     }
@@ -89,26 +100,26 @@ impl FilterManagementLocal for PipewireManager {
         let (send, recv) = oneshot::channel();
 
         props.ready_sender = Some(send);
-        self.pipewire()
-            .send_message(PipewireMessage::CreateFilterNode(props))?;
-        recv.await?;
+        self.pipewire()?
+            .send_message(PipewireMessage::CreateFilterNode(props))
+            .map_err(ApiError::pipewire)?;
+        recv.await.map_err(ApiError::pipewire)?;
 
         Ok(())
     }
 
     async fn filter_pw_remove(&self, id: Ulid) -> Result<()> {
         let message = PipewireMessage::RemoveFilterNode(id);
-        self.pipewire().send_message(message)
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
     }
 
     fn filter_pass_get_props(&self, name: String, id: Ulid) -> FilterProperties {
-        let description = name.to_lowercase().replace(" ", "-");
-
         FilterProperties {
             filter_id: id,
             filter_name: "Pass-Through".into(),
             filter_nick: name.to_string(),
-            filter_description: format!("{}/{}", APP_NAME_ID, description),
+            filter_description: filter_description(&name),
 
             class: MediaClass::Duplex,
             app_id: APP_ID.to_string(),
@@ -122,13 +133,11 @@ impl FilterManagementLocal for PipewireManager {
     }
 
     fn filter_volume_get_props(&self, name: String, id: Ulid) -> FilterProperties {
-        let description = name.to_lowercase().replace(" ", "-");
-
         FilterProperties {
             filter_id: id,
             filter_name: "Volume".into(),
             filter_nick: name.to_string(),
-            filter_description: format!("{}/{}", APP_NAME_ID, description),
+            filter_description: filter_description(&name),
 
             class: MediaClass::Duplex,
             app_id: APP_ID.to_string(),
@@ -142,13 +151,11 @@ impl FilterManagementLocal for PipewireManager {
     }
 
     fn filter_meter_get_props(&self, node: Ulid, name: String, id: Ulid) -> FilterProperties {
-        let description = name.to_lowercase().replace(" ", "-");
-
         FilterProperties {
             filter_id: id,
             filter_name: "Meter".into(),
             filter_nick: name.to_string(),
-            filter_description: format!("{}/{}", APP_NAME_ID, description),
+            filter_description: filter_description(&name),
 
             class: MediaClass::Duplex,
             app_id: APP_ID.to_string(),
@@ -161,3 +168,34 @@ impl FilterManagementLocal for PipewireManager {
         }
     }
 }
+
+fn filter_description(name: &str) -> String {
+    format!("{}/{}", APP_NAME_ID, name.to_lowercase().replace(" ", "-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_ipc::commands::ErrorCode;
+    use pipeweaver_profile::Profile;
+
+    #[tokio::test]
+    async fn volume_errors_are_reported() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let id = manager.filter_volume_create("Test".into()).await.unwrap();
+
+        manager.filter_volume_set(id, 40).await.unwrap();
+        assert_eq!(pipewire.graph().filter_volume(id), Some(40));
+
+        let error = ApiError::from(manager.filter_volume_set(id, 101).await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+
+        let error = ApiError::from(manager.filter_volume_set(Ulid::new(), 40).await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::Pipewire);
+
+        pipewire.disconnect();
+        let error = ApiError::from(manager.filter_volume_set(id, 60).await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::Pipewire);
+    }
+}
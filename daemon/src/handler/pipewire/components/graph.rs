@@ -0,0 +1,164 @@
+use crate::handler::pipewire::manager::PipewireManager;
+use anyhow::Result;
+use pipeweaver_ipc::commands::{
+    ApiError, FilterRole, GraphEndpoint, GraphFilter, GraphLink, GraphNode, PipewireGraph,
+};
+use pipeweaver_pipewire::oneshot;
+use pipeweaver_pipewire::{LinkType, PipewireMessage};
+use pipeweaver_shared::Mix;
+use std::collections::HashMap;
+use strum::IntoEnumIterator;
+use ulid::Ulid;
+
+pub(crate) trait GraphManagement {
+    async fn graph_get(&self) -> Result<PipewireGraph>;
+}
+
+impl GraphManagement for PipewireManager {
+    async fn graph_get(&self) -> Result<PipewireGraph> {
+        let (send, recv) = oneshot::channel();
+
+        let message = PipewireMessage::GetGraph(send);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        let snapshot = recv.await.map_err(ApiError::pipewire)?;
+
+        let roles = self.graph_filter_roles();
+
+        let mut nodes: Vec<GraphNode> = snapshot
+            .nodes
+            .into_iter()
+            .map(|node| GraphNode {
+                id: node.id,
+                name: self.graph_node_name(node.id),
+                pw_id: node.pw_id,
+                ready: node.ready,
+            })
+            .collect();
+
+        let mut filters: Vec<GraphFilter> = snapshot
+            .filters
+            .into_iter()
+            .map(|filter| {
+                let (role, owner) = match roles.get(&filter.id) {
+                    Some(&(role, owner)) => (role, Some(owner)),
+                    None => (FilterRole::Unknown, None),
+                };
+
+                GraphFilter {
+                    id: filter.id,
+                    pw_id: filter.pw_id,
+                    role,
+                    owner,
+                    owner_name: owner.and_then(|owner| self.graph_node_name(owner)),
+                }
+            })
+            .collect();
+
+        let links = snapshot
+            .links
+            .into_iter()
+            .map(|link| GraphLink {
+                source: graph_endpoint(link.source),
+                destination: graph_endpoint(link.destination),
+                pw_ids: link.pw_ids,
+                ready: link.ready,
+            })
+            .collect();
+
+        // Ulids sort by creation time, which keeps the output stable between requests
+        nodes.sort_by_key(|node| node.id);
+        filters.sort_by_key(|filter| filter.id);
+
+        Ok(PipewireGraph { nodes, filters, links })
+    }
+}
+
+trait GraphManagementLocal {
+    fn graph_filter_roles(&self) -> HashMap<Ulid, (FilterRole, Ulid)>;
+    fn graph_node_name(&self, id: Ulid) -> Option<String>;
+}
+
+impl GraphManagementLocal for PipewireManager {
+    fn graph_filter_roles(&self) -> HashMap<Ulid, (FilterRole, Ulid)> {
+        let mut roles = HashMap::new();
+
+        // Physical Sources and Targets use the node's ID for their entry filter
+        for device in &self.profile.devices.sources.physical_devices {
+            let id = device.description.id;
+            roles.insert(id, (FilterRole::PassThrough, id));
+        }
+        for device in &self.profile.devices.targets.physical_devices {
+            let id = device.description.id;
+            roles.insert(id, (FilterRole::TargetVolume, id));
+        }
+
+        for (&id, mixes) in &self.source_map {
+            for mix in Mix::iter() {
+                roles.insert(mixes[mix], (FilterRole::Volume(mix), id));
+            }
+        }
+        for (&id, &volume) in &self.target_map {
+            roles.insert(volume, (FilterRole::TargetVolume, id));
+        }
+        for (&id, &meter) in &self.meter_map {
+            roles.insert(meter, (FilterRole::Meter, id));
+        }
+
+        roles
+    }
+
+    fn graph_node_name(&self, id: Ulid) -> Option<String> {
+        let sources = &self.profile.devices.sources;
+        let targets = &self.profile.devices.targets;
+
+        let description = sources
+            .physical_devices
+            .iter()
+            .map(|d| &d.description)
+            .chain(sources.virtual_devices.iter().map(|d| &d.description))
+            .chain(targets.physical_devices.iter().map(|d| &d.description))
+            .chain(targets.virtual_devices.iter().map(|d| &d.description))
+            .find(|description| description.id == id)?;
+
+        Some(description.name.clone())
+    }
+}
+
+fn graph_endpoint(link: LinkType) -> GraphEndpoint {
+    match link {
+        LinkType::Node(id) => GraphEndpoint::Node(id),
+        LinkType::Filter(id) => GraphEndpoint::Filter(id),
+        LinkType::UnmanagedNode(id) => GraphEndpoint::Unmanaged(id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::components::node::NodeManagement;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_profile::Profile;
+
+    #[tokio::test]
+    async fn graph_labels_every_filter() {
+        let (manager, _pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+
+        let graph = manager.graph_get().await.unwrap();
+        assert!(graph.filters.iter().all(|filter| filter.role != FilterRole::Unknown));
+
+        let node = graph.nodes.iter().find(|node| node.id == stream).unwrap();
+        assert_eq!(node.name.as_deref(), Some("Stream Mix"));
+
+        let mix_a = manager.source_map[&system][Mix::A];
+        let filter = graph.filters.iter().find(|filter| filter.id == mix_a).unwrap();
+        assert_eq!(filter.role, FilterRole::Volume(Mix::A));
+        assert_eq!(filter.owner_name.as_deref(), Some("System"));
+
+        // The Stream Mix volume feeds its node
+        let volume = manager.get_target_filter_node(stream).unwrap();
+        let link = (GraphEndpoint::Filter(volume), GraphEndpoint::Node(stream));
+        assert!(graph.links.iter().any(|l| (l.source, l.destination) == link));
+    }
+}
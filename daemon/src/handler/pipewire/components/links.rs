@@ -1,5 +1,6 @@
 use crate::handler::pipewire::manager::PipewireManager;
 use anyhow::Result;
+use pipeweaver_ipc::commands::ApiError;
 use pipeweaver_pipewire::oneshot;
 use pipeweaver_pipewire::{LinkType, PipewireMessage};
 use ulid::Ulid;
@@ -25,6 +26,9 @@ pub(crate) trait LinkManagement {
 
     async fn link_remove_type_to_type(&self, source: LinkType, target: LinkType) -> Result<()>;
 
+    /// Drops any extra copies of a link, leaving one in place
+    async fn link_dedupe_type_to_type(&self, source: LinkType, target: LinkType) -> Result<()>;
+
     async fn link_remove_node_to_node(&self, source: Ulid, target: Ulid) -> Result<()>;
     async fn link_remove_node_to_filter(&self, source: Ulid, target: Ulid) -> Result<()>;
     async fn link_remove_node_to_unmanaged(&self, source: Ulid, target: u32) -> Result<()>;
@@ -76,6 +80,11 @@ impl LinkManagement for PipewireManager {
     async fn link_remove_type_to_type(&self, source: LinkType, target: LinkType) -> Result<()> {
         self.remove_link(source, target).await
     }
+    async fn link_dedupe_type_to_type(&self, source: LinkType, target: LinkType) -> Result<()> {
+        let message = PipewireMessage::RemoveDuplicateLinks(source, target);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
+    }
     async fn link_remove_node_to_node(&self, source: Ulid, target: Ulid) -> Result<()> {
         self.remove_link(LinkType::Node(source), LinkType::Node(target)).await
     }
@@ -116,14 +125,15 @@ impl LinkManagementLocal for PipewireManager {
     async fn create_link(&self, source: LinkType, target: LinkType) -> Result<()> {
         let (send, recv) = oneshot::channel();
         let message = PipewireMessage::CreateDeviceLink(source, target, Some(send));
-        self.pipewire().send_message(message)?;
-        recv.await?;
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        recv.await.map_err(ApiError::pipewire)?;
 
         Ok(())
     }
 
     async fn remove_link(&self, source: LinkType, target: LinkType) -> Result<()> {
         let message = PipewireMessage::RemoveDeviceLink(source, target);
-        self.pipewire().send_message(message)
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,346 @@
+use crate::handler::mpris::{player_matches, MprisMessage};
+use crate::handler::pipewire::components::node::NodeManagement;
+use crate::handler::pipewire::components::volume::VolumeManager;
+use crate::handler::pipewire::manager::PipewireManager;
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+use pipeweaver_ipc::commands::{APICommand, ApiError};
+use pipeweaver_pipewire::PipewireMessage;
+use pipeweaver_profile::{LiveAction, LiveConfiguration, LiveTrigger};
+use pipeweaver_shared::NodeType;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use ulid::Ulid;
+
+#[derive(Default)]
+pub(crate) struct LiveState {
+    pub(crate) live: bool,
+    last_active: Option<Instant>,
+
+    // Application nodes we've ducked, the volume to restore them to, and the ducked volume
+    ducked: HashMap<u32, (u8, u8)>,
+
+    // Whether metering has been requested by something other than live detection
+    pub(crate) metering_requested: bool,
+
+    pub(crate) mpris: Option<mpsc::Sender<MprisMessage>>,
+}
+
+pub(crate) trait LiveManagement {
+    async fn live_set_configuration(&mut self, config: LiveConfiguration) -> Result<()>;
+    async fn live_set_metering(&mut self, enabled: bool) -> Result<()>;
+
+    async fn live_meter_event(&mut self, id: Ulid, percent: u8);
+    async fn live_check_release(&mut self, now: Instant);
+    async fn live_refresh(&mut self);
+
+    async fn live_application_added(&mut self);
+    fn live_application_removed(&mut self, id: u32);
+}
+
+/// Whether a command can change the outcome of the Unmuted trigger
+pub(crate) fn live_affected_by(command: &APICommand) -> bool {
+    matches!(
+        command,
+        APICommand::AddSourceMuteTarget(..)
+            | APICommand::DelSourceMuteTarget(..)
+            | APICommand::RemoveNode(..)
+    )
+}
+
+impl LiveManagement for PipewireManager {
+    async fn live_set_configuration(&mut self, config: LiveConfiguration) -> Result<()> {
+        if let Some(trigger) = config.trigger {
+            self.live_check_source(trigger.source())?;
+        }
+        for action in &config.actions {
+            if let LiveAction::Duck { volume, .. } = action {
+                if *volume > 100 {
+                    bail!(ApiError::invalid(None, "Volume Must be between 0 and 100"));
+                }
+            }
+        }
+
+        // Leave the live state cleanly before the configuration changes underneath us
+        self.live_set_state(false).await;
+        self.profile.live = config;
+
+        self.live_update_metering().await?;
+        self.live_refresh().await;
+        Ok(())
+    }
+
+    async fn live_set_metering(&mut self, enabled: bool) -> Result<()> {
+        self.live_state.metering_requested = enabled;
+        self.live_update_metering().await
+    }
+
+    async fn live_meter_event(&mut self, id: Ulid, percent: u8) {
+        let Some(LiveTrigger::Meter { source, threshold }) = self.profile.live.trigger else {
+            return;
+        };
+        if source != id {
+            return;
+        }
+
+        let now = Instant::now();
+        if percent >= threshold {
+            self.live_state.last_active = Some(now);
+            self.live_set_state(true).await;
+            return;
+        }
+        self.live_check_release(now).await;
+    }
+
+    async fn live_check_release(&mut self, now: Instant) {
+        if !self.live_requires_meter() {
+            return;
+        }
+
+        // Only drop out of live once we've been quiet for the release period. This is also
+        // checked on a timer, as the meter may stop reporting (if the source goes away).
+        let release = Duration::from_millis(self.profile.live.release_ms as u64);
+        if let Some(last_active) = self.live_state.last_active {
+            if now.duration_since(last_active) >= release {
+                self.live_state.last_active = None;
+                self.live_set_state(false).await;
+            }
+        }
+    }
+
+    async fn live_refresh(&mut self) {
+        if let Some(LiveTrigger::Unmuted { source, target }) = self.profile.live.trigger {
+            let state = self.profile.get_source_state(source);
+            match state.map(|(_, mute_states)| mute_states.mute_state.contains(&target)) {
+                Some(muted) => self.live_set_state(!muted).await,
+                None => warn!("[Live] Unable to locate Source {}", source),
+            }
+        }
+    }
+
+    async fn live_application_added(&mut self) {
+        // A player which starts while we're live should be ducked as well
+        if self.live_state.live {
+            self.live_apply_ducking().await;
+        }
+    }
+
+    fn live_application_removed(&mut self, id: u32) {
+        self.live_state.ducked.remove(&id);
+    }
+}
+
+trait LiveManagementLocal {
+    fn live_check_source(&self, id: Ulid) -> Result<()>;
+    fn live_requires_meter(&self) -> bool;
+    async fn live_update_metering(&mut self) -> Result<()>;
+
+    async fn live_set_state(&mut self, live: bool);
+    async fn live_apply_ducking(&mut self);
+    async fn live_release_ducking(&mut self);
+}
+
+impl LiveManagementLocal for PipewireManager {
+    fn live_check_source(&self, id: Ulid) -> Result<()> {
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Unknown Node"))?;
+        if !matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
+            bail!(ApiError::invalid(id, "Provided Node is not a Source"));
+        }
+        Ok(())
+    }
+
+    fn live_requires_meter(&self) -> bool {
+        matches!(self.profile.live.trigger, Some(LiveTrigger::Meter { .. }))
+    }
+
+    async fn live_update_metering(&mut self) -> Result<()> {
+        let enabled = self.live_state.metering_requested || self.live_requires_meter();
+        self.set_metering(enabled).await
+    }
+
+    async fn live_set_state(&mut self, live: bool) {
+        if self.live_state.live == live {
+            return;
+        }
+        self.live_state.live = live;
+        info!("[Live] {}", if live { "Going Live" } else { "No Longer Live" });
+
+        for action in self.profile.live.actions.clone() {
+            match action {
+                LiveAction::Pause { player } => {
+                    if let Some(mpris) = &self.live_state.mpris {
+                        let message = match live {
+                            true => MprisMessage::Pause(player),
+                            false => MprisMessage::Resume(player),
+                        };
+                        let _ = mpris.send(message).await;
+                    }
+                }
+                LiveAction::Duck { .. } => {}
+            }
+        }
+
+        match live {
+            true => self.live_apply_ducking().await,
+            false => self.live_release_ducking().await,
+        }
+    }
+
+    async fn live_apply_ducking(&mut self) {
+        let mut ducks = vec![];
+        for action in &self.profile.live.actions {
+            let LiveAction::Duck { player, volume } = action else {
+                continue;
+            };
+
+            let ducked = &self.live_state.ducked;
+            let nodes = self.application_nodes.values();
+            let nodes = nodes.filter(|node| !ducked.contains_key(&node.node_id));
+            for node in nodes.filter(|node| player_matches(&node.name, player)) {
+                // Pipewire may not have told us the volume yet, in which case it's at the default
+                ducks.push((node.node_id, node.volume.unwrap_or(100), *volume));
+            }
+        }
+
+        for (id, original, volume) in ducks {
+            // More than one action may match the same application
+            if self.live_state.ducked.contains_key(&id) {
+                continue;
+            }
+            let volume = original.min(volume);
+            let message = PipewireMessage::SetApplicationVolume(id, volume);
+            match self.pipewire().and_then(|pipewire| pipewire.send_message(message)) {
+                Ok(()) => {
+                    debug!("[Live] Ducking Application {}", id);
+                    self.live_state.ducked.insert(id, (original, volume));
+                }
+                Err(e) => warn!("[Live] Unable to Duck Application {}: {}", id, e),
+            }
+        }
+    }
+
+    async fn live_release_ducking(&mut self) {
+        let ducked: Vec<_> = self.live_state.ducked.drain().collect();
+        let Ok(pipewire) = self.pipewire() else {
+            return;
+        };
+
+        for (id, (original, volume)) in ducked {
+            // The application may have gone away while we were live
+            let Some(node) = self.application_nodes.get(&id) else {
+                continue;
+            };
+
+            // If the volume was changed while ducked, the user's choice wins
+            if node.volume.is_some_and(|current| current != volume) {
+                debug!("[Live] Volume for {} changed while Ducked, leaving it", id);
+                continue;
+            }
+
+            let message = PipewireMessage::SetApplicationVolume(id, original);
+            if let Err(e) = pipewire.send_message(message) {
+                warn!("[Live] Unable to Restore Volume for {}: {}", id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::ipc::IPCHandler;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_pipewire::{ApplicationNode, MediaClass};
+    use pipeweaver_profile::Profile;
+    use pipeweaver_shared::MuteTarget;
+
+    fn application(node_id: u32, name: &str, volume: Option<u8>) -> ApplicationNode {
+        ApplicationNode { node_id, node_class: MediaClass::Source, name: name.to_string(), volume }
+    }
+
+    #[tokio::test]
+    async fn ducking_follows_the_player() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let microphone = manager.profile.find_node("Microphone").unwrap();
+        manager.application_nodes.insert(10, application(10, "Spotify", Some(80)));
+        manager.application_nodes.insert(11, application(11, "Firefox", Some(100)));
+
+        // The microphone isn't muted, so we go live straight away
+        let config = LiveConfiguration {
+            trigger: Some(LiveTrigger::Unmuted { source: microphone, target: MuteTarget::TargetA }),
+            actions: vec![LiveAction::Duck { player: "spotify".into(), volume: 30 }],
+            release_ms: 0,
+        };
+        manager.live_set_configuration(config).await.unwrap();
+        assert!(manager.live_state.live);
+        assert_eq!(pipewire.graph().application_volumes, HashMap::from([(10, 30)]));
+
+        // A player stream which appears while live is ducked too, unless it's already quieter
+        manager.application_nodes.insert(12, application(12, "spotify", Some(20)));
+        manager.live_application_added().await;
+        assert_eq!(pipewire.graph().application_volumes[&12], 20);
+
+        // Muting the microphone releases the duck, restoring the original volumes
+        manager.application_nodes.get_mut(&10).unwrap().volume = Some(30);
+        manager.application_nodes.remove(&12);
+        manager.live_application_removed(12);
+        pipewire.graph().application_volumes.clear();
+
+        let command = APICommand::AddSourceMuteTarget(microphone, MuteTarget::TargetA);
+        assert!(live_affected_by(&command));
+        manager.handle_command(command).await.unwrap();
+        manager.live_refresh().await;
+        assert!(!manager.live_state.live);
+        assert_eq!(pipewire.graph().application_volumes, HashMap::from([(10, 80)]));
+    }
+
+    #[tokio::test]
+    async fn ducking_keeps_volume_changes() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let microphone = manager.profile.find_node("Microphone").unwrap();
+        manager.application_nodes.insert(10, application(10, "Spotify", Some(80)));
+        manager.application_nodes.insert(11, application(11, "Spotify", Some(90)));
+
+        let config = LiveConfiguration {
+            trigger: Some(LiveTrigger::Unmuted { source: microphone, target: MuteTarget::TargetA }),
+            actions: vec![LiveAction::Duck { player: "spotify".into(), volume: 30 }],
+            release_ms: 0,
+        };
+        manager.live_set_configuration(config).await.unwrap();
+
+        // Pipewire reports the ducked volumes back, then the user turns one of them up
+        manager.application_nodes.get_mut(&10).unwrap().volume = Some(30);
+        manager.application_nodes.get_mut(&11).unwrap().volume = Some(50);
+        pipewire.graph().application_volumes.clear();
+
+        let command = APICommand::AddSourceMuteTarget(microphone, MuteTarget::TargetA);
+        manager.handle_command(command).await.unwrap();
+        manager.live_refresh().await;
+        assert_eq!(pipewire.graph().application_volumes, HashMap::from([(10, 80)]));
+    }
+
+    #[tokio::test]
+    async fn meter_release_is_timed() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let microphone = manager.profile.find_node("Microphone").unwrap();
+        manager.application_nodes.insert(10, application(10, "Spotify", None));
+
+        let config = LiveConfiguration {
+            trigger: Some(LiveTrigger::Meter { source: microphone, threshold: 50 }),
+            actions: vec![LiveAction::Duck { player: "spotify".into(), volume: 30 }],
+            release_ms: 500,
+        };
+        manager.live_set_configuration(config).await.unwrap();
+        manager.live_meter_event(microphone, 80).await;
+        assert!(manager.live_state.live);
+        assert_eq!(pipewire.graph().application_volumes[&10], 30);
+
+        // The meter may never report again, so the release timer is what ends it
+        manager.live_check_release(Instant::now()).await;
+        assert!(manager.live_state.live);
+        manager.live_check_release(Instant::now() + Duration::from_secs(1)).await;
+        assert!(!manager.live_state.live);
+        assert_eq!(pipewire.graph().application_volumes[&10], 100);
+    }
+}
@@ -1,14 +1,20 @@
+use crate::handler::pipewire::components::defaults::DefaultManagement;
 use crate::handler::pipewire::components::node::NodeManagement;
+use crate::handler::pipewire::components::physical::PhysicalDevices;
 use crate::handler::pipewire::components::routing::RoutingManagement;
 use crate::handler::pipewire::components::volume::VolumeManager;
 use crate::handler::pipewire::manager::PipewireManager;
 use anyhow::Result;
 use log::{debug, warn};
-use pipeweaver_profile::DeviceDescription;
+use pipeweaver_profile::{DeviceDescription, Profile};
 use pipeweaver_shared::{NodeType, OrderGroup};
+use ulid::Ulid;
 
 pub(crate) trait LoadProfile {
     async fn load_profile(&mut self) -> Result<()>;
+
+    /// Tears down the current profile, and replaces it with a new one
+    async fn reload_profile(&mut self, profile: Profile) -> Result<()>;
 }
 
 impl LoadProfile for PipewireManager {
@@ -17,6 +23,46 @@ impl LoadProfile for PipewireManager {
         self.profile_load_volumes().await?;
         self.profile_apply_routing().await?;
 
+        // A failure here shouldn't stop the rest of the profile from working
+        if let Err(e) = self.default_apply().await {
+            warn!("Unable to set Default Devices: {}", e);
+        }
+        Ok(())
+    }
+
+    async fn reload_profile(&mut self, profile: Profile) -> Result<()> {
+        let (sources, targets) = (&self.profile.devices.sources, &self.profile.devices.targets);
+        let nodes: Vec<Ulid> = sources
+            .physical_devices
+            .iter()
+            .map(|d| d.description.id)
+            .chain(sources.virtual_devices.iter().map(|d| d.description.id))
+            .chain(targets.physical_devices.iter().map(|d| d.description.id))
+            .chain(targets.virtual_devices.iter().map(|d| d.description.id))
+            .collect();
+
+        // Carry on if something fails here, we want as much of the old tree gone as possible
+        for id in nodes {
+            if let Err(e) = self.node_unload(id).await {
+                warn!("Unable to Unload Node {}: {}", id, e);
+            }
+        }
+
+        self.profile = profile;
+        self.load_profile().await?;
+
+        // Physical devices are already present, so they won't be announced again, attach them
+        let (sources, targets) = (&self.profile.devices.sources, &self.profile.devices.targets);
+        let physical: Vec<Ulid> = sources
+            .physical_devices
+            .iter()
+            .map(|d| d.description.id)
+            .chain(targets.physical_devices.iter().map(|d| d.description.id))
+            .collect();
+
+        for id in physical {
+            self.connect_for_node(id).await?;
+        }
         Ok(())
     }
 }
@@ -0,0 +1,86 @@
+use crate::handler::pipewire::components::node::NodeManagement;
+use crate::handler::pipewire::manager::PipewireManager;
+use anyhow::{bail, Result};
+use log::debug;
+use pipeweaver_ipc::commands::ApiError;
+use pipeweaver_profile::{MidiAction, MidiMapping, MidiMessage};
+use pipeweaver_shared::NodeType;
+
+pub(crate) trait MidiManagement {
+    fn midi_add_device(&mut self, name: String) -> Result<()>;
+    fn midi_remove_device(&mut self, name: String) -> Result<()>;
+
+    fn midi_add_mapping(&mut self, message: MidiMessage, action: MidiAction) -> Result<()>;
+    fn midi_remove_mapping(&mut self, message: MidiMessage) -> Result<()>;
+
+    fn midi_set_learn(&mut self, action: Option<MidiAction>) -> Result<()>;
+}
+
+impl MidiManagement for PipewireManager {
+    fn midi_add_device(&mut self, name: String) -> Result<()> {
+        let devices = &mut self.profile.midi.devices;
+        if devices.contains(&name) {
+            bail!(ApiError::no_change(None, "MIDI Device Already Present"));
+        }
+        devices.push(name);
+        Ok(())
+    }
+
+    fn midi_remove_device(&mut self, name: String) -> Result<()> {
+        let devices = &mut self.profile.midi.devices;
+        let index = devices.iter().position(|d| d == &name);
+        let index = index.ok_or(ApiError::not_found(None, "MIDI Device Not Found"))?;
+        devices.remove(index);
+        Ok(())
+    }
+
+    fn midi_add_mapping(&mut self, message: MidiMessage, action: MidiAction) -> Result<()> {
+        self.midi_validate_action(action)?;
+
+        // A message can only ever drive a single action, so replace any existing mapping
+        let mappings = &mut self.profile.midi.mappings;
+        mappings.retain(|mapping| mapping.message != message);
+        mappings.push(MidiMapping { message, action });
+
+        // If we were learning this action, we're done
+        if self.midi_learn == Some(action) {
+            debug!("MIDI Learn Complete: {:?} -> {:?}", message, action);
+            self.midi_learn = None;
+        }
+        Ok(())
+    }
+
+    fn midi_remove_mapping(&mut self, message: MidiMessage) -> Result<()> {
+        let mappings = &mut self.profile.midi.mappings;
+        let index = mappings.iter().position(|m| m.message == message);
+        let index = index.ok_or(ApiError::not_found(None, "MIDI Mapping Not Found"))?;
+        mappings.remove(index);
+        Ok(())
+    }
+
+    fn midi_set_learn(&mut self, action: Option<MidiAction>) -> Result<()> {
+        if let Some(action) = action {
+            self.midi_validate_action(action)?;
+        }
+        self.midi_learn = action;
+        Ok(())
+    }
+}
+
+trait MidiManagementLocal {
+    fn midi_validate_action(&self, action: MidiAction) -> Result<()>;
+}
+
+impl MidiManagementLocal for PipewireManager {
+    fn midi_validate_action(&self, action: MidiAction) -> Result<()> {
+        let source = matches!(action, MidiAction::SourceVolume(..) | MidiAction::SourceMute(..));
+
+        let err = ApiError::not_found(action.node_id(), "Unknown Node");
+        let node_type = self.get_node_type(action.node_id()).ok_or(err)?;
+        let is_source = matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource);
+        if is_source != source {
+            bail!(ApiError::invalid(action.node_id(), "MIDI Action does not match the Node Type"));
+        }
+        Ok(())
+    }
+}
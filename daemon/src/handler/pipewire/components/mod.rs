@@ -1,10 +1,16 @@
 mod audio_filters;
+pub(crate) mod defaults;
 mod filters;
+pub(crate) mod graph;
 pub(crate) mod links;
+pub(crate) mod live;
 pub(crate) mod load_profile;
+pub(crate) mod midi;
 pub(crate) mod mute;
 pub(crate) mod node;
+pub(crate) mod policy;
 pub(crate) mod profile;
+pub(crate) mod reconcile;
 pub(crate) mod routing;
 pub(crate) mod volume;
 pub(crate) mod physical;
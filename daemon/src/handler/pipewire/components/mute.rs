@@ -5,8 +5,9 @@ use crate::handler::pipewire::components::profile::ProfileManagement;
 use crate::handler::pipewire::components::routing::RoutingManagement;
 use crate::handler::pipewire::components::volume::VolumeManager;
 use crate::handler::pipewire::manager::PipewireManager;
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 use log::{debug, info, warn};
+use pipeweaver_ipc::commands::ApiError;
 use pipeweaver_profile::MuteStates;
 use pipeweaver_shared::{Mix, MuteState, MuteTarget, NodeType};
 use std::collections::HashSet;
@@ -28,9 +29,10 @@ pub(crate) trait MuteManager {
 
 impl MuteManager for PipewireManager {
     async fn add_target_mute_node(&mut self, id: Ulid, state: MuteTarget, target: Ulid) -> Result<()> {
-        let node_type = self.get_node_type(target).ok_or(anyhow!("Unknown Node"))?;
+        let err = ApiError::not_found(target, "Unknown Node");
+        let node_type = self.get_node_type(target).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(target, "Provided Target is a Source Node"));
         }
 
         // First get the total target nodes available in the current configuration
@@ -39,7 +41,7 @@ impl MuteManager for PipewireManager {
         // Check whether this target is already present in this mute state
         let mute_state = self.get_source_mute_states_mut(id)?;
         if mute_state.mute_targets[state].contains(&target) {
-            bail!("Target Already in Mute Target");
+            bail!(ApiError::no_change(target, "Target Already in Mute Target"));
         }
 
         // If this MuteTarget is already muted, we should 'fix' the change
@@ -64,15 +66,16 @@ impl MuteManager for PipewireManager {
     }
 
     async fn del_target_mute_node(&mut self, id: Ulid, state: MuteTarget, target: Ulid) -> Result<()> {
-        let node_type = self.get_node_type(target).ok_or(anyhow!("Unknown Node"))?;
+        let err = ApiError::not_found(target, "Unknown Node");
+        let node_type = self.get_node_type(target).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(target, "Provided Target is a Source Node"));
         }
 
         // Check whether this target is already present in this mute state
         let mute_state = self.get_source_mute_states_mut(id)?;
         if !mute_state.mute_targets[state].contains(&target) {
-            bail!("Target Not Present in Mute Target");
+            bail!(ApiError::no_change(target, "Target Not Present in Mute Target"));
         }
 
         // If this MuteTarget is already muted, we should 'fix' the change
@@ -175,30 +178,28 @@ impl MuteManager for PipewireManager {
     }
 
     async fn set_target_mute_state(&mut self, id: Ulid, state: MuteState) -> Result<()> {
-        let node_type = self.get_node_type(id).ok_or(anyhow!("Unknown Node"))?;
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Unknown Node"))?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(id, "Provided Target is a Source Node"));
         }
 
         // Fetch the Filter Ulid for this Target
         let target_filter = &self.get_target_filter_node(id)?;
 
         // Attempt to Grab the 'Unmuted' Volume for this Target
-        let err = anyhow!("Unable to Locate Target");
+        let err = ApiError::not_found(id, "Unable to Locate Target");
         let profile_volume = if node_type == NodeType::PhysicalTarget {
             self.get_physical_target(id).ok_or(err)?.volume
         } else {
             self.get_virtual_target(id).ok_or(err)?.volume
         };
 
-        // Get the Current Mute state in a mutable form (we can safely unwrap here, missing nodes
-        // would have failed above)
+        // Get the Current Mute state in a mutable form
+        let err = ApiError::not_found(id, "Unable to Locate Target");
         let current_state = if node_type == NodeType::PhysicalTarget {
-            let device = self.get_physical_target_mut(id).unwrap();
-            &mut device.mute_state
+            &mut self.get_physical_target_mut(id).ok_or(err)?.mute_state
         } else {
-            let device = self.get_virtual_target_mut(id).unwrap();
-            &mut device.mute_state
+            &mut self.get_virtual_target_mut(id).ok_or(err)?.mute_state
         };
 
         if current_state == &state {
@@ -245,12 +246,13 @@ impl MuteManager for PipewireManager {
     }
 
     async fn get_target_mute_state(&self, target: Ulid) -> Result<MuteState> {
-        let node_type = self.get_node_type(target).ok_or(anyhow!("Unknown Node"))?;
+        let err = ApiError::not_found(target, "Unknown Node");
+        let node_type = self.get_node_type(target).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(target, "Provided Source is a Target Node"));
         }
 
-        let err = anyhow!("Unable to Find Target");
+        let err = ApiError::not_found(target, "Unable to Find Target");
         let state = if node_type == NodeType::PhysicalTarget {
             &self.get_physical_target(target).ok_or(err)?.mute_state
         } else {
@@ -290,12 +292,13 @@ impl MuteManagerLocal for PipewireManager {
     }
 
     fn get_source_mute_states(&self, source: Ulid) -> Result<&MuteStates> {
-        let node_type = self.get_node_type(source).ok_or(anyhow!("Unknown Node"))?;
+        let err = ApiError::not_found(source, "Unknown Node");
+        let node_type = self.get_node_type(source).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(source, "Provided Source is a Target Node"));
         }
 
-        let err = anyhow!("Unable to Find Source");
+        let err = ApiError::not_found(source, "Unable to Find Source");
         let states = if node_type == NodeType::PhysicalSource {
             &self.get_physical_source(source).ok_or(err)?.mute_states
         } else {
@@ -306,12 +309,13 @@ impl MuteManagerLocal for PipewireManager {
     }
 
     fn get_source_mute_states_mut(&mut self, source: Ulid) -> Result<&mut MuteStates> {
-        let node_type = self.get_node_type(source).ok_or(anyhow!("Unknown Node"))?;
+        let err = ApiError::not_found(source, "Unknown Node");
+        let node_type = self.get_node_type(source).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(source, "Provided Source is a Target Node"));
         }
 
-        let err = anyhow!("Unable to Find Source");
+        let err = ApiError::not_found(source, "Unable to Find Source");
         let states = if node_type == NodeType::PhysicalSource {
             &mut self.get_physical_source_mut(source).ok_or(err)?.mute_states
         } else {
@@ -323,7 +327,7 @@ impl MuteManagerLocal for PipewireManager {
 
 
     async fn mute_remove_volume(&mut self, source: Ulid) -> Result<()> {
-        let mix_err = anyhow!("Unable to Find Source Mixes");
+        let mix_err = ApiError::not_found(source, "Unable to Find Source Mixes");
         let map = self.source_map.get(&source).copied().ok_or(mix_err)?;
 
         debug!("Action: Set Volume to 0 for Channel");
@@ -345,17 +349,18 @@ impl MuteManagerLocal for PipewireManager {
     }
 
     async fn mute_remove_route(&mut self, source: Ulid, target: Ulid) -> Result<()> {
-        let mix_err = anyhow!("Unable to Find Source Mixes");
+        let mix_err = ApiError::not_found(source, "Unable to Find Source Mixes");
         let map = self.source_map.get(&source).copied().ok_or(mix_err)?;
 
         if !self.routing_route_exists(source, target).await? {
             // We don't have a route here anyway, so nothing to remove.
-            bail!("Route doesn't Exist");
+            bail!(ApiError::not_found(target, "Route doesn't Exist"));
         }
 
-        let node_type = self.get_node_type(target).ok_or(anyhow!("Cannot Find Node"))?;
+        let err = ApiError::not_found(target, "Cannot Find Node");
+        let node_type = self.get_node_type(target).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(target, "Provided Target is a Source Node"));
         }
 
         let target_node = self.get_target_filter_node(target)?;
@@ -366,7 +371,7 @@ impl MuteManagerLocal for PipewireManager {
     }
 
     async fn mute_restore_volume(&mut self, source: Ulid) -> Result<()> {
-        let mix_err = anyhow!("Unable to Find Source Mixes");
+        let mix_err = ApiError::not_found(source, "Unable to Find Source Mixes");
         let map = self.source_map.get(&source).copied().ok_or(mix_err)?;
 
         let profile_volume_a = self.get_node_volume(source, Mix::A)?;
@@ -391,17 +396,18 @@ impl MuteManagerLocal for PipewireManager {
     }
 
     async fn mute_restore_route(&mut self, source: Ulid, target: Ulid) -> Result<()> {
-        let mix_err = anyhow!("Unable to Find Source Mixes");
+        let mix_err = ApiError::not_found(source, "Unable to Find Source Mixes");
         let map = self.source_map.get(&source).copied().ok_or(mix_err)?;
 
         if !self.routing_route_exists(source, target).await? {
             // We don't have a route here anyway, so nothing to remove.
-            bail!("Route doesn't Exist");
+            bail!(ApiError::not_found(target, "Route doesn't Exist"));
         }
 
-        let node_type = self.get_node_type(target).ok_or(anyhow!("Cannot Find Node"))?;
+        let err = ApiError::not_found(target, "Cannot Find Node");
+        let node_type = self.get_node_type(target).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(target, "Provided Target is a Source Node"));
         }
 
         let target_node = self.get_target_filter_node(target)?;
@@ -410,3 +416,69 @@ impl MuteManagerLocal for PipewireManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_profile::Profile;
+
+    #[tokio::test]
+    async fn mute_to_all_silences_both_mixes() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let music = manager.profile.find_node("Music").unwrap();
+        let mixes = manager.source_map[&music];
+        let volume = pipewire.graph().filter_volume(mixes[Mix::A]).unwrap();
+
+        manager.set_source_mute_state(music, MuteTarget::TargetA, MuteState::Muted).await.unwrap();
+        assert_eq!(pipewire.graph().filter_volume(mixes[Mix::A]), Some(0));
+        assert_eq!(pipewire.graph().filter_volume(mixes[Mix::B]), Some(0));
+
+        let state = MuteState::Unmuted;
+        manager.set_source_mute_state(music, MuteTarget::TargetA, state).await.unwrap();
+        assert_eq!(pipewire.graph().filter_volume(mixes[Mix::A]), Some(volume));
+    }
+
+    #[tokio::test]
+    async fn mute_to_target_only_removes_that_route() {
+        // Game's Target A mutes it to the Stream Mix only
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let game = manager.profile.find_node("Game").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+
+        let mixes = manager.source_map[&game];
+        let stream_filter = manager.get_target_filter_node(stream).unwrap();
+        let stream_mix = manager.routing_get_target_mix(&stream).await.unwrap();
+        let headphones_filter = manager.get_target_filter_node(headphones).unwrap();
+        let headphones_mix = manager.routing_get_target_mix(&headphones).await.unwrap();
+
+        assert!(pipewire.graph().is_linked(mixes[stream_mix], stream_filter));
+
+        manager.set_source_mute_state(game, MuteTarget::TargetA, MuteState::Muted).await.unwrap();
+        {
+            let graph = pipewire.graph();
+            assert!(!graph.is_linked(mixes[stream_mix], stream_filter));
+            assert!(graph.is_linked(mixes[headphones_mix], headphones_filter));
+            assert_ne!(graph.filter_volume(mixes[Mix::A]), Some(0));
+        }
+
+        manager.set_source_mute_state(game, MuteTarget::TargetA, MuteState::Unmuted).await.unwrap();
+        assert!(pipewire.graph().is_linked(mixes[stream_mix], stream_filter));
+    }
+
+    #[tokio::test]
+    async fn target_mute_silences_the_target() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+        let filter = manager.get_target_filter_node(headphones).unwrap();
+        let volume = pipewire.graph().filter_volume(filter).unwrap();
+
+        manager.set_target_mute_state(headphones, MuteState::Muted).await.unwrap();
+        assert_eq!(pipewire.graph().filter_volume(filter), Some(0));
+        assert_eq!(manager.get_target_mute_state(headphones).await.unwrap(), MuteState::Muted);
+
+        manager.set_target_mute_state(headphones, MuteState::Unmuted).await.unwrap();
+        assert_eq!(pipewire.graph().filter_volume(filter), Some(volume));
+    }
+}
@@ -1,25 +1,35 @@
+use crate::handler::pipewire::components::defaults::DefaultManagement;
 use crate::handler::pipewire::components::filters::FilterManagement;
 use crate::handler::pipewire::components::links::LinkManagement;
 use crate::handler::pipewire::components::physical::PhysicalDevices;
+use crate::handler::pipewire::components::policy::PolicyManagement;
 use crate::handler::pipewire::components::profile::ProfileManagement;
 use crate::handler::pipewire::components::routing::RoutingManagement;
 use crate::handler::pipewire::components::volume::VolumeManager;
 use crate::handler::pipewire::manager::PipewireManager;
 use crate::{APP_ID, APP_NAME};
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 use enum_map::{enum_map, EnumMap};
+use log::debug;
+use pipeweaver_ipc::commands::ApiError;
 use pipeweaver_pipewire::oneshot;
-use pipeweaver_pipewire::{MediaClass, NodeProperties, PipewireMessage};
+use pipeweaver_pipewire::{MediaClass, NodeLabels, NodeProperties, PipewireError, PipewireMessage};
 use pipeweaver_profile::{
     DeviceDescription, PhysicalSourceDevice, PhysicalTargetDevice, VirtualSourceDevice,
     VirtualTargetDevice,
 };
 use pipeweaver_shared::{Colour, Mix, NodeType, OrderGroup};
+use std::env;
+use std::time::Duration;
 use strum::IntoEnumIterator;
+use tokio::time;
 use ulid::Ulid;
 
 type GroupList = EnumMap<OrderGroup, Vec<Ulid>>;
 
+// How long Pipewire gets to show a renamed node's new labels before it's rebuilt instead
+const RENAME_TIMEOUT: Duration = Duration::from_millis(500);
+
 /// This crate contains everything needed to create a Pipewire node
 pub(crate) trait NodeManagement {
     fn get_node_type(&self, id: Ulid) -> Option<NodeType>;
@@ -33,8 +43,12 @@ pub(crate) trait NodeManagement {
         description: &DeviceDescription,
     ) -> Result<()>;
     async fn node_rename(&mut self, id: Ulid, name: String) -> Result<()>;
+    async fn node_rebuild(&mut self, id: Ulid) -> Result<()>;
     async fn node_remove(&mut self, id: Ulid) -> Result<()>;
 
+    /// Tears down the node in Pipewire, while leaving it in the profile
+    async fn node_unload(&mut self, id: Ulid) -> Result<()>;
+
     async fn node_set_group(&mut self, id: Ulid, group: OrderGroup) -> Result<()>;
     async fn node_set_position(&mut self, id: Ulid, position: u8) -> Result<()>;
 
@@ -79,19 +93,19 @@ impl NodeManagement for PipewireManager {
     }
 
     fn get_target_filter_node(&self, id: Ulid) -> Result<Ulid> {
-        let err = anyhow!("Target Node not Found");
+        let err = ApiError::not_found(id, "Target Node not Found");
         let node_type = self.get_node_type(id).ok_or(err)?;
         if !matches!(
             node_type,
             NodeType::PhysicalTarget | NodeType::VirtualTarget
         ) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(id, "Provided Target is a Source Node"));
         }
 
         if node_type == NodeType::PhysicalTarget {
             Ok(id)
         } else {
-            let err = anyhow!("Unable to Locate Volume for Target");
+            let err = ApiError::not_found(id, "Unable to Locate Volume for Target");
             Ok(*self.target_map.get(&id).ok_or(err)?)
         }
     }
@@ -177,15 +191,20 @@ impl NodeManagement for PipewireManager {
     }
 
     async fn node_rename(&mut self, id: Ulid, name: String) -> Result<()> {
-        // If we're renaming a node, we need to teardown the original node, then rebuild it with
-        // the new name. I've checked for an easy way to do this directly in PipeWire, but
-        // ultimately couldn't find one.
-        //
-        // What we need to do here, is teardown the original, update the profile descriptor, then
-        // create a new one with the same settings.
+        // Rebuilding a node drops any audio passing through it, and disconnects anything
+        // attached to it, so we try to update the names in place first. Pipewire doesn't allow
+        // this for every node, in which case we fall back to tearing down and recreating it.
+        self.get_device_description(id)?.name = name;
+        if let Err(e) = self.node_pw_rename(id).await {
+            debug!("[{}] Unable to rename in place, rebuilding: {}", id, e);
+            return self.node_rebuild(id).await;
+        }
+        Ok(())
+    }
 
+    async fn node_rebuild(&mut self, id: Ulid) -> Result<()> {
         // First thing we need to do, is to find this node
-        let err = anyhow!("Unable to find Node");
+        let err = ApiError::not_found(id, "Unable to find Node");
         let node_type = self.get_node_type(id).ok_or(err)?;
 
         // Now we remove it, and all associated filters, while leaving it in the profile
@@ -196,12 +215,8 @@ impl NodeManagement for PipewireManager {
             NodeType::VirtualTarget => self.node_remove_virtual_target(id, false).await?,
         }
 
-        // Update the name in the profile
-        let description = self.get_device_description(id)?;
-        description.name = name;
-
         // Create a local version of this description, create the node tree and load volumes
-        let local_desc = description.clone();
+        let local_desc = self.get_device_description(id)?.clone();
         self.node_create(node_type, &local_desc).await?;
         self.load_initial_volume(id).await?;
 
@@ -219,6 +234,12 @@ impl NodeManagement for PipewireManager {
             self.connect_for_node(id).await?;
         }
 
+        // Pipewire forgets the default when the node goes away, so it needs setting again
+        let defaults = self.profile.defaults;
+        if defaults.sink == Some(id) || defaults.source == Some(id) {
+            self.default_apply().await?;
+        }
+
         Ok(())
     }
 
@@ -232,10 +253,36 @@ impl NodeManagement for PipewireManager {
                 NodeType::PhysicalTarget => self.node_remove_physical_target(id, true).await?,
                 NodeType::VirtualTarget => self.node_remove_virtual_target(id, true).await?,
             }
+
+            // Drop any MIDI mappings and Live configuration which referenced this node
+            self.profile.midi.mappings.retain(|m| m.action.node_id() != id);
+
+            let live = &mut self.profile.live;
+            if live.trigger.is_some_and(|trigger| trigger.source() == id) {
+                live.trigger = None;
+            }
+
+            let defaults = &mut self.profile.defaults;
+            if defaults.sink == Some(id) {
+                defaults.sink = None;
+            }
+            if defaults.source == Some(id) {
+                defaults.source = None;
+            }
         }
         Ok(())
     }
 
+    async fn node_unload(&mut self, id: Ulid) -> Result<()> {
+        let err = ApiError::not_found(id, "Unable to find Node");
+        match self.get_node_type(id).ok_or(err)? {
+            NodeType::PhysicalSource => self.node_remove_physical_source(id, false).await,
+            NodeType::VirtualSource => self.node_remove_virtual_source(id, false).await,
+            NodeType::PhysicalTarget => self.node_remove_physical_target(id, false).await,
+            NodeType::VirtualTarget => self.node_remove_virtual_target(id, false).await,
+        }
+    }
+
     async fn node_set_group(&mut self, id: Ulid, group: OrderGroup) -> Result<()> {
         let device_order = self.get_device_order_group(id)?;
 
@@ -321,33 +368,21 @@ impl NodeManagement for PipewireManager {
     // }
 
     async fn node_set_colour(&mut self, id: Ulid, colour: Colour) -> Result<()> {
-        if let Some(node_type) = self.get_node_type(id) {
-            let err = anyhow!("Cannot Find Node");
-            match node_type {
-                NodeType::PhysicalSource => {
-                    self.get_physical_source_mut(id)
-                        .ok_or(err)?
-                        .description
-                        .colour = colour
-                }
-                NodeType::PhysicalTarget => {
-                    self.get_physical_target_mut(id)
-                        .ok_or(err)?
-                        .description
-                        .colour = colour
-                }
-                NodeType::VirtualSource => {
-                    self.get_virtual_source_mut(id)
-                        .ok_or(err)?
-                        .description
-                        .colour = colour
-                }
-                NodeType::VirtualTarget => {
-                    self.get_virtual_target_mut(id)
-                        .ok_or(err)?
-                        .description
-                        .colour = colour
-                }
+        let err = || ApiError::not_found(id, "Cannot Find Node");
+        let node_type = self.get_node_type(id).ok_or_else(err)?;
+
+        match node_type {
+            NodeType::PhysicalSource => {
+                self.get_physical_source_mut(id).ok_or_else(err)?.description.colour = colour
+            }
+            NodeType::PhysicalTarget => {
+                self.get_physical_target_mut(id).ok_or_else(err)?.description.colour = colour
+            }
+            NodeType::VirtualSource => {
+                self.get_virtual_source_mut(id).ok_or_else(err)?.description.colour = colour
+            }
+            NodeType::VirtualTarget => {
+                self.get_virtual_target_mut(id).ok_or_else(err)?.description.colour = colour
             }
         }
         Ok(())
@@ -373,12 +408,18 @@ trait NodeManagementLocal {
     async fn node_remove_physical_target(&mut self, id: Ulid, profile_remove: bool) -> Result<()>;
     async fn node_remove_virtual_target(&mut self, id: Ulid, profile_remove: bool) -> Result<()>;
     async fn node_pw_remove(&mut self, id: Ulid) -> Result<()>;
+    async fn node_pw_rename(&mut self, id: Ulid) -> Result<()>;
 
     /// Used to Remove all Links from a Filter
     async fn remove_routes(&mut self, source: Ulid, target: Ulid) -> Result<()>;
 
     /// Used to set up the parameters needed for a Pipewire Node
-    fn create_node_props(&self, class: MediaClass, desc: &DeviceDescription) -> NodeProperties;
+    fn create_node_props(
+        &self,
+        class: MediaClass,
+        desc: &DeviceDescription,
+    ) -> Result<NodeProperties>;
+    fn create_node_labels(&self, desc: &DeviceDescription) -> NodeLabels;
 
     fn get_device_order_group(&mut self, id: Ulid) -> Result<&mut GroupList>;
     fn find_order_group_by_id(id: Ulid, map: &mut GroupList) -> Result<&mut Vec<Ulid>>;
@@ -416,7 +457,7 @@ impl NodeManagementLocal for PipewireManager {
 
     async fn node_create_virtual_source(&mut self, desc: &DeviceDescription) -> Result<()> {
         // A 'Virtual' source is a pipewire node that's selectable by the user.
-        let properties = self.create_node_props(MediaClass::Sink, desc);
+        let properties = self.create_node_props(MediaClass::Sink, desc)?;
         self.node_pw_create(properties).await?;
 
         // Create a Meter
@@ -461,7 +502,7 @@ impl NodeManagementLocal for PipewireManager {
 
     async fn node_create_virtual_target(&mut self, desc: &DeviceDescription) -> Result<()> {
         // Virtual Targets (Such as Stream Mix) have a volume node and a target node
-        let properties = self.create_node_props(MediaClass::Source, desc);
+        let properties = self.create_node_props(MediaClass::Source, desc)?;
         self.node_pw_create(properties).await?;
 
         // Link the Volume to the Target Node
@@ -498,8 +539,8 @@ impl NodeManagementLocal for PipewireManager {
         props.ready_sender = Some(send);
 
         let message = PipewireMessage::CreateDeviceNode(props);
-        self.pipewire().send_message(message)?;
-        recv.await?;
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        recv.await.map_err(ApiError::pipewire)?;
 
         Ok(())
     }
@@ -742,7 +783,51 @@ impl NodeManagementLocal for PipewireManager {
 
     async fn node_pw_remove(&mut self, id: Ulid) -> Result<()> {
         let message = PipewireMessage::RemoveDeviceNode(id);
-        self.pipewire().send_message(message)?;
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
+    }
+
+    async fn node_pw_rename(&mut self, id: Ulid) -> Result<()> {
+        let err = ApiError::not_found(id, "Unable to find Node");
+        let node_type = self.get_node_type(id).ok_or(err)?;
+        let desc = self.get_device_description(id)?.clone();
+
+        // The node goes first, as it's the one Pipewire is most likely to refuse
+        if matches!(node_type, NodeType::VirtualSource | NodeType::VirtualTarget) {
+            let labels = self.create_node_labels(&desc);
+            let (send, recv) = oneshot::channel();
+            let message = PipewireMessage::UpdateNodeProperties(id, labels, Some(send));
+            self.pipewire()?.send_message(message)?;
+
+            // Pipewire will happily accept properties it then ignores, so only count the node as
+            // renamed once it reports the new labels
+            if !matches!(time::timeout(RENAME_TIMEOUT, recv).await, Ok(Ok(()))) {
+                bail!(PipewireError::ReadOnly(id));
+            }
+        }
+
+        // The filters are named the same way the node_create functions name them
+        let mut filters = vec![];
+        match node_type {
+            NodeType::PhysicalSource | NodeType::PhysicalTarget => {
+                filters.push((id, desc.name.clone()));
+            }
+            NodeType::VirtualTarget => {
+                filters.push((self.get_target_filter_node(id)?, desc.name.clone()));
+            }
+            NodeType::VirtualSource => {}
+        }
+        if let Some(mixes) = self.source_map.get(&id) {
+            filters.push((mixes[Mix::A], format!("{} A", desc.name)));
+            filters.push((mixes[Mix::B], format!("{} B", desc.name)));
+        }
+        if let Some(&meter) = self.meter_map.get(&id) {
+            filters.push((meter, format!("{}-meter", desc.name)));
+        }
+
+        for (filter, name) in filters {
+            self.filter_set_name(filter, name).await?;
+        }
         Ok(())
     }
 
@@ -756,26 +841,49 @@ impl NodeManagementLocal for PipewireManager {
         Ok(())
     }
 
-    fn create_node_props(&self, class: MediaClass, desc: &DeviceDescription) -> NodeProperties {
-        let volume = self.get_node_volume(desc.id, Mix::A).unwrap();
+    fn create_node_props(
+        &self,
+        class: MediaClass,
+        desc: &DeviceDescription,
+    ) -> Result<NodeProperties> {
+        let volume = self.get_node_volume(desc.id, Mix::A)?;
 
-        let identifier = format!("{} {}", APP_NAME, desc.name)
-            .to_lowercase()
-            .replace(" ", "_");
+        let labels = self.create_node_labels(desc);
+        let policy = self.get_node_policy(desc.id).unwrap_or_default();
+        let metadata = self.get_node_metadata(desc.id).unwrap_or_default();
 
-        NodeProperties {
+        Ok(NodeProperties {
             node_id: desc.id,
-            node_name: identifier.clone(),
-            node_nick: identifier,
-            node_description: format!("{} {}", APP_NAME, desc.name),
+            node_name: labels.nick.clone(),
+            node_nick: labels.nick,
+            node_description: labels.description,
             initial_volume: volume,
             app_id: APP_ID.to_string(),
             app_name: APP_NAME.to_lowercase(),
             linger: false,
             class,
+            dont_reconnect: policy.dont_reconnect,
+            autoconnect: policy.autoconnect,
+            priority_session: policy.priority_session,
+            priority_driver: policy.priority_driver,
+            media_role: metadata.media_role,
+            icon_name: metadata.icon_name,
+            virtual_microphone: metadata.virtual_microphone,
             buffer: 512,
             ready_sender: None,
-        }
+        })
+    }
+
+    fn create_node_labels(&self, desc: &DeviceDescription) -> NodeLabels {
+        let identifier = format!("{} {}", APP_NAME, desc.name).to_lowercase().replace(" ", "_");
+        let metadata = self.get_node_metadata(desc.id).unwrap_or_default();
+
+        // Use a translated description if we have one for the current locale
+        let description = current_locale()
+            .and_then(|locale| metadata.description_for(&locale).cloned())
+            .unwrap_or_else(|| format!("{} {}", APP_NAME, desc.name));
+
+        NodeLabels { nick: identifier, description }
     }
 
     fn get_device_order_group(&mut self, id: Ulid) -> Result<&mut GroupList> {
@@ -790,7 +898,7 @@ impl NodeManagementLocal for PipewireManager {
             };
             return Ok(device_order);
         }
-        bail!("Node Id {} not found", id)
+        bail!(ApiError::not_found(id, "Node not found"))
     }
 
 
@@ -800,7 +908,7 @@ impl NodeManagementLocal for PipewireManager {
                 return Ok(vec);
             }
         }
-        bail!("Id Not Found in Vec List");
+        bail!(ApiError::not_found(id, "Node not found in the Device Order"));
     }
 
     fn get_colour(&self, name: String) -> Colour {
@@ -870,3 +978,119 @@ impl NodeManagementLocal for PipewireManager {
         }
     }
 }
+
+/// The locale messages are shown in, following the same precedence as gettext
+fn current_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|key| env::var(key).ok())
+        .find(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_ipc::commands::ErrorCode;
+    use pipeweaver_pipewire::LinkType;
+    use pipeweaver_profile::Profile;
+
+    #[tokio::test]
+    async fn removals_report_pipewire_errors() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let filter = manager.get_target_filter_node(stream).unwrap();
+        pipewire.disconnect();
+
+        let error = ApiError::from(manager.node_pw_remove(system).await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::Pipewire);
+        let result = manager.link_remove_unmanaged_to_filter(10, filter).await;
+        assert_eq!(ApiError::from(result.unwrap_err()).code, ErrorCode::Pipewire);
+    }
+
+    #[tokio::test]
+    async fn rename_rebuilds_the_node_and_its_routes() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        pipewire.graph().read_only.insert(stream);
+
+        let (volume, filters, routes) = {
+            let graph = pipewire.graph();
+            let filter = manager.get_target_filter_node(stream).unwrap();
+            (graph.nodes[&stream].volume, graph.filters.len(), graph.links_to(filter))
+        };
+
+        manager.node_rename(stream, "Broadcast".into()).await.unwrap();
+        assert_eq!(manager.profile.find_node("Broadcast"), Some(stream));
+
+        // The tree is rebuilt with a new volume filter, which should have the same routes
+        let filter = manager.get_target_filter_node(stream).unwrap();
+        let graph = pipewire.graph();
+        assert_eq!(graph.nodes[&stream].description, format!("{} Broadcast", APP_NAME));
+        assert_eq!(graph.nodes[&stream].volume, volume);
+        assert_eq!(graph.filters.len(), filters);
+        assert_eq!(graph.links_to(filter), routes);
+    }
+
+    #[tokio::test]
+    async fn rename_keeps_filters_and_links() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let mic = manager.profile.find_node("Microphone").unwrap();
+        let mixes = manager.source_map[&mic];
+        let links = pipewire.graph().links.clone();
+
+        manager.node_rename(mic, "Podcast Mic".into()).await.unwrap();
+        assert_eq!(manager.profile.find_node("Podcast Mic"), Some(mic));
+
+        // Physical sources are only filters, so can be renamed without touching the tree
+        let graph = pipewire.graph();
+        assert_eq!(manager.source_map[&mic], mixes);
+        assert_eq!(graph.links, links);
+        assert_eq!(graph.filter_names[&mic], "Podcast Mic");
+        assert_eq!(graph.filter_names[&mixes[Mix::B]], "Podcast Mic B");
+        assert_eq!(graph.filter_names[&manager.meter_map[&mic]], "Podcast Mic-meter");
+    }
+
+    #[tokio::test]
+    async fn rename_keeps_application_links() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let mixes = manager.source_map[&system];
+
+        // An application is playing into the source
+        let application = (LinkType::UnmanagedNode(90), LinkType::Node(system));
+        pipewire.graph().links.push(application);
+        let links = pipewire.graph().links.clone();
+
+        manager.node_rename(system, "Desktop".into()).await.unwrap();
+        assert_eq!(manager.profile.find_node("Desktop"), Some(system));
+
+        // The node is updated in place, so nothing attached to it is disturbed
+        let graph = pipewire.graph();
+        let name = format!("{} Desktop", APP_NAME);
+        assert_eq!(graph.nodes[&system].name, name.to_lowercase().replace(' ', "_"));
+        assert_eq!(graph.nodes[&system].description, name);
+        assert_eq!(manager.source_map[&system], mixes);
+        assert_eq!(graph.links, links);
+        assert_eq!(graph.filter_names[&mixes[Mix::A]], "Desktop A");
+    }
+
+    #[tokio::test]
+    async fn rename_rebuilds_when_properties_are_ignored() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let mixes = manager.source_map[&system];
+        pipewire.graph().ignores_properties.insert(system);
+
+        manager.node_rename(system, "Desktop".into()).await.unwrap();
+        assert_eq!(manager.profile.find_node("Desktop"), Some(system));
+
+        // Pipewire took the new properties without applying them, so the node was rebuilt
+        let graph = pipewire.graph();
+        let name = format!("{} Desktop", APP_NAME);
+        assert_ne!(manager.source_map[&system], mixes);
+        assert_eq!(graph.nodes[&system].name, name.to_lowercase().replace(' ', "_"));
+        assert_eq!(graph.nodes[&system].description, name);
+    }
+}
@@ -3,15 +3,20 @@ use crate::handler::pipewire::components::node::NodeManagement;
 use crate::handler::pipewire::components::profile::ProfileManagement;
 use crate::handler::pipewire::manager::PipewireManager;
 use crate::handler::primary_worker::WorkerMessage;
-use anyhow::{anyhow, bail, Result};
-use log::debug;
-use pipeweaver_ipc::commands::PhysicalDevice;
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use pipeweaver_ipc::commands::{ApiError, PhysicalDevice};
 use pipeweaver_pipewire::DeviceNode;
-use pipeweaver_profile::{PhysicalDeviceDescriptor, PhysicalSourceDevice};
+use pipeweaver_profile::{DeviceIdentity, PhysicalDeviceDescriptor};
 use pipeweaver_shared::{DeviceType, NodeType};
+use std::cmp::Reverse;
+use std::collections::HashSet;
 use tokio::sync::mpsc::Sender;
 use ulid::Ulid;
 
+// A profile device's index, its ID, the Pipewire node it's paired with, and the attached index
+type Pairing = (usize, Ulid, u32, usize);
+
 pub(crate) trait PhysicalDevices {
     async fn connect_for_node(&mut self, id: Ulid) -> Result<()>;
 
@@ -21,65 +26,38 @@ pub(crate) trait PhysicalDevices {
     async fn source_device_removed(&mut self, node_id: u32) -> Result<()>;
     async fn target_device_removed(&mut self, node_id: u32) -> Result<()>;
 
+    /// Every profile device (by index) paired with the present node it matches, and the index
+    /// of the attached device it matched through
+    fn physical_pairings(&self, device_type: DeviceType, list: &[PhysicalDevice]) -> Vec<Pairing>;
+
     async fn add_device_to_node(&mut self, id: Ulid, node_id: u32) -> Result<()>;
     async fn remove_device_from_node(&mut self, id: Ulid, vec_index: usize) -> Result<()>;
 }
 
 impl PhysicalDevices for PipewireManager {
     async fn connect_for_node(&mut self, id: Ulid) -> Result<()> {
-        let err = anyhow!("Cannot Locate Node");
+        let err = ApiError::not_found(id, "Cannot Locate Node");
         let node_type = self.get_node_type(id).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::PhysicalSource) {
-            bail!("Provided Target is not a Physical Node");
+            bail!(ApiError::invalid(id, "Provided Target is not a Physical Node"));
         }
 
-        let err = anyhow!("Cannot Find Target Node by ID: {}", id);
-        let devices = match node_type {
-            NodeType::PhysicalSource => {
-                let node = self.profile.devices.sources.physical_devices.iter().find(|node| {
-                    node.description.id == id
-                }).ok_or(err)?;
-                node.attached_devices.clone()
-            }
-            NodeType::PhysicalTarget => {
-                let node = self.profile.devices.targets.physical_devices.iter().find(|node| {
-                    node.description.id == id
-                }).ok_or(err)?;
-                node.attached_devices.clone()
-            }
-            _ => {
-                bail!("Incorrect Node Type");
-            }
+        let device_type = match node_type {
+            NodeType::PhysicalSource => DeviceType::Source,
+            NodeType::PhysicalTarget => DeviceType::Target,
+            _ => bail!(ApiError::invalid(id, "Incorrect Node Type")),
         };
 
-
-        // While during 'device added' we attempt to fix the profile, we're not going to do that
-        // here, as we're iterating over known configuration, so we'll avoid the 'best guess'
-        // description checks, the node Names should be valid.
-        match node_type {
-            NodeType::PhysicalSource => {
-                'start: for device in &self.node_list[DeviceType::Source] {
-                    // Try and match this against our node, check by Name first
-                    for paired in &devices {
-                        // Check by Name First
-                        if paired.name == device.name {
-                            self.link_create_unmanaged_to_filter(device.node_id, id).await?;
-                        }
-                    }
-                }
+        // Each attached device is linked to whichever present node it's paired with
+        let list = &self.node_list[device_type];
+        for (_, device, node_id, _) in self.physical_pairings(device_type, list) {
+            if device != id {
+                continue;
             }
-            NodeType::PhysicalTarget => {
-                'start: for device in &self.node_list[DeviceType::Target] {
-                    // Try and match this against our node, check by Name first
-                    for paired in &devices {
-                        // Check by Name First
-                        if paired.name == device.name {
-                            self.link_create_filter_to_unmanaged(id, device.node_id).await?;
-                        }
-                    }
-                }
+            match device_type {
+                DeviceType::Source => self.link_create_unmanaged_to_filter(node_id, id).await?,
+                DeviceType::Target => self.link_create_filter_to_unmanaged(id, node_id).await?,
             }
-            _ => {}
         }
 
         Ok(())
@@ -88,140 +66,12 @@ impl PhysicalDevices for PipewireManager {
 
     async fn source_device_added(&mut self, node: PhysicalDevice, sender: Sender<WorkerMessage>) -> Result<()> {
         self.node_list[DeviceType::Source].push(node.clone());
-
-        // We need to check through our profile to see if we can find this device
-        let devices = self.profile.devices.sources.physical_devices.clone();
-        'start: for (dev_i, device) in devices.iter().enumerate()
-        {
-            // Clarification needed first, we go over the 'attached device' list twice, the
-            // first time to match the absolute ALSA defined name, and the second time to
-            // attempt to match the description (Human Readable Name). The main reason we do
-            // this is so if you have two devices with the same description, we don't want to
-            // incorrectly match, or consider the work done, the ALSA match should hopefully catch
-            // them all.
-
-            if let Some(node_name) = &node.name {
-                for (name_i, dev) in device.attached_devices.iter().enumerate() {
-                    if let Some(name) = &dev.name {
-                        if name == node_name {
-                            debug!("Attaching Node {} to {}", node.node_id, device.description.id);
-
-                            // Got a hit, attach to our filter, and bring it into the tree
-                            self.link_create_unmanaged_to_filter(node.node_id, device.description.id)
-                                .await?;
-
-                            // We'll force upgrade the description regardless, just to ensure the
-                            // node is accurately represented
-                            let mut descriptor = dev.clone();
-                            descriptor.description = node.description.clone();
-
-                            let mut device = device.clone();
-                            device.attached_devices[name_i] = descriptor;
-                            self.profile.devices.sources.physical_devices[dev_i] = device;
-
-                            // Let the Primary Worker know we've changed the config
-                            let _ = sender.send(WorkerMessage::ProfileChanged).await;
-
-                            break 'start;
-                        }
-                    }
-                }
-            }
-
-            if let Some(node_desc) = &node.description {
-                for (desc_i, dev) in device.attached_devices.iter().enumerate() {
-                    if let Some(desc) = &dev.description {
-                        if desc == node_desc {
-                            // Firstly, attach the Node
-                            debug!("Attaching Node {} to {}", node.node_id, device.description.id);
-                            self.link_create_unmanaged_to_filter(node.node_id, device.description.id)
-                                .await?;
-
-                            debug!("Updating Profile Node to Name: {:?}", node.name);
-
-                            // This is kinda ugly, but due to likely a changed node name, we need to
-                            // update the profile to ensure this now matches the new location for
-                            // future checks. Again, we *WANT* to defer to the name where possible.
-                            let mut descriptor = dev.clone();
-                            descriptor.name = node.name.clone();
-
-                            let mut device = device.clone();
-                            device.attached_devices[desc_i] = descriptor;
-                            self.profile.devices.sources.physical_devices[dev_i] = device;
-                            let _ = sender.send(WorkerMessage::ProfileChanged).await;
-
-                            // Let the Primary Worker know we've changed the config
-                            let _ = sender.send(WorkerMessage::ProfileChanged).await;
-
-                            break 'start;
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(())
+        self.physical_device_attach(DeviceType::Source, node, sender).await
     }
 
     async fn target_device_added(&mut self, node: PhysicalDevice, sender: Sender<WorkerMessage>) -> Result<()> {
         self.node_list[DeviceType::Target].push(node.clone());
-
-        // Same as source node above, so read the comments there :)
-        let devices = self.profile.devices.targets.physical_devices.clone();
-        'start: for (dev_i, device) in devices.iter().enumerate()
-        {
-            if let Some(node_name) = &node.name {
-                for (name_i, dev) in device.attached_devices.iter().enumerate() {
-                    if let Some(name) = &dev.name {
-                        if name == node_name {
-                            debug!("Attaching Node {} to {}", node.node_id, device.description.id);
-
-                            // Got a hit, attach to our filter, and bring it into the tree
-                            self.link_create_filter_to_unmanaged(device.description.id, node.node_id).await?;
-
-                            let mut descriptor = dev.clone();
-                            descriptor.description = node.description.clone();
-
-                            let mut device = device.clone();
-                            device.attached_devices[name_i] = descriptor;
-                            self.profile.devices.targets.physical_devices[dev_i] = device;
-
-                            // Let the Primary Worker know we've changed the config
-                            let _ = sender.send(WorkerMessage::ProfileChanged).await;
-
-                            break 'start;
-                        }
-                    }
-                }
-            }
-
-            if let Some(node_desc) = &node.description {
-                for (desc_i, dev) in device.attached_devices.iter().enumerate() {
-                    if let Some(desc) = &dev.description {
-                        if desc == node_desc {
-                            // Firstly, attach the Node
-                            debug!("Attaching Node {} to {}", device.description.id, node.node_id);
-                            self.link_create_filter_to_unmanaged(device.description.id, node.node_id).await?;
-
-                            debug!("Updating Profile Node to Name: {:?}", node.name);
-                            let mut descriptor = dev.clone();
-                            descriptor.name = node.name.clone();
-
-                            let mut device = device.clone();
-                            device.attached_devices[desc_i] = descriptor;
-                            self.profile.devices.targets.physical_devices[dev_i] = device;
-
-                            // Let the Primary Worker know we've changed the config
-                            let _ = sender.send(WorkerMessage::ProfileChanged).await;
-
-                            break 'start;
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(())
+        self.physical_device_attach(DeviceType::Target, node, sender).await
     }
 
     async fn source_device_removed(&mut self, node_id: u32) -> Result<()> {
@@ -234,10 +84,54 @@ impl PhysicalDevices for PipewireManager {
         Ok(())
     }
 
+    fn physical_pairings(&self, device_type: DeviceType, list: &[PhysicalDevice]) -> Vec<Pairing> {
+        let sources = &self.profile.devices.sources.physical_devices;
+        let targets = &self.profile.devices.targets.physical_devices;
+        let devices: Vec<_> = match device_type {
+            DeviceType::Source => {
+                sources.iter().map(|d| (d.description.id, &d.attached_devices)).collect()
+            }
+            DeviceType::Target => {
+                targets.iter().map(|d| (d.description.id, &d.attached_devices)).collect()
+            }
+        };
+
+        // Every way a present node could be one of the saved devices, best first
+        let live: Vec<_> = list.iter().map(|node| (node.node_id, descriptor(node))).collect();
+        let mut candidates = vec![];
+        for (dev_i, (id, attached)) in devices.into_iter().enumerate() {
+            for (index, saved) in attached.iter().enumerate() {
+                for (node_id, live) in &live {
+                    if let Some(score) = saved.match_score(live) {
+                        candidates.push((score, (dev_i, id, *node_id, index)));
+                    }
+                }
+            }
+        }
+        candidates.sort_by_key(|(score, _)| Reverse(*score));
+
+        // Two identical devices can share a name or description, so the strongest matches are
+        // claimed first, and a node or saved device is out of the running once it's taken
+        let mut nodes: Vec<u32> = list.iter().map(|node| node.node_id).collect();
+        let mut claimed = HashSet::new();
+        let mut pairings = vec![];
+        for (_, pairing) in candidates {
+            let (dev_i, _, node_id, index) = pairing;
+            if !nodes.contains(&node_id) || claimed.contains(&(dev_i, index)) {
+                continue;
+            }
+            nodes.retain(|&node| node != node_id);
+            claimed.insert((dev_i, index));
+            pairings.push(pairing);
+        }
+        pairings
+    }
+
     async fn add_device_to_node(&mut self, id: Ulid, node_id: u32) -> Result<()> {
-        let node_type = self.get_node_type(id).ok_or(anyhow!("Unknown Node"))?;
-        let error = anyhow!("Unable to Locate Node: {}", id);
-        let pw_error = anyhow!("Unable to locate Pipewire Node: {}", node_id);
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Unknown Node"))?;
+        let error = ApiError::not_found(id, "Unable to Locate Node");
+        let message = format!("Unable to locate Pipewire Node: {}", node_id);
+        let pw_error = ApiError::not_found(None, message);
 
         // Find the Pipewire Node
         let node = self.device_nodes.get(&node_id).ok_or(pw_error)?.clone();
@@ -245,10 +139,7 @@ impl PhysicalDevices for PipewireManager {
             NodeType::PhysicalSource => {
                 let device = self.get_physical_source_mut(id).ok_or(error)?;
 
-                let new_node = PhysicalDeviceDescriptor {
-                    name: node.name.clone(),
-                    description: node.description.clone(),
-                };
+                let new_node = descriptor(&physical_device(&node));
 
                 device.attached_devices.push(new_node.clone());
                 let pw_node = self.locate_node(new_node);
@@ -259,10 +150,7 @@ impl PhysicalDevices for PipewireManager {
             NodeType::PhysicalTarget => {
                 let device = self.get_physical_target_mut(id).ok_or(error)?;
 
-                let new_node = PhysicalDeviceDescriptor {
-                    name: node.name.clone(),
-                    description: node.description.clone(),
-                };
+                let new_node = descriptor(&physical_device(&node));
 
                 device.attached_devices.push(new_node.clone());
                 let pw_node = self.locate_node(new_node);
@@ -270,15 +158,15 @@ impl PhysicalDevices for PipewireManager {
                     self.link_create_filter_to_unmanaged(id, node.node_id).await?;
                 }
             }
-            _ => bail!("Node is not a Physical Node"),
+            _ => bail!(ApiError::invalid(id, "Node is not a Physical Node")),
         }
 
         Ok(())
     }
 
     async fn remove_device_from_node(&mut self, id: Ulid, vec_index: usize) -> Result<()> {
-        let node_type = self.get_node_type(id).ok_or(anyhow!("Unknown Node"))?;
-        let error = anyhow!("Unable to Locate Node: {}", id);
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Unknown Node"))?;
+        let error = ApiError::not_found(id, "Unable to Locate Node");
 
         match node_type {
             NodeType::PhysicalSource => {
@@ -301,7 +189,7 @@ impl PhysicalDevices for PipewireManager {
                     self.link_remove_filter_to_unmanaged(id, node.node_id).await?;
                 }
             }
-            _ => bail!("Node is not a Physical Node")
+            _ => bail!(ApiError::invalid(id, "Node is not a Physical Node")),
         }
 
         Ok(())
@@ -309,22 +197,285 @@ impl PhysicalDevices for PipewireManager {
 }
 
 trait PhysicalDevicesLocal {
+    async fn physical_device_attach(
+        &mut self,
+        device_type: DeviceType,
+        node: PhysicalDevice,
+        sender: Sender<WorkerMessage>,
+    ) -> Result<()>;
+
     fn locate_node(&self, descriptor: PhysicalDeviceDescriptor) -> Option<&DeviceNode>;
 }
 
 impl PhysicalDevicesLocal for PipewireManager {
-    fn locate_node(&self, descriptor: PhysicalDeviceDescriptor) -> Option<&DeviceNode> {
-        if let Some(name) = descriptor.name {
-            let node = self.device_nodes.values().find(|node| node.name.as_ref() == Some(&name));
-            if let Some(node) = node {
-                return Some(node);
+    async fn physical_device_attach(
+        &mut self,
+        device_type: DeviceType,
+        node: PhysicalDevice,
+        sender: Sender<WorkerMessage>,
+    ) -> Result<()> {
+        // The new node may be a better fit for a device than a node which is already attached
+        // (two identical devices arriving in the wrong order), so the pairings are worked out
+        // with and without it, and only the difference is applied.
+        let list = self.node_list[device_type].clone();
+        let others: Vec<_> = list.iter().filter(|n| n.node_id != node.node_id).cloned().collect();
+        let before = self.physical_pairings(device_type, &others);
+        let after = self.physical_pairings(device_type, &list);
+        let paired = |pairings: &[Pairing], (_, id, node_id, _): Pairing| {
+            pairings.iter().any(|p| p.1 == id && p.2 == node_id)
+        };
+
+        for &pairing in before.iter().filter(|&&p| !paired(&after, p)) {
+            let (_, id, node_id, _) = pairing;
+            debug!("Detaching Node {} from {}", node_id, id);
+            let result = match device_type {
+                DeviceType::Source => self.link_remove_unmanaged_to_filter(node_id, id).await,
+                DeviceType::Target => self.link_remove_filter_to_unmanaged(id, node_id).await,
+            };
+            if let Err(e) = result {
+                warn!("Unable to Detach Node {} from {}: {}", node_id, id, e);
+            }
+        }
+
+        // A failed link is reported once everything else has been attached
+        let mut failures = vec![];
+        let mut changed = false;
+        for &pairing in after.iter().filter(|&&p| !paired(&before, p)) {
+            let (dev_i, id, node_id, index) = pairing;
+            let Some(live) = list.iter().find(|n| n.node_id == node_id).map(descriptor) else {
+                continue;
+            };
+
+            debug!("Attaching Node {} to {}", node_id, id);
+            let result = match device_type {
+                DeviceType::Source => self.link_create_unmanaged_to_filter(node_id, id).await,
+                DeviceType::Target => self.link_create_filter_to_unmanaged(id, node_id).await,
+            };
+            if let Err(e) = result {
+                warn!("Unable to Attach Node {} to {}: {}", node_id, id, e);
+                failures.push(format!("{} to {}: {}", node_id, id, e));
+                continue;
+            }
+
+            let device = match device_type {
+                DeviceType::Source => {
+                    &mut self.profile.devices.sources.physical_devices[dev_i].attached_devices
+                }
+                DeviceType::Target => {
+                    &mut self.profile.devices.targets.physical_devices[dev_i].attached_devices
+                }
+            };
+
+            // The name may have changed (or the identity was never saved), so update the profile
+            // to ensure this now matches the device for future checks. Anything short of a
+            // certain match could lock in the wrong device, so those are left as they are.
+            if device[index] != live && certain_match(&device[index], &live) {
+                debug!("Updating Profile Node to Name: {:?}", live.name);
+                device[index] = live;
+                changed = true;
             }
         }
 
-        if let Some(desc) = descriptor.description {
-            return self.device_nodes.values().find(|node| node.description.as_ref() == Some(&desc));
+        if changed {
+            // Let the Primary Worker know we've changed the config
+            let _ = sender.send(WorkerMessage::ProfileChanged).await;
         }
+        if !failures.is_empty() {
+            let message = format!("Unable to attach {}", failures.join(", "));
+            return Err(ApiError::pipewire(message).into());
+        }
+        Ok(())
+    }
+
+    fn locate_node(&self, descriptor: PhysicalDeviceDescriptor) -> Option<&DeviceNode> {
+        self.device_nodes
+            .values()
+            .filter_map(|node| {
+                let score = descriptor.match_score(&self::descriptor(&physical_device(node)))?;
+                Some((node, score))
+            })
+            .max_by_key(|(_, score)| *score)
+            .map(|(node, _)| node)
+    }
+}
+
+/// Converts a Pipewire device into the form we hand to the API
+pub(crate) fn physical_device(node: &DeviceNode) -> PhysicalDevice {
+    let identity = &node.identity;
+    PhysicalDevice {
+        node_id: node.node_id,
+        name: node.name.clone(),
+        description: node.description.clone(),
+        identity: DeviceIdentity {
+            bus_path: identity.bus_path.clone(),
+            serial: identity.serial.clone(),
+            card_name: identity.card_name.clone(),
+            vendor_id: identity.vendor_id.clone(),
+            product_id: identity.product_id.clone(),
+            profile_name: identity.profile_name.clone(),
+            port_names: identity.port_names.clone(),
+        },
+    }
+}
+
+// A serial or an exact name identifies a device, anything else could be its twin. Descriptors
+// from before identities were stored have nothing else to go on, so are always updated.
+fn certain_match(saved: &PhysicalDeviceDescriptor, live: &PhysicalDeviceDescriptor) -> bool {
+    let same = |a: &Option<String>, b: &Option<String>| a.is_some() && a == b;
+    same(&saved.identity.serial, &live.identity.serial)
+        || same(&saved.name, &live.name)
+        || saved.identity == DeviceIdentity::default()
+}
 
-        None
+/// The form a device is stored in the profile
+fn descriptor(device: &PhysicalDevice) -> PhysicalDeviceDescriptor {
+    PhysicalDeviceDescriptor {
+        name: device.name.clone(),
+        description: device.description.clone(),
+        identity: device.identity.clone(),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_pipewire::LinkType::{Filter, UnmanagedNode};
+    use pipeweaver_profile::Profile;
+    use tokio::sync::mpsc;
+
+    fn interface(node_id: u32, name: &str, serial: &str) -> PhysicalDevice {
+        PhysicalDevice {
+            node_id,
+            name: Some(String::from(name)),
+            description: Some(String::from("USB Audio Interface")),
+            identity: DeviceIdentity {
+                serial: Some(String::from(serial)),
+                profile_name: Some(String::from("analog-stereo")),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_devices_attach_by_serial() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let mic = manager.profile.find_node("Microphone").unwrap();
+        let line_in = manager.profile.find_node("PC Line In").unwrap();
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        // Two of the same interface, which only differ by their serial number
+        let first = descriptor(&interface(0, "alsa_input.usb-1", "AAA"));
+        let second = descriptor(&interface(0, "alsa_input.usb-2", "BBB"));
+        manager.get_physical_source_mut(mic).unwrap().attached_devices = vec![first];
+        manager.get_physical_source_mut(line_in).unwrap().attached_devices = vec![second];
+
+        // They come back with their ALSA names swapped, the serial should still win
+        manager
+            .source_device_added(interface(60, "alsa_input.usb-1", "BBB"), sender.clone())
+            .await
+            .unwrap();
+        manager
+            .source_device_added(interface(61, "alsa_input.usb-2", "AAA"), sender)
+            .await
+            .unwrap();
+
+        {
+            let graph = pipewire.graph();
+            let linked = |node, id| graph.links.contains(&(UnmanagedNode(node), Filter(id)));
+            assert!(linked(60, line_in) && linked(61, mic));
+            assert!(!linked(60, mic) && !linked(61, line_in));
+        }
+
+        // The profile is updated to the new names
+        let saved = &manager.get_physical_source_mut(mic).unwrap().attached_devices[0];
+        assert_eq!(saved.name.as_deref(), Some("alsa_input.usb-2"));
+        assert!(matches!(receiver.try_recv(), Ok(WorkerMessage::ProfileChanged)));
+    }
+
+    #[tokio::test]
+    async fn a_node_is_only_paired_with_one_device() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let mic = manager.profile.find_node("Microphone").unwrap();
+        let line_in = manager.profile.find_node("PC Line In").unwrap();
+        let (sender, _receiver) = mpsc::channel(10);
+
+        // Two devices with the same name, only the Microphone's was saved with its serial
+        let first = descriptor(&interface(0, "alsa_input.usb", "AAA"));
+        let mut second = descriptor(&interface(0, "alsa_input.usb", "BBB"));
+        second.identity.serial = None;
+        manager.get_physical_source_mut(mic).unwrap().attached_devices = vec![first];
+        manager.get_physical_source_mut(line_in).unwrap().attached_devices = vec![second];
+
+        // Both saved devices describe the first to arrive, but it can only be one of them
+        manager
+            .source_device_added(interface(60, "alsa_input.usb", "AAA"), sender.clone())
+            .await
+            .unwrap();
+        {
+            let graph = pipewire.graph();
+            assert!(graph.links.contains(&(UnmanagedNode(60), Filter(mic))));
+            assert!(!graph.links.contains(&(UnmanagedNode(60), Filter(line_in))));
+        }
+
+        manager.source_device_added(interface(61, "alsa_input.usb", "BBB"), sender).await.unwrap();
+        let graph = pipewire.graph();
+        assert!(graph.links.contains(&(UnmanagedNode(61), Filter(line_in))));
+        assert!(!graph.links.contains(&(UnmanagedNode(61), Filter(mic))));
+    }
+
+    #[tokio::test]
+    async fn serial_less_devices_are_repaired_when_the_other_arrives() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let mic = manager.profile.find_node("Microphone").unwrap();
+        let line_in = manager.profile.find_node("PC Line In").unwrap();
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        // Two of the same interface without serials, only their names and USB ports differ
+        let device = |node_id, name: &str, bus: &str| {
+            let mut device = interface(node_id, name, "");
+            device.identity.serial = None;
+            device.identity.bus_path = Some(String::from(bus));
+            device
+        };
+        let first = descriptor(&device(0, "alsa_input.usb-1", "pci-1"));
+        let second = descriptor(&device(0, "alsa_input.usb-2", "pci-2"));
+        manager.get_physical_source_mut(mic).unwrap().attached_devices = vec![first.clone()];
+        manager.get_physical_source_mut(line_in).unwrap().attached_devices = vec![second];
+
+        // The second arrives first, while it's alone it could be either device
+        manager
+            .source_device_added(device(61, "alsa_input.usb-2", "pci-2"), sender.clone())
+            .await
+            .unwrap();
+        manager.source_device_added(device(60, "alsa_input.usb-1", "pci-1"), sender).await.unwrap();
+
+        {
+            let graph = pipewire.graph();
+            let linked = |node, id| graph.links.contains(&(UnmanagedNode(node), Filter(id)));
+            assert!(linked(60, mic) && linked(61, line_in));
+            assert!(!linked(61, mic) && !linked(60, line_in));
+        }
+
+        // Neither descriptor was rewritten with the other device's details
+        let saved = &manager.get_physical_source_mut(mic).unwrap().attached_devices[0];
+        assert_eq!(saved, &first);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn legacy_descriptors_match_by_description() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let mic = manager.profile.find_node("Microphone").unwrap();
+        let (sender, _receiver) = mpsc::channel(10);
+
+        // Profiles from before identities were stored only have the description
+        let mut device = interface(70, "alsa_input.usb-beacn", "CCC");
+        device.description = Some(String::from("BEACN Mic Microphone"));
+        manager.source_device_added(device, sender).await.unwrap();
+
+        assert!(pipewire.graph().links.contains(&(UnmanagedNode(70), Filter(mic))));
+        let saved = &manager.get_physical_source_mut(mic).unwrap().attached_devices[0];
+        assert_eq!(saved.identity.serial.as_deref(), Some("CCC"));
+    }
+}
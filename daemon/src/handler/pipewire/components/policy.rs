@@ -0,0 +1,158 @@
+use crate::handler::pipewire::components::node::NodeManagement;
+use crate::handler::pipewire::components::profile::ProfileManagement;
+use crate::handler::pipewire::manager::PipewireManager;
+use anyhow::{bail, Result};
+use log::debug;
+use pipeweaver_ipc::commands::ApiError;
+use pipeweaver_pipewire::PipewireMessage;
+use pipeweaver_profile::{NodeMetadata, NodePolicy};
+use pipeweaver_shared::NodeType;
+use ulid::Ulid;
+
+pub(crate) trait PolicyManagement {
+    fn get_node_policy(&self, id: Ulid) -> Option<NodePolicy>;
+    async fn node_set_policy(&mut self, id: Ulid, policy: NodePolicy) -> Result<()>;
+
+    fn get_node_metadata(&self, id: Ulid) -> Option<NodeMetadata>;
+    async fn node_set_metadata(&mut self, id: Ulid, metadata: NodeMetadata) -> Result<()>;
+
+    /// Called when something other than us links to one of our nodes
+    async fn policy_link_added(&self, link: u32, node: Ulid) -> Result<()>;
+}
+
+impl PolicyManagement for PipewireManager {
+    fn get_node_policy(&self, id: Ulid) -> Option<NodePolicy> {
+        // Only Virtual nodes exist in Pipewire as nodes, Physical nodes are just filters
+        match self.get_node_type(id)? {
+            NodeType::VirtualSource => self.get_virtual_source(id).map(|d| d.policy),
+            NodeType::VirtualTarget => self.get_virtual_target(id).map(|d| d.policy),
+            _ => None,
+        }
+    }
+
+    async fn node_set_policy(&mut self, id: Ulid, policy: NodePolicy) -> Result<()> {
+        let err = ApiError::not_found(id, "Unable to find Node");
+        let node_type = self.get_node_type(id).ok_or(err)?;
+
+        let err = ApiError::not_found(id, "Failed to Locate Node");
+        let current = match node_type {
+            NodeType::VirtualSource => &mut self.get_virtual_source_mut(id).ok_or(err)?.policy,
+            NodeType::VirtualTarget => &mut self.get_virtual_target_mut(id).ok_or(err)?.policy,
+            _ => bail!(ApiError::invalid(id, "Policy can only be set on Virtual Nodes")),
+        };
+
+        if *current == policy {
+            bail!(ApiError::no_change(id, "Policy is already set"));
+        }
+        *current = policy;
+
+        // These properties are only read by the session manager when the node appears, so the
+        // node needs to be rebuilt for them to take effect.
+        self.node_rebuild(id).await
+    }
+
+    fn get_node_metadata(&self, id: Ulid) -> Option<NodeMetadata> {
+        match self.get_node_type(id)? {
+            NodeType::VirtualSource => self.get_virtual_source(id).map(|d| d.metadata.clone()),
+            NodeType::VirtualTarget => self.get_virtual_target(id).map(|d| d.metadata.clone()),
+            _ => None,
+        }
+    }
+
+    async fn node_set_metadata(&mut self, id: Ulid, metadata: NodeMetadata) -> Result<()> {
+        let err = ApiError::not_found(id, "Unable to find Node");
+        let node_type = self.get_node_type(id).ok_or(err)?;
+
+        if self.profile.defaults.source == Some(id) && !metadata.virtual_microphone {
+            let message = "The default source has to stay a Virtual Microphone";
+            bail!(ApiError::invalid(id, message));
+        }
+
+        let err = ApiError::not_found(id, "Failed to Locate Node");
+        let current = match node_type {
+            NodeType::VirtualSource => &mut self.get_virtual_source_mut(id).ok_or(err)?.metadata,
+            NodeType::VirtualTarget => &mut self.get_virtual_target_mut(id).ok_or(err)?.metadata,
+            _ => bail!(ApiError::invalid(id, "Metadata can only be set on Virtual Nodes")),
+        };
+
+        if *current == metadata {
+            bail!(ApiError::no_change(id, "Metadata is already set"));
+        }
+        *current = metadata;
+
+        // As with the policy, the media class and icons are only picked up on creation
+        self.node_rebuild(id).await
+    }
+
+    async fn policy_link_added(&self, link: u32, node: Ulid) -> Result<()> {
+        if !self.get_node_policy(node).is_some_and(|policy| policy.exclusive) {
+            return Ok(());
+        }
+
+        debug!("Removing Unmanaged Link {} from Exclusive Node {}", link, node);
+        let message = PipewireMessage::DestroyUnmanagedLink(link);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_profile::Profile;
+
+    #[tokio::test]
+    async fn exclusive_nodes_drop_foreign_links() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        assert!(!pipewire.graph().nodes[&system].autoconnect);
+        assert!(pipewire.graph().nodes[&system].dont_reconnect);
+
+        // Without exclusive mode, applications are free to link to us
+        manager.policy_link_added(40, system).await.unwrap();
+        assert!(pipewire.graph().destroyed_links.is_empty());
+
+        let policy = NodePolicy { autoconnect: true, exclusive: true, ..Default::default() };
+        manager.node_set_policy(system, policy).await.unwrap();
+        assert!(pipewire.graph().nodes[&system].autoconnect);
+        assert!(manager.node_set_policy(system, policy).await.is_err());
+
+        manager.policy_link_added(41, system).await.unwrap();
+        assert_eq!(pipewire.graph().destroyed_links, vec![41]);
+    }
+
+    #[tokio::test]
+    async fn metadata_rebuilds_the_node() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        assert!(pipewire.graph().nodes[&stream].virtual_microphone);
+        assert_eq!(pipewire.graph().nodes[&stream].media_class, "Audio/Source/Virtual");
+
+        let metadata = NodeMetadata {
+            media_role: Some(String::from("Communication")),
+            virtual_microphone: false,
+            ..Default::default()
+        };
+        manager.node_set_metadata(stream, metadata.clone()).await.unwrap();
+        assert_eq!(manager.get_node_metadata(stream), Some(metadata.clone()));
+
+        {
+            let graph = pipewire.graph();
+            assert!(!graph.nodes[&stream].virtual_microphone);
+            assert_eq!(graph.nodes[&stream].media_class, "Audio/Sink/Internal");
+            assert_eq!(graph.nodes[&stream].media_role.as_deref(), Some("Communication"));
+        }
+
+        assert!(manager.node_set_metadata(stream, metadata).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn physical_nodes_have_no_policy() {
+        let (mut manager, _pipewire) = mock_manager(Profile::base_settings()).await;
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+
+        assert!(manager.get_node_policy(headphones).is_none());
+        assert!(manager.node_set_policy(headphones, NodePolicy::default()).await.is_err());
+    }
+}
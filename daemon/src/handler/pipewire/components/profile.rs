@@ -1,7 +1,7 @@
 use crate::handler::pipewire::components::node::NodeManagement;
 use crate::handler::pipewire::manager::PipewireManager;
-use anyhow::anyhow;
 use anyhow::Result;
+use pipeweaver_ipc::commands::ApiError;
 use pipeweaver_profile::{DeviceDescription, PhysicalSourceDevice, PhysicalTargetDevice, VirtualSourceDevice, VirtualTargetDevice};
 use pipeweaver_shared::NodeType;
 use ulid::Ulid;
@@ -98,10 +98,10 @@ impl ProfileManagement for PipewireManager {
     }
 
     fn get_device_description(&mut self, id: Ulid) -> Result<&mut DeviceDescription> {
-        let err = anyhow!("Unable to Locate Node");
+        let err = ApiError::not_found(id, "Unable to Locate Node");
         let node_type = self.get_node_type(id).ok_or(err)?;
 
-        let err = anyhow!("Failed to Get Node Type");
+        let err = ApiError::not_found(id, "Failed to Get Node Type");
         match node_type {
             NodeType::PhysicalSource => Ok(&mut self.get_physical_source_mut(id).ok_or(err)?.description),
             NodeType::PhysicalTarget => Ok(&mut self.get_physical_target_mut(id).ok_or(err)?.description),
@@ -0,0 +1,359 @@
+use crate::handler::pipewire::components::links::LinkManagement;
+use crate::handler::pipewire::components::mute::MuteManager;
+use crate::handler::pipewire::components::node::NodeManagement;
+use crate::handler::pipewire::components::physical::PhysicalDevices;
+use crate::handler::pipewire::components::policy::PolicyManagement;
+use crate::handler::pipewire::components::routing::RoutingManagement;
+use crate::handler::pipewire::manager::PipewireManager;
+use anyhow::Result;
+use log::{debug, info, warn};
+use pipeweaver_ipc::commands::ApiError;
+use pipeweaver_pipewire::oneshot;
+use pipeweaver_pipewire::{GraphSnapshot, LinkType, PipewireMessage};
+use pipeweaver_shared::{DeviceType, Mix, NodeType};
+use std::collections::{HashMap, HashSet};
+use strum::IntoEnumIterator;
+
+type Link = (LinkType, LinkType);
+
+pub(crate) trait ReconcileManagement {
+    /// Compares the links we've built against what the profile says should exist, and applies
+    /// the smallest set of changes needed to bring them back in line.
+    async fn reconcile(&mut self) -> Result<()>;
+}
+
+impl ReconcileManagement for PipewireManager {
+    async fn reconcile(&mut self) -> Result<()> {
+        let desired = self.reconcile_desired_links().await;
+
+        let (send, recv) = oneshot::channel();
+        let message = PipewireMessage::GetGraph(send);
+        self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
+        let snapshot = recv.await.map_err(ApiError::pipewire)?;
+
+        // A link can be present more than once if it was recreated while a drop was pending
+        let mut existing: HashMap<Link, (usize, bool)> = HashMap::new();
+        for link in &snapshot.links {
+            let entry = existing.entry((link.source, link.destination)).or_insert((0, true));
+            entry.0 += 1;
+            entry.1 &= link.ready;
+        }
+
+        // Failures are collected so a single bad link doesn't stop the rest being repaired
+        let mut failures = vec![];
+
+        let mut removed = 0;
+        for (&(source, target), &(count, ready)) in &existing {
+            // Links which are still coming up are left alone, the next pass will check them
+            if !ready || (desired.contains(&(source, target)) && count == 1) {
+                continue;
+            }
+
+            // Duplicates of a link we want only need the extra copies dropping
+            let result = if desired.contains(&(source, target)) {
+                debug!("[Reconcile] Removing Duplicate Links {:?} -> {:?}", source, target);
+                self.link_dedupe_type_to_type(source, target).await
+            } else {
+                debug!("[Reconcile] Removing Link {:?} -> {:?}", source, target);
+                self.link_remove_type_to_type(source, target).await
+            };
+            if let Err(e) = result {
+                warn!("[Reconcile] Unable to remove {:?} -> {:?}: {}", source, target, e);
+                failures.push(format!("remove {:?} -> {:?}: {}", source, target, e));
+                continue;
+            }
+            removed += 1;
+        }
+
+        let mut created = 0;
+        for &(source, target) in &desired {
+            if existing.contains_key(&(source, target)) {
+                continue;
+            }
+
+            debug!("[Reconcile] Creating Link {:?} -> {:?}", source, target);
+            if let Err(e) = self.link_create_type_to_type(source, target).await {
+                // This is most likely a filter which never came up, nothing we can do from here
+                warn!("[Reconcile] Unable to create {:?} -> {:?}: {}", source, target, e);
+                failures.push(format!("create {:?} -> {:?}: {}", source, target, e));
+                continue;
+            }
+            self.metrics.link_restored();
+            created += 1;
+        }
+
+        let foreign = self.reconcile_foreign_links(&snapshot);
+        for &pw_id in &foreign {
+            debug!("[Reconcile] Removing Unmanaged Links on {}", pw_id);
+            let message = PipewireMessage::DestroyUnmanagedLinks(pw_id);
+            if let Err(e) = self.pipewire()?.send_message(message) {
+                warn!("[Reconcile] Unable to clear unmanaged links on {}: {}", pw_id, e);
+                failures.push(format!("clear {}: {}", pw_id, e));
+            }
+        }
+
+        if created + removed + foreign.len() > 0 {
+            info!(
+                "[Reconcile] Created {} links, removed {} links, cleared {} nodes",
+                created,
+                removed,
+                foreign.len()
+            );
+        }
+
+        if !failures.is_empty() {
+            let message = format!("{} changes failed: {}", failures.len(), failures.join(", "));
+            return Err(ApiError::pipewire(message).into());
+        }
+        Ok(())
+    }
+}
+
+trait ReconcileManagementLocal {
+    async fn reconcile_desired_links(&self) -> HashSet<Link>;
+    fn reconcile_foreign_links(&self, snapshot: &GraphSnapshot) -> HashSet<u32>;
+}
+
+impl ReconcileManagementLocal for PipewireManager {
+    async fn reconcile_desired_links(&self) -> HashSet<Link> {
+        let mut links = HashSet::new();
+        // Attached hardware, paired the same way physical_device_attach pairs them
+        let sources = &self.node_list[DeviceType::Source];
+        for (_, id, node_id, _) in self.physical_pairings(DeviceType::Source, sources) {
+            links.insert((LinkType::UnmanagedNode(node_id), LinkType::Filter(id)));
+        }
+        let targets = &self.node_list[DeviceType::Target];
+        for (_, id, node_id, _) in self.physical_pairings(DeviceType::Target, targets) {
+            links.insert((LinkType::Filter(id), LinkType::UnmanagedNode(node_id)));
+        }
+
+        // Every source feeds both of its mixes
+        for (&id, mixes) in &self.source_map {
+            let source = match self.get_node_type(id) {
+                Some(NodeType::PhysicalSource) => LinkType::Filter(id),
+                Some(NodeType::VirtualSource) => LinkType::Node(id),
+                _ => continue,
+            };
+            for mix in Mix::iter() {
+                links.insert((source, LinkType::Filter(mixes[mix])));
+            }
+        }
+
+        // Virtual targets have a volume filter in front of the node
+        for (&id, &volume) in &self.target_map {
+            links.insert((LinkType::Filter(volume), LinkType::Node(id)));
+        }
+
+        if self.meter_enabled {
+            for (&id, &meter) in &self.meter_map {
+                let source = match self.get_node_type(id) {
+                    Some(NodeType::PhysicalSource | NodeType::PhysicalTarget) => {
+                        LinkType::Filter(id)
+                    }
+                    Some(NodeType::VirtualSource) => LinkType::Node(id),
+                    Some(NodeType::VirtualTarget) => match self.target_map.get(&id) {
+                        Some(&volume) => LinkType::Filter(volume),
+                        None => continue,
+                    },
+                    None => continue,
+                };
+                links.insert((source, LinkType::Filter(meter)));
+            }
+        }
+
+        // Routes, unless the source is muted to that target
+        for (source, targets) in &self.profile.routes {
+            let Some(mixes) = self.source_map.get(source) else {
+                continue;
+            };
+
+            for target in targets {
+                if self.is_source_muted_to_some(*source, *target).await.unwrap_or(true) {
+                    continue;
+                }
+                let (Ok(mix), Ok(filter)) = (
+                    self.routing_get_target_mix(target).await,
+                    self.get_target_filter_node(*target),
+                ) else {
+                    warn!("[Reconcile] Route from {} to unknown target {}", source, target);
+                    continue;
+                };
+                links.insert((LinkType::Filter(mixes[mix]), LinkType::Filter(filter)));
+            }
+        }
+
+        links
+    }
+
+    fn reconcile_foreign_links(&self, snapshot: &GraphSnapshot) -> HashSet<u32> {
+        // Applications are expected to link to our nodes, but nothing else should be touching
+        // our filters (or exclusive nodes), so anything attached to them was made behind our
+        // back.
+        let mut protected: HashSet<u32> = snapshot.filters.iter().filter_map(|f| f.pw_id).collect();
+        for node in &snapshot.nodes {
+            if self.get_node_policy(node.id).is_some_and(|policy| policy.exclusive) {
+                protected.extend(node.pw_id);
+            }
+        }
+
+        let mut foreign = HashSet::new();
+        for link in &snapshot.unmanaged_links {
+            for node in [link.output_node, link.input_node] {
+                if protected.contains(&node) {
+                    foreign.insert(node);
+                }
+            }
+        }
+        foreign
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_ipc::commands::ErrorCode;
+    use pipeweaver_pipewire::UnmanagedLinkSnapshot;
+    use pipeweaver_profile::Profile;
+
+    #[tokio::test]
+    async fn reconcile_repairs_drift() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+
+        let system_mixes = manager.source_map[&system];
+        let filter = manager.get_target_filter_node(headphones).unwrap();
+        let mix = manager.routing_get_target_mix(&headphones).await.unwrap();
+        assert!(pipewire.graph().is_linked(system_mixes[mix], filter));
+
+        // Something outside drops a route, and links to a meter which should be disabled
+        let meter = manager.meter_map[&headphones];
+        let bogus = (LinkType::Filter(system_mixes[mix]), LinkType::Filter(meter));
+        let route = (LinkType::Filter(system_mixes[mix]), LinkType::Filter(filter));
+        {
+            let mut graph = pipewire.graph();
+            graph.links.retain(|link| *link != route);
+            graph.links.push(bogus);
+        }
+        let before = pipewire.graph().links.len();
+        assert!(!manager.meter_enabled);
+
+        manager.reconcile().await.unwrap();
+        let graph = pipewire.graph();
+        assert!(graph.links.contains(&route));
+        assert!(!graph.links.contains(&bogus));
+        assert_eq!(graph.links.len(), before);
+    }
+
+    #[tokio::test]
+    async fn reconcile_leaves_pending_links() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+
+        let system_mixes = manager.source_map[&system];
+        let filter = manager.get_target_filter_node(headphones).unwrap();
+        let mix = manager.routing_get_target_mix(&headphones).await.unwrap();
+
+        // A route and a link we don't want are both still being brought up by Pipewire
+        let meter = manager.meter_map[&headphones];
+        let bogus = (LinkType::Filter(system_mixes[mix]), LinkType::Filter(meter));
+        let route = (LinkType::Filter(system_mixes[mix]), LinkType::Filter(filter));
+        {
+            let mut graph = pipewire.graph();
+            graph.links.push(bogus);
+            graph.pending_links = vec![route, bogus];
+        }
+        let before = pipewire.graph().links.clone();
+
+        manager.reconcile().await.unwrap();
+        assert_eq!(pipewire.graph().links, before);
+
+        // Once it's up, the one we don't want goes
+        pipewire.graph().pending_links.clear();
+        manager.reconcile().await.unwrap();
+        let graph = pipewire.graph();
+        assert!(graph.links.contains(&route));
+        assert!(!graph.links.contains(&bogus));
+    }
+
+    #[tokio::test]
+    async fn reconcile_keeps_one_copy_of_duplicates() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+
+        let system_mixes = manager.source_map[&system];
+        let filter = manager.get_target_filter_node(headphones).unwrap();
+        let mix = manager.routing_get_target_mix(&headphones).await.unwrap();
+        let route = (LinkType::Filter(system_mixes[mix]), LinkType::Filter(filter));
+
+        pipewire.graph().links.push(route);
+        let position = pipewire.graph().links.iter().position(|link| *link == route);
+
+        // The original copy stays where it was rather than being torn down and recreated
+        manager.reconcile().await.unwrap();
+        let graph = pipewire.graph();
+        assert_eq!(graph.links.iter().filter(|link| **link == route).count(), 1);
+        assert_eq!(graph.links.iter().position(|link| *link == route), position);
+    }
+
+    #[tokio::test]
+    async fn reconcile_continues_past_failures() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let game = manager.profile.find_node("Game").unwrap();
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+
+        let mixes = manager.source_map[&game];
+        let headphones_filter = manager.get_target_filter_node(headphones).unwrap();
+        let stream_filter = manager.get_target_filter_node(stream).unwrap();
+        let headphones_mix = manager.routing_get_target_mix(&headphones).await.unwrap();
+        let stream_mix = manager.routing_get_target_mix(&stream).await.unwrap();
+        let lost = (LinkType::Filter(mixes[headphones_mix]), LinkType::Filter(headphones_filter));
+        let route = (LinkType::Filter(mixes[stream_mix]), LinkType::Filter(stream_filter));
+        assert!(pipewire.graph().links.contains(&route));
+
+        // Both routes drop, but the headphone filter has gone so only one can come back
+        {
+            let mut graph = pipewire.graph();
+            graph.links.retain(|link| *link != lost && *link != route);
+            graph.filters.remove(&headphones_filter);
+        }
+
+        let error = ApiError::from(manager.reconcile().await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::Pipewire);
+        let graph = pipewire.graph();
+        assert!(graph.links.contains(&route));
+        assert!(!graph.links.contains(&lost));
+    }
+
+    #[tokio::test]
+    async fn reconcile_clears_foreign_links() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let headphones = manager.profile.find_node("Headphones").unwrap();
+        let filter = manager.get_target_filter_node(headphones).unwrap();
+        let filter_pw_id = pipewire.graph().pw_ids[&filter];
+
+        // Something else links into one of our filters, and two other nodes together
+        let foreign =
+            UnmanagedLinkSnapshot { pw_id: 900, output_node: 901, input_node: filter_pw_id };
+        let other = UnmanagedLinkSnapshot { pw_id: 910, output_node: 911, input_node: 912 };
+        pipewire.graph().unmanaged_links = vec![foreign, other];
+
+        manager.reconcile().await.unwrap();
+        let graph = pipewire.graph();
+        let remaining: Vec<u32> = graph.unmanaged_links.iter().map(|link| link.pw_id).collect();
+        assert_eq!(remaining, vec![910]);
+    }
+
+    #[tokio::test]
+    async fn reconcile_reports_failures() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+
+        pipewire.disconnect();
+        let error = ApiError::from(manager.reconcile().await.unwrap_err());
+        assert_eq!(error.code, ErrorCode::Pipewire);
+    }
+}
@@ -3,8 +3,9 @@ use crate::handler::pipewire::components::mute::MuteManager;
 use crate::handler::pipewire::components::node::NodeManagement;
 use crate::handler::pipewire::components::profile::ProfileManagement;
 use crate::handler::pipewire::manager::PipewireManager;
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
 use log::{debug, warn};
+use pipeweaver_ipc::commands::ApiError;
 use pipeweaver_shared::{Mix, NodeType};
 use ulid::Ulid;
 
@@ -77,28 +78,28 @@ impl RoutingManagement for PipewireManager {
 
     async fn routing_set_route(&mut self, source: Ulid, target: Ulid, enabled: bool) -> Result<()> {
         // This is actually more complicated that it sounds, first lets find some stuff out..
-        let source_type = self.get_node_type(source).ok_or(anyhow!("Source Not Found"))?;
-        let target_type = self.get_node_type(target).ok_or(anyhow!("Target Not Found"))?;
+        let err = ApiError::not_found(source, "Source Not Found");
+        let source_type = self.get_node_type(source).ok_or(err)?;
+        let err = ApiError::not_found(target, "Target Not Found");
+        let target_type = self.get_node_type(target).ok_or(err)?;
 
         // Make sure the user is being sane
         if !matches!(source_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(source, "Provided Source is a Target Node"));
         }
         if !matches!(target_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(target, "Provided Target is a Source Node"));
         }
 
-        // This should already be here, but it's not, so create it
         let target_id = self.get_target_filter_node(target)?;
-        self.profile.routes.entry(source).or_insert_with(|| {
+
+        // This should already be here, but if it's not, create it
+        let route = self.profile.routes.entry(source).or_insert_with(|| {
             warn!("[Routing] Table Missing for Source {}, Creating", source);
             Default::default()
         });
-
-        // This unwrap is safe, so just grab the Set and check what we're doing
-        let route = self.profile.routes.get_mut(&source).unwrap();
         if enabled == route.contains(&target) {
-            bail!("Requested route change already set");
+            bail!(ApiError::no_change(target, "Requested route change already set"));
         }
         if enabled { route.insert(target); } else { route.remove(&target); }
 
@@ -116,7 +117,7 @@ impl RoutingManagement for PipewireManager {
                 self.link_remove_filter_to_filter(map[mix], target_id).await?;
             }
         } else {
-            bail!("Unable to obtain volume map for Source");
+            bail!(ApiError::not_found(source, "Unable to obtain volume map for Source"));
         }
 
 
@@ -124,32 +125,31 @@ impl RoutingManagement for PipewireManager {
     }
 
     async fn routing_route_exists(&self, source: Ulid, target: Ulid) -> Result<bool> {
-        let source_type = self.get_node_type(source).ok_or(anyhow!("Source Not Found"))?;
-        let target_type = self.get_node_type(target).ok_or(anyhow!("Target Not Found"))?;
+        let err = ApiError::not_found(source, "Source Not Found");
+        let source_type = self.get_node_type(source).ok_or(err)?;
+        let err = ApiError::not_found(target, "Target Not Found");
+        let target_type = self.get_node_type(target).ok_or(err)?;
 
         // Make sure the user is being sane
         if !matches!(source_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(source, "Provided Source is a Target Node"));
         }
         if !matches!(target_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
-        }
-
-        if !self.profile.routes.contains_key(&source) {
-            return Ok(false);
+            bail!(ApiError::invalid(target, "Provided Target is a Source Node"));
         }
 
-        Ok(self.profile.routes.get(&source).unwrap().contains(&target))
+        let routes = self.profile.routes.get(&source);
+        Ok(routes.is_some_and(|routes| routes.contains(&target)))
     }
 
     async fn routing_get_target_mix(&self, id: &Ulid) -> Result<Mix> {
-        let error = anyhow!("Cannot Locate Node");
+        let error = ApiError::not_found(*id, "Cannot Locate Node");
         let node_type = self.get_node_type(*id).ok_or(error)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(*id, "Provided Target is a Source Node"));
         }
 
-        let err = anyhow!("Failed to Locate Target");
+        let err = ApiError::not_found(*id, "Failed to Locate Target");
         let mix = if node_type == NodeType::PhysicalTarget {
             self.get_physical_target(*id).ok_or(err)?.mix
         } else {
@@ -163,13 +163,13 @@ impl RoutingManagement for PipewireManager {
 
         // Ok, first thing's first, lets see if this is actually changed
         if current == mix {
-            bail!("Nothing to Do, Mixes Match");
+            bail!(ApiError::no_change(None, "Nothing to Do, Mixes Match"));
         }
 
-        let error = anyhow!("Cannot Locate Node");
+        let error = ApiError::not_found(target, "Cannot Locate Node");
         let node_type = self.get_node_type(target).ok_or(error)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Target is a Source Node");
+            bail!(ApiError::invalid(target, "Provided Target is a Source Node"));
         }
 
         let target_node = self.get_target_filter_node(target)?;
@@ -190,11 +190,42 @@ impl RoutingManagement for PipewireManager {
         }
 
         // Update the Profile
+        let err = ApiError::not_found(target, "Unknown Node");
         if node_type == NodeType::PhysicalTarget {
-            self.get_physical_target_mut(target).ok_or(anyhow!("Unknown Node"))?.mix = mix;
+            self.get_physical_target_mut(target).ok_or(err)?.mix = mix;
         } else {
-            self.get_virtual_target_mut(target).ok_or(anyhow!("Unknown Node"))?.mix = mix;
+            self.get_virtual_target_mut(target).ok_or(err)?.mix = mix;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_profile::Profile;
+
+    #[tokio::test]
+    async fn routes_follow_the_profile() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+
+        let mixes = manager.source_map[&system];
+        let filter = manager.get_target_filter_node(stream).unwrap();
+        let mix = manager.routing_get_target_mix(&stream).await.unwrap();
+        assert!(!pipewire.graph().is_linked(mixes[mix], filter));
+
+        manager.routing_set_route(system, stream, true).await.unwrap();
+        assert!(manager.routing_route_exists(system, stream).await.unwrap());
+        assert!(pipewire.graph().is_linked(mixes[mix], filter));
+
+        // Setting the same route again shouldn't touch anything
+        assert!(manager.routing_set_route(system, stream, true).await.is_err());
+
+        manager.routing_set_route(system, stream, false).await.unwrap();
+        assert!(!manager.routing_route_exists(system, stream).await.unwrap());
+        assert!(!pipewire.graph().is_linked(mixes[mix], filter));
+    }
+}
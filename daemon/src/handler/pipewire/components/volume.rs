@@ -4,8 +4,9 @@ use crate::handler::pipewire::components::mute::MuteManager;
 use crate::handler::pipewire::components::node::NodeManagement;
 use crate::handler::pipewire::components::profile::ProfileManagement;
 use crate::handler::pipewire::manager::PipewireManager;
-use anyhow::{anyhow, bail, Error, Result};
+use anyhow::{bail, Error, Result};
 use log::debug;
+use pipeweaver_ipc::commands::ApiError;
 use pipeweaver_pipewire::{FilterValue, PipewireMessage};
 use pipeweaver_profile::Volumes;
 use pipeweaver_shared::{Mix, MuteState, NodeType};
@@ -46,10 +47,10 @@ impl VolumeManager for PipewireManager {
     }
 
     async fn load_initial_volume(&self, id: Ulid) -> Result<()> {
-        let error = anyhow!("Unable to Locate Node");
+        let error = ApiError::not_found(id, "Unable to Locate Node");
         let node_type = self.get_node_type(id).ok_or(error)?;
 
-        let error = anyhow!("Unable to Locate Target Node");
+        let error = ApiError::not_found(id, "Unable to Locate Target Node");
         match node_type {
             NodeType::PhysicalSource | NodeType::VirtualSource => {
                 debug!("Loading Volume for {}", id);
@@ -70,23 +71,27 @@ impl VolumeManager for PipewireManager {
     }
 
     async fn sync_all_pipewire_volumes(&mut self) {
+        let Ok(pipewire) = self.pipewire() else {
+            return;
+        };
+
         for &id in self.source_map.keys() {
             if let Ok(volume) = self.get_node_volume(id, Mix::A) {
                 let message = PipewireMessage::SetNodeVolume(id, volume);
-                let _ = self.pipewire().send_message(message);
+                let _ = pipewire.send_message(message);
             }
         }
 
         for &id in self.target_map.keys() {
             if let Ok(volume) = self.get_node_volume(id, Mix::A) {
                 let message = PipewireMessage::SetNodeVolume(id, volume);
-                let _ = self.pipewire().send_message(message);
+                let _ = pipewire.send_message(message);
             }
         }
     }
 
     async fn sync_node_volume(&mut self, id: Ulid, volume: u8) -> Result<()> {
-        let node_type = self.get_node_type(id).ok_or(anyhow!("Node Not Found"))?;
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Node Not Found"))?;
         match node_type {
             NodeType::PhysicalSource | NodeType::VirtualSource => {
                 debug!("Setting Volume: {}", volume);
@@ -102,7 +107,7 @@ impl VolumeManager for PipewireManager {
 
     async fn set_source_volume(&mut self, id: Ulid, mix: Mix, volume: u8, api: bool) -> Result<()> {
         if !(0..=100).contains(&volume) {
-            bail!("Volume Must be between 0 and 100");
+            bail!(ApiError::invalid(None, "Volume Must be between 0 and 100"));
         }
 
         // Now, pull out the correct part of the profile..
@@ -130,7 +135,7 @@ impl VolumeManager for PipewireManager {
         // If this is coming from the API for Mix A, update the pipewire node volume
         if mix == Mix::A && api {
             let message = PipewireMessage::SetNodeVolume(id, volume);
-            let _ = self.pipewire().send_message(message);
+            self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
         }
 
         // If we're linked, update the other Mix as well
@@ -138,7 +143,7 @@ impl VolumeManager for PipewireManager {
             // If the original request was for B, but we're changing A, tell pipewire
             if mix == Mix::B && api {
                 let message = PipewireMessage::SetNodeVolume(id, volume);
-                let _ = self.pipewire().send_message(message);
+                self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
             }
 
             self.volume_set_source(id, other_mix, volume).await?;
@@ -152,7 +157,7 @@ impl VolumeManager for PipewireManager {
         let volumes = self.get_volumes(id)?;
 
         if linked == volumes.volumes_linked.is_some() {
-            bail!("Requested State matches current state");
+            bail!(ApiError::no_change(None, "Requested State matches current state"));
         }
 
         if !linked {
@@ -174,19 +179,19 @@ impl VolumeManager for PipewireManager {
 
     async fn set_target_volume(&mut self, id: Ulid, volume: u8, api: bool) -> Result<()> {
         if !(0..=100).contains(&volume) {
-            bail!("Volume Must be between 0 and 100");
+            bail!(ApiError::invalid(None, "Volume Must be between 0 and 100"));
         }
-        let node_type = self.get_node_type(id).ok_or(anyhow!("Unknown Node"))?;
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Unknown Node"))?;
         if self.get_target_mute_state(id).await? == MuteState::Unmuted {
             let filter_target = self.get_target_filter_node(id)?;
             self.filter_volume_set(filter_target, volume).await?;
         }
 
-        // We can safely unwrap here, errors will be thrown by get_target_mute_state if it's wrong
+        let err = ApiError::not_found(id, "Unable to Locate Target");
         if node_type == NodeType::PhysicalTarget {
-            self.get_physical_target_mut(id).unwrap().volume = volume;
+            self.get_physical_target_mut(id).ok_or(err)?.volume = volume;
         } else {
-            self.get_virtual_target_mut(id).unwrap().volume = volume;
+            self.get_virtual_target_mut(id).ok_or(err)?.volume = volume;
         }
 
         Ok(())
@@ -200,13 +205,13 @@ impl VolumeManager for PipewireManager {
 
         for (&node, &meter) in &self.meter_map {
             let message = PipewireMessage::SetFilterValue(meter, 0, FilterValue::Bool(enabled));
-            self.pipewire().send_message(message)?;
+            self.pipewire()?.send_message(message).map_err(ApiError::pipewire)?;
 
             let node_type = match self.get_node_type(node) {
                 Some(node_type) => node_type,
                 None => {
                     debug!("Failed to get Node Type for {}", node);
-                    bail!("Unable to obtain node type");
+                    bail!(ApiError::not_found(node, "Unable to obtain node type"));
                 }
             };
             match node_type {
@@ -226,7 +231,8 @@ impl VolumeManager for PipewireManager {
                 }
                 NodeType::VirtualTarget => {
                     // Virtual Targets need to be attached / detached against the volume
-                    let &volume = self.target_map.get(&node).ok_or(anyhow!("Nope"))?;
+                    let err = ApiError::not_found(node, "Unable to Locate Volume for Target");
+                    let &volume = self.target_map.get(&node).ok_or(err)?;
                     if enabled {
                         self.link_create_filter_to_filter(volume, meter).await?;
                     } else {
@@ -240,10 +246,10 @@ impl VolumeManager for PipewireManager {
     }
 
     fn get_node_volume(&self, id: Ulid, mix: Mix) -> Result<u8> {
-        let err = anyhow!("Node not Found: {}", id);
+        let err = ApiError::not_found(id, "Node not Found");
         let node_type = self.get_node_type(id).ok_or(err)?;
 
-        let err = anyhow!("Unable to Locate Node");
+        let err = ApiError::not_found(id, "Unable to Locate Node");
         match node_type {
             NodeType::PhysicalSource => {
                 Ok(self.get_physical_source(id).ok_or(err)?.volumes.volume[mix])
@@ -267,9 +273,9 @@ trait VolumeManagerLocal {
 
 impl VolumeManagerLocal for PipewireManager {
     async fn volume_set_source(&mut self, id: Ulid, mix: Mix, volume: u8) -> Result<()> {
-        let node_type = self.get_node_type(id).ok_or(anyhow!("Node Not Found"))?;
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Node Not Found"))?;
         if !matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(id, "Provided Source is a Target Node"));
         }
 
         // First, check whether we need to apply this change to the filter
@@ -279,40 +285,41 @@ impl VolumeManagerLocal for PipewireManager {
                 let filter_id = map[mix];
                 self.filter_volume_set(filter_id, volume).await?;
             } else {
-                bail!("Source not found in the Source Map");
+                bail!(ApiError::not_found(id, "Source not found in the Source Map"));
             }
         }
         Ok(())
     }
 
     fn get_volumes(&mut self, id: Ulid) -> Result<&mut Volumes> {
-        let node_type = self.get_node_type(id).ok_or(anyhow!("Node Not Found"))?;
+        let node_type = self.get_node_type(id).ok_or(ApiError::not_found(id, "Node Not Found"))?;
         if !matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(id, "Provided Source is a Target Node"));
         }
 
         // Now, pull out the correct part of the profile..
+        let err = ApiError::not_found(id, "Node not Found");
         if node_type == NodeType::PhysicalSource {
-            Ok(&mut self.get_physical_source_mut(id).ok_or(anyhow!("Node not Found"))?.volumes)
+            Ok(&mut self.get_physical_source_mut(id).ok_or(err)?.volumes)
         } else {
-            Ok(&mut self.get_virtual_source_mut(id).ok_or(anyhow!("Node not Found"))?.volumes)
+            Ok(&mut self.get_virtual_source_mut(id).ok_or(err)?.volumes)
         }
     }
 
     async fn volume_source_load_with_mute(&self, id: Ulid) -> Result<()> {
-        let err = anyhow!("Unable to Locate Node");
+        let err = ApiError::not_found(id, "Unable to Locate Node");
         let node_type = self.get_node_type(id).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource) {
-            bail!("Provided Source is a Target Node");
+            bail!(ApiError::invalid(id, "Provided Source is a Target Node"));
         }
 
-        let err = anyhow!("Unable to Locate Mixes for Node");
+        let err = ApiError::not_found(id, "Unable to Locate Mixes for Node");
         let mixes = self.source_map.get(&id).ok_or(err)?;
 
         let (a, b) = if self.is_source_muted_to_all(id).await? {
             (0, 0)
         } else {
-            let err = anyhow!("Unable to Find Node");
+            let err = ApiError::not_found(id, "Unable to Find Node");
             let volumes = if node_type == NodeType::PhysicalSource {
                 &self.get_physical_source(id).ok_or(err)?.volumes
             } else {
@@ -330,10 +337,10 @@ impl VolumeManagerLocal for PipewireManager {
     }
 
     async fn volume_target_load_with_mute(&self, id: Ulid, volume: u8) -> Result<()> {
-        let err = anyhow!("Unable to Locate Node");
+        let err = ApiError::not_found(id, "Unable to Locate Node");
         let node_type = self.get_node_type(id).ok_or(err)?;
         if !matches!(node_type, NodeType::PhysicalTarget | NodeType::VirtualTarget) {
-            bail!("Provided Source is a Source Node");
+            bail!(ApiError::invalid(id, "Provided Source is a Source Node"));
         }
 
         let target = self.get_target_filter_node(id)?;
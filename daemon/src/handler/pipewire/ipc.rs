@@ -1,17 +1,33 @@
+use crate::handler::pipewire::components::defaults::DefaultManagement;
+use crate::handler::pipewire::components::live::LiveManagement;
+use crate::handler::pipewire::components::load_profile::LoadProfile;
+use crate::handler::pipewire::components::midi::MidiManagement;
 use crate::handler::pipewire::components::mute::MuteManager;
 use crate::handler::pipewire::components::node::NodeManagement;
 use crate::handler::pipewire::components::physical::PhysicalDevices;
+use crate::handler::pipewire::components::policy::PolicyManagement;
+use crate::handler::pipewire::components::profile::ProfileManagement;
 use crate::handler::pipewire::components::routing::RoutingManagement;
 use crate::handler::pipewire::components::volume::VolumeManager;
 use crate::handler::pipewire::manager::PipewireManager;
 use anyhow::Error;
-use pipeweaver_ipc::commands::{APICommand, APICommandResponse};
+use log::{error, warn};
+use pipeweaver_ipc::commands::{APICommand, APICommandResponse, ApiError, ErrorCode};
+use pipeweaver_pipewire::DefaultDevice;
+use pipeweaver_profile::{MidiAction, Profile};
 use pipeweaver_shared::MuteState::{Muted, Unmuted};
 
 type Cmd = APICommand;
 type Resp = APICommandResponse;
+
+/// A step in undoing a batch command, most are just another command
+enum Undo {
+    Command(Cmd),
+    ClearDefault(DefaultDevice),
+}
 pub(crate) trait IPCHandler {
     async fn handle_command(&mut self, command: Cmd) -> Result<Resp, Error>;
+    async fn handle_batch(&mut self, commands: Vec<Cmd>, atomic: bool) -> Vec<Resp>;
 }
 
 impl IPCHandler for PipewireManager {
@@ -29,6 +45,14 @@ impl IPCHandler for PipewireManager {
             Cmd::RemoveNode(id) => {
                 self.node_remove(id).await.map(|_| Resp::Ok)
             }
+            Cmd::SetNodePolicy(id, policy) => {
+                self.node_set_policy(id, policy).await.map(|_| Resp::Ok)
+            }
+            Cmd::SetNodeMetadata(id, metadata) => {
+                self.node_set_metadata(id, metadata).await.map(|_| Resp::Ok)
+            }
+            Cmd::SetDefaultSink(id) => self.default_set_sink(id).await.map(|_| Resp::Ok),
+            Cmd::SetDefaultSource(id) => self.default_set_source(id).await.map(|_| Resp::Ok),
             Cmd::SetSourceVolume(id, mix, volume) => {
                 self.set_source_volume(id, mix, volume, true).await.map(|_| Resp::Ok)
             }
@@ -75,6 +99,388 @@ impl IPCHandler for PipewireManager {
             Cmd::SetOrder(id, position) => {
                 self.node_set_position(id, position).await.map(|_| Resp::Ok)
             }
+
+            Cmd::AddMidiDevice(name) => self.midi_add_device(name).map(|_| Resp::Ok),
+            Cmd::RemoveMidiDevice(name) => self.midi_remove_device(name).map(|_| Resp::Ok),
+            Cmd::AddMidiMapping(message, action) => {
+                self.midi_add_mapping(message, action).map(|_| Resp::Ok)
+            }
+            Cmd::RemoveMidiMapping(message) => self.midi_remove_mapping(message).map(|_| Resp::Ok),
+            Cmd::SetMidiLearn(action) => self.midi_set_learn(action).map(|_| Resp::Ok),
+
+            Cmd::SetLiveConfiguration(config) => {
+                self.live_set_configuration(config).await.map(|_| Resp::Ok)
+            }
+        }
+    }
+
+    async fn handle_batch(&mut self, commands: Vec<Cmd>, atomic: bool) -> Vec<Resp> {
+        let snapshot = atomic.then(|| (self.profile.clone(), self.midi_learn));
+        let count = commands.len();
+
+        let mut undo = Vec::new();
+        let mut responses = Vec::with_capacity(count);
+        for (index, command) in commands.into_iter().enumerate() {
+            // Work out how to undo the command before it changes the profile
+            let inverse = if atomic { self.inverse(&command) } else { None };
+            match self.handle_command(command).await {
+                Ok(response) => {
+                    // A new node can only be removed once we know its id
+                    let inverse = match response {
+                        Resp::Id(id) => Some(vec![Undo::Command(Cmd::RemoveNode(id))]),
+                        _ => inverse,
+                    };
+                    undo.push(inverse);
+                    responses.push(response);
+                }
+                Err(e) => {
+                    let error = ApiError::from(e);
+                    if let Some((profile, midi_learn)) = snapshot {
+                        warn!("Batch Command Failed, Rolling Back: {}", error);
+                        self.batch_rollback(undo, profile, midi_learn).await;
+
+                        let failed = |what| {
+                            let message = format!("{}, Batch command {} failed", what, index);
+                            Resp::Err(ApiError::rolled_back(message))
+                        };
+
+                        // Nothing in the batch stuck, so flag everything that ran as failed
+                        responses.fill(failed("Rolled Back"));
+                        responses.push(Resp::Err(error));
+
+                        // Flag the rest of the batch as not having been run
+                        responses.resize_with(count, || failed("Skipped"));
+                        break;
+                    }
+                    responses.push(Resp::Err(error));
+                }
+            }
+        }
+        responses
+    }
+}
+
+trait IPCHandlerLocal {
+    /// Returns the steps needed to undo this command, or None if it can't be undone
+    fn inverse(&self, command: &Cmd) -> Option<Vec<Undo>>;
+    async fn batch_rollback(
+        &mut self,
+        undo: Vec<Option<Vec<Undo>>>,
+        profile: Profile,
+        midi_learn: Option<MidiAction>,
+    );
+}
+
+impl IPCHandlerLocal for PipewireManager {
+    fn inverse(&self, command: &Cmd) -> Option<Vec<Undo>> {
+        let profile = &self.profile;
+        let inverse = match command {
+            // These only touch the profile, which is restored wholesale once we're done
+            Cmd::SetNodeColour(..)
+            | Cmd::SetSourceVolumeLinked(..)
+            | Cmd::SetOrderGroup(..)
+            | Cmd::SetOrder(..)
+            | Cmd::AddMidiDevice(..)
+            | Cmd::RemoveMidiDevice(..)
+            | Cmd::AddMidiMapping(..)
+            | Cmd::RemoveMidiMapping(..)
+            | Cmd::SetMidiLearn(..) => vec![],
+
+            // This is handled once we have the new node's id
+            Cmd::CreateNode(..) => vec![],
+
+            // Removing a node loses the Pipewire state attached to it, so can't be undone
+            Cmd::RemoveNode(..) | Cmd::RemovePhysicalNode(..) => return None,
+
+            Cmd::RenameNode(id, _) => {
+                let name = if let Some(device) = self.get_physical_source(*id) {
+                    &device.description.name
+                } else if let Some(device) = self.get_virtual_source(*id) {
+                    &device.description.name
+                } else if let Some(device) = self.get_physical_target(*id) {
+                    &device.description.name
+                } else {
+                    &self.get_virtual_target(*id)?.description.name
+                };
+                vec![Cmd::RenameNode(*id, name.clone())]
+            }
+            Cmd::SetNodePolicy(id, _) => {
+                let policy = match self.get_virtual_source(*id) {
+                    Some(device) => device.policy,
+                    None => self.get_virtual_target(*id)?.policy,
+                };
+                vec![Cmd::SetNodePolicy(*id, policy)]
+            }
+            Cmd::SetNodeMetadata(id, _) => {
+                let metadata = match self.get_virtual_source(*id) {
+                    Some(device) => device.metadata.clone(),
+                    None => self.get_virtual_target(*id)?.metadata.clone(),
+                };
+                vec![Cmd::SetNodeMetadata(*id, metadata)]
+            }
+
+            // If there wasn't a default before, the choice goes back to the session manager
+            Cmd::SetDefaultSink(_) => match profile.defaults.sink {
+                Some(id) => vec![Cmd::SetDefaultSink(id)],
+                None => return Some(vec![Undo::ClearDefault(DefaultDevice::Sink)]),
+            },
+            Cmd::SetDefaultSource(_) => match profile.defaults.source {
+                Some(id) => vec![Cmd::SetDefaultSource(id)],
+                None => return Some(vec![Undo::ClearDefault(DefaultDevice::Source)]),
+            },
+
+            Cmd::SetSourceVolume(id, mix, _) => {
+                let (volumes, _) = profile.get_source_state(*id)?;
+                vec![Cmd::SetSourceVolume(*id, *mix, volumes.volume[*mix])]
+            }
+            Cmd::SetTargetVolume(id, _) => {
+                let (volume, _) = profile.get_target_state(*id)?;
+                vec![Cmd::SetTargetVolume(*id, volume)]
+            }
+            Cmd::SetTargetMix(id, _) => {
+                let mix = match self.get_physical_target(*id) {
+                    Some(device) => device.mix,
+                    None => self.get_virtual_target(*id)?.mix,
+                };
+                vec![Cmd::SetTargetMix(*id, mix)]
+            }
+            Cmd::SetRoute(source, target, _) => {
+                let routes = profile.routes.get(source);
+                let enabled = routes.is_some_and(|routes| routes.contains(target));
+                vec![Cmd::SetRoute(*source, *target, enabled)]
+            }
+
+            Cmd::AddSourceMuteTarget(id, target) | Cmd::DelSourceMuteTarget(id, target) => {
+                let (_, mute_states) = profile.get_source_state(*id)?;
+                if mute_states.mute_state.contains(target) {
+                    vec![Cmd::AddSourceMuteTarget(*id, *target)]
+                } else {
+                    vec![Cmd::DelSourceMuteTarget(*id, *target)]
+                }
+            }
+            Cmd::AddMuteTargetNode(id, target, node) => {
+                let (_, mute_states) = profile.get_source_state(*id)?;
+                if mute_states.mute_targets[*target].contains(node) {
+                    vec![]
+                } else {
+                    vec![Cmd::DelMuteTargetNode(*id, *target, *node)]
+                }
+            }
+            Cmd::DelMuteTargetNode(id, target, node) => {
+                let (_, mute_states) = profile.get_source_state(*id)?;
+                if mute_states.mute_targets[*target].contains(node) {
+                    vec![Cmd::AddMuteTargetNode(*id, *target, *node)]
+                } else {
+                    vec![]
+                }
+            }
+            Cmd::ClearMuteTargetNodes(id, target) => {
+                let (_, mute_states) = profile.get_source_state(*id)?;
+                let nodes = mute_states.mute_targets[*target].iter();
+                nodes.map(|node| Cmd::AddMuteTargetNode(*id, *target, *node)).collect()
+            }
+            Cmd::SetTargetMuteState(id, _) => {
+                let (_, state) = profile.get_target_state(*id)?;
+                vec![Cmd::SetTargetMuteState(*id, state)]
+            }
+
+            Cmd::AttachPhysicalNode(id, _) => {
+                // The device is attached to the end of the list
+                let index = match self.get_physical_source(*id) {
+                    Some(device) => device.attached_devices.len(),
+                    None => self.get_physical_target(*id)?.attached_devices.len(),
+                };
+                vec![Cmd::RemovePhysicalNode(*id, index)]
+            }
+
+            Cmd::SetLiveConfiguration(_) => vec![Cmd::SetLiveConfiguration(profile.live.clone())],
+        };
+        Some(inverse.into_iter().map(Undo::Command).collect())
+    }
+
+    async fn batch_rollback(
+        &mut self,
+        undo: Vec<Option<Vec<Undo>>>,
+        profile: Profile,
+        midi_learn: Option<MidiAction>,
+    ) {
+        // Undo the commands which were applied, newest first, so only what the batch touched
+        // gets rebuilt. If that's not possible, fall back to reloading the whole profile.
+        let mut undone = true;
+        'undo: for inverse in undo.into_iter().rev() {
+            let Some(inverse) = inverse else {
+                undone = false;
+                break;
+            };
+            for step in inverse {
+                let result = match step {
+                    Undo::Command(command) => self.handle_command(command).await.map(|_| ()),
+                    Undo::ClearDefault(device) => self.default_clear(device).await,
+                };
+                if let Err(e) = result {
+                    let error = ApiError::from(e);
+                    if error.code != ErrorCode::NoChange {
+                        warn!("Unable to Undo Batch Command: {}", error);
+                        undone = false;
+                        break 'undo;
+                    }
+                }
+            }
+        }
+
+        self.midi_learn = midi_learn;
+        if undone {
+            // Pipewire is back where it was, put back anything that only lived in the profile
+            self.profile = profile;
+            return;
         }
+
+        warn!("Unable to Undo Batch, Reloading Profile");
+        if let Err(e) = self.reload_profile(profile).await {
+            error!("Unable to Roll Back Profile: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::mock::mock_manager;
+    use pipeweaver_shared::{Mix, NodeType};
+    use ulid::Ulid;
+    use ErrorCode::{NotFound, RolledBack};
+
+    fn failed(response: &Resp) -> bool {
+        matches!(response, Resp::Err(_))
+    }
+
+    #[tokio::test]
+    async fn batch_runs_every_command() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let filter = manager.get_target_filter_node(stream).unwrap();
+
+        let commands =
+            vec![Cmd::SetTargetVolume(stream, 20), Cmd::RenameNode(stream, "Broadcast".into())];
+        let responses = manager.handle_batch(commands, true).await;
+        assert!(!responses.iter().any(failed));
+
+        assert_eq!(manager.profile.find_node("Broadcast"), Some(stream));
+        let filter = manager.get_target_filter_node(stream).unwrap_or(filter);
+        assert_eq!(pipewire.graph().filter_volume(filter), Some(20));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn failures_continue_unless_atomic() {
+        let (mut manager, _pipewire) = mock_manager(Profile::base_settings()).await;
+        let mic = manager.profile.find_node("Microphone").unwrap();
+
+        let commands = vec![
+            Cmd::RenameNode(Ulid::new(), "Missing".into()),
+            Cmd::RenameNode(mic, "Podcast Mic".into()),
+        ];
+        let responses = manager.handle_batch(commands, false).await;
+        assert!(failed(&responses[0]));
+        assert!(!failed(&responses[1]));
+        assert_eq!(manager.profile.find_node("Podcast Mic"), Some(mic));
+    }
+
+    #[tokio::test]
+    async fn atomic_batch_undoes_only_what_ran() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let profile = manager.profile.clone();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let system = manager.profile.find_node("System").unwrap();
+        let mic = manager.profile.find_node("Microphone").unwrap();
+        let filter = manager.get_target_filter_node(stream).unwrap();
+        let (volume, links) = {
+            let graph = pipewire.graph();
+            (graph.filter_volume(filter), graph.links.clone())
+        };
+
+        let commands = vec![
+            Cmd::SetTargetVolume(stream, 20),
+            Cmd::RenameNode(mic, "Podcast Mic".into()),
+            Cmd::SetRoute(system, stream, true),
+            Cmd::SetOrder(mic, 5),
+            Cmd::RenameNode(Ulid::new(), "Missing".into()),
+            Cmd::SetTargetVolume(stream, 40),
+        ];
+        let responses = manager.handle_batch(commands, true).await;
+        assert_eq!(responses.len(), 6);
+        assert!(responses.iter().all(failed));
+
+        // Everything should be back where it was, without the tree having been rebuilt
+        let graph = pipewire.graph();
+        assert_eq!(manager.get_target_filter_node(stream).ok(), Some(filter));
+        assert_eq!(graph.filter_volume(filter), volume);
+        assert_eq!(graph.filter_names[&mic], "Microphone");
+        assert_eq!(graph.links, links);
+        assert_eq!(manager.profile.find_node("Microphone"), Some(mic));
+        assert_eq!(
+            serde_json::to_value(&manager.profile).unwrap(),
+            serde_json::to_value(&profile).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn atomic_batch_removes_created_nodes() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let nodes = pipewire.graph().nodes.len();
+
+        let commands = vec![
+            Cmd::CreateNode(NodeType::VirtualSource, "Guest".into()),
+            Cmd::RenameNode(Ulid::new(), "Missing".into()),
+        ];
+        let responses = manager.handle_batch(commands, true).await;
+        assert!(responses.iter().all(failed));
+        assert_eq!(manager.profile.find_node("Guest"), None);
+        assert_eq!(pipewire.graph().nodes.len(), nodes);
+    }
+
+    #[tokio::test]
+    async fn new_defaults_are_cleared_on_rollback() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let filter = manager.get_target_filter_node(stream).unwrap();
+
+        let commands = vec![
+            Cmd::SetDefaultSink(system),
+            Cmd::RenameNode(Ulid::new(), "Missing".into()),
+            Cmd::SetTargetVolume(stream, 20),
+        ];
+        let responses = manager.handle_batch(commands, true).await;
+        let code = |index: usize| match &responses[index] {
+            Resp::Err(error) => error.code,
+            _ => panic!("Command {} should have failed", index),
+        };
+        assert_eq!([code(0), code(1), code(2)], [RolledBack, NotFound, RolledBack]);
+        assert_eq!(Resp::batch_error(&responses).map(|e| e.code), Some(NotFound));
+
+        // The default is handed back, without rebuilding the tree to do it
+        assert_eq!(manager.profile.defaults.sink, None);
+        assert!(!pipewire.graph().defaults.contains_key(&DefaultDevice::Sink));
+        assert_eq!(manager.get_target_filter_node(stream).ok(), Some(filter));
+    }
+
+    #[tokio::test]
+    async fn unknown_nodes_are_not_found() {
+        let (mut manager, _pipewire) = mock_manager(Profile::base_settings()).await;
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let missing = Ulid::new();
+
+        let commands = vec![
+            Cmd::SetTargetVolume(missing, 20),
+            Cmd::SetTargetMuteState(missing, Muted),
+            Cmd::SetTargetMix(missing, Mix::B),
+            Cmd::SetRoute(missing, stream, true),
+            Cmd::SetOrder(missing, 1),
+            Cmd::SetNodeColour(missing, Default::default()),
+        ];
+        for command in commands {
+            let error = ApiError::from(manager.handle_command(command).await.unwrap_err());
+            assert_eq!(error.code, ErrorCode::NotFound, "{}", error);
+        }
+    }
+}
@@ -1,36 +1,54 @@
+use crate::handler::mpris::spawn_mpris_handler;
+use crate::handler::pipewire::components::defaults::DefaultManagement;
+use crate::handler::pipewire::components::graph::GraphManagement;
 use crate::handler::pipewire::components::links::LinkManagement;
+use crate::handler::pipewire::components::live::{live_affected_by, LiveManagement, LiveState};
 use crate::handler::pipewire::components::load_profile::LoadProfile;
-use crate::handler::pipewire::components::physical::PhysicalDevices;
+use crate::handler::pipewire::components::physical::{physical_device, PhysicalDevices};
+use crate::handler::pipewire::components::policy::PolicyManagement;
+use crate::handler::pipewire::components::reconcile::ReconcileManagement;
 use crate::handler::pipewire::components::volume::VolumeManager;
 use crate::handler::pipewire::ipc::IPCHandler;
 use crate::handler::primary_worker::{ManagerMessage, WorkerMessage};
+use crate::metrics::Metrics;
 use crate::servers::http_server::MeterEvent;
+use anyhow::{bail, Result};
 use enum_map::EnumMap;
 use log::{debug, error, info, warn};
-use pipeweaver_ipc::commands::{APICommandResponse, AudioConfiguration, PhysicalDevice};
+use pipeweaver_ipc::commands::{
+    APICommandResponse, ApiError, AudioConfiguration, DaemonEvent, DaemonHealth, PhysicalDevice,
+};
 use pipeweaver_pipewire::{
-    ApplicationNode, DeviceNode, MediaClass, PipewireMessage, PipewireReceiver, PipewireRunner,
+    ApplicationNode, DeviceNode, MediaClass, PipewireBackend, PipewireError, PipewireMessage,
+    PipewireReceiver, PipewireRunner,
 };
-use pipeweaver_profile::Profile;
+use pipeweaver_profile::{DefaultDevices, MidiAction, Profile};
 use pipeweaver_shared::{DeviceType, Mix};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::select;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::task::JoinHandle;
-use tokio::time::sleep;
+use tokio::time::{interval, interval_at, sleep, MissedTickBehavior};
 use ulid::Ulid;
 
 type StdRecv = std::sync::mpsc::Receiver<PipewireReceiver>;
 
+// How often the links are checked against the profile, in case something has changed them
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+
+// How often a meter triggered live state is checked for release
+const LIVE_RELEASE_INTERVAL: Duration = Duration::from_millis(100);
+
 pub(crate) struct PipewireManager {
     command_receiver: mpsc::Receiver<ManagerMessage>,
     worker_sender: Sender<WorkerMessage>,
     ready_sender: Option<oneshot::Sender<()>>,
 
-    pub(crate) pipewire: Option<PipewireRunner>,
+    pub(crate) pipewire: Option<Box<dyn PipewireBackend>>,
 
     pub(crate) profile: Profile,
     pub(crate) source_map: HashMap<Ulid, EnumMap<Mix, Ulid>>,
@@ -48,12 +66,25 @@ pub(crate) struct PipewireManager {
     meter_receiver: Option<mpsc::Receiver<(Ulid, u8)>>,
     meter_broadcast: broadcast::Sender<MeterEvent>,
 
+    // Device and Application changes, for IPC subscribers
+    event_broadcast: broadcast::Sender<DaemonEvent>,
+    pub(crate) metrics: Arc<Metrics>,
+
     // A list of physical nodes
     pub(crate) node_list: EnumMap<DeviceType, Vec<PhysicalDevice>>,
     pub(crate) device_nodes: HashMap<u32, DeviceNode>,
 
     // A list of application nodes
     pub(crate) application_nodes: HashMap<u32, ApplicationNode>,
+
+    // The action to be bound to the next incoming MIDI message
+    pub(crate) midi_learn: Option<MidiAction>,
+
+    // Tracks whether we're currently 'Live'
+    pub(crate) live_state: LiveState,
+
+    // The system defaults, as last reported by Pipewire
+    pub(crate) current_defaults: DefaultDevices,
 }
 
 impl PipewireManager {
@@ -80,25 +111,82 @@ impl PipewireManager {
             meter_callback: meter_tx,
             meter_receiver: Some(meter_rx),
             meter_broadcast: config.meter_sender,
+            event_broadcast: config.event_sender,
+            metrics: config.metrics,
 
             node_list: Default::default(),
             device_nodes: Default::default(),
 
             application_nodes: Default::default(),
+
+            midi_learn: None,
+
+            live_state: LiveState::default(),
+
+            current_defaults: DefaultDevices::default(),
         }
     }
 
-    pub(crate) fn pipewire(&self) -> &PipewireRunner {
+    pub(crate) fn pipewire(&self) -> Result<&dyn PipewireBackend> {
         if let Some(pipewire) = &self.pipewire {
-            return pipewire;
+            return Ok(pipewire.as_ref());
         }
-        panic!("Attempted to Get Pipewire before starting");
+        bail!(ApiError::pipewire(PipewireError::NotRunning));
+    }
+
+    async fn pipewire_disconnected(&mut self) {
+        // Everything we created, and every device we knew about, went away with Pipewire
+        self.source_map.clear();
+        self.target_map.clear();
+        self.physical_source.clear();
+        self.physical_target.clear();
+        self.meter_map.clear();
+        self.current_defaults = DefaultDevices::default();
+        for nodes in self.node_list.values_mut() {
+            nodes.clear();
+        }
+
+        for (id, _) in self.device_nodes.drain() {
+            let _ = self.event_broadcast.send(DaemonEvent::DeviceRemoved(id));
+        }
+        for (id, _) in self.application_nodes.drain() {
+            let _ = self.event_broadcast.send(DaemonEvent::ApplicationRemoved(id));
+        }
+
+        let health = DaemonHealth::Failed(PipewireError::Disconnected.to_string());
+        let _ = self.worker_sender.send(WorkerMessage::Health(health)).await;
+    }
+
+    async fn pipewire_reconnected(&mut self) {
+        // Meters are recreated disabled, so they need enabling again once the tree is rebuilt
+        self.meter_enabled = false;
+
+        debug!("Rebuilding Profile");
+        let result = self.load_profile().await;
+        if let Err(e) = self.live_set_metering(self.live_state.metering_requested).await {
+            warn!("Unable to configure Metering: {}", e);
+        }
+        self.report_profile_health(result).await;
+    }
+
+    // Pipewire is running, but if the profile couldn't be built the daemon is only partly working
+    async fn report_profile_health(&self, result: Result<()>) {
+        let health = match result {
+            Ok(()) => DaemonHealth::Healthy,
+            Err(e) => {
+                error!("Error Loading Profile: {}", e);
+                DaemonHealth::Degraded(e.to_string())
+            }
+        };
+        let _ = self.worker_sender.send(WorkerMessage::Health(health)).await;
     }
 
     async fn get_audio_config(&self) -> AudioConfiguration {
         AudioConfiguration {
             profile: self.profile.clone(),
             devices: self.node_list.clone(),
+            midi_learn: self.midi_learn,
+            defaults: self.current_defaults,
         }
     }
 
@@ -116,12 +204,30 @@ impl PipewireManager {
         let receiver = thread::spawn(|| run_receiver_wrapper(recv, send_async));
 
         // Run up the Pipewire Handler
-        self.pipewire = Some(PipewireRunner::new(send.clone()).unwrap());
+        let stats = self.metrics.pipewire.clone();
+        match PipewireRunner::new(send.clone(), stats) {
+            Ok(runner) => {
+                self.pipewire = Some(Box::new(runner));
+
+                debug!("Loading Profile");
+                let result = self.load_profile().await;
+                self.report_profile_health(result).await;
+            }
+            Err(e) => {
+                // We can't do anything useful without Pipewire, but we keep running so that
+                // the reason can be reported to any clients.
+                error!("Unable to start Pipewire: {}", e);
+                let health = DaemonHealth::Failed(e.to_string());
+                let _ = self.worker_sender.send(WorkerMessage::Health(health)).await;
+            }
+        }
 
-        debug!("Loading Profile");
-        if let Err(e) = self.load_profile().await {
-            error!("Error Loading Profile: {}", e);
+        // Prepare the Live Detection
+        self.live_state.mpris = Some(spawn_mpris_handler());
+        if let Err(e) = self.live_set_metering(false).await {
+            warn!("Unable to configure Metering: {}", e);
         }
+        self.live_refresh().await;
 
         // Wait 1 second to process volume inputs from Pipewire
         let mut volumes_ready = false;
@@ -148,24 +254,57 @@ impl PipewireManager {
         let mut meter_receiver = self.meter_receiver.take().unwrap();
         let mut meter_buffer: Vec<(Ulid, u8)> = Vec::with_capacity(64);
 
+        let mut reconcile_timer =
+            interval_at(tokio::time::Instant::now() + RECONCILE_INTERVAL, RECONCILE_INTERVAL);
+        reconcile_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let mut live_timer = interval(LIVE_RELEASE_INTERVAL);
+        live_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         loop {
             select!(
                 Some(command) = self.command_receiver.recv() => {
                     match command {
                         ManagerMessage::Execute(command, tx) => {
+                            let started = Instant::now();
+                            let refresh_live = live_affected_by(&command);
                             let result = self.handle_command(command).await;
+                            self.metrics.record_command(started.elapsed());
+                            if refresh_live {
+                                self.live_refresh().await;
+                            }
 
                             // Map the result to a PW Response and send it
                             let _ = tx.send(match result {
                                 Ok(response) => response,
-                                Err(e) => APICommandResponse::Err(e.to_string())
+                                Err(e) => APICommandResponse::Err(e.into())
                             });
                         }
+                        ManagerMessage::ExecuteBatch(commands, atomic, tx) => {
+                            let started = Instant::now();
+                            let refresh_live = commands.iter().any(live_affected_by);
+                            let responses = self.handle_batch(commands, atomic).await;
+                            self.metrics.record_command(started.elapsed());
+                            if refresh_live {
+                                self.live_refresh().await;
+                            }
+                            let _ = tx.send(responses);
+                        }
                         ManagerMessage::GetAudioConfiguration(tx) => {
                             let _ = tx.send(self.get_audio_config().await);
                         }
                         ManagerMessage::SetMetering(enabled) => {
-                            let _ = self.set_metering(enabled).await;
+                            let _ = self.live_set_metering(enabled).await;
+                        }
+                        ManagerMessage::Reconcile(tx) => {
+                            let result = self.reconcile().await;
+                            if let Err(e) = &result {
+                                warn!("Unable to Reconcile Links: {}", e);
+                            }
+                            let _ = tx.send(result.map_err(ApiError::from));
+                        }
+                        ManagerMessage::GetGraph(tx) => {
+                            let _ = tx.send(self.graph_get().await.map_err(ApiError::from));
                         }
                         ManagerMessage::Quit => {
                             info!("[Manager] Stopping");
@@ -213,23 +352,58 @@ impl PipewireManager {
                                         }
                                     }
                                     let _ = self.worker_sender.send(WorkerMessage::DevicesChanged).await;
+                                    let _ = self.event_broadcast.send(DaemonEvent::DeviceRemoved(id));
+                                    self.metrics.device_removed();
                                 }
                             }
                         }
                         PipewireReceiver::ManagedLinkDropped(source, target) => {
                             warn!("Managed Link Removed: {:?} {:?}, reestablishing", source, target);
+                            self.metrics.link_restored();
                             if let Err(e) = self.link_create_type_to_type(source, target).await {
                                 warn!("Unable to reestablish link: {}", e);
                             }
                         }
+                        PipewireReceiver::DefaultDeviceChanged(device, id) => {
+                            // Nothing in the profile changes, but the status needs to update
+                            self.default_changed(device, id);
+                            let _ = self.worker_sender.send(WorkerMessage::DevicesChanged).await;
+                        }
+                        PipewireReceiver::UnmanagedLinkAdded(link, node) => {
+                            if let Err(e) = self.policy_link_added(link, node).await {
+                                warn!("Unable to apply Node Policy: {}", e);
+                            }
+                        }
                         PipewireReceiver::ApplicationAdded(node) => {
                             info!("Application Node Appeared: {}, {}", node.node_id, node.name);
+                            let event = DaemonEvent::ApplicationAdded(node.node_id, node.name.clone());
+                            let _ = self.event_broadcast.send(event);
                             self.application_nodes.insert(node.node_id, node);
+                            self.live_application_added().await;
                         }
                         PipewireReceiver::ApplicationRemoved(id) => {
                             if let Some(node) = self.application_nodes.remove(&id) {
                                 info!("Application Node Removed: {}, {}", node.node_id, node.name);
+                                let _ = self.event_broadcast.send(DaemonEvent::ApplicationRemoved(id));
+                                self.live_application_removed(id);
+                            }
+                        }
+                        PipewireReceiver::ApplicationVolumeChanged(id, volume) => {
+                            if let Some(node) = self.application_nodes.get_mut(&id) {
+                                node.volume = Some(volume);
+                            }
+                        }
+                        PipewireReceiver::Disconnected => {
+                            warn!("[Manager] Lost connection to Pipewire, waiting for it to return");
+                            for (_, handle) in device_timers.drain() {
+                                handle.abort();
                             }
+                            discovered_devices.clear();
+                            self.pipewire_disconnected().await;
+                        }
+                        PipewireReceiver::Reconnected => {
+                            info!("[Manager] Reconnected to Pipewire");
+                            self.pipewire_reconnected().await;
                         }
                         PipewireReceiver::NodeVolumeChanged(id, volume) => {
                             if volumes_ready {
@@ -257,11 +431,10 @@ impl PipewireManager {
                         device_timers.remove(&node_id);
 
                         // Create the 'Status' object
-                        let node = PhysicalDevice {
-                            node_id: device.node_id,
-                            name: device.name.clone(),
-                            description: device.description.clone()
-                        };
+                        let node = physical_device(&device);
+
+                        let _ = self.event_broadcast.send(DaemonEvent::DeviceAdded(node.clone()));
+                        self.metrics.device_added();
 
                         let sender = self.worker_sender.clone();
                         match device.node_class {
@@ -281,12 +454,22 @@ impl PipewireManager {
                         self.device_nodes.insert(device.node_id, device);
                         let _ = self.worker_sender.send(WorkerMessage::DevicesChanged).await;
                     } else {
-                        panic!("Got a Timer Ready for non-existent Node");
+                        warn!("Got a Timer Ready for non-existent Node {}", node_id);
+                    }
+                }
+                _ = reconcile_timer.tick(), if volumes_ready => {
+                    if let Err(e) = self.reconcile().await {
+                        debug!("Unable to Reconcile Links: {}", e);
                     }
                 }
+                _ = live_timer.tick(), if self.live_state.live => {
+                    self.live_check_release(Instant::now()).await;
+                }
                 result = meter_receiver.recv_many(&mut meter_buffer, 64) => {
                     if result > 0 {
                         for (id, percent) in meter_buffer.drain(..result) {
+                            self.live_meter_event(id, percent).await;
+                            self.metrics.record_meter(id, percent);
                             let _ = self.meter_broadcast.send(MeterEvent {
                                 id,
                                 percent
@@ -297,7 +480,9 @@ impl PipewireManager {
             );
         }
         info!("[Manager] Stopping Pipewire");
-        let _ = self.pipewire().send_message(PipewireMessage::Quit);
+        if let Ok(pipewire) = self.pipewire() {
+            let _ = pipewire.send_message(PipewireMessage::Quit);
+        }
         let runtime = self.pipewire.take();
         drop(runtime);
 
@@ -338,6 +523,88 @@ pub(crate) struct PipewireManagerConfig {
     pub(crate) worker_sender: Sender<WorkerMessage>,
 
     pub(crate) meter_sender: broadcast::Sender<MeterEvent>,
+    pub(crate) event_sender: broadcast::Sender<DaemonEvent>,
+    pub(crate) metrics: Arc<Metrics>,
 
     pub(crate) ready_sender: Option<oneshot::Sender<()>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::pipewire::components::node::NodeManagement;
+    use crate::handler::pipewire::mock::{mock_manager, MockGraph};
+
+    fn health(message: Option<WorkerMessage>) -> Option<DaemonHealth> {
+        match message {
+            Some(WorkerMessage::Health(health)) => Some(health),
+            _ => None,
+        }
+    }
+
+    // How many of a source's targets it's currently linked to
+    fn routed(manager: &PipewireManager, graph: &MockGraph, source: Ulid) -> usize {
+        let mixes = manager.source_map[&source];
+        manager.profile.routes[&source]
+            .iter()
+            .filter(|&&target| {
+                let target = manager.get_target_filter_node(target).unwrap();
+                mixes.values().any(|&mix| graph.is_linked(mix, target))
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn reconnecting_rebuilds_the_profile() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let system = manager.profile.find_node("System").unwrap();
+        let stream = manager.profile.find_node("Stream Mix").unwrap();
+        let (nodes, filters, links, routes) = {
+            let graph = pipewire.graph();
+            let routes = routed(&manager, &graph, system);
+            (graph.nodes.len(), graph.filters.len(), graph.links.len(), routes)
+        };
+        assert!(routes > 0);
+
+        pipewire.disconnect();
+        manager.pipewire_disconnected().await;
+        assert!(manager.source_map.is_empty());
+        assert!(manager.target_map.is_empty());
+
+        pipewire.reconnect();
+        manager.pipewire_reconnected().await;
+
+        let graph = pipewire.graph();
+        assert_eq!(graph.nodes.len(), nodes);
+        assert_eq!(graph.filters.len(), filters);
+        assert_eq!(graph.links.len(), links);
+        assert!(graph.nodes.contains_key(&system));
+        assert!(graph.nodes.contains_key(&stream));
+
+        // Everything is mapped again, so the routes are back in place
+        assert_eq!(routed(&manager, &graph, system), routes);
+    }
+
+    #[tokio::test]
+    async fn failed_rebuilds_are_reported() {
+        let (mut manager, pipewire) = mock_manager(Profile::base_settings()).await;
+        let (worker_sender, mut worker_receiver) = mpsc::channel(4);
+        manager.worker_sender = worker_sender;
+
+        pipewire.disconnect();
+        manager.pipewire_disconnected().await;
+        let reason = PipewireError::Disconnected.to_string();
+        assert_eq!(health(worker_receiver.recv().await), Some(DaemonHealth::Failed(reason)));
+
+        // Pipewire is back, but refuses to build anything
+        manager.pipewire_reconnected().await;
+        let health_state = health(worker_receiver.recv().await);
+        assert!(matches!(health_state, Some(DaemonHealth::Degraded(_))));
+
+        pipewire.reconnect();
+        manager.pipewire_disconnected().await;
+        worker_receiver.recv().await;
+        manager.pipewire_reconnected().await;
+        assert_eq!(health(worker_receiver.recv().await), Some(DaemonHealth::Healthy));
+    }
+}
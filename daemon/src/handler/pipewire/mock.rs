@@ -0,0 +1,296 @@
+use crate::handler::pipewire::components::load_profile::LoadProfile;
+use crate::handler::pipewire::manager::{PipewireManager, PipewireManagerConfig};
+use crate::metrics::Metrics;
+use anyhow::Result;
+use pipeweaver_pipewire::{
+    DefaultDevice, FilterHandler, FilterSnapshot, FilterValue, GraphSnapshot, LinkSnapshot,
+    LinkType, NodeSnapshot, PipewireBackend, PipewireError, PipewireMessage, UnmanagedLinkSnapshot,
+};
+use pipeweaver_profile::Profile;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::{broadcast, mpsc};
+use ulid::Ulid;
+
+pub(crate) struct MockNode {
+    pub(crate) name: String,
+    pub(crate) description: String,
+    pub(crate) volume: u8,
+    pub(crate) autoconnect: bool,
+    pub(crate) dont_reconnect: bool,
+    pub(crate) media_role: Option<String>,
+    pub(crate) virtual_microphone: bool,
+    pub(crate) media_class: &'static str,
+}
+
+/// What the daemon has built in 'Pipewire', filters keep their handler so values set on them
+/// can be read back.
+#[derive(Default)]
+pub(crate) struct MockGraph {
+    pub(crate) nodes: HashMap<Ulid, MockNode>,
+    pub(crate) filters: HashMap<Ulid, Box<dyn FilterHandler>>,
+    pub(crate) filter_names: HashMap<Ulid, String>,
+    pub(crate) links: Vec<(LinkType, LinkType)>,
+
+    /// Links which are reported as still coming up
+    pub(crate) pending_links: Vec<(LinkType, LinkType)>,
+
+    /// Nodes which refuse to have their properties updated
+    pub(crate) read_only: HashSet<Ulid>,
+
+    /// Nodes which accept property updates, but never apply them
+    pub(crate) ignores_properties: HashSet<Ulid>,
+
+    /// Pipewire IDs given to the nodes and filters as they're created
+    pub(crate) pw_ids: HashMap<Ulid, u32>,
+
+    /// Links made by something other than the daemon
+    pub(crate) unmanaged_links: Vec<UnmanagedLinkSnapshot>,
+
+    /// Unmanaged links which the daemon has asked to be destroyed
+    pub(crate) destroyed_links: Vec<u32>,
+
+    /// What the daemon has asked to be the system defaults
+    pub(crate) defaults: HashMap<DefaultDevice, Ulid>,
+
+    /// Volumes the daemon has set on application streams
+    pub(crate) application_volumes: HashMap<u32, u8>,
+}
+
+impl MockGraph {
+    pub(crate) fn filter_volume(&self, id: Ulid) -> Option<u8> {
+        let property = self.filters.get(&id)?.get_property(0).ok()?;
+        match property.value {
+            FilterValue::UInt8(volume) => Some(volume),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_linked(&self, source: Ulid, target: Ulid) -> bool {
+        let link = (LinkType::Filter(source), LinkType::Filter(target));
+        self.links.contains(&link)
+    }
+
+    pub(crate) fn links_to(&self, target: Ulid) -> usize {
+        let target = LinkType::Filter(target);
+        self.links.iter().filter(|(_, t)| *t == target).count()
+    }
+
+    // Pipewire drops any links attached to a node when it goes away
+    fn remove_links(&mut self, id: Ulid) {
+        let attached =
+            |link: &LinkType| matches!(link, LinkType::Node(n) | LinkType::Filter(n) if *n == id);
+        self.links.retain(|(source, target)| !attached(source) && !attached(target));
+    }
+
+    // Links can only be made to something which exists
+    fn check_link_end(&self, end: LinkType) -> Result<(), PipewireError> {
+        match end {
+            LinkType::Node(id) if !self.nodes.contains_key(&id) => {
+                Err(PipewireError::NodeNotFound(id))
+            }
+            LinkType::Filter(id) if !self.filters.contains_key(&id) => {
+                Err(PipewireError::FilterNotFound(id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn assign_pw_id(&mut self, id: Ulid) {
+        let pw_id = 100 + self.pw_ids.len() as u32;
+        self.pw_ids.entry(id).or_insert(pw_id);
+    }
+}
+
+/// An in-memory stand-in for PipewireRunner, clones share the same graph so a test can keep one
+/// to inspect after handing the other to the manager.
+#[derive(Clone, Default)]
+pub(crate) struct MockPipewire {
+    graph: Arc<Mutex<MockGraph>>,
+    disconnected: Arc<AtomicBool>,
+}
+
+impl MockPipewire {
+    pub(crate) fn graph(&self) -> MutexGuard<'_, MockGraph> {
+        self.graph.lock().unwrap()
+    }
+
+    /// Drops everything in the graph, and fails every message until reconnected
+    pub(crate) fn disconnect(&self) {
+        *self.graph() = MockGraph::default();
+        self.disconnected.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reconnect(&self) {
+        self.disconnected.store(false, Ordering::Relaxed);
+    }
+}
+
+impl PipewireBackend for MockPipewire {
+    fn send_message(&self, message: PipewireMessage) -> Result<()> {
+        if self.disconnected.load(Ordering::Relaxed) {
+            return Err(PipewireError::Disconnected.into());
+        }
+        let mut graph = self.graph();
+
+        match message {
+            PipewireMessage::CreateDeviceNode(props) => {
+                let media_class = props.media_class();
+                let node = MockNode {
+                    name: props.node_name,
+                    description: props.node_description,
+                    volume: props.initial_volume,
+                    autoconnect: props.autoconnect,
+                    dont_reconnect: props.dont_reconnect,
+                    media_role: props.media_role,
+                    virtual_microphone: props.virtual_microphone,
+                    media_class,
+                };
+                graph.nodes.insert(props.node_id, node);
+                graph.assign_pw_id(props.node_id);
+                if let Some(ready) = props.ready_sender {
+                    let _ = ready.send(());
+                }
+            }
+            PipewireMessage::CreateFilterNode(props) => {
+                graph.filters.insert(props.filter_id, props.callback);
+                graph.filter_names.insert(props.filter_id, props.filter_nick);
+                graph.assign_pw_id(props.filter_id);
+                if let Some(ready) = props.ready_sender {
+                    let _ = ready.send(());
+                }
+            }
+            PipewireMessage::CreateDeviceLink(source, target, ready) => {
+                graph.check_link_end(source)?;
+                graph.check_link_end(target)?;
+                if !graph.links.contains(&(source, target)) {
+                    graph.links.push((source, target));
+                }
+                if let Some(ready) = ready {
+                    let _ = ready.send(());
+                }
+            }
+            PipewireMessage::RemoveDeviceNode(id) => {
+                graph.nodes.remove(&id).ok_or(PipewireError::NodeNotFound(id))?;
+                graph.defaults.retain(|_, default| *default != id);
+                graph.remove_links(id);
+            }
+            PipewireMessage::RemoveFilterNode(id) => {
+                graph.filters.remove(&id).ok_or(PipewireError::FilterNotFound(id))?;
+                graph.filter_names.remove(&id);
+                graph.remove_links(id);
+            }
+            PipewireMessage::RemoveDeviceLink(source, target) => {
+                graph.links.retain(|link| *link != (source, target));
+            }
+            PipewireMessage::RemoveDuplicateLinks(source, target) => {
+                let mut seen = false;
+                graph.links.retain(|link| {
+                    let duplicate = seen && *link == (source, target);
+                    seen |= *link == (source, target);
+                    !duplicate
+                });
+            }
+            PipewireMessage::SetFilterValue(id, key, value) => {
+                let filter = graph.filters.get_mut(&id).ok_or(PipewireError::FilterNotFound(id))?;
+                filter.set_property(key, value)?;
+            }
+            PipewireMessage::SetNodeVolume(id, volume) => {
+                let node = graph.nodes.get_mut(&id).ok_or(PipewireError::NodeNotFound(id))?;
+                node.volume = volume;
+            }
+            PipewireMessage::SetApplicationVolume(id, volume) => {
+                graph.application_volumes.insert(id, volume);
+            }
+            PipewireMessage::UpdateNodeProperties(id, labels, applied) => {
+                if graph.read_only.contains(&id) {
+                    return Err(PipewireError::ReadOnly(id).into());
+                }
+                if graph.ignores_properties.contains(&id) {
+                    // The confirmation is dropped, as it would never arrive
+                    return Ok(());
+                }
+                if let Some(node) = graph.nodes.get_mut(&id) {
+                    node.name = labels.nick;
+                    node.description = labels.description;
+                    if let Some(applied) = applied {
+                        let _ = applied.send(());
+                    }
+                    return Ok(());
+                }
+                let name = graph.filter_names.get_mut(&id);
+                *name.ok_or(PipewireError::FilterNotFound(id))? = labels.nick;
+            }
+            PipewireMessage::DestroyUnmanagedLink(id) => graph.destroyed_links.push(id),
+            PipewireMessage::SetDefaultDevice(device, id) => {
+                graph.nodes.get(&id).ok_or(PipewireError::NodeNotFound(id))?;
+                graph.defaults.insert(device, id);
+            }
+            PipewireMessage::ClearDefaultDevice(device) => {
+                graph.defaults.remove(&device);
+            }
+            PipewireMessage::DestroyUnmanagedLinks(id) => {
+                let unmanaged = LinkType::UnmanagedNode(id);
+                graph.links.retain(|(source, target)| *source != unmanaged && *target != unmanaged);
+                graph
+                    .unmanaged_links
+                    .retain(|link| link.output_node != id && link.input_node != id);
+            }
+            PipewireMessage::GetGraph(sender) => {
+                // Nodes in the mock are created instantly, so they're always ready
+                let _ = sender.send(GraphSnapshot {
+                    nodes: graph
+                        .nodes
+                        .keys()
+                        .map(|&id| NodeSnapshot {
+                            id,
+                            pw_id: graph.pw_ids.get(&id).copied(),
+                            ready: true,
+                        })
+                        .collect(),
+                    filters: graph
+                        .filters
+                        .keys()
+                        .map(|&id| FilterSnapshot { id, pw_id: graph.pw_ids.get(&id).copied() })
+                        .collect(),
+                    links: graph
+                        .links
+                        .iter()
+                        .map(|&(source, destination)| LinkSnapshot {
+                            source,
+                            destination,
+                            pw_ids: vec![],
+                            ready: !graph.pending_links.contains(&(source, destination)),
+                        })
+                        .collect(),
+                    unmanaged_links: graph.unmanaged_links.clone(),
+                });
+            }
+            PipewireMessage::Quit => {}
+        }
+        Ok(())
+    }
+}
+
+/// Creates a manager backed by a MockPipewire, with the profile already loaded
+pub(crate) async fn mock_manager(profile: Profile) -> (PipewireManager, MockPipewire) {
+    let (_, command_receiver) = mpsc::channel(1);
+    let (worker_sender, _) = mpsc::channel(1);
+
+    let mut manager = PipewireManager::new(PipewireManagerConfig {
+        profile,
+        command_receiver,
+        worker_sender,
+        meter_sender: broadcast::channel(1).0,
+        event_sender: broadcast::channel(1).0,
+        metrics: Arc::new(Metrics::default()),
+        ready_sender: None,
+    });
+
+    let pipewire = MockPipewire::default();
+    manager.pipewire = Some(Box::new(pipewire.clone()));
+    manager.load_profile().await.unwrap();
+
+    (manager, pipewire)
+}
@@ -1,3 +1,5 @@
 mod components;
 pub(crate) mod manager;
 mod ipc;
+#[cfg(test)]
+mod mock;
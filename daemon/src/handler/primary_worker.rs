@@ -1,6 +1,10 @@
 use crate::handler::messaging::DaemonMessage;
 use crate::handler::pipewire::manager::{run_pipewire_manager, PipewireManagerConfig};
-use crate::handler::primary_worker::ManagerMessage::{Execute, GetAudioConfiguration, SetMetering};
+use crate::handler::primary_worker::ManagerMessage::{
+    Execute, ExecuteBatch, GetAudioConfiguration, GetGraph, Reconcile, SetMetering,
+};
+use crate::metrics::Metrics;
+use crate::servers::event_stream::PatchHistory;
 use crate::servers::http_server::{MeterEvent, PatchEvent};
 use crate::stop::Stop;
 use crate::APP_NAME_ID;
@@ -8,33 +12,56 @@ use anyhow::Context;
 use anyhow::Result;
 use json_patch::diff;
 use log::{debug, error, info, warn};
-use pipeweaver_ipc::commands::{APICommand, APICommandResponse, AudioConfiguration, DaemonCommand, DaemonResponse, DaemonStatus};
+use pipeweaver_ipc::commands::{
+    APICommand, APICommandResponse, ApiError, AudioConfiguration, DaemonCommand, DaemonEvent,
+    DaemonHealth, DaemonResponse, DaemonStatus, PipewireGraph,
+};
 use pipeweaver_profile::Profile;
 use std::fs;
 use std::fs::{create_dir_all, File};
 use std::io::ErrorKind;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast::Sender;
 use tokio::sync::{mpsc, oneshot};
 use tokio::{select, task, time};
+use ulid::Ulid;
 
 type Manage = mpsc::Sender<ManagerMessage>;
 
+/// How many patches are kept for clients asking for the patches since a revision
+const PATCH_HISTORY: usize = 128;
+
 pub struct PrimaryWorker {
     last_status: DaemonStatus,
+    health: DaemonHealth,
+    patch_history: PatchHistory,
     patch_broadcast: Sender<PatchEvent>,
     meter_broadcast: Sender<MeterEvent>,
+    event_broadcast: Sender<DaemonEvent>,
+    metrics: Arc<Metrics>,
 
     shutdown: Stop,
 }
 
 impl PrimaryWorker {
-    fn new(shutdown: Stop, patch: Sender<PatchEvent>, meter: Sender<MeterEvent>) -> Self {
+    fn new(
+        shutdown: Stop,
+        patch: Sender<PatchEvent>,
+        meter: Sender<MeterEvent>,
+        event: Sender<DaemonEvent>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Self {
-            last_status: DaemonStatus::default(),
+            // Revisions restart with the daemon, the epoch tells clients they have
+            last_status: DaemonStatus { epoch: Ulid::new(), ..Default::default() },
+            health: DaemonHealth::default(),
+            patch_history: PatchHistory::new(PATCH_HISTORY),
             patch_broadcast: patch,
             meter_broadcast: meter,
+            event_broadcast: event,
+            metrics,
 
             shutdown,
         }
@@ -66,6 +93,8 @@ impl PrimaryWorker {
             worker_sender,
 
             meter_sender: self.meter_broadcast.clone(),
+            event_sender: self.event_broadcast.clone(),
+            metrics: self.metrics.clone(),
             ready_sender: Some(ready_sender),
         };
         task::spawn(run_pipewire_manager(config, stop_sender));
@@ -96,6 +125,19 @@ impl PrimaryWorker {
                             self.update_status(&command_sender).await;
                             profile_changed = true;
                         }
+                        WorkerMessage::Health(health) => {
+                            match &health {
+                                DaemonHealth::Healthy => {}
+                                DaemonHealth::Degraded(reason) => {
+                                    warn!("[PrimaryWorker] Pipewire Manager Degraded: {}", reason);
+                                }
+                                DaemonHealth::Failed(reason) => {
+                                    error!("[PrimaryWorker] Pipewire Manager Failed: {}", reason);
+                                }
+                            }
+                            self.health = health;
+                            self.update_status(&command_sender).await;
+                        }
                     }
                 }
 
@@ -130,19 +172,42 @@ impl PrimaryWorker {
             DaemonMessage::GetStatus(tx) => {
                 let _ = tx.send(self.last_status.clone());
             }
+            DaemonMessage::GetPatchesSince(epoch, revision, tx) => {
+                let status = &self.last_status;
+                let patches = self.patch_history.since(epoch, revision, status);
+                let response = match patches {
+                    Some(patches) => DaemonResponse::Patches(patches),
+                    None => DaemonResponse::Status(self.last_status.clone()),
+                };
+                let _ = tx.send(response);
+            }
             DaemonMessage::RunDaemon(command, tx) => {
-                match command {
+                let response = match command {
                     DaemonCommand::SetMetering(enabled) => {
                         let _ = pw_tx.send(SetMetering(enabled)).await;
+                        DaemonResponse::Ok
                     }
-                }
-                let _ = tx.send(DaemonResponse::Ok);
+                    DaemonCommand::Reconcile => {
+                        // The caller wants to know whether the links could be put right
+                        let (reconcile_tx, reconcile_rx) = oneshot::channel();
+                        match pw_tx.send(Reconcile(reconcile_tx)).await {
+                            Ok(()) => match reconcile_rx.await {
+                                Ok(Ok(())) => DaemonResponse::Ok,
+                                Ok(Err(error)) => DaemonResponse::Err(error),
+                                Err(e) => DaemonResponse::Err(ApiError::internal(e.to_string())),
+                            },
+                            Err(e) => DaemonResponse::Err(ApiError::internal(e.to_string())),
+                        }
+                    }
+                };
+                let _ = tx.send(response);
                 update = true;
             }
             DaemonMessage::RunPipewire(command, response) => {
                 let (tx, rx) = oneshot::channel();
                 if let Err(e) = pw_tx.send(Execute(command, tx)).await {
-                    let _ = response.send(APICommandResponse::Err(e.to_string()));
+                    let _ =
+                        response.send(APICommandResponse::Err(ApiError::internal(e.to_string())));
                     return false;
                 }
                 match rx.await {
@@ -151,10 +216,37 @@ impl PrimaryWorker {
                         update = true;
                     }
                     Err(e) => {
-                        let _ = response.send(APICommandResponse::Err(e.to_string()));
+                        let _ = response
+                            .send(APICommandResponse::Err(ApiError::internal(e.to_string())));
                     }
                 }
             }
+            DaemonMessage::RunBatch(batch, response) => {
+                // The whole batch runs in one go, so we only need to update the status once
+                let (tx, rx) = oneshot::channel();
+                let message = ExecuteBatch(batch.commands, batch.atomic, tx);
+                if let Err(e) = pw_tx.send(message).await {
+                    let _ = response
+                        .send(vec![APICommandResponse::Err(ApiError::internal(e.to_string()))]);
+                    return false;
+                }
+                match rx.await {
+                    Ok(responses) => {
+                        let _ = response.send(responses);
+                        update = true;
+                    }
+                    Err(e) => {
+                        let _ = response
+                            .send(vec![APICommandResponse::Err(ApiError::internal(e.to_string()))]);
+                    }
+                }
+            }
+            DaemonMessage::GetGraph(response) => {
+                // This is a read-only request, so the sender goes straight through to the manager
+                if let Err(e) = pw_tx.send(GetGraph(response)).await {
+                    warn!("Unable to request the Pipewire Graph: {}", e);
+                }
+            }
         }
         update
     }
@@ -175,16 +267,30 @@ impl PrimaryWorker {
         };
 
         status.audio = config;
+        status.health = self.health.clone();
+        status.epoch = self.last_status.epoch;
+        status.revision = self.last_status.revision;
 
         let previous = serde_json::to_value(&self.last_status).unwrap();
-        let new = serde_json::to_value(&status).unwrap();
+        let mut new = serde_json::to_value(&status).unwrap();
 
-        let patch = diff(&previous, &new);
-        if !patch.0.is_empty() {
-            // Something has changed in our config, broadcast it to listeners
-            let _ = self.patch_broadcast.send(PatchEvent { data: patch });
+        if diff(&previous, &new).0.is_empty() {
+            return;
         }
 
+        // Something has changed in our config, bump the revision and broadcast it to listeners,
+        // the revision change is part of the patch so clients can track where they're up to
+        status.revision += 1;
+        new["revision"] = status.revision.into();
+
+        let event = PatchEvent {
+            data: diff(&previous, &new),
+            epoch: status.epoch,
+            revision: status.revision,
+        };
+        self.patch_history.push(event.clone());
+        let _ = self.patch_broadcast.send(event);
+
         self.last_status = status;
     }
 
@@ -237,14 +343,18 @@ impl PrimaryWorker {
 #[derive(Debug)]
 pub enum ManagerMessage {
     Execute(APICommand, oneshot::Sender<APICommandResponse>),
+    ExecuteBatch(Vec<APICommand>, bool, oneshot::Sender<Vec<APICommandResponse>>),
     GetAudioConfiguration(oneshot::Sender<AudioConfiguration>),
     SetMetering(bool),
+    GetGraph(oneshot::Sender<Result<PipewireGraph, ApiError>>),
+    Reconcile(oneshot::Sender<Result<(), ApiError>>),
     Quit,
 }
 
 pub enum WorkerMessage {
     DevicesChanged,
     ProfileChanged,
+    Health(DaemonHealth),
 }
 
 pub async fn start_primary_worker(
@@ -252,8 +362,10 @@ pub async fn start_primary_worker(
     shutdown: Stop,
     broadcast_tx: Sender<PatchEvent>,
     meter_tx: Sender<MeterEvent>,
+    event_tx: Sender<DaemonEvent>,
+    metrics: Arc<Metrics>,
     config_path: PathBuf,
 ) {
-    let mut manager = PrimaryWorker::new(shutdown, broadcast_tx, meter_tx);
+    let mut manager = PrimaryWorker::new(shutdown, broadcast_tx, meter_tx, event_tx, metrics);
     manager.run(message_receiver, config_path).await;
 }
@@ -1,18 +1,25 @@
 mod stop;
 mod servers;
 mod handler;
+mod metrics;
 mod platform;
 mod settings;
 
 use crate::handler::primary_worker::start_primary_worker;
+use crate::metrics::Metrics;
 use crate::platform::spawn_runtime;
+use crate::servers::dbus_server::spawn_dbus_server;
 use crate::servers::http_server::spawn_http_server;
-use crate::servers::ipc_server::{bind_socket, spawn_ipc_server};
+use crate::servers::ipc_server::{bind_socket, spawn_ipc_server, EventSources};
+use crate::servers::midi_server::spawn_midi_server;
+use crate::servers::osc_server::spawn_osc_server;
+use crate::settings::SettingsHandle;
 use crate::stop::Stop;
 use anyhow::{anyhow, bail, Context, Result};
 use directories::ProjectDirs;
 use log::{error, info, LevelFilter};
-use pipeweaver_ipc::commands::HttpSettings;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
 use simplelog::{ColorChoice, CombinedLogger, ConfigBuilder, TermLogger, TerminalMode};
 use tokio::sync::{broadcast, mpsc};
 use tokio::{join, task};
@@ -52,9 +59,27 @@ async fn main() -> Result<()> {
 
     let shutdown = Stop::new();
 
+    // Load the Daemon Settings
+    let config_dir = dirs.config_dir().to_path_buf();
+    let settings = SettingsHandle::load(config_dir.join("pipeweaver-settings.json"));
+
     // Create the Global Manager Channels...
     let (manager_send, manager_recv) = mpsc::channel(32);
 
+    // Create the Broadcast Channels..
+    let (broadcast_tx, broadcast_rx) = broadcast::channel(16);
+    let (meter_tx, meter_rx) = broadcast::channel(32);
+    let (event_tx, event_rx) = broadcast::channel(32);
+    drop(broadcast_rx);
+    drop(meter_rx);
+    drop(event_rx);
+
+    // Shared between the Meter Websocket and IPC subscribers
+    let meter_clients = Arc::new(AtomicUsize::new(0));
+
+    // Exported by the HTTP Server at /metrics
+    let metrics = Arc::new(Metrics::default());
+
     // Prepare the IPC Socket
     let ipc_socket = bind_socket().await;
     if ipc_socket.is_err() {
@@ -65,38 +90,59 @@ async fn main() -> Result<()> {
     let communications_handle = tokio::spawn(spawn_ipc_server(
         ipc_socket,
         manager_send.clone(),
+        settings.clone(),
+        EventSources {
+            patches: broadcast_tx.clone(),
+            meters: meter_tx.clone(),
+            events: event_tx.clone(),
+            meter_clients: meter_clients.clone(),
+        },
         shutdown.clone(),
     ));
 
     // Prepare the HTTP Server
-    let http_settings = HttpSettings {
-        enabled: true,
-        bind_address: "0.0.0.0".to_string(),
-        cors_enabled: false,
-        port: 14565,
-    };
-
     let (httpd_tx, httpd_rx) = tokio::sync::oneshot::channel();
-    let (broadcast_tx, broadcast_rx) = broadcast::channel(16);
-    let (meter_tx, meter_rx) = broadcast::channel(32);
-    drop(broadcast_rx);
-    drop(meter_rx);
 
     tokio::spawn(spawn_http_server(
         manager_send.clone(),
         httpd_tx,
         broadcast_tx.clone(),
         meter_tx.clone(),
-        http_settings,
+        settings.clone(),
+        meter_clients,
+        metrics.clone(),
     ));
     let http_server = httpd_rx.await?;
 
-    let config_dir = dirs.config_dir().to_path_buf();
+    // Spawn the MIDI Controller Handler
+    let midi_handle = tokio::spawn(spawn_midi_server(
+        manager_send.clone(),
+        broadcast_tx.clone(),
+        shutdown.clone(),
+    ));
+
+    // Prepare the OSC Server
+    let osc_handle = tokio::spawn(spawn_osc_server(
+        manager_send.clone(),
+        broadcast_tx.clone(),
+        settings.clone(),
+        shutdown.clone(),
+    ));
+
+    // Export the D-Bus Interface
+    let dbus_handle = tokio::spawn(spawn_dbus_server(
+        manager_send.clone(),
+        broadcast_tx.clone(),
+        shutdown.clone(),
+    ));
+
     let task = task::spawn(start_primary_worker(
         manager_recv,
         shutdown.clone(),
         broadcast_tx.clone(),
         meter_tx.clone(),
+        event_tx.clone(),
+        metrics,
         config_dir,
     ));
 
@@ -106,7 +152,15 @@ async fn main() -> Result<()> {
     let _ = shutdown.clone().recv().await;
 
     // Join on the Threads until they all end
-    let _ = join!(task, communications_handle, runtime, http_server.stop(false));
+    let _ = join!(
+        task,
+        communications_handle,
+        midi_handle,
+        osc_handle,
+        dbus_handle,
+        runtime,
+        http_server.stop(false)
+    );
 
     Ok(())
 }
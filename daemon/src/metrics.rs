@@ -0,0 +1,281 @@
+use enum_map::EnumMap;
+use pipeweaver_ipc::commands::DaemonStatus;
+use pipeweaver_pipewire::PipewireStats;
+use pipeweaver_profile::{DeviceDescription, MuteStates};
+use pipeweaver_shared::{Mix, MuteState, MuteTarget};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use strum::IntoEnumIterator;
+use ulid::Ulid;
+
+/// Upper bounds (in seconds) of the command latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Counters exported at /metrics in the Prometheus text format. Gauges (volumes, mute states)
+/// are read from the DaemonStatus at scrape time, so only things which can't be derived from
+/// the status are tracked here.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    pub(crate) pipewire: Arc<PipewireStats>,
+
+    links_restored: AtomicU64,
+    devices_added: AtomicU64,
+    devices_removed: AtomicU64,
+    websocket_clients: AtomicUsize,
+
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_sum_us: AtomicU64,
+    latency_count: AtomicU64,
+
+    /// The highest meter value seen for each node since the last scrape
+    meter_peaks: Mutex<HashMap<Ulid, u8>>,
+
+    /// Set while scrapes are keeping metering enabled, and when the last of them happened
+    pub(crate) metering: AtomicBool,
+    last_scrape: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    pub(crate) fn link_restored(&self) {
+        self.links_restored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn device_added(&self) {
+        self.devices_added.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn device_removed(&self) {
+        self.devices_removed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn websocket_connected(&self) {
+        self.websocket_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn websocket_disconnected(&self) {
+        self.websocket_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_command(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (index, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                self.latency_buckets[index].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_sum_us.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_meter(&self, id: Ulid, percent: u8) {
+        let mut peaks = self.meter_peaks.lock().unwrap_or_else(|e| e.into_inner());
+        let peak = peaks.entry(id).or_default();
+        *peak = (*peak).max(percent);
+    }
+
+    pub(crate) fn scraped(&self) {
+        *self.last_scrape.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    }
+
+    /// Whether there's been no scrape for at least the timeout
+    pub(crate) fn scrapes_idle(&self, timeout: Duration) -> bool {
+        let last = *self.last_scrape.lock().unwrap_or_else(|e| e.into_inner());
+        last.is_none_or(|last| last.elapsed() >= timeout)
+    }
+
+    /// Renders all metrics, meter peaks are reset after each call
+    pub(crate) fn render(&self, status: &DaemonStatus, meter_clients: usize) -> String {
+        let devices = &status.audio.profile.devices;
+        let mut out = String::new();
+
+        let sources = devices.sources.physical_devices.iter().map(|d| Source {
+            description: &d.description,
+            mute_states: &d.mute_states,
+            volumes: &d.volumes.volume,
+        });
+        let virtual_sources = devices.sources.virtual_devices.iter().map(|d| Source {
+            description: &d.description,
+            mute_states: &d.mute_states,
+            volumes: &d.volumes.volume,
+        });
+        let sources: Vec<Source> = sources.chain(virtual_sources).collect();
+
+        let targets = devices.targets.physical_devices.iter().map(|d| Target {
+            description: &d.description,
+            mute_state: d.mute_state,
+            volume: d.volume,
+        });
+        let virtual_targets = devices.targets.virtual_devices.iter().map(|d| Target {
+            description: &d.description,
+            mute_state: d.mute_state,
+            volume: d.volume,
+        });
+        let targets: Vec<Target> = targets.chain(virtual_targets).collect();
+
+        header(&mut out, "pipeweaver_source_volume", "gauge", "Source volume for each mix");
+        for source in &sources {
+            let labels = node_labels(source.description);
+            for (mix, volume) in source.volumes {
+                let metric = "pipeweaver_source_volume";
+                let _ = writeln!(out, "{}{{{},mix=\"{}\"}} {}", metric, labels, mix, volume);
+            }
+        }
+
+        header(&mut out, "pipeweaver_target_volume", "gauge", "Target volume");
+        for target in &targets {
+            let labels = node_labels(target.description);
+            let _ = writeln!(out, "pipeweaver_target_volume{{{}}} {}", labels, target.volume);
+        }
+
+        header(&mut out, "pipeweaver_source_muted", "gauge", "Whether a source mute is active");
+        for source in &sources {
+            let labels = node_labels(source.description);
+            for target in MuteTarget::iter() {
+                let muted = source.mute_states.mute_state.contains(&target) as u8;
+                let metric = "pipeweaver_source_muted";
+                let _ = writeln!(out, "{}{{{},target=\"{}\"}} {}", metric, labels, target, muted);
+            }
+        }
+
+        header(&mut out, "pipeweaver_target_muted", "gauge", "Whether a target is muted");
+        for target in &targets {
+            let labels = node_labels(target.description);
+            let muted = (target.mute_state == MuteState::Muted) as u8;
+            let _ = writeln!(out, "pipeweaver_target_muted{{{}}} {}", labels, muted);
+        }
+
+        // Meters for nodes which have since been removed are dropped
+        let source_names = sources.iter().map(|s| s.description);
+        let names: HashMap<Ulid, &DeviceDescription> =
+            targets.iter().map(|t| t.description).chain(source_names).map(|d| (d.id, d)).collect();
+
+        let help = "Peak meter value since the last scrape";
+        header(&mut out, "pipeweaver_meter_peak", "gauge", help);
+        let peaks = mem::take(&mut *self.meter_peaks.lock().unwrap_or_else(|e| e.into_inner()));
+        for (id, peak) in peaks {
+            if let Some(description) = names.get(&id) {
+                let labels = node_labels(description);
+                let _ = writeln!(out, "pipeweaver_meter_peak{{{}}} {}", labels, peak);
+            }
+        }
+
+        let overruns = self.pipewire.filter_overruns.load(Ordering::Relaxed);
+        let help = "Filter process callbacks which overran the quantum";
+        header(&mut out, "pipeweaver_filter_overruns_total", "counter", help);
+        let _ = writeln!(out, "pipeweaver_filter_overruns_total {}", overruns);
+
+        let restored = self.links_restored.load(Ordering::Relaxed);
+        let help = "Managed links re-established after being removed";
+        header(&mut out, "pipeweaver_links_restored_total", "counter", help);
+        let _ = writeln!(out, "pipeweaver_links_restored_total {}", restored);
+
+        let help = "Physical device hotplug events";
+        header(&mut out, "pipeweaver_device_hotplug_total", "counter", help);
+        let added = self.devices_added.load(Ordering::Relaxed);
+        let removed = self.devices_removed.load(Ordering::Relaxed);
+        let _ = writeln!(out, "pipeweaver_device_hotplug_total{{event=\"added\"}} {}", added);
+        let _ = writeln!(out, "pipeweaver_device_hotplug_total{{event=\"removed\"}} {}", removed);
+
+        let websockets = self.websocket_clients.load(Ordering::Relaxed);
+        header(&mut out, "pipeweaver_websocket_clients", "gauge", "Connected websocket clients");
+        let _ = writeln!(out, "pipeweaver_websocket_clients {}", websockets);
+
+        header(&mut out, "pipeweaver_meter_clients", "gauge", "Clients receiving meter data");
+        let _ = writeln!(out, "pipeweaver_meter_clients {}", meter_clients);
+
+        let metric = "pipeweaver_command_duration_seconds";
+        header(&mut out, metric, "histogram", "Time taken to execute commands");
+        for (index, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            let count = self.latency_buckets[index].load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", metric, bound, count);
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let sum = self.latency_sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", metric, count);
+        let _ = writeln!(out, "{}_sum {}", metric, sum);
+        let _ = writeln!(out, "{}_count {}", metric, count);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+struct Source<'a> {
+    description: &'a DeviceDescription,
+    mute_states: &'a MuteStates,
+    volumes: &'a EnumMap<Mix, u8>,
+}
+
+struct Target<'a> {
+    description: &'a DeviceDescription,
+    mute_state: MuteState,
+    volume: u8,
+}
+
+fn node_labels(description: &DeviceDescription) -> String {
+    format!("node=\"{}\",name=\"{}\"", description.id, escape(&description.name))
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pipeweaver_profile::Profile;
+
+    #[test]
+    fn render_reports_state_and_resets_peaks() {
+        let status = DaemonStatus {
+            audio: pipeweaver_ipc::commands::AudioConfiguration {
+                profile: Profile::base_settings(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mic = status.audio.profile.find_node("Microphone").unwrap();
+
+        let metrics = Metrics::default();
+        metrics.record_meter(mic, 20);
+        metrics.record_meter(mic, 70);
+        metrics.record_meter(mic, 40);
+        metrics.record_meter(Ulid::new(), 90);
+        metrics.record_command(Duration::from_millis(3));
+        metrics.device_added();
+
+        let labels = format!("node=\"{}\",name=\"Microphone\"", mic);
+        let out = metrics.render(&status, 2);
+        assert!(out.contains(&format!("pipeweaver_source_volume{{{},mix=\"A\"}} ", labels)));
+        assert!(out.contains(&format!("pipeweaver_meter_peak{{{}}} 70\n", labels)));
+        assert_eq!(out.matches("pipeweaver_meter_peak{").count(), 1);
+        assert!(out.contains("pipeweaver_device_hotplug_total{event=\"added\"} 1\n"));
+        assert!(out.contains("pipeweaver_meter_clients 2\n"));
+
+        let metric = "pipeweaver_command_duration_seconds";
+        assert!(out.contains(&format!("{}_bucket{{le=\"0.0025\"}} 0\n", metric)));
+        assert!(out.contains(&format!("{}_bucket{{le=\"0.005\"}} 1\n", metric)));
+        assert!(out.contains(&format!("{}_count 1\n", metric)));
+
+        // Peaks only cover the time since the last scrape
+        let out = metrics.render(&status, 2);
+        assert!(!out.contains("pipeweaver_meter_peak{"));
+    }
+
+    #[test]
+    fn scrapes_go_idle() {
+        let metrics = Metrics::default();
+        assert!(metrics.scrapes_idle(Duration::from_secs(60)));
+        metrics.scraped();
+        assert!(!metrics.scrapes_idle(Duration::from_secs(60)));
+        assert!(metrics.scrapes_idle(Duration::ZERO));
+    }
+}
@@ -0,0 +1,611 @@
+use crate::handler::packet::{handle_packet, Messenger};
+use crate::servers::http_server::PatchEvent;
+use crate::stop::Stop;
+use anyhow::Result;
+use log::{debug, info, warn};
+use pipeweaver_ipc::commands::{
+    APICommand, APICommandResponse, ApiError, DaemonRequest, DaemonResponse, DaemonStatus,
+    ErrorCode,
+};
+use pipeweaver_profile::{
+    DeviceDescription, LiveConfiguration, MidiAction, MidiMessage, NodeMetadata, NodePolicy,
+};
+use pipeweaver_shared::{Colour, Mix, MuteState, MuteTarget, NodeType, OrderGroup};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender as BroadcastSender;
+use ulid::Ulid;
+use zbus::names::InterfaceName;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{connection, fdo, interface, Connection};
+
+const DBUS_NAME: &str = "io.github.pipeweaver";
+const DBUS_PATH: &str = "/io/github/pipeweaver";
+
+// Allows the daemon to be pointed at a private bus (useful for testing via dbus-daemon)
+const DBUS_ADDRESS_ENV: &str = "PIPEWEAVER_DBUS_ADDRESS";
+
+pub async fn spawn_dbus_server(
+    messenger: Messenger,
+    broadcast_tx: BroadcastSender<PatchEvent>,
+    mut shutdown: Stop,
+) {
+    let connection = match dbus_builder() {
+        Ok(builder) => create_connection(builder, messenger).await,
+        Err(e) => Err(e),
+    };
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!("Error Running D-Bus Server: {}", e);
+            return;
+        }
+    };
+    info!("Started D-Bus Interface at {} {}", DBUS_NAME, DBUS_PATH);
+
+    let mut broadcast_rx = broadcast_tx.subscribe();
+    loop {
+        select! {
+            event = broadcast_rx.recv() => {
+                let patch = match event {
+                    Ok(event) => Some(event.data),
+                    Err(RecvError::Lagged(_)) => None,
+                    Err(RecvError::Closed) => break,
+                };
+                if let Err(e) = emit_changes(&connection, patch).await {
+                    warn!("[D-Bus] Unable to emit changes: {}", e);
+                }
+            }
+            _ = shutdown.recv() => {
+                info!("[D-Bus] Stopped");
+                break;
+            }
+        }
+    }
+}
+
+/// Creates a connection builder for the session bus, or the bus defined in the environment
+pub(crate) fn dbus_builder() -> Result<connection::Builder<'static>> {
+    match std::env::var(DBUS_ADDRESS_ENV) {
+        Ok(address) => {
+            debug!("[D-Bus] Connecting to {}", address);
+            Ok(connection::Builder::address(address.as_str())?)
+        }
+        Err(_) => Ok(connection::Builder::session()?),
+    }
+}
+
+async fn create_connection(
+    builder: connection::Builder<'_>,
+    messenger: Messenger,
+) -> Result<Connection> {
+    let interface = PipeweaverInterface { messenger, status: Mutex::new(None) };
+    let connection = builder.name(DBUS_NAME)?.serve_at(DBUS_PATH, interface)?.build().await?;
+    Ok(connection)
+}
+
+/// Refreshes the cached status, then sends the patch (if we have it) followed by a single
+/// PropertiesChanged carrying the new values of all our properties.
+async fn emit_changes(connection: &Connection, patch: Option<json_patch::Patch>) -> Result<()> {
+    let object_server = connection.object_server();
+    let interface = object_server.interface::<_, PipeweaverInterface>(DBUS_PATH).await?;
+    let emitter = interface.signal_emitter();
+
+    let status = get_status(&interface.get().await.messenger).await?;
+    if let Some(patch) = patch {
+        let patch = serde_json::to_string(&patch)?;
+        PipeweaverInterface::patch(emitter, &patch).await?;
+    }
+
+    let changed = HashMap::from([
+        ("Status", Value::new(status_json(&status)?)),
+        ("Nodes", Value::new(get_nodes(&status))),
+        ("Volumes", Value::new(get_volumes(&status))),
+    ]);
+    *interface.get().await.status.lock().unwrap() = Some(status);
+
+    let name = InterfaceName::from_static_str_unchecked(DBUS_NAME);
+    fdo::Properties::properties_changed(emitter, name, changed, Cow::Borrowed(&[])).await?;
+    Ok(())
+}
+
+struct PipeweaverInterface {
+    messenger: Messenger,
+
+    // Updated on every patch, so reading properties doesn't need to fetch the status
+    status: Mutex<Option<DaemonStatus>>,
+}
+
+/// Mirrors the APICommand list, node IDs are passed as their string representation and enums
+/// by their variant name (for example 'A', 'TargetA', 'VirtualSource').
+#[interface(name = "io.github.pipeweaver")]
+impl PipeweaverInterface {
+    async fn create_node(&self, node_type: &str, name: String) -> fdo::Result<String> {
+        let node_type = parse_enum::<NodeType>(node_type)?;
+        match self.run(APICommand::CreateNode(node_type, name)).await? {
+            APICommandResponse::Id(id) => Ok(id.to_string()),
+            _ => Err(fdo::Error::Failed("No ID Returned".to_string())),
+        }
+    }
+
+    async fn rename_node(&self, id: &str, name: String) -> fdo::Result<()> {
+        self.execute(APICommand::RenameNode(parse_id(id)?, name)).await
+    }
+
+    async fn set_node_colour(&self, id: &str, red: u8, green: u8, blue: u8) -> fdo::Result<()> {
+        let colour = Colour { red, green, blue };
+        self.execute(APICommand::SetNodeColour(parse_id(id)?, colour)).await
+    }
+
+    async fn remove_node(&self, id: &str) -> fdo::Result<()> {
+        self.execute(APICommand::RemoveNode(parse_id(id)?)).await
+    }
+
+    async fn set_source_volume(&self, id: &str, mix: &str, volume: u8) -> fdo::Result<()> {
+        let mix = parse_enum::<Mix>(mix)?;
+        self.execute(APICommand::SetSourceVolume(parse_id(id)?, mix, volume)).await
+    }
+
+    async fn set_source_volume_linked(&self, id: &str, linked: bool) -> fdo::Result<()> {
+        self.execute(APICommand::SetSourceVolumeLinked(parse_id(id)?, linked)).await
+    }
+
+    async fn set_target_volume(&self, id: &str, volume: u8) -> fdo::Result<()> {
+        self.execute(APICommand::SetTargetVolume(parse_id(id)?, volume)).await
+    }
+
+    async fn set_target_mix(&self, id: &str, mix: &str) -> fdo::Result<()> {
+        let mix = parse_enum::<Mix>(mix)?;
+        self.execute(APICommand::SetTargetMix(parse_id(id)?, mix)).await
+    }
+
+    async fn set_route(&self, source: &str, target: &str, enabled: bool) -> fdo::Result<()> {
+        let (source, target) = (parse_id(source)?, parse_id(target)?);
+        self.execute(APICommand::SetRoute(source, target, enabled)).await
+    }
+
+    async fn add_source_mute_target(&self, id: &str, target: &str) -> fdo::Result<()> {
+        let target = parse_enum::<MuteTarget>(target)?;
+        self.execute(APICommand::AddSourceMuteTarget(parse_id(id)?, target)).await
+    }
+
+    async fn del_source_mute_target(&self, id: &str, target: &str) -> fdo::Result<()> {
+        let target = parse_enum::<MuteTarget>(target)?;
+        self.execute(APICommand::DelSourceMuteTarget(parse_id(id)?, target)).await
+    }
+
+    async fn add_mute_target_node(&self, id: &str, target: &str, node: &str) -> fdo::Result<()> {
+        let target = parse_enum::<MuteTarget>(target)?;
+        let command = APICommand::AddMuteTargetNode(parse_id(id)?, target, parse_id(node)?);
+        self.execute(command).await
+    }
+
+    async fn del_mute_target_node(&self, id: &str, target: &str, node: &str) -> fdo::Result<()> {
+        let target = parse_enum::<MuteTarget>(target)?;
+        let command = APICommand::DelMuteTargetNode(parse_id(id)?, target, parse_id(node)?);
+        self.execute(command).await
+    }
+
+    async fn clear_mute_target_nodes(&self, id: &str, target: &str) -> fdo::Result<()> {
+        let target = parse_enum::<MuteTarget>(target)?;
+        self.execute(APICommand::ClearMuteTargetNodes(parse_id(id)?, target)).await
+    }
+
+    async fn set_target_mute_state(&self, id: &str, muted: bool) -> fdo::Result<()> {
+        let state = if muted { MuteState::Muted } else { MuteState::Unmuted };
+        self.execute(APICommand::SetTargetMuteState(parse_id(id)?, state)).await
+    }
+
+    async fn attach_physical_node(&self, id: &str, node_id: u32) -> fdo::Result<()> {
+        self.execute(APICommand::AttachPhysicalNode(parse_id(id)?, node_id)).await
+    }
+
+    async fn remove_physical_node(&self, id: &str, index: u32) -> fdo::Result<()> {
+        let index = index as usize;
+        self.execute(APICommand::RemovePhysicalNode(parse_id(id)?, index)).await
+    }
+
+    async fn set_order_group(&self, id: &str, group: &str) -> fdo::Result<()> {
+        let group = parse_enum::<OrderGroup>(group)?;
+        self.execute(APICommand::SetOrderGroup(parse_id(id)?, group)).await
+    }
+
+    async fn set_order(&self, id: &str, position: u8) -> fdo::Result<()> {
+        self.execute(APICommand::SetOrder(parse_id(id)?, position)).await
+    }
+
+    async fn add_midi_device(&self, name: String) -> fdo::Result<()> {
+        self.execute(APICommand::AddMidiDevice(name)).await
+    }
+
+    async fn remove_midi_device(&self, name: String) -> fdo::Result<()> {
+        self.execute(APICommand::RemoveMidiDevice(name)).await
+    }
+
+    async fn add_midi_mapping(
+        &self,
+        message: (&str, u8, u8),
+        action: (&str, &str, &str),
+    ) -> fdo::Result<()> {
+        let command = APICommand::AddMidiMapping(parse_midi(message)?, parse_action(action)?);
+        self.execute(command).await
+    }
+
+    async fn remove_midi_mapping(&self, message: (&str, u8, u8)) -> fdo::Result<()> {
+        self.execute(APICommand::RemoveMidiMapping(parse_midi(message)?)).await
+    }
+
+    /// Binds the next incoming MIDI message to this action
+    async fn set_midi_learn(&self, action: (&str, &str, &str)) -> fdo::Result<()> {
+        self.execute(APICommand::SetMidiLearn(Some(parse_action(action)?))).await
+    }
+
+    async fn cancel_midi_learn(&self) -> fdo::Result<()> {
+        self.execute(APICommand::SetMidiLearn(None)).await
+    }
+
+    /// The configuration is nested several levels deep, so it's passed as JSON
+    async fn set_live_configuration(&self, configuration: &str) -> fdo::Result<()> {
+        let configuration = serde_json::from_str::<LiveConfiguration>(configuration)
+            .map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        self.execute(APICommand::SetLiveConfiguration(configuration)).await
+    }
+
+    /// Options are 'DontReconnect' (b), 'Autoconnect' (b), 'PrioritySession' (i),
+    /// 'PriorityDriver' (i) and 'Exclusive' (b), any not provided are set to their default.
+    async fn set_node_policy(
+        &self,
+        id: &str,
+        mut options: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<()> {
+        let mut policy = NodePolicy::default();
+        if let Some(value) = take_option(&mut options, "DontReconnect")? {
+            policy.dont_reconnect = value;
+        }
+        if let Some(value) = take_option(&mut options, "Autoconnect")? {
+            policy.autoconnect = value;
+        }
+        policy.priority_session = take_option(&mut options, "PrioritySession")?;
+        policy.priority_driver = take_option(&mut options, "PriorityDriver")?;
+        if let Some(value) = take_option(&mut options, "Exclusive")? {
+            policy.exclusive = value;
+        }
+        check_options(options)?;
+        self.execute(APICommand::SetNodePolicy(parse_id(id)?, policy)).await
+    }
+
+    /// Options are 'MediaRole' (s), 'IconName' (s), 'Descriptions' (a{ss}) and
+    /// 'VirtualMicrophone' (b), any not provided are set to their default.
+    async fn set_node_metadata(
+        &self,
+        id: &str,
+        mut options: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<()> {
+        let mut metadata = NodeMetadata {
+            media_role: take_option(&mut options, "MediaRole")?,
+            icon_name: take_option(&mut options, "IconName")?,
+            ..Default::default()
+        };
+        if let Some(value) = take_option(&mut options, "Descriptions")? {
+            metadata.descriptions = value;
+        }
+        if let Some(value) = take_option(&mut options, "VirtualMicrophone")? {
+            metadata.virtual_microphone = value;
+        }
+        check_options(options)?;
+        self.execute(APICommand::SetNodeMetadata(parse_id(id)?, metadata)).await
+    }
+
+    async fn set_default_sink(&self, id: &str) -> fdo::Result<()> {
+        self.execute(APICommand::SetDefaultSink(parse_id(id)?)).await
+    }
+
+    async fn set_default_source(&self, id: &str) -> fdo::Result<()> {
+        self.execute(APICommand::SetDefaultSource(parse_id(id)?)).await
+    }
+
+    /// Runs a JSON serialised APICommand, returns the JSON serialised APICommandResponse
+    async fn execute_json(&self, command: &str) -> fdo::Result<String> {
+        let command = serde_json::from_str::<APICommand>(command)
+            .map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        let response = self.run(command).await?;
+        serde_json::to_string(&response).map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// The full JSON serialised daemon status
+    #[zbus(property)]
+    async fn status(&self) -> fdo::Result<String> {
+        status_json(&self.get_status().await?).map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// A list of (ID, Name, NodeType) for all nodes in the profile
+    #[zbus(property)]
+    async fn nodes(&self) -> fdo::Result<Vec<(String, String, String)>> {
+        Ok(get_nodes(&self.get_status().await?))
+    }
+
+    /// Node ID -> Volumes, Sources report their 'A' and 'B' mixes, Targets report 'Volume'
+    #[zbus(property)]
+    async fn volumes(&self) -> fdo::Result<HashMap<String, HashMap<String, u8>>> {
+        Ok(get_volumes(&self.get_status().await?))
+    }
+
+    /// Emitted with the JSON Patch for every change to the daemon status
+    #[zbus(signal)]
+    async fn patch(emitter: &SignalEmitter<'_>, patch: &str) -> zbus::Result<()>;
+}
+
+impl PipeweaverInterface {
+    async fn execute(&self, command: APICommand) -> fdo::Result<()> {
+        self.run(command).await.map(|_| ())
+    }
+
+    async fn run(&self, command: APICommand) -> fdo::Result<APICommandResponse> {
+        let request = DaemonRequest::Pipewire(command);
+        let response = handle_packet(request, self.messenger.clone()).await;
+        match response.map_err(|e| fdo::Error::Failed(e.to_string()))? {
+            DaemonResponse::Pipewire(APICommandResponse::Err(e)) => Err(map_error(e)),
+            DaemonResponse::Pipewire(response) => Ok(response),
+            response => Err(fdo::Error::Failed(format!("Unexpected Response: {:?}", response))),
+        }
+    }
+
+    async fn get_status(&self) -> fdo::Result<DaemonStatus> {
+        if let Some(status) = self.status.lock().unwrap().as_ref() {
+            return Ok(status.clone());
+        }
+
+        let status = get_status(&self.messenger).await;
+        let status = status.map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        *self.status.lock().unwrap() = Some(status.clone());
+        Ok(status)
+    }
+}
+
+async fn get_status(messenger: &Messenger) -> Result<DaemonStatus> {
+    match handle_packet(DaemonRequest::GetStatus, messenger.clone()).await? {
+        DaemonResponse::Status(status) => Ok(status),
+        response => anyhow::bail!("Unexpected Response: {:?}", response),
+    }
+}
+
+fn status_json(status: &DaemonStatus) -> Result<String> {
+    Ok(serde_json::to_string(status)?)
+}
+
+fn get_nodes(status: &DaemonStatus) -> Vec<(String, String, String)> {
+    let profile = &status.audio.profile;
+    let (sources, targets) = (&profile.devices.sources, &profile.devices.targets);
+
+    let mut nodes = vec![];
+    let mut add = |node_type: NodeType, description: &DeviceDescription| {
+        let id = description.id.to_string();
+        nodes.push((id, description.name.clone(), node_type.to_string()));
+    };
+
+    sources.physical_devices.iter().for_each(|d| add(NodeType::PhysicalSource, &d.description));
+    sources.virtual_devices.iter().for_each(|d| add(NodeType::VirtualSource, &d.description));
+    targets.physical_devices.iter().for_each(|d| add(NodeType::PhysicalTarget, &d.description));
+    targets.virtual_devices.iter().for_each(|d| add(NodeType::VirtualTarget, &d.description));
+    nodes
+}
+
+fn get_volumes(status: &DaemonStatus) -> HashMap<String, HashMap<String, u8>> {
+    let profile = &status.audio.profile;
+    let (sources, targets) = (&profile.devices.sources, &profile.devices.targets);
+
+    let mut volumes = HashMap::new();
+    let source_volumes = sources
+        .physical_devices
+        .iter()
+        .map(|d| (&d.description, &d.volumes))
+        .chain(sources.virtual_devices.iter().map(|d| (&d.description, &d.volumes)));
+    for (description, device_volumes) in source_volumes {
+        let values = device_volumes.volume.iter().map(|(mix, v)| (mix.to_string(), *v));
+        volumes.insert(description.id.to_string(), values.collect());
+    }
+
+    let target_volumes = targets
+        .physical_devices
+        .iter()
+        .map(|d| (&d.description, d.volume))
+        .chain(targets.virtual_devices.iter().map(|d| (&d.description, d.volume)));
+    for (description, volume) in target_volumes {
+        let values = HashMap::from([(String::from("Volume"), volume)]);
+        volumes.insert(description.id.to_string(), values);
+    }
+    volumes
+}
+
+fn parse_id(id: &str) -> fdo::Result<Ulid> {
+    Ulid::from_string(id).map_err(|e| fdo::Error::InvalidArgs(format!("Invalid ID {}: {}", id, e)))
+}
+
+fn parse_enum<T: FromStr>(value: &str) -> fdo::Result<T> {
+    value.parse::<T>().map_err(|_| fdo::Error::InvalidArgs(format!("Invalid Value: {}", value)))
+}
+
+/// MIDI messages are passed as (Type, Channel, Control or Note), where the type is either
+/// 'ControlChange' or 'Note'
+fn parse_midi((kind, channel, number): (&str, u8, u8)) -> fdo::Result<MidiMessage> {
+    match kind {
+        "ControlChange" => Ok(MidiMessage::ControlChange { channel, control: number }),
+        "Note" => Ok(MidiMessage::Note { channel, note: number }),
+        _ => Err(fdo::Error::InvalidArgs(format!("Invalid MIDI Message Type: {}", kind))),
+    }
+}
+
+/// MIDI actions are passed as (Action, Node ID, Argument), the argument is the Mix for
+/// 'SourceVolume', the Mute Target for 'SourceMute', and empty for 'TargetVolume' and 'TargetMute'
+fn parse_action((action, id, argument): (&str, &str, &str)) -> fdo::Result<MidiAction> {
+    let id = parse_id(id)?;
+    match action {
+        "SourceVolume" => Ok(MidiAction::SourceVolume(id, parse_enum(argument)?)),
+        "TargetVolume" => Ok(MidiAction::TargetVolume(id)),
+        "SourceMute" => Ok(MidiAction::SourceMute(id, parse_enum(argument)?)),
+        "TargetMute" => Ok(MidiAction::TargetMute(id)),
+        _ => Err(fdo::Error::InvalidArgs(format!("Invalid MIDI Action: {}", action))),
+    }
+}
+
+fn take_option<T>(options: &mut HashMap<String, OwnedValue>, key: &str) -> fdo::Result<Option<T>>
+where
+    T: TryFrom<OwnedValue>,
+    T::Error: Display,
+{
+    let Some(value) = options.remove(key) else {
+        return Ok(None);
+    };
+    let error = |e: T::Error| fdo::Error::InvalidArgs(format!("Invalid {}: {}", key, e));
+    T::try_from(value).map(Some).map_err(error)
+}
+
+fn check_options(options: HashMap<String, OwnedValue>) -> fdo::Result<()> {
+    match options.keys().next() {
+        Some(key) => Err(fdo::Error::InvalidArgs(format!("Unknown Option: {}", key))),
+        None => Ok(()),
+    }
+}
+
+fn map_error(error: ApiError) -> fdo::Error {
+    match error.code {
+        ErrorCode::NotFound => fdo::Error::UnknownObject(error.to_string()),
+        ErrorCode::InvalidArgument => fdo::Error::InvalidArgs(error.to_string()),
+        ErrorCode::Unauthorized | ErrorCode::Forbidden => {
+            fdo::Error::AccessDenied(error.to_string())
+        }
+        _ => fdo::Error::Failed(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::handler::mock_daemon::mock_daemon;
+    use futures::StreamExt;
+    use pipeweaver_profile::Profile;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::time::Duration;
+    use tokio::time;
+
+    /// A dbus-daemon run for the length of a test
+    pub(crate) struct PrivateBus {
+        process: Child,
+        pub(crate) address: String,
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.process.kill();
+            let _ = self.process.wait();
+        }
+    }
+
+    pub(crate) fn start_bus() -> Option<PrivateBus> {
+        let process = Command::new("dbus-daemon")
+            .args(["--session", "--print-address", "--nofork"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut process = process.ok()?;
+
+        let mut address = String::new();
+        let stdout = process.stdout.take()?;
+        BufReader::new(stdout).read_line(&mut address).ok()?;
+        let address = address.trim().to_string();
+        Some(PrivateBus { process, address })
+    }
+
+    // These need `dbus-daemon`, run them with `cargo test -p pipeweaver-daemon -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs dbus-daemon"]
+    async fn private_bus_round_trip() {
+        let bus = start_bus().expect("dbus-daemon should be available");
+
+        let mut status = DaemonStatus::default();
+        status.audio.profile = Profile::base_settings();
+        let game = status.audio.profile.find_node("Game").unwrap();
+        let chat_mic = status.audio.profile.find_node("Chat Mic").unwrap();
+        let (messenger, daemon) = mock_daemon(status);
+
+        let builder = connection::Builder::address(bus.address.as_str()).unwrap();
+        let server = create_connection(builder, messenger).await.unwrap();
+        let client = connection::Builder::address(bus.address.as_str()).unwrap();
+        let client = client.build().await.unwrap();
+        let proxy = zbus::Proxy::new(&client, DBUS_NAME, DBUS_PATH, DBUS_NAME).await.unwrap();
+
+        // Commands added to the API after the original interface
+        let message = ("ControlChange", 1_u8, 7_u8);
+        let action = ("SourceVolume", game.to_string(), "B");
+        let action = (action.0, action.1.as_str(), action.2);
+        let _: () = proxy.call("AddMidiMapping", &(message, action)).await.unwrap();
+        let _: () = proxy.call("CancelMidiLearn", &()).await.unwrap();
+
+        let options = HashMap::from([
+            ("Autoconnect", Value::new(true)),
+            ("PriorityDriver", Value::new(1500_i32)),
+        ]);
+        let _: () = proxy.call("SetNodePolicy", &(game.to_string(), options)).await.unwrap();
+        let options = HashMap::from([("VirtualMicrophone", Value::new(false))]);
+        let id = chat_mic.to_string();
+        let _: () = proxy.call("SetNodeMetadata", &(id.as_str(), options)).await.unwrap();
+        let _: () = proxy.call("SetDefaultSource", &(id.as_str(),)).await.unwrap();
+
+        let options = HashMap::from([("Unknown", Value::new(true))]);
+        let result: zbus::Result<()> = proxy.call("SetNodePolicy", &(id.as_str(), options)).await;
+        assert!(result.is_err());
+
+        {
+            let commands = daemon.commands();
+            let midi = MidiMessage::ControlChange { channel: 1, control: 7 };
+            let action = MidiAction::SourceVolume(game, Mix::B);
+            assert_eq!(commands.len(), 5);
+            assert!(matches!(&commands[0], APICommand::AddMidiMapping(m, a)
+                if *m == midi && *a == action));
+            assert!(matches!(commands[1], APICommand::SetMidiLearn(None)));
+            assert!(matches!(&commands[2], APICommand::SetNodePolicy(id, policy)
+                if *id == game && policy.autoconnect && policy.priority_driver == Some(1500)
+                    && policy.priority_session.is_none()));
+            assert!(matches!(&commands[3], APICommand::SetNodeMetadata(id, metadata)
+                if *id == chat_mic && !metadata.virtual_microphone));
+            assert!(matches!(commands[4], APICommand::SetDefaultSource(id) if id == chat_mic));
+        }
+
+        // A change produces a single PropertiesChanged, and a single status fetch
+        let properties = fdo::PropertiesProxy::builder(&client)
+            .destination(DBUS_NAME)
+            .unwrap()
+            .path(DBUS_PATH)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let mut changes = properties.receive_properties_changed().await.unwrap();
+
+        let requests = daemon.status_requests();
+        daemon.status().audio.profile.devices.targets.physical_devices[0].volume = 40;
+        emit_changes(&server, Some(json_patch::Patch(vec![]))).await.unwrap();
+        assert_eq!(daemon.status_requests(), requests + 1);
+
+        let change = time::timeout(Duration::from_secs(2), changes.next()).await.unwrap();
+        let change = change.unwrap();
+        let args = change.args().unwrap();
+        let changed: Vec<_> = args.changed_properties().keys().copied().collect();
+        assert_eq!(changed.len(), 3);
+        assert!(time::timeout(Duration::from_millis(200), changes.next()).await.is_err());
+
+        // And the properties are then read from the cache
+        let volumes: HashMap<String, HashMap<String, u8>> =
+            proxy.get_property("Volumes").await.unwrap();
+        let headphones = volumes.values().find(|v| v.get("Volume") == Some(&40));
+        assert!(headphones.is_some());
+        assert_eq!(daemon.status_requests(), requests + 1);
+    }
+}
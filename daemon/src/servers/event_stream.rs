@@ -0,0 +1,452 @@
+use crate::handler::packet::{handle_packet, Messenger};
+use crate::servers::http_server::{
+    get_access, meter_client_added, meter_client_removed, unauthorized, AppData, ClientCounter,
+    MeterEvent, PatchEvent,
+};
+use actix_web::web::{Bytes, Data};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use futures::stream;
+use json_patch::Patch;
+use log::{debug, warn};
+use pipeweaver_ipc::commands::{DaemonRequest, DaemonResponse, DaemonStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex as AsyncMutex;
+use ulid::Ulid;
+
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize)]
+struct EventQuery {
+    /// Include meter events in the stream
+    #[serde(default)]
+    meters: bool,
+
+    /// Browsers can't set the Last-Event-ID header on the first connection, so allow it here
+    last_event_id: Option<String>,
+}
+
+/// A Server-Sent Events stream of the daemon's patches and (optionally) meters, there are three
+/// event types:
+///
+/// status - The full DaemonStatus, sent on connection, or when patches have been missed
+/// patch  - A JSON Patch to apply to the last status
+/// meter  - A MeterEvent, only sent when requested with ?meters=true
+///
+/// Both status and patch events have the status epoch and revision as their ID (epoch:revision),
+/// reconnecting with the Last-Event-ID header (or the last_event_id query parameter) will resume
+/// the stream from that point, if possible.
+#[get("/api/events")]
+pub(crate) async fn events(
+    app_data: Data<AsyncMutex<AppData>>,
+    req: HttpRequest,
+    query: web::Query<EventQuery>,
+) -> HttpResponse {
+    // Take what we need and release the lock, so nothing waits on us while we talk to the daemon
+    let data = app_data.lock().await;
+    if get_access(&data.settings, &req).0.is_none() {
+        return HttpResponse::Unauthorized().json(unauthorized());
+    }
+    let messenger = data.messenger.clone();
+    let counter = data.client_counter.clone();
+
+    // Subscribe before fetching, so nothing can be missed between the two
+    let patch_rx = data.broadcast_tx.subscribe();
+    let meter_rx = query.meters.then(|| data.meter_tx.subscribe());
+    drop(data);
+
+    let header = req.headers().get("Last-Event-ID").and_then(|h| h.to_str().ok());
+    let last_event_id = header.or(query.last_event_id.as_deref()).and_then(parse_event_id);
+
+    let mut initial = vec![];
+    let revision = match last_event_id {
+        Some((epoch, revision)) => {
+            let request = DaemonRequest::GetPatchesSince(epoch, revision);
+            match handle_packet(request, messenger.clone()).await {
+                Ok(DaemonResponse::Patches(patches)) => {
+                    let count = patches.len();
+                    debug!("[SSE] Client Resumed from {}, sending {} patches", revision, count);
+                    for (index, patch) in patches.iter().enumerate() {
+                        let id = (epoch, revision + 1 + index as u64);
+                        initial.push(format_event("patch", Some(id), patch));
+                    }
+                    revision + count as u64
+                }
+                Ok(DaemonResponse::Status(status)) => {
+                    initial.push(format_event("status", Some(status_id(&status)), &status));
+                    status.revision
+                }
+                result => {
+                    warn!("[SSE] Unable to fetch Patches: {:?}", result);
+                    return HttpResponse::InternalServerError().finish();
+                }
+            }
+        }
+        None => match get_status(messenger.clone()).await {
+            Some(status) => {
+                initial.push(format_event("status", Some(status_id(&status)), &status));
+                status.revision
+            }
+            None => return HttpResponse::InternalServerError().finish(),
+        },
+    };
+
+    // Only subscribe to meters if they've been requested, as they're pretty noisy
+    let meters = match meter_rx {
+        Some(receiver) => {
+            meter_client_added(counter.clone(), messenger.clone()).await;
+            Some(MeterSubscription {
+                receiver,
+                _guard: MeterGuard { counter, messenger: messenger.clone() },
+            })
+        }
+        None => None,
+    };
+
+    let state = EventState {
+        patches: PatchFollower::new(patch_rx, messenger, revision),
+        meters,
+        initial: initial.into(),
+        keepalive: actix_web::rt::time::interval(KEEPALIVE),
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream::unfold(state, |mut state| async move {
+            let event = state.next().await?;
+            Some((Ok::<_, actix_web::Error>(event), state))
+        }))
+}
+
+struct EventState {
+    patches: PatchFollower,
+    meters: Option<MeterSubscription>,
+    initial: VecDeque<Bytes>,
+    keepalive: actix_web::rt::time::Interval,
+}
+
+impl EventState {
+    async fn next(&mut self) -> Option<Bytes> {
+        if let Some(event) = self.initial.pop_front() {
+            return Some(event);
+        }
+
+        loop {
+            let meters = &mut self.meters;
+            let meter = async {
+                match meters {
+                    Some(meters) => meters.receiver.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            select! {
+                update = self.patches.next() => {
+                    return match update? {
+                        PatchUpdate::Patch(event) => {
+                            let id = (event.epoch, event.revision);
+                            Some(format_event("patch", Some(id), &event.data))
+                        }
+                        PatchUpdate::Status(status) => {
+                            Some(format_event("status", Some(status_id(&status)), &status))
+                        }
+                    };
+                }
+                meter = meter => {
+                    match meter {
+                        Ok(meter) => return Some(format_event("meter", None, &meter)),
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+                _ = self.keepalive.tick() => {
+                    return Some(Bytes::from_static(b": keepalive\n\n"));
+                }
+            }
+        }
+    }
+}
+
+/// What a patch subscriber should be sent next
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum PatchUpdate {
+    Patch(PatchEvent),
+
+    /// The subscriber has missed patches, so needs the full status to continue from
+    Status(DaemonStatus),
+}
+
+/// Follows the patch broadcast for a single subscriber. If it falls behind, it's resynced with
+/// the full status, and any patches which that status already covers are skipped.
+pub(crate) struct PatchFollower {
+    receiver: Receiver<PatchEvent>,
+    messenger: Messenger,
+
+    /// The revision of the last status or patch sent
+    revision: u64,
+
+    /// Set when we've lagged, and kept until the status is fetched, so that being cancelled by
+    /// a select! while fetching it doesn't lose the resync
+    resync: bool,
+}
+
+impl PatchFollower {
+    pub(crate) fn new(receiver: Receiver<PatchEvent>, messenger: Messenger, revision: u64) -> Self {
+        Self { receiver, messenger, revision, resync: false }
+    }
+
+    /// Returns the next update, or None if the broadcast has closed or the status is unavailable
+    pub(crate) async fn next(&mut self) -> Option<PatchUpdate> {
+        loop {
+            if self.resync {
+                let status = get_status(self.messenger.clone()).await?;
+                self.resync = false;
+                self.revision = status.revision;
+                return Some(PatchUpdate::Status(status));
+            }
+
+            match self.receiver.recv().await {
+                Ok(event) => {
+                    // Already covered by what we've sent
+                    if event.revision <= self.revision {
+                        continue;
+                    }
+                    self.revision = event.revision;
+                    return Some(PatchUpdate::Patch(event));
+                }
+                Err(RecvError::Lagged(count)) => {
+                    debug!("Subscriber lagged by {} patches, sending Status", count);
+                    self.resync = true;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// The most recent patches, so clients which reconnect can catch up on what they've missed
+pub(crate) struct PatchHistory {
+    events: VecDeque<PatchEvent>,
+    capacity: usize,
+}
+
+impl PatchHistory {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { events: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub(crate) fn push(&mut self, event: PatchEvent) {
+        self.events.push_back(event);
+        if self.events.len() > self.capacity {
+            self.events.pop_front();
+        }
+    }
+
+    /// Returns the patches following a revision, up to the current status, or None if some of
+    /// them are no longer available. Revisions from another epoch belong to a different history
+    /// (the daemon has restarted since), so can never be followed on from.
+    pub(crate) fn since(
+        &self,
+        epoch: Ulid,
+        revision: u64,
+        status: &DaemonStatus,
+    ) -> Option<Vec<Patch>> {
+        let current = status.revision;
+        if epoch != status.epoch || revision > current {
+            return None;
+        }
+
+        let oldest = self.events.front().map(|event| event.revision);
+        if revision + 1 < oldest.unwrap_or(current + 1) {
+            return None;
+        }
+
+        let patches = self.events.iter().filter(|event| event.revision > revision);
+        Some(patches.map(|event| event.data.clone()).collect())
+    }
+}
+
+struct MeterSubscription {
+    receiver: Receiver<MeterEvent>,
+    _guard: MeterGuard,
+}
+
+/// Stops metering (if this was the last client) when the stream is dropped
+struct MeterGuard {
+    counter: ClientCounter,
+    messenger: Messenger,
+}
+
+impl Drop for MeterGuard {
+    fn drop(&mut self) {
+        actix_web::rt::spawn(meter_client_removed(self.counter.clone(), self.messenger.clone()));
+    }
+}
+
+pub(crate) async fn get_status(messenger: Messenger) -> Option<DaemonStatus> {
+    match handle_packet(DaemonRequest::GetStatus, messenger).await {
+        Ok(DaemonResponse::Status(status)) => Some(status),
+        result => {
+            warn!("Unable to fetch Status: {:?}", result);
+            None
+        }
+    }
+}
+
+fn format_event<T: Serialize>(event: &str, id: Option<(Ulid, u64)>, data: &T) -> Bytes {
+    let data = serde_json::to_string(data).unwrap_or_default();
+    match id {
+        Some((epoch, revision)) => {
+            Bytes::from(format!("event: {}\nid: {}:{}\ndata: {}\n\n", event, epoch, revision, data))
+        }
+        None => Bytes::from(format!("event: {}\ndata: {}\n\n", event, data)),
+    }
+}
+
+fn status_id(status: &DaemonStatus) -> (Ulid, u64) {
+    (status.epoch, status.revision)
+}
+
+/// Event IDs are 'epoch:revision', anything else can't be resumed from
+fn parse_event_id(id: &str) -> Option<(Ulid, u64)> {
+    let (epoch, revision) = id.split_once(':')?;
+    Some((Ulid::from_string(epoch).ok()?, revision.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::mock_daemon::mock_daemon;
+    use crate::servers::http_server::tests::{app_data, TestSettings};
+    use actix_web::body::MessageBody;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use json_patch::Patch;
+    use pipeweaver_ipc::commands::{AccessLevel, DaemonCommand};
+    use std::future::poll_fn;
+    use std::sync::atomic::Ordering;
+
+    async fn next_event<B: MessageBody + Unpin>(body: &mut B) -> String {
+        let chunk = poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_next(cx)).await;
+        let chunk = chunk.unwrap().ok().unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn patches_follow_the_status() {
+        let epoch = Ulid::new();
+        let status = DaemonStatus { epoch, revision: 4, ..Default::default() };
+        let (messenger, daemon) = mock_daemon(status);
+        let settings = TestSettings::new();
+        let data = app_data(messenger, settings.handle.clone());
+        let patches = data.lock().await.broadcast_tx.clone();
+        let counter = data.lock().await.client_counter.clone();
+
+        let app = init_service(App::new().app_data(data).service(events)).await;
+        let request = TestRequest::get().uri("/api/events?meters=true").to_request();
+        let mut body = call_service(&app, request).await.into_body();
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+        assert!(matches!(daemon.daemon_commands()[..], [DaemonCommand::SetMetering(true)]));
+
+        let status = format!("event: status\nid: {}:4\n", epoch);
+        assert!(next_event(&mut body).await.starts_with(&status));
+
+        // Anything the status already covers is skipped
+        for revision in [4, 5] {
+            let _ = patches.send(PatchEvent { data: Patch(vec![]), epoch, revision });
+        }
+        let patch = format!("event: patch\nid: {}:5\ndata: []\n\n", epoch);
+        assert_eq!(next_event(&mut body).await, patch);
+    }
+
+    #[actix_web::test]
+    async fn resuming_too_far_back_sends_the_status() {
+        let epoch = Ulid::new();
+        let status = DaemonStatus { epoch, revision: 9, ..Default::default() };
+        let (messenger, _daemon) = mock_daemon(status);
+        let settings = TestSettings::new();
+        let data = app_data(messenger, settings.handle.clone());
+        let app = init_service(App::new().app_data(data).service(events)).await;
+
+        // IDs from before epochs were added can't be resumed from either
+        for id in [format!("{}:2", epoch), String::from("2")] {
+            let request = TestRequest::get().uri("/api/events");
+            let request = request.insert_header(("Last-Event-ID", id));
+            let mut body = call_service(&app, request.to_request()).await.into_body();
+            let status = format!("event: status\nid: {}:9\n", epoch);
+            assert!(next_event(&mut body).await.starts_with(&status));
+        }
+    }
+
+    #[actix_web::test]
+    async fn streams_need_a_token() {
+        let (messenger, _daemon) = mock_daemon(DaemonStatus::default());
+        let settings = TestSettings::new();
+        settings.handle.add_token("Viewer".into(), AccessLevel::ReadOnly).unwrap();
+        let data = app_data(messenger, settings.handle.clone());
+
+        let app = init_service(App::new().app_data(data).service(events)).await;
+        let response = call_service(&app, TestRequest::get().uri("/api/events").to_request()).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+
+    fn event(revision: u64) -> PatchEvent {
+        let patch = serde_json::json!([{ "op": "add", "path": "/revision", "value": revision }]);
+        PatchEvent { data: serde_json::from_value(patch).unwrap(), epoch: Ulid::nil(), revision }
+    }
+
+    #[test]
+    fn history_covers_recent_revisions() {
+        let mut history = PatchHistory::new(3);
+        let mut status = DaemonStatus::default();
+        let epoch = status.epoch;
+        assert_eq!(history.since(epoch, 0, &status), Some(vec![]));
+        assert_eq!(history.since(epoch, 1, &status), None);
+
+        for revision in 1..=5 {
+            history.push(event(revision));
+        }
+        status.revision = 5;
+
+        // Only revisions 3 to 5 are kept, so we can go back as far as 2
+        assert_eq!(history.since(epoch, 5, &status), Some(vec![]));
+        assert_eq!(history.since(epoch, 3, &status), Some(vec![event(4).data, event(5).data]));
+        assert_eq!(history.since(epoch, 2, &status).map(|patches| patches.len()), Some(3));
+        assert_eq!(history.since(epoch, 1, &status), None);
+        assert_eq!(history.since(epoch, 6, &status), None);
+
+        // After a restart the same revisions describe different changes
+        assert_eq!(history.since(Ulid::new(), 3, &status), None);
+    }
+
+    #[tokio::test]
+    async fn lagging_resyncs_from_the_status() {
+        let status = DaemonStatus { revision: 5, ..Default::default() };
+        let (messenger, daemon) = mock_daemon(status);
+        let (sender, receiver) = tokio::sync::broadcast::channel(2);
+        let mut patches = PatchFollower::new(receiver, messenger, 0);
+
+        // Revisions 1 to 3 fall out of the channel, and the status covers 4 and 5
+        for revision in 1..=6 {
+            let _ = sender.send(event(revision));
+        }
+        match patches.next().await {
+            Some(PatchUpdate::Status(status)) => assert_eq!(status.revision, 5),
+            _ => panic!("Expected a Status"),
+        }
+        match patches.next().await {
+            Some(PatchUpdate::Patch(event)) => assert_eq!(event.revision, 6),
+            _ => panic!("Expected a Patch"),
+        }
+        assert_eq!(daemon.status_requests(), 1);
+
+        drop(sender);
+        assert!(patches.next().await.is_none());
+    }
+}
@@ -1,4 +1,8 @@
 use crate::handler::packet::{handle_packet, Messenger};
+use crate::metrics::Metrics;
+use crate::servers::event_stream::{PatchFollower, PatchUpdate};
+use crate::servers::{event_stream, rest_api};
+use crate::settings::SettingsHandle;
 use crate::APP_NAME;
 use actix::{
     Actor, ActorContext, AsyncContext, ContextFutureSpawner, Handler, Message, StreamHandler,
@@ -6,7 +10,8 @@ use actix::{
 };
 use actix_cors::Cors;
 use actix_web::dev::ServerHandle;
-use actix_web::http::header::ContentType;
+use actix_web::http::header::{ContentType, AUTHORIZATION};
+use actix_web::http::StatusCode;
 use actix_web::middleware::Condition;
 use actix_web::web::Data;
 use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer};
@@ -14,25 +19,45 @@ use actix_web_actors::ws;
 use actix_web_actors::ws::{CloseCode, CloseReason, ProtocolError};
 use anyhow::{anyhow, Result};
 use include_dir::{include_dir, Dir};
-use json_patch::Patch;
+use json_patch::{Patch, PatchOperation};
 use log::{debug, error, info, warn};
 use mime_guess::MimeGuess;
 use pipeweaver_ipc::commands::DaemonCommand::SetMetering;
 use pipeweaver_ipc::commands::{
-    DaemonRequest, DaemonResponse, DaemonStatus, HttpSettings, WebsocketRequest, WebsocketResponse,
+    APICommandResponse, AccessLevel, ApiError, DaemonRequest, DaemonResponse, DaemonStatus,
+    ErrorCode, StatusPatch, WebsocketRequest, WebsocketResponse,
 };
+use pipeweaver_ipc::schema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Sender as BroadcastSender;
 use tokio::sync::oneshot::Sender;
 use tokio::sync::Mutex;
 use ulid::Ulid;
 
 const WEB_CONTENT: Dir = include_dir!("./daemon/web-content/");
-type ClientCounter = Arc<AtomicUsize>;
+const REQUEST_ID: &str = "X-Request-Id";
+
+/// Bounds for the update rate (in ms) a meter client can ask for
+const METER_RATE_DEFAULT: u64 = 100;
+const METER_RATE_MIN: u64 = 20;
+const METER_RATE_MAX: u64 = 5000;
+
+/// How long a websocket has to send its token (when auth is enabled) before being closed
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long after the last /metrics scrape metering is kept running
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Tracks how many clients want meter data, metering is only enabled while this is non-zero
+pub(crate) type ClientCounter = Arc<AtomicUsize>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
 #[rtype(result = "()")]
@@ -41,57 +66,222 @@ pub struct MeterEvent {
     pub(crate) percent: u8,
 }
 
+/// The levels which have changed since the last tick, and the nodes which have been removed
+#[derive(Debug, Default, PartialEq, Serialize, Message)]
+#[rtype(result = "()")]
+struct MeterBatch {
+    levels: HashMap<Ulid, u8>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<Ulid>,
+}
+
+/// The version byte at the start of each binary meter frame
+const METER_FRAME_VERSION: u8 = 1;
+
+/// Sent in place of a level in binary frames, when the node has been removed
+const METER_FRAME_REMOVED: u8 = u8::MAX;
+
+impl MeterBatch {
+    fn binary_frame(&self) -> Vec<u8> {
+        let size = 1 + (self.levels.len() + self.removed.len()) * 17;
+        let mut frame = Vec::with_capacity(size);
+        frame.push(METER_FRAME_VERSION);
+
+        let removed = self.removed.iter().map(|id| (id, &METER_FRAME_REMOVED));
+        for (id, level) in self.levels.iter().chain(removed) {
+            frame.extend_from_slice(&u128::from(*id).to_be_bytes());
+            frame.push(*level);
+        }
+        frame
+    }
+}
+
+/// Tracks the levels a meter websocket has sent, so only changes need sending on each tick
+#[derive(Default)]
+struct MeterLevels {
+    sent: HashMap<Ulid, u8>,
+    pending: HashMap<Ulid, u8>,
+    removed: HashSet<Ulid>,
+}
+
+impl MeterLevels {
+    fn update(&mut self, id: Ulid, level: u8) {
+        self.removed.remove(&id);
+        if self.sent.get(&id) == Some(&level) {
+            self.pending.remove(&id);
+        } else {
+            self.pending.insert(id, level);
+        }
+    }
+
+    /// Forgets any nodes which no longer exist
+    fn prune(&mut self, nodes: &HashSet<Ulid>) {
+        self.pending.retain(|id, _| nodes.contains(id));
+        for id in self.sent.keys().filter(|id| !nodes.contains(id)) {
+            self.removed.insert(*id);
+        }
+        self.sent.retain(|id, _| nodes.contains(id));
+    }
+
+    /// Returns what's changed since the last call, if anything has
+    fn take(&mut self) -> Option<MeterBatch> {
+        if self.pending.is_empty() && self.removed.is_empty() {
+            return None;
+        }
+        self.sent.extend(&self.pending);
+        Some(MeterBatch {
+            levels: std::mem::take(&mut self.pending),
+            removed: self.removed.drain().collect(),
+        })
+    }
+}
+
+/// Other than unthrottled json, only levels which have changed since the last tick are sent
+#[derive(Debug, Default, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MeterFormat {
+    /// A JSON MeterEvent for every meter update, as they arrive, or per node each tick if a rate
+    /// has been requested
+    #[default]
+    Json,
+
+    /// A JSON MeterBatch per tick
+    Batch,
+
+    /// A binary frame per tick, starting with a version byte (currently 1), followed by 17 bytes
+    /// per node: the 16 byte ID (big endian), then the level, or 255 if the node was removed
+    Binary,
+}
+
+#[derive(Deserialize)]
+struct MeterQuery {
+    #[serde(default)]
+    format: MeterFormat,
+
+    /// How often (in ms) updates are sent, json without a rate sends them as they arrive
+    rate: Option<u64>,
+
+    /// A comma separated list of node IDs to send, all nodes are sent if this is missing
+    nodes: Option<String>,
+}
+
 struct MeterWebsocket {
     messenger: Messenger,
     client_counter: ClientCounter,
     broadcast_tx: BroadcastSender<MeterEvent>,
+    patch_tx: BroadcastSender<PatchEvent>,
+    metrics: Arc<Metrics>,
+
+    format: MeterFormat,
+
+    /// How often levels are sent, None sends every update as it arrives
+    rate: Option<Duration>,
+    nodes: Option<HashSet<Ulid>>,
+
+    settings: SettingsHandle,
+    access: Option<AccessLevel>,
 }
 
 impl Actor for MeterWebsocket {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.metrics.websocket_connected();
+        match self.access {
+            Some(_) => self.start_metering(ctx),
+            None => auth_timeout(ctx, |actor: &Self| actor.access.is_some()),
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.metrics.websocket_disconnected();
+
+        // If we never authenticated, we never started metering
+        if self.access.is_none() {
+            return;
+        }
+
+        let counter = self.client_counter.clone();
+        actix::spawn(meter_client_removed(counter, self.messenger.clone()));
+    }
+}
+
+impl MeterWebsocket {
+    fn start_metering(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
         let address = ctx.address();
         let mut broadcast_rx = self.broadcast_tx.subscribe();
+        let mut patch_rx = self.patch_tx.subscribe();
+        let messenger = self.messenger.clone();
+
+        let nodes = self.nodes.clone();
+        let rate = self.rate;
+        let period = rate.unwrap_or(Duration::from_millis(METER_RATE_DEFAULT));
+        let mut ticker = actix_web::rt::time::interval(period);
 
         let future = Box::pin(async move {
+            let mut levels = MeterLevels::default();
+
             loop {
-                if let Ok(event) = broadcast_rx.recv().await {
-                    if let Err(error) = address.clone().try_send(event) {
-                        error!("Failed to send Meter to websocket: {:?}", error);
-                        break;
+                select! {
+                    event = broadcast_rx.recv() => {
+                        let event = match event {
+                            Ok(event) => event,
+                            Err(RecvError::Lagged(_)) => continue,
+                            Err(RecvError::Closed) => break,
+                        };
+                        if nodes.as_ref().is_some_and(|nodes| !nodes.contains(&event.id)) {
+                            continue;
+                        }
+
+                        if rate.is_some() {
+                            levels.update(event.id, event.percent);
+                        } else if let Err(error) = address.try_send(event) {
+                            error!("Failed to send Meter to websocket: {:?}", error);
+                            break;
+                        }
+                    }
+                    patch = patch_rx.recv(), if rate.is_some() => {
+                        match patch {
+                            Ok(event) if !removes_devices(&event.data) => continue,
+                            Err(RecvError::Closed) => break,
+                            _ => {}
+                        }
+
+                        // Nodes have gone, so work out which, and stop tracking them
+                        if let Some(status) = event_stream::get_status(messenger.clone()).await {
+                            let profile = &status.audio.profile;
+                            levels.prune(&profile.descriptions().map(|d| d.id).collect());
+                        }
+                    }
+                    _ = ticker.tick(), if rate.is_some() => {
+                        let Some(batch) = levels.take() else {
+                            continue;
+                        };
+                        if let Err(error) = address.try_send(batch) {
+                            error!("Failed to send Meter to websocket: {:?}", error);
+                            break;
+                        }
                     }
                 }
             }
         });
 
-        let count = self.client_counter.fetch_add(1, Ordering::SeqCst);
-        if count == 0 {
-            debug!("Client Connected, starting metering...");
-            let messenger = self.messenger.clone();
-            let future = async move {
-                let request = DaemonRequest::Daemon(SetMetering(true));
-                let _ = handle_packet(request, messenger).await;
-            };
-            future.into_actor(self).spawn(ctx);
-        }
+        let counter = self.client_counter.clone();
+        let metering = meter_client_added(counter, self.messenger.clone());
+        metering.into_actor(self).spawn(ctx);
 
         let future = future.into_actor(self);
         ctx.spawn(future);
     }
+}
 
-    fn stopped(&mut self, ctx: &mut Self::Context) {
-        let count = self.client_counter.fetch_sub(1, Ordering::SeqCst) - 1;
-        if count == 0 {
-            // No clients left connected, terminate metering
-            debug!("Last Client disconnected, stopping metering");
-            let messenger = self.messenger.clone();
-            actix::spawn(async move {
-                let request = DaemonRequest::Daemon(SetMetering(false));
-                let _ = handle_packet(request, messenger).await;
-            });
-        }
-    }
+/// Whether a patch has removed anything from the devices, which might mean a node has gone
+fn removes_devices(patch: &Patch) -> bool {
+    patch.iter().any(|operation| match operation {
+        PatchOperation::Remove(op) => op.path.as_str().starts_with("/audio/profile/devices"),
+        _ => false,
+    })
 }
 
 impl Handler<MeterEvent> for MeterWebsocket {
@@ -104,50 +294,132 @@ impl Handler<MeterEvent> for MeterWebsocket {
     }
 }
 
+impl Handler<MeterBatch> for MeterWebsocket {
+    type Result = ();
+
+    fn handle(&mut self, msg: MeterBatch, ctx: &mut Self::Context) -> Self::Result {
+        match self.format {
+            MeterFormat::Binary => ctx.binary(msg.binary_frame()),
+            MeterFormat::Batch => {
+                if let Ok(result) = serde_json::to_string(&msg) {
+                    ctx.text(result);
+                }
+            }
+            MeterFormat::Json => {
+                for (id, percent) in msg.levels {
+                    if let Ok(result) = serde_json::to_string(&MeterEvent { id, percent }) {
+                        ctx.text(result);
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ProtocolError>> for MeterWebsocket {
     fn handle(&mut self, item: Result<ws::Message, ProtocolError>, ctx: &mut Self::Context) {
-        // This is an omi-directional stream, all we need to check for is close
-        if let Ok(ws::Message::Close(reason)) = item {
-            ctx.close(reason);
-            ctx.stop();
+        // This is an omi-directional stream, all we need to check for is close (and auth)
+        match item {
+            Ok(ws::Message::Text(text)) if self.access.is_none() => {
+                match authenticate(&self.settings, text.as_ref()) {
+                    Some(access) => {
+                        self.access = Some(access);
+                        self.start_metering(ctx);
+                    }
+                    None => reject(ctx),
+                }
+            }
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
         }
     }
 }
 
+pub(crate) async fn meter_client_added(counter: ClientCounter, messenger: Messenger) {
+    let count = counter.fetch_add(1, Ordering::SeqCst);
+    if count == 0 {
+        debug!("Client Connected, starting metering...");
+        let request = DaemonRequest::Daemon(SetMetering(true));
+        let _ = handle_packet(request, messenger).await;
+    }
+}
+
+pub(crate) async fn meter_client_removed(counter: ClientCounter, messenger: Messenger) {
+    let count = counter.fetch_sub(1, Ordering::SeqCst) - 1;
+    if count == 0 {
+        // No clients left connected, terminate metering
+        debug!("Last Client disconnected, stopping metering");
+        let request = DaemonRequest::Daemon(SetMetering(false));
+        let _ = handle_packet(request, messenger).await;
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PatchEvent {
     pub data: Patch,
+
+    /// The epoch of the status being patched, this changes when the daemon restarts
+    pub epoch: Ulid,
+
+    /// The status revision after this patch has been applied
+    pub revision: u64,
 }
 
 struct Websocket {
     usb_tx: Messenger,
     broadcast_tx: BroadcastSender<PatchEvent>,
+    metrics: Arc<Metrics>,
+
+    settings: SettingsHandle,
+    access: Option<AccessLevel>,
 }
 
 impl Actor for Websocket {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.metrics.websocket_connected();
+
+        // Patches shouldn't be sent until the client has authenticated
+        match self.access {
+            Some(_) => self.start_patches(ctx),
+            None => auth_timeout(ctx, |actor: &Self| actor.access.is_some()),
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.metrics.websocket_disconnected();
+    }
+}
+
+impl Websocket {
+    fn start_patches(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
         let address = ctx.address();
-        let mut broadcast_rx = self.broadcast_tx.subscribe();
+        let receiver = self.broadcast_tx.subscribe();
+        let mut patches = PatchFollower::new(receiver, self.usb_tx.clone(), 0);
 
         // Create a future that simply monitors the global broadcast bus, and pushes any changes
         // out to the WebSocket.
         let future = Box::pin(async move {
-            loop {
-                if let Ok(event) = broadcast_rx.recv().await {
-                    // We've received a message, attempt to trigger the WsMessage Handle..
-                    if let Err(error) = address.clone().try_send(WsResponse(WebsocketResponse {
-                        id: u64::MAX,
-                        data: DaemonResponse::Patch(event.data),
-                    })) {
-                        error!(
-                            "Error Occurred when sending message to websocket: {:?}",
-                            error
-                        );
-                        warn!("Aborting Websocket pushes for this client.");
-                        break;
-                    }
+            while let Some(update) = patches.next().await {
+                let data = match update {
+                    PatchUpdate::Patch(event) => DaemonResponse::Patch(StatusPatch {
+                        epoch: event.epoch,
+                        revision: event.revision,
+                        patch: event.data,
+                    }),
+                    PatchUpdate::Status(status) => DaemonResponse::Status(status),
+                };
+
+                // We've received a message, attempt to trigger the WsMessage Handle..
+                let response = WsResponse(WebsocketResponse { id: u64::MAX, data });
+                if let Err(error) = address.clone().try_send(response) {
+                    error!("Error Occurred when sending message to websocket: {:?}", error);
+                    warn!("Aborting Websocket pushes for this client.");
+                    break;
                 }
             }
         });
@@ -171,55 +443,42 @@ impl Handler<WsResponse> for Websocket {
     }
 }
 
+// Every response is passed back as is, if the request fails the error is sent instead
+async fn websocket_response(request: WebsocketRequest, messenger: Messenger) -> WebsocketResponse {
+    let data = match handle_packet(request.data, messenger).await {
+        Ok(response) => response,
+        Err(error) => DaemonResponse::Err(error.into()),
+    };
+    WebsocketResponse { id: request.id, data }
+}
+
 impl StreamHandler<Result<ws::Message, ProtocolError>> for Websocket {
     fn handle(&mut self, msg: Result<ws::Message, ProtocolError>, ctx: &mut Self::Context) {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Text(text)) if self.access.is_none() => {
+                match authenticate(&self.settings, text.as_ref()) {
+                    Some(access) => {
+                        self.access = Some(access);
+                        self.start_patches(ctx);
+                    }
+                    None => reject(ctx),
+                }
+            }
             Ok(ws::Message::Text(text)) => {
                 match serde_json::from_slice::<WebsocketRequest>(text.as_ref()) {
+                    Ok(request) if !is_permitted(&request.data, self.access) => {
+                        ctx.address().do_send(WsResponse(WebsocketResponse {
+                            id: request.id,
+                            data: DaemonResponse::Err(forbidden()),
+                        }));
+                    }
                     Ok(request) => {
                         let recipient = ctx.address().recipient();
                         let usb_tx = self.usb_tx.clone();
                         let future = async move {
-                            let request_id = request.id;
-                            let result = handle_packet(request.data, usb_tx).await;
-                            match result {
-                                Ok(resp) => match resp {
-                                    DaemonResponse::Ok => {
-                                        recipient.do_send(WsResponse(WebsocketResponse {
-                                            id: request_id,
-                                            data: DaemonResponse::Ok,
-                                        }));
-                                    }
-                                    DaemonResponse::Err(error) => {
-                                        recipient.do_send(WsResponse(WebsocketResponse {
-                                            id: request_id,
-                                            data: DaemonResponse::Err(error),
-                                        }));
-                                    }
-                                    DaemonResponse::Status(status) => {
-                                        recipient.do_send(WsResponse(WebsocketResponse {
-                                            id: request_id,
-                                            data: DaemonResponse::Status(status),
-                                        }));
-                                    }
-                                    DaemonResponse::Pipewire(result) => {
-                                        recipient.do_send(WsResponse(WebsocketResponse {
-                                            id: request_id,
-                                            data: DaemonResponse::Pipewire(result),
-                                        }));
-                                    }
-                                    _ => {
-                                        panic!("Unexpected Response!");
-                                    }
-                                },
-                                Err(error) => {
-                                    recipient.do_send(WsResponse(WebsocketResponse {
-                                        id: request_id,
-                                        data: DaemonResponse::Err(error.to_string()),
-                                    }));
-                                }
-                            }
+                            let response = websocket_response(request, usb_tx).await;
+                            recipient.do_send(WsResponse(response));
                         };
                         future.into_actor(self).spawn(ctx);
                     }
@@ -236,9 +495,10 @@ impl StreamHandler<Result<ws::Message, ProtocolError>> for Websocket {
                             Ok(value) => {
                                 if let Some(request_id) = value["id"].as_u64() {
                                     let recipient = ctx.address().recipient();
+                                    let error = ApiError::invalid(None, error.to_string());
                                     recipient.do_send(WsResponse(WebsocketResponse {
                                         id: request_id,
-                                        data: DaemonResponse::Err(error.to_string()),
+                                        data: DaemonResponse::Err(error),
                                     }));
                                 } else {
                                     warn!("id missing, Cannot continue. Closing connection");
@@ -278,11 +538,13 @@ impl StreamHandler<Result<ws::Message, ProtocolError>> for Websocket {
     }
 }
 
-struct AppData {
-    messenger: Messenger,
-    broadcast_tx: BroadcastSender<PatchEvent>,
-    meter_tx: BroadcastSender<MeterEvent>,
-    client_counter: ClientCounter,
+pub(crate) struct AppData {
+    pub(crate) messenger: Messenger,
+    pub(crate) settings: SettingsHandle,
+    pub(crate) broadcast_tx: BroadcastSender<PatchEvent>,
+    pub(crate) meter_tx: BroadcastSender<MeterEvent>,
+    pub(crate) client_counter: ClientCounter,
+    metrics: Arc<Metrics>,
 }
 
 pub async fn spawn_http_server(
@@ -290,9 +552,13 @@ pub async fn spawn_http_server(
     handle_tx: Sender<ServerHandle>,
     broadcast_tx: tokio::sync::broadcast::Sender<PatchEvent>,
     meter_tx: tokio::sync::broadcast::Sender<MeterEvent>,
-    settings: HttpSettings,
+    settings_handle: SettingsHandle,
+    client_counter: ClientCounter,
+    metrics: Arc<Metrics>,
 ) {
-    let client_counter = Arc::new(AtomicUsize::new(0));
+    let settings = settings_handle.get_http_settings();
+    let auth_enabled = settings_handle.auth_enabled();
+
     let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin_fn(|origin, _req_head| {
@@ -306,14 +572,21 @@ pub async fn spawn_http_server(
             .wrap(Condition::new(settings.cors_enabled, cors))
             .app_data(Data::new(Mutex::new(AppData {
                 messenger: messenger.clone(),
+                settings: settings_handle.clone(),
                 broadcast_tx: broadcast_tx.clone(),
                 meter_tx: meter_tx.clone(),
                 client_counter: client_counter.clone(),
+                metrics: metrics.clone(),
             })))
             .service(execute_command)
             .service(get_devices)
+            .service(get_schema)
+            .service(get_openapi)
+            .service(get_metrics)
             .service(websocket)
             .service(websocket_meter)
+            .service(event_stream::events)
+            .configure(rest_api::configure)
             .default_service(web::to(default))
     })
         .bind((settings.bind_address.clone(), settings.port));
@@ -330,6 +603,9 @@ pub async fn spawn_http_server(
         settings.bind_address.as_str(),
         settings.port,
     );
+    if !auth_enabled {
+        warn!("[HTTP] No API Tokens configured, authentication is disabled");
+    }
 
     let _ = handle_tx.send(server.handle());
 
@@ -348,60 +624,214 @@ async fn websocket(
 ) -> Result<HttpResponse, actix_web::Error> {
     let data = usb_mutex.lock().await;
 
+    // A token can be provided in the query string, otherwise it's expected as the first message
+    let (access, token) = get_access(&data.settings, &req);
+    if token.is_some() && access.is_none() {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
     ws::start(
         Websocket {
             usb_tx: data.messenger.clone(),
             broadcast_tx: data.broadcast_tx.clone(),
+            metrics: data.metrics.clone(),
+            settings: data.settings.clone(),
+            access,
         },
         &req,
         stream,
     )
 }
 
+/// Streams meter levels, the format, rate and nodes are selected with the query parameters in
+/// MeterQuery. With no parameters, a JSON MeterEvent is sent for every update.
 #[get("/api/websocket/meter")]
 async fn websocket_meter(
     usb_mutex: Data<Mutex<AppData>>,
     req: HttpRequest,
+    query: web::Query<MeterQuery>,
     stream: web::Payload,
 ) -> Result<HttpResponse, actix_web::Error> {
     let data = usb_mutex.lock().await;
 
+    let (access, token) = get_access(&data.settings, &req);
+    if token.is_some() && access.is_none() {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let query = query.into_inner();
+    let nodes = match query.nodes.map(|nodes| parse_nodes(&nodes)).transpose() {
+        Ok(nodes) => nodes,
+        Err(error) => return Ok(HttpResponse::BadRequest().json(error)),
+    };
+    let rate = match (query.format, query.rate) {
+        (MeterFormat::Json, None) => None,
+        (_, rate) => Some(rate.unwrap_or(METER_RATE_DEFAULT)),
+    };
+    let rate = rate.map(|rate| Duration::from_millis(rate.clamp(METER_RATE_MIN, METER_RATE_MAX)));
+
     ws::start(
         MeterWebsocket {
             messenger: data.messenger.clone(),
             broadcast_tx: data.meter_tx.clone(),
+            patch_tx: data.broadcast_tx.clone(),
             client_counter: data.client_counter.clone(),
+            metrics: data.metrics.clone(),
+            format: query.format,
+            rate,
+            nodes,
+            settings: data.settings.clone(),
+            access,
         },
         &req,
         stream,
     )
 }
 
+fn parse_nodes(nodes: &str) -> Result<HashSet<Ulid>, ApiError> {
+    let nodes = nodes.split(',').map(str::trim).filter(|node| !node.is_empty());
+    nodes
+        .map(|node| {
+            let message = format!("Invalid Node ID: {}", node);
+            Ulid::from_string(node).map_err(|_| ApiError::invalid(None, message))
+        })
+        .collect()
+}
+
 // So, fun note, according to the actix manual, web::Json uses serde_json to deserialise, good
 // news everybody! So do we.. :)
 #[post("/api/command")]
 async fn execute_command(
     request: web::Json<DaemonRequest>,
     app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
 ) -> HttpResponse {
     let mut guard = app_data.lock().await;
     let sender = guard.deref_mut();
 
-    // Errors propagate weirdly in the javascript world, so send all as OK, and handle there.
-    match handle_packet(request.0, sender.messenger.clone()).await {
-        Ok(result) => HttpResponse::Ok().json(result),
-        Err(error) => HttpResponse::Ok().json(DaemonResponse::Err(error.to_string())),
+    // Use the client's Request ID if it's provided one, so it can match up our logs with theirs
+    let request_id = req.headers().get(REQUEST_ID).and_then(|h| h.to_str().ok());
+    let request_id = request_id.map(String::from).unwrap_or_else(|| Ulid::new().to_string());
+
+    let (access, _) = get_access(&sender.settings, &req);
+    let response = if access.is_none() {
+        DaemonResponse::Err(unauthorized())
+    } else if !is_permitted(&request.0, access) {
+        DaemonResponse::Err(forbidden())
+    } else {
+        match handle_packet(request.0, sender.messenger.clone()).await {
+            Ok(result) => result,
+            Err(error) => DaemonResponse::Err(error.into()),
+        }
+    };
+
+    let error = match &response {
+        DaemonResponse::Err(error) => Some(error),
+        DaemonResponse::Pipewire(APICommandResponse::Err(error)) => Some(error),
+        _ => None,
+    };
+
+    let mut builder = match error {
+        Some(error) => {
+            debug!("[HTTP] Request {} Failed: {}", request_id, error);
+            HttpResponse::build(get_status_code(error.code))
+        }
+        None => HttpResponse::Ok(),
+    };
+    builder.insert_header((REQUEST_ID, request_id));
+    builder.json(response)
+}
+
+pub(crate) fn get_status_code(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::InvalidArgument => StatusCode::BAD_REQUEST,
+        ErrorCode::NoChange => StatusCode::CONFLICT,
+        ErrorCode::Pipewire => StatusCode::BAD_GATEWAY,
+        ErrorCode::RolledBack => StatusCode::FAILED_DEPENDENCY,
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
+pub(crate) fn unauthorized() -> ApiError {
+    ApiError::new(ErrorCode::Unauthorized, None, "Unauthorized")
+}
+
+pub(crate) fn forbidden() -> ApiError {
+    ApiError::new(ErrorCode::Forbidden, None, "Permission Denied")
+}
+
 #[get("/api/get-devices")]
-async fn get_devices(app_data: Data<Mutex<AppData>>) -> HttpResponse {
+async fn get_devices(app_data: Data<Mutex<AppData>>, req: HttpRequest) -> HttpResponse {
+    if get_access(&app_data.lock().await.settings, &req).0.is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
     if let Ok(response) = get_status(app_data).await {
         return HttpResponse::Ok().json(&response);
     }
     HttpResponse::InternalServerError().finish()
 }
 
+#[get("/api/schema")]
+async fn get_schema(app_data: Data<Mutex<AppData>>, req: HttpRequest) -> HttpResponse {
+    if get_access(&app_data.lock().await.settings, &req).0.is_none() {
+        return HttpResponse::Unauthorized().json(unauthorized());
+    }
+    HttpResponse::Ok().json(schema::get_json_schema())
+}
+
+#[get("/api/openapi.json")]
+async fn get_openapi(app_data: Data<Mutex<AppData>>, req: HttpRequest) -> HttpResponse {
+    if get_access(&app_data.lock().await.settings, &req).0.is_none() {
+        return HttpResponse::Unauthorized().json(unauthorized());
+    }
+    HttpResponse::Ok().json(schema::get_openapi())
+}
+
+/// Exports the daemon's state in the Prometheus text format. Meter peaks are only available
+/// while metering is active, so scraping holds metering on until scrapes stop for SCRAPE_TIMEOUT.
+#[get("/metrics")]
+async fn get_metrics(app_data: Data<Mutex<AppData>>, req: HttpRequest) -> HttpResponse {
+    let data = app_data.lock().await;
+    if get_access(&data.settings, &req).0.is_none() {
+        return HttpResponse::Unauthorized().json(unauthorized());
+    }
+
+    let metrics = data.metrics.clone();
+    let counter = data.client_counter.clone();
+    let messenger = data.messenger.clone();
+    drop(data);
+
+    metrics.scraped();
+    if !metrics.metering.swap(true, Ordering::SeqCst) {
+        meter_client_added(counter.clone(), messenger.clone()).await;
+        actix_web::rt::spawn(release_scrape_metering(metrics.clone(), counter.clone(), messenger));
+    }
+
+    match get_status(app_data).await {
+        Ok(status) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(metrics.render(&status, counter.load(Ordering::SeqCst))),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Drops the scraper's hold on metering once it's stopped scraping
+async fn release_scrape_metering(metrics: Arc<Metrics>, counter: ClientCounter, tx: Messenger) {
+    loop {
+        actix_web::rt::time::sleep(SCRAPE_TIMEOUT).await;
+        if metrics.scrapes_idle(SCRAPE_TIMEOUT) {
+            break;
+        }
+    }
+
+    debug!("[HTTP] Metrics are no longer being scraped, releasing metering");
+    metrics.metering.store(false, Ordering::SeqCst);
+    meter_client_removed(counter, tx).await;
+}
+
 async fn default(req: HttpRequest) -> HttpResponse {
     let path = if req.path() == "/" || req.path() == "" {
         "/index.html"
@@ -432,3 +862,224 @@ async fn get_status(app_data: Data<Mutex<AppData>>) -> Result<DaemonStatus> {
         _ => Err(anyhow!("Unexpected Daemon Status Result: {:?}", result)),
     }
 }
+
+#[derive(Deserialize)]
+struct AuthMessage {
+    token: String,
+}
+
+/// Pulls a token from either the Authorization header, or the 'token' query parameter, and
+/// returns the access level it grants alongside the token itself.
+pub(crate) fn get_access(
+    settings: &SettingsHandle,
+    req: &HttpRequest,
+) -> (Option<AccessLevel>, Option<String>) {
+    let header = req.headers().get(AUTHORIZATION).and_then(|h| h.to_str().ok());
+    let token = header.and_then(|h| h.strip_prefix("Bearer ")).map(String::from).or_else(|| {
+        let query = web::Query::<AuthMessage>::from_query(req.query_string()).ok();
+        query.map(|q| q.into_inner().token)
+    });
+
+    (settings.get_access(token.as_deref()), token)
+}
+
+/// Handles a websocket's first message, which should be {"token": "<token>"}
+fn authenticate(settings: &SettingsHandle, text: &str) -> Option<AccessLevel> {
+    let message = serde_json::from_str::<AuthMessage>(text).ok()?;
+    settings.get_access(Some(&message.token))
+}
+
+fn reject<A>(ctx: &mut ws::WebsocketContext<A>)
+where
+    A: Actor<Context = ws::WebsocketContext<A>>,
+{
+    warn!("[HTTP] Websocket failed to Authenticate, closing connection");
+    ctx.close(Some(CloseReason {
+        code: CloseCode::Policy,
+        description: Some(String::from("Authentication Failed")),
+    }));
+    ctx.stop();
+}
+
+/// Closes the websocket if it hasn't authenticated within the AUTH_TIMEOUT
+fn auth_timeout<A, F>(ctx: &mut ws::WebsocketContext<A>, authenticated: F)
+where
+    A: Actor<Context = ws::WebsocketContext<A>>,
+    F: FnOnce(&A) -> bool + 'static,
+{
+    ctx.run_later(AUTH_TIMEOUT, move |actor, ctx| {
+        if !authenticated(actor) {
+            debug!("[HTTP] Websocket didn't Authenticate in time");
+            reject(ctx);
+        }
+    });
+}
+
+fn is_permitted(request: &DaemonRequest, access: Option<AccessLevel>) -> bool {
+    match access {
+        Some(AccessLevel::Full) => true,
+        Some(AccessLevel::ReadOnly) => {
+            matches!(
+                request,
+                DaemonRequest::Ping
+                    | DaemonRequest::GetStatus
+                    | DaemonRequest::GetPatchesSince(..)
+                    | DaemonRequest::GetGraph(_)
+            )
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::handler::mock_daemon::mock_daemon;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use pipeweaver_ipc::commands::GraphFormat;
+    use std::path::PathBuf;
+
+    /// Settings stored in a temporary file, which is removed when they're dropped
+    pub(crate) struct TestSettings {
+        pub(crate) handle: SettingsHandle,
+        path: PathBuf,
+    }
+
+    impl TestSettings {
+        pub(crate) fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("settings-{}.json", Ulid::new()));
+            let handle = SettingsHandle::load(path.clone());
+            Self { handle, path }
+        }
+    }
+
+    impl Drop for TestSettings {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    pub(crate) fn app_data(messenger: Messenger, settings: SettingsHandle) -> Data<Mutex<AppData>> {
+        Data::new(Mutex::new(AppData {
+            messenger,
+            settings,
+            broadcast_tx: tokio::sync::broadcast::channel(16).0,
+            meter_tx: tokio::sync::broadcast::channel(16).0,
+            client_counter: Default::default(),
+            metrics: Default::default(),
+        }))
+    }
+
+    #[actix_web::test]
+    async fn schemas_need_a_token_when_auth_is_enabled() {
+        let (messenger, _daemon) = mock_daemon(DaemonStatus::default());
+        let settings = TestSettings::new();
+        let data = app_data(messenger, settings.handle.clone());
+        let app = App::new().app_data(data).service(get_schema).service(get_openapi);
+        let app = init_service(app).await;
+
+        // Without any tokens configured, anyone can read them
+        for uri in ["/api/schema", "/api/openapi.json"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let token = settings.handle.add_token("Viewer".into(), AccessLevel::ReadOnly).unwrap();
+        for uri in ["/api/schema", "/api/openapi.json"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            let header = (AUTHORIZATION, format!("Bearer {}", token));
+            let request = TestRequest::get().uri(uri).insert_header(header).to_request();
+            assert_eq!(call_service(&app, request).await.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn websocket_passes_every_response_back() {
+        let (messenger, _daemon) = mock_daemon(DaemonStatus::default());
+        let request = |id, data| WebsocketRequest { id, data };
+
+        let graph = request(1, DaemonRequest::GetGraph(GraphFormat::Json));
+        let response = websocket_response(graph, messenger.clone()).await;
+        assert_eq!(response.id, 1);
+        assert!(matches!(response.data, DaemonResponse::Graph(_)));
+
+        let dot = request(2, DaemonRequest::GetGraph(GraphFormat::Dot));
+        let response = websocket_response(dot, messenger).await;
+        assert!(matches!(response.data, DaemonResponse::GraphDot(_)));
+
+        // If the daemon can't be reached, the client is told rather than left waiting
+        let (messenger, _) = tokio::sync::mpsc::channel(1);
+        let status = request(3, DaemonRequest::GetStatus);
+        let response = websocket_response(status, messenger).await;
+        assert_eq!(response.id, 3);
+        assert!(matches!(response.data, DaemonResponse::Err(_)));
+    }
+
+    #[test]
+    fn meters_only_send_changes() {
+        let (first, second) = (Ulid::new(), Ulid::new());
+        let mut levels = MeterLevels::default();
+        assert_eq!(levels.take(), None);
+
+        levels.update(first, 10);
+        levels.update(second, 20);
+        let batch = levels.take().unwrap();
+        assert_eq!(batch.levels, HashMap::from([(first, 10), (second, 20)]));
+
+        // Repeating a level, or changing it and back before the tick, sends nothing
+        levels.update(first, 10);
+        levels.update(second, 30);
+        levels.update(second, 20);
+        assert_eq!(levels.take(), None);
+
+        levels.update(second, 40);
+        assert_eq!(levels.take().unwrap().levels, HashMap::from([(second, 40)]));
+    }
+
+    #[test]
+    fn removed_nodes_are_pruned() {
+        let (first, second) = (Ulid::new(), Ulid::new());
+        let mut levels = MeterLevels::default();
+        levels.update(first, 10);
+        levels.update(second, 20);
+        levels.take();
+
+        levels.update(second, 25);
+        levels.prune(&HashSet::from([first]));
+        let batch = levels.take().unwrap();
+        assert!(batch.levels.is_empty());
+        assert_eq!(batch.removed, vec![second]);
+        assert_eq!(levels.sent.keys().collect::<Vec<_>>(), vec![&first]);
+        assert_eq!(levels.take(), None);
+    }
+
+    #[test]
+    fn binary_frames_are_versioned() {
+        let (first, second) = (Ulid::new(), Ulid::new());
+        let batch = MeterBatch { levels: HashMap::from([(first, 42)]), removed: vec![second] };
+
+        let frame = batch.binary_frame();
+        assert_eq!(frame.len(), 1 + 2 * 17);
+        assert_eq!(frame[0], METER_FRAME_VERSION);
+        assert_eq!(frame[1..17], u128::from(first).to_be_bytes());
+        assert_eq!(frame[17], 42);
+        assert_eq!(frame[18..34], u128::from(second).to_be_bytes());
+        assert_eq!(frame[34], METER_FRAME_REMOVED);
+    }
+
+    #[test]
+    fn only_device_removals_prune_meters() {
+        let patch = |op: Value| serde_json::from_value::<Patch>(Value::Array(vec![op])).unwrap();
+        let path = "/audio/profile/devices/sources/virtual_devices/2";
+        assert!(removes_devices(&patch(serde_json::json!({ "op": "remove", "path": path }))));
+
+        let path = "/audio/profile/routes/01J0000000000000000000000";
+        assert!(!removes_devices(&patch(serde_json::json!({ "op": "remove", "path": path }))));
+
+        let path = "/audio/profile/devices/targets/virtual_devices/0/volume";
+        let op = serde_json::json!({ "op": "replace", "path": path, "value": 20 });
+        assert!(!removes_devices(&patch(op)));
+    }
+}
@@ -1,88 +1,65 @@
 use crate::handler::packet::{handle_packet, Messenger};
-use anyhow::{bail, Result};
-use interprocess::local_socket::tokio::prelude::{LocalSocketListener, LocalSocketStream};
-use interprocess::local_socket::traits::tokio::{Listener, Stream};
-use interprocess::local_socket::{
-    GenericFilePath, ListenerOptions, ToFsName,
+use crate::servers::event_stream::{PatchFollower, PatchUpdate};
+use crate::servers::http_server::{
+    meter_client_added, meter_client_removed, ClientCounter, MeterEvent, PatchEvent,
 };
+use crate::settings::SettingsHandle;
+use anyhow::Result;
+use interprocess::local_socket::tokio::prelude::LocalSocketListener;
+use interprocess::local_socket::traits::tokio::Listener;
 use log::{debug, info, warn};
+use pipeweaver_ipc::clients::ipc::ipc_path::{
+    get_socket_path, listener_options, prepare_socket_path, tidy_socket,
+};
 use pipeweaver_ipc::clients::ipc::ipc_socket::Socket;
-use pipeweaver_ipc::commands::{DaemonRequest, DaemonResponse};
+use pipeweaver_ipc::commands::{
+    ApiError, AuthCommand, AuthResponse, DaemonEvent, DaemonRequest, DaemonResponse, StatusPatch,
+    Subscription,
+};
 use std::fs;
-use std::path::Path;
-
-use crate::{Stop, APP_NAME, APP_NAME_ID};
-
-async fn ipc_tidy() -> Result<()> {
-    let socket_path = format!("/tmp/{}.socket", APP_NAME);
-
-    if !Path::new(&socket_path).exists() {
-        return Ok(());
-    }
-    let socket = socket_path.clone().to_fs_name::<GenericFilePath>()?;
-    let connection = LocalSocketStream::connect(socket).await;
-
-    if connection.is_err() {
-        match cfg!(windows) {
-            true => {
-                debug!("Named Pipe not running, continuing..");
-            }
-            false => {
-                debug!("Connection Failed. Socket File is stale, removing..");
-                fs::remove_file(socket_path)?;
-            }
-        }
-        return Ok(());
-    }
+use tokio::select;
+use tokio::sync::broadcast::Sender as BroadcastSender;
 
-    debug!("Connected to socket, seeing if there's a Daemon on the other side..");
-    let connection = connection?;
-
-    let mut socket: Socket<DaemonResponse, DaemonRequest> = Socket::new(connection);
-    if let Err(e) = socket.send(DaemonRequest::Ping).await {
-        match cfg!(windows) {
-            true => {
-                debug!("Our named pipe is broken, something is horribly wrong..");
-                bail!("Named Pipe Error: {}", e);
-            }
-            false => {
-                debug!("Unable to send messages, removing socket..");
-                fs::remove_file(socket_path)?;
-            }
-        }
-        return Ok(());
-    }
-
-    // If we get here, there's an active Daemon running!
-    bail!("The {} Daemon is already running.", APP_NAME);
-}
+use crate::Stop;
 
 pub async fn bind_socket() -> Result<LocalSocketListener> {
-    let socket_path = format!("/tmp/{}.socket", APP_NAME_ID);
-    ipc_tidy().await?;
+    let socket_path = get_socket_path();
+    prepare_socket_path(&socket_path)?;
+    tidy_socket(&socket_path).await?;
 
-    let name = socket_path.to_fs_name::<GenericFilePath>()?;
-    let opts = ListenerOptions::new().name(name.clone());
-    let listener = opts.create_tokio()?;
+    let listener = listener_options(&socket_path)?.create_tokio()?;
 
-    info!("Bound IPC Socket @ {:?}", name);
+    info!("Bound IPC Socket @ {:?}", socket_path);
     Ok(listener)
 }
 
+/// The broadcast channels which subscribed IPC clients can receive events from
+#[derive(Clone)]
+pub struct EventSources {
+    pub patches: BroadcastSender<PatchEvent>,
+    pub meters: BroadcastSender<MeterEvent>,
+    pub events: BroadcastSender<DaemonEvent>,
+    pub meter_clients: ClientCounter,
+}
+
 pub async fn spawn_ipc_server(
     listener: LocalSocketListener,
     usb_tx: Messenger,
+    settings: SettingsHandle,
+    sources: EventSources,
     mut shutdown_signal: Stop,
 ) {
-    let socket_path = format!("/tmp/{}.socket", APP_NAME);
+    let socket_path = get_socket_path();
     debug!("Running IPC Server..");
     loop {
         tokio::select! {
             Ok(connection) = listener.accept() => {
                 let socket = Socket::new(connection);
                 let usb_tx = usb_tx.clone();
+                let settings = settings.clone();
+                let sources = sources.clone();
                 tokio::spawn(async move {
-                    handle_connection(socket, usb_tx).await;
+                    handle_connection(socket, usb_tx, settings, sources).await;
                 });
             }
             () = shutdown_signal.recv() => {
@@ -98,9 +75,26 @@ pub async fn spawn_ipc_server(
 async fn handle_connection(
     mut socket: Socket<DaemonRequest, DaemonResponse>,
     usb_tx: Messenger,
+    settings: SettingsHandle,
+    sources: EventSources,
 ) {
     while let Some(msg) = socket.read().await {
         match msg {
+            Ok(DaemonRequest::Subscribe(subscriptions)) => {
+                if socket.send(DaemonResponse::Ok).await.is_err() {
+                    return;
+                }
+                return handle_subscription(socket, subscriptions, usb_tx, sources).await;
+            }
+            // Token management is only permitted here, as access to the socket implies full control
+            Ok(DaemonRequest::Auth(command)) => {
+                let response = handle_auth(command, &settings);
+                let response = response.unwrap_or_else(|e| DaemonResponse::Err(e.into()));
+                if let Err(e) = socket.send(response).await {
+                    warn!("Couldn't reply to {:?}: {}", socket.address(), e);
+                    return;
+                }
+            }
             Ok(msg) => match handle_packet(msg, usb_tx.clone()).await {
                 Ok(response) => {
                     if let Err(e) = socket.send(response).await {
@@ -109,7 +103,7 @@ async fn handle_connection(
                     }
                 }
                 Err(e) => {
-                    if let Err(e) = socket.send(DaemonResponse::Err(e.to_string())).await {
+                    if let Err(e) = socket.send(DaemonResponse::Err(e.into())).await {
                         warn!("Couldn't reply to {:?}: {}", socket.address(), e);
                         return;
                     }
@@ -117,7 +111,8 @@ async fn handle_connection(
             },
             Err(e) => {
                 warn!("Invalid message from {:?}: {}", socket.address(), e);
-                if let Err(e) = socket.send(DaemonResponse::Err(e.to_string())).await {
+                let error = ApiError::invalid(None, e.to_string());
+                if let Err(e) = socket.send(DaemonResponse::Err(error)).await {
                     warn!("Could not reply to {:?}: {}", socket.address(), e);
                     return;
                 }
@@ -126,3 +121,220 @@ async fn handle_connection(
     }
     debug!("Disconnected {:?}", socket.address());
 }
+
+async fn handle_subscription(
+    mut socket: Socket<DaemonRequest, DaemonResponse>,
+    subscriptions: Vec<Subscription>,
+    usb_tx: Messenger,
+    sources: EventSources,
+) {
+    debug!("Subscribing {:?} to {:?}", socket.address(), subscriptions);
+    let wants = |subscription| subscriptions.contains(&subscription);
+
+    let mut patches = PatchFollower::new(sources.patches.subscribe(), usb_tx.clone(), 0);
+    let mut meter_rx = sources.meters.subscribe();
+    let mut event_rx = sources.events.subscribe();
+
+    // Metering is only active while something is listening to it
+    let meters = wants(Subscription::Meters);
+    if meters {
+        meter_client_added(sources.meter_clients.clone(), usb_tx.clone()).await;
+    }
+
+    loop {
+        // If the meter or event receivers lag, the pattern fails and the branch is skipped, we'll
+        // simply pick up from the oldest message still available on the next iteration.
+        let event = select! {
+            msg = socket.read() => {
+                if msg.is_none() {
+                    break;
+                }
+                let message = "Connection is Subscribed, open a new one for requests";
+                let error = ApiError::invalid(None, message);
+                if socket.send(DaemonResponse::Err(error)).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            update = patches.next(), if wants(Subscription::Patches) => {
+                // Patches can't be skipped, so a lagging subscriber is sent the full status
+                match update {
+                    Some(PatchUpdate::Patch(event)) => DaemonEvent::Patch(StatusPatch {
+                        epoch: event.epoch,
+                        revision: event.revision,
+                        patch: event.data,
+                    }),
+                    Some(PatchUpdate::Status(status)) => DaemonEvent::Status(status),
+                    None => break,
+                }
+            }
+            Ok(meter) = meter_rx.recv(), if meters => {
+                DaemonEvent::Meter(meter.id, meter.percent)
+            }
+            Ok(event) = event_rx.recv() => {
+                if !wants(event.subscription()) {
+                    continue;
+                }
+                event
+            }
+        };
+
+        if socket.send(DaemonResponse::Event(event)).await.is_err() {
+            break;
+        }
+    }
+
+    if meters {
+        meter_client_removed(sources.meter_clients, usb_tx).await;
+    }
+    debug!("Subscriber Disconnected {:?}", socket.address());
+}
+
+fn handle_auth(command: AuthCommand, settings: &SettingsHandle) -> Result<DaemonResponse> {
+    match command {
+        AuthCommand::AddToken(name, access) => {
+            let token = settings.add_token(name, access)?;
+            Ok(DaemonResponse::Auth(AuthResponse::Token(token)))
+        }
+        AuthCommand::RemoveToken(name) => {
+            settings.remove_token(name)?;
+            Ok(DaemonResponse::Ok)
+        }
+        AuthCommand::ListTokens => {
+            let tokens = settings.get_tokens();
+            Ok(DaemonResponse::Auth(AuthResponse::Tokens(tokens)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::mock_daemon::mock_daemon;
+    use futures::StreamExt;
+    use interprocess::local_socket::tokio::prelude::LocalSocketStream;
+    use interprocess::local_socket::traits::tokio::Stream as _;
+    use interprocess::local_socket::{GenericFilePath, ToFsName};
+    use json_patch::Patch;
+    use pipeweaver_ipc::clients::ipc::ipc_client::IPCClient;
+    use pipeweaver_ipc::commands::DaemonStatus;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tokio::sync::broadcast;
+    use tokio::time;
+    use ulid::Ulid;
+
+    /// Holds a test's socket and settings, and is removed when the test ends (even on failure)
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("pipeweaver-test-{}", Ulid::new()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_patches() {
+        let dir = TestDir::new();
+        let path = dir.0.join("pipeweaver.socket");
+        let listener = listener_options(&path).unwrap().create_tokio().unwrap();
+
+        let (messenger, _daemon) = mock_daemon(DaemonStatus::default());
+        let settings = SettingsHandle::load(dir.0.join("settings.json"));
+        let sources = EventSources {
+            patches: broadcast::channel(4).0,
+            meters: broadcast::channel(4).0,
+            events: broadcast::channel(4).0,
+            meter_clients: Default::default(),
+        };
+
+        let server_sources = sources.clone();
+        let server = tokio::spawn(async move {
+            let socket = Socket::new(listener.accept().await.unwrap());
+            handle_connection(socket, messenger, settings, server_sources).await;
+        });
+
+        let name = path.clone().to_fs_name::<GenericFilePath>().unwrap();
+        let stream = LocalSocketStream::connect(name).await.unwrap();
+        let client = IPCClient::new(Socket::new(stream));
+        let mut events = client.subscribe(vec![Subscription::Patches]).await.unwrap();
+
+        // The subscription has to outlive the request which created it, so give the server a
+        // chance to notice if the client's side has been shut down
+        time::sleep(Duration::from_millis(100)).await;
+        let patch: Patch = serde_json::from_value(serde_json::json!([
+            { "op": "replace", "path": "/audio/profile/devices", "value": null }
+        ]))
+        .unwrap();
+        let event = PatchEvent { data: patch.clone(), epoch: Ulid::nil(), revision: 1 };
+        sources.patches.send(event).unwrap();
+
+        let event = time::timeout(Duration::from_secs(2), events.next()).await.unwrap();
+        match event.unwrap().unwrap() {
+            DaemonEvent::Patch(received) => {
+                assert_eq!(received.patch, patch);
+                assert_eq!(received.revision, 1);
+            }
+            event => panic!("Unexpected Event: {:?}", event),
+        }
+
+        drop(events);
+        time::timeout(Duration::from_secs(2), server).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn lagging_subscribers_are_resynced() {
+        let dir = TestDir::new();
+        let path = dir.0.join("pipeweaver.socket");
+        let listener = listener_options(&path).unwrap().create_tokio().unwrap();
+
+        let status = DaemonStatus { revision: 5, ..Default::default() };
+        let (messenger, _daemon) = mock_daemon(status);
+        let settings = SettingsHandle::load(dir.0.join("settings.json"));
+        let sources = EventSources {
+            patches: broadcast::channel(4).0,
+            meters: broadcast::channel(4).0,
+            events: broadcast::channel(4).0,
+            meter_clients: Default::default(),
+        };
+
+        let server_sources = sources.clone();
+        tokio::spawn(async move {
+            let socket = Socket::new(listener.accept().await.unwrap());
+            handle_connection(socket, messenger, settings, server_sources).await;
+        });
+
+        let name = path.clone().to_fs_name::<GenericFilePath>().unwrap();
+        let stream = LocalSocketStream::connect(name).await.unwrap();
+        let client = IPCClient::new(Socket::new(stream));
+        let mut events = client.subscribe(vec![Subscription::Patches]).await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+
+        // Nothing else runs between these, so the subscriber falls behind
+        for revision in 1..=6 {
+            let event = PatchEvent { data: Patch(vec![]), epoch: Ulid::nil(), revision };
+            let _ = sources.patches.send(event);
+        }
+
+        let mut next = async || {
+            let event = time::timeout(Duration::from_secs(2), events.next()).await.unwrap();
+            event.unwrap().unwrap()
+        };
+        match next().await {
+            DaemonEvent::Status(status) => assert_eq!(status.revision, 5),
+            event => panic!("Unexpected Event: {:?}", event),
+        }
+        match next().await {
+            DaemonEvent::Patch(patch) => assert_eq!(patch.revision, 6),
+            event => panic!("Unexpected Event: {:?}", event),
+        }
+    }
+}
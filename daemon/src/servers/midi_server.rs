@@ -0,0 +1,519 @@
+use crate::handler::packet::{handle_packet, Messenger};
+use crate::servers::http_server::PatchEvent;
+use crate::stop::Stop;
+use crate::APP_NAME;
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use midir::os::unix::{VirtualInput, VirtualOutput};
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use pipeweaver_ipc::commands::{APICommand, DaemonRequest, DaemonResponse, DaemonStatus};
+use pipeweaver_profile::{MidiAction, MidiMessage, Profile};
+use pipeweaver_shared::MuteState;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender as BroadcastSender;
+use tokio::sync::mpsc;
+use tokio::{select, time};
+
+// The key used for our own virtual ports in the connection maps
+const VIRTUAL_PORT: &str = "__virtual__";
+
+/// The MIDI server exposes a virtual 'PipeWeaver' port (so things can be tested and driven
+/// without hardware), and attaches to any hardware ports listed in the profile. Incoming CC and
+/// Note messages are mapped to API commands, and state changes are sent back out to drive
+/// LEDs and motorised faders.
+pub async fn spawn_midi_server(
+    messenger: Messenger,
+    broadcast_tx: BroadcastSender<PatchEvent>,
+    mut shutdown: Stop,
+) {
+    let (input_tx, mut input_rx) = mpsc::channel(256);
+    let mut handler = MidiHandler::new(messenger, input_tx);
+
+    if let Err(e) = handler.create_virtual_ports() {
+        warn!("[MIDI] Unable to create Virtual Ports: {}", e);
+    }
+
+    let mut broadcast_rx = broadcast_tx.subscribe();
+
+    // Hardware can come and go, so periodically check whether our devices have appeared
+    let mut rescan = time::interval(Duration::from_secs(5));
+
+    info!("[MIDI] Started");
+    loop {
+        select! {
+            Some(bytes) = input_rx.recv() => {
+                if let Err(e) = handler.handle_input(bytes).await {
+                    warn!("[MIDI] Error Handling Message: {}", e);
+                }
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) = broadcast_rx.recv() => {
+                handler.status_changed().await;
+            }
+            _ = rescan.tick() => {
+                handler.rescan().await;
+            }
+            _ = shutdown.recv() => {
+                info!("[MIDI] Stopping");
+                break;
+            }
+        }
+    }
+
+    drop(handler);
+    info!("[MIDI] Stopped");
+}
+
+struct MidiHandler {
+    messenger: Messenger,
+    input_tx: mpsc::Sender<Vec<u8>>,
+
+    inputs: HashMap<String, MidiInputConnection<()>>,
+    outputs: HashMap<String, MidiOutputConnection>,
+
+    // The status as of the last patch, so incoming messages don't need to fetch it
+    status: Option<DaemonStatus>,
+
+    // The last value sent (or received) for a message, so we only send changes
+    feedback: HashMap<MidiMessage, u8>,
+}
+
+impl MidiHandler {
+    fn new(messenger: Messenger, input_tx: mpsc::Sender<Vec<u8>>) -> Self {
+        Self {
+            messenger,
+            input_tx,
+
+            inputs: HashMap::new(),
+            outputs: HashMap::new(),
+
+            status: None,
+            feedback: HashMap::new(),
+        }
+    }
+
+    fn create_virtual_ports(&mut self) -> Result<()> {
+        let input = MidiInput::new(APP_NAME)?;
+        let tx = self.input_tx.clone();
+        let connection = input
+            .create_virtual(
+                APP_NAME,
+                move |_, bytes, _| {
+                    let _ = tx.try_send(bytes.to_vec());
+                },
+                (),
+            )
+            .map_err(|e| anyhow!("{}", e))?;
+        self.inputs.insert(VIRTUAL_PORT.to_string(), connection);
+
+        let output = MidiOutput::new(APP_NAME)?;
+        let connection = output.create_virtual(APP_NAME).map_err(|e| anyhow!("{}", e))?;
+        self.outputs.insert(VIRTUAL_PORT.to_string(), connection);
+
+        debug!("[MIDI] Created Virtual Ports");
+        Ok(())
+    }
+
+    /// Called when the status has been patched, the ports are only re-enumerated if the list
+    /// of devices has changed, otherwise the periodic rescan will pick up new hardware.
+    async fn status_changed(&mut self) {
+        let previous = self.status.take().map(|status| status.audio.profile.midi.devices);
+        let status = match self.get_status().await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("[MIDI] Unable to fetch Status: {}", e);
+                return;
+            }
+        };
+
+        if previous.as_ref() != Some(&status.audio.profile.midi.devices) {
+            if let Err(e) = self.refresh_devices(&status.audio.profile.midi.devices) {
+                warn!("[MIDI] Unable to refresh Devices: {}", e);
+            }
+        }
+        self.send_feedback(&status.audio.profile);
+        self.status = Some(status);
+    }
+
+    async fn rescan(&mut self) {
+        let Some(status) = self.status.take() else {
+            // We've not got a status yet, so fetch one, this will also attach the devices
+            self.status_changed().await;
+            return;
+        };
+
+        if let Err(e) = self.refresh_devices(&status.audio.profile.midi.devices) {
+            warn!("[MIDI] Unable to refresh Devices: {}", e);
+        }
+
+        // Anything newly attached needs to be brought up to date
+        self.send_feedback(&status.audio.profile);
+        self.status = Some(status);
+    }
+
+    fn refresh_devices(&mut self, devices: &[String]) -> Result<()> {
+        let wanted = |name: &str| {
+            // Never attach to ourselves, otherwise feedback will loop straight back in
+            !name.starts_with(APP_NAME) && devices.iter().any(|d| name.contains(d.as_str()))
+        };
+
+        let input = MidiInput::new(APP_NAME)?;
+        let input_ports: Vec<_> = input
+            .ports()
+            .into_iter()
+            .filter_map(|p| input.port_name(&p).ok().map(|name| (name, p)))
+            .collect();
+
+        let output = MidiOutput::new(APP_NAME)?;
+        let output_ports: Vec<_> = output
+            .ports()
+            .into_iter()
+            .filter_map(|p| output.port_name(&p).ok().map(|name| (name, p)))
+            .collect();
+
+        // Drop any connections which are no longer configured, or have disappeared
+        self.inputs.retain(|name, _| {
+            name == VIRTUAL_PORT || (wanted(name) && input_ports.iter().any(|(n, _)| n == name))
+        });
+        self.outputs.retain(|name, _| {
+            name == VIRTUAL_PORT || (wanted(name) && output_ports.iter().any(|(n, _)| n == name))
+        });
+
+        for (name, port) in input_ports {
+            if !wanted(&name) || self.inputs.contains_key(&name) {
+                continue;
+            }
+
+            debug!("[MIDI] Attaching Input: {}", name);
+            let tx = self.input_tx.clone();
+            let input = MidiInput::new(APP_NAME)?;
+            let connection = input.connect(
+                &port,
+                APP_NAME,
+                move |_, bytes, _| {
+                    let _ = tx.try_send(bytes.to_vec());
+                },
+                (),
+            );
+
+            match connection {
+                Ok(connection) => {
+                    self.inputs.insert(name, connection);
+                }
+                Err(e) => warn!("[MIDI] Unable to attach Input {}: {}", name, e),
+            }
+        }
+
+        for (name, port) in output_ports {
+            if !wanted(&name) || self.outputs.contains_key(&name) {
+                continue;
+            }
+
+            debug!("[MIDI] Attaching Output: {}", name);
+            let output = MidiOutput::new(APP_NAME)?;
+            match output.connect(&port, APP_NAME) {
+                Ok(connection) => {
+                    self.outputs.insert(name, connection);
+
+                    // A new surface knows nothing about our state, so send everything again
+                    self.feedback.clear();
+                }
+                Err(e) => warn!("[MIDI] Unable to attach Output {}: {}", name, e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_input(&mut self, bytes: Vec<u8>) -> Result<()> {
+        let Some((message, value)) = parse_message(&bytes) else {
+            return Ok(());
+        };
+
+        if self.status.is_none() {
+            self.status = Some(self.get_status().await?);
+        }
+        let Some(status) = &self.status else {
+            return Ok(());
+        };
+        let profile = &status.audio.profile;
+
+        // If we're in learn mode, the first 'press' or movement binds to the pending action
+        if let Some(action) = status.audio.midi_learn {
+            if value == 0 {
+                return Ok(());
+            }
+
+            // The patch which ends learn mode takes a moment to arrive, so end it here too,
+            // otherwise a fader sweep or a double press would bind several messages
+            if let Some(status) = &mut self.status {
+                status.audio.midi_learn = None;
+            }
+            debug!("[MIDI] Learned {:?} for {:?}", message, action);
+            return self.run_command(APICommand::AddMidiMapping(message, action)).await;
+        }
+
+        let mapping = profile.midi.mappings.iter().find(|m| m.message == message);
+        let Some(mapping) = mapping else {
+            return Ok(());
+        };
+
+        let command = match mapping.action {
+            MidiAction::SourceVolume(id, mix) => {
+                self.feedback.insert(message, value);
+                APICommand::SetSourceVolume(id, mix, to_volume(value))
+            }
+            MidiAction::TargetVolume(id) => {
+                self.feedback.insert(message, value);
+                APICommand::SetTargetVolume(id, to_volume(value))
+            }
+            MidiAction::SourceMute(id, target) => {
+                // Buttons send on press and release (a value of 0), we only toggle on the press
+                // regardless of how hard it was pressed
+                if value == 0 {
+                    return Ok(());
+                }
+                let err = anyhow!("Unknown Source");
+                let (_, mute_states) = profile.get_source_state(id).ok_or(err)?;
+                match mute_states.mute_state.contains(&target) {
+                    true => APICommand::DelSourceMuteTarget(id, target),
+                    false => APICommand::AddSourceMuteTarget(id, target),
+                }
+            }
+            MidiAction::TargetMute(id) => {
+                if value == 0 {
+                    return Ok(());
+                }
+                let err = anyhow!("Unknown Target");
+                let (_, state) = profile.get_target_state(id).ok_or(err)?;
+                match state {
+                    MuteState::Muted => APICommand::SetTargetMuteState(id, MuteState::Unmuted),
+                    MuteState::Unmuted => APICommand::SetTargetMuteState(id, MuteState::Muted),
+                }
+            }
+        };
+        self.run_command(command).await
+    }
+
+    fn send_feedback(&mut self, profile: &Profile) {
+        for mapping in &profile.midi.mappings {
+            let value = match mapping.action {
+                MidiAction::SourceVolume(id, mix) => {
+                    profile.get_source_state(id).map(|(volumes, _)| volumes.volume[mix])
+                }
+                MidiAction::TargetVolume(id) => {
+                    profile.get_target_state(id).map(|(volume, _)| volume)
+                }
+                MidiAction::SourceMute(id, target) => profile
+                    .get_source_state(id)
+                    .map(|(_, mute)| if mute.mute_state.contains(&target) { 127 } else { 0 }),
+                MidiAction::TargetMute(id) => {
+                    profile
+                        .get_target_state(id)
+                        .map(|(_, mute)| if mute == MuteState::Muted { 127 } else { 0 })
+                }
+            };
+            let Some(value) = value else {
+                continue;
+            };
+
+            // Volumes need scaling, and shouldn't be re-sent if the last value already maps to
+            // this volume, otherwise motorised faders will jitter against the user.
+            let value = match mapping.action {
+                MidiAction::SourceVolume(..) | MidiAction::TargetVolume(..) => {
+                    if let Some(last) = self.feedback.get(&mapping.message) {
+                        if to_volume(*last) == value {
+                            continue;
+                        }
+                    }
+                    from_volume(value)
+                }
+                _ => value,
+            };
+
+            if self.feedback.get(&mapping.message) == Some(&value) {
+                continue;
+            }
+            self.feedback.insert(mapping.message, value);
+
+            let bytes = match mapping.message {
+                MidiMessage::ControlChange { channel, control } => [0xB0 | channel, control, value],
+                MidiMessage::Note { channel, note } => [0x90 | channel, note, value],
+            };
+            for (name, output) in &mut self.outputs {
+                if let Err(e) = output.send(&bytes) {
+                    warn!("[MIDI] Unable to send Feedback to {}: {}", name, e);
+                }
+            }
+        }
+    }
+
+    async fn run_command(&self, command: APICommand) -> Result<()> {
+        let request = DaemonRequest::Pipewire(command);
+        handle_packet(request, self.messenger.clone()).await?;
+        Ok(())
+    }
+
+    async fn get_status(&self) -> Result<DaemonStatus> {
+        let result = handle_packet(DaemonRequest::GetStatus, self.messenger.clone()).await?;
+        match result {
+            DaemonResponse::Status(status) => Ok(status),
+            _ => Err(anyhow!("Unexpected Daemon Status Result: {:?}", result)),
+        }
+    }
+}
+
+fn parse_message(bytes: &[u8]) -> Option<(MidiMessage, u8)> {
+    let [status, data, value] = bytes else {
+        return None;
+    };
+
+    let channel = status & 0x0F;
+    match status & 0xF0 {
+        0xB0 => Some((MidiMessage::ControlChange { channel, control: *data }, *value)),
+        0x90 => Some((MidiMessage::Note { channel, note: *data }, *value)),
+
+        // Note Off is treated as a Note On with a velocity of 0
+        0x80 => Some((MidiMessage::Note { channel, note: *data }, 0)),
+        _ => None,
+    }
+}
+
+fn to_volume(value: u8) -> u8 {
+    ((value.min(127) as u32 * 100 + 63) / 127) as u8
+}
+
+fn from_volume(volume: u8) -> u8 {
+    ((volume.min(100) as u32 * 127 + 50) / 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::mock_daemon::{mock_daemon, MockDaemon};
+    use pipeweaver_profile::MidiMapping;
+    use pipeweaver_shared::MuteTarget;
+
+    const MUTE: MidiMessage = MidiMessage::Note { channel: 0, note: 10 };
+    const FADER: MidiMessage = MidiMessage::ControlChange { channel: 1, control: 7 };
+
+    fn midi_handler() -> (MidiHandler, MockDaemon, mpsc::Receiver<Vec<u8>>) {
+        let mut status = DaemonStatus::default();
+        let profile = &mut status.audio.profile;
+        *profile = Profile::base_settings();
+
+        let mic = profile.find_node("Microphone").unwrap();
+        let headphones = profile.find_node("Headphones").unwrap();
+        profile.midi.mappings = vec![
+            MidiMapping { message: MUTE, action: MidiAction::SourceMute(mic, MuteTarget::TargetA) },
+            MidiMapping { message: FADER, action: MidiAction::TargetVolume(headphones) },
+        ];
+
+        let (messenger, daemon) = mock_daemon(status);
+        let (input_tx, input_rx) = mpsc::channel(16);
+        (MidiHandler::new(messenger, input_tx), daemon, input_rx)
+    }
+
+    #[test]
+    fn volumes_survive_scaling() {
+        assert_eq!(to_volume(127), 100);
+        assert_eq!(from_volume(100), 127);
+        for volume in 0..=100 {
+            assert_eq!(to_volume(from_volume(volume)), volume);
+        }
+
+        let message = MidiMessage::Note { channel: 2, note: 60 };
+        assert_eq!(parse_message(&[0x92, 60, 90]), Some((message, 90)));
+        assert_eq!(parse_message(&[0x82, 60, 90]), Some((message, 0)));
+        assert_eq!(parse_message(&[0xF8]), None);
+    }
+
+    #[tokio::test]
+    async fn soft_presses_toggle_mutes() {
+        let (mut handler, daemon, _) = midi_handler();
+
+        // A gentle press on a velocity sensitive pad is still a press
+        handler.handle_input(vec![0x90, 10, 20]).await.unwrap();
+        handler.handle_input(vec![0x80, 10, 0]).await.unwrap();
+
+        let commands = daemon.commands();
+        assert_eq!(commands.len(), 1);
+        assert!(matches!(commands[0], APICommand::AddSourceMuteTarget(_, MuteTarget::TargetA)));
+    }
+
+    #[tokio::test]
+    async fn status_is_only_fetched_on_change() {
+        let (mut handler, daemon, _) = midi_handler();
+        handler.status_changed().await;
+
+        for value in [10, 20, 30] {
+            handler.handle_input(vec![0xB1, 7, value]).await.unwrap();
+        }
+        assert_eq!(daemon.status_requests(), 1);
+        assert!(matches!(daemon.commands()[2], APICommand::SetTargetVolume(_, 24)));
+
+        handler.status_changed().await;
+        assert_eq!(daemon.status_requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn learning_binds_only_the_first_message() {
+        let (mut handler, daemon, _) = midi_handler();
+        let headphones = Profile::base_settings().find_node("Headphones").unwrap();
+        daemon.status().audio.midi_learn = Some(MidiAction::TargetMute(headphones));
+
+        // A fader sweep sends several messages before learn mode's end is patched back
+        handler.handle_input(vec![0xB2, 1, 40]).await.unwrap();
+        handler.handle_input(vec![0xB2, 1, 41]).await.unwrap();
+
+        let commands = daemon.commands();
+        assert_eq!(commands.len(), 1);
+        assert!(matches!(commands[0], APICommand::AddMidiMapping(_, MidiAction::TargetMute(_))));
+    }
+
+    // This needs a MIDI sequencer (ALSA on Linux), which CI machines often don't have, run it
+    // with `cargo test -p pipeweaver-daemon -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a MIDI sequencer"]
+    async fn virtual_ports_receive_and_send() {
+        let (mut handler, _daemon, mut input_rx) = midi_handler();
+        handler.create_virtual_ports().unwrap();
+
+        let output = MidiOutput::new("PipeWeaver Test").unwrap();
+        let port = output
+            .ports()
+            .into_iter()
+            .find(|port| output.port_name(port).is_ok_and(|name| name.contains(APP_NAME)));
+        let port = port.expect("The virtual port should be visible");
+
+        let (feedback_tx, mut feedback_rx) = mpsc::channel(16);
+        let input = MidiInput::new("PipeWeaver Test").unwrap();
+        let input_port = input
+            .ports()
+            .into_iter()
+            .find(|port| input.port_name(port).is_ok_and(|name| name.contains(APP_NAME)));
+        let _feedback = input_port.map(|port| {
+            input
+                .connect(
+                    &port,
+                    "feedback",
+                    move |_, bytes, _| {
+                        let _ = feedback_tx.try_send(bytes.to_vec());
+                    },
+                    (),
+                )
+                .unwrap()
+        });
+
+        // Anything sent to the virtual input should arrive at the handler
+        let mut connection = output.connect(&port, "test").unwrap();
+        connection.send(&[0xB1, 7, 64]).unwrap();
+        let received = time::timeout(Duration::from_secs(2), input_rx.recv()).await;
+        assert_eq!(received.unwrap(), Some(vec![0xB1, 7, 64]));
+
+        // While the current state is sent out of the virtual output
+        handler.status_changed().await;
+        let sent = time::timeout(Duration::from_secs(2), feedback_rx.recv()).await;
+        assert!(sent.unwrap().is_some());
+    }
+}
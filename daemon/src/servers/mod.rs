@@ -1,2 +1,7 @@
+pub(crate) mod dbus_server;
+pub(crate) mod event_stream;
 pub(crate) mod http_server;
-pub(crate) mod ipc_server;
\ No newline at end of file
+pub(crate) mod ipc_server;
+pub(crate) mod midi_server;
+pub(crate) mod osc_server;
+pub(crate) mod rest_api;
@@ -0,0 +1,513 @@
+use crate::handler::packet::{handle_packet, Messenger};
+use crate::servers::http_server::PatchEvent;
+use crate::settings::SettingsHandle;
+use crate::stop::Stop;
+use anyhow::{anyhow, bail, Result};
+use log::{debug, info, warn};
+use pipeweaver_ipc::commands::{
+    APICommand, APICommandResponse, DaemonRequest, DaemonResponse, DaemonStatus,
+};
+use pipeweaver_profile::{DeviceDescription, Profile};
+use pipeweaver_shared::{Mix, MuteState, MuteTarget};
+use rosc::{OscMessage, OscPacket, OscType};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use strum::IntoEnumIterator;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{Receiver as BroadcastReceiver, Sender as BroadcastSender};
+use tokio::time::Instant;
+use tokio::{select, time};
+use ulid::Ulid;
+
+const PREFIX: &str = "pipeweaver";
+
+/// How long a subscription lasts without being renewed
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A simple OSC listener, the following addresses are supported (nodes can be referenced either
+/// by their ID or their name, with spaces replaced by underscores, if two nodes end up with the
+/// same address name, only their ID can be used):
+///
+/// /pipeweaver/source/<node>/volume/<A|B>          - Float (0.0 - 1.0) or Int (0 - 100)
+/// /pipeweaver/source/<node>/mute/<TargetA|TargetB> - Bool / Int / Float, or no args to toggle
+/// /pipeweaver/target/<node>/volume                 - Float (0.0 - 1.0) or Int (0 - 100)
+/// /pipeweaver/target/<node>/mute                   - Bool / Int / Float, or no args to toggle
+///
+/// /pipeweaver/subscribe and /pipeweaver/unsubscribe register the sender to receive the above
+/// addresses (as floats) whenever their values change. Subscriptions expire after a minute, so
+/// clients should send /pipeweaver/subscribe periodically to keep receiving updates.
+///
+/// OSC has no way to authenticate, so when API tokens are configured only local clients are
+/// accepted.
+pub async fn spawn_osc_server(
+    messenger: Messenger,
+    broadcast_tx: BroadcastSender<PatchEvent>,
+    settings: SettingsHandle,
+    shutdown: Stop,
+) {
+    let osc_settings = settings.get_osc_settings();
+    if !osc_settings.enabled {
+        return;
+    }
+
+    let mut bind_address = osc_settings.bind_address.as_str();
+    if settings.auth_enabled() && !is_loopback(bind_address) {
+        warn!("[OSC] Authentication is enabled, only accepting connections from localhost");
+        bind_address = "127.0.0.1";
+    }
+
+    let address = (bind_address, osc_settings.port);
+    let socket = match UdpSocket::bind(address).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("Error Running OSC Server: {}", e);
+            return;
+        }
+    };
+    info!("Started OSC Server at udp://{}:{}/", bind_address, osc_settings.port);
+
+    let handler = OscHandler::new(messenger, settings);
+    run_osc_server(socket, handler, broadcast_tx.subscribe(), shutdown).await;
+}
+
+async fn run_osc_server(
+    socket: UdpSocket,
+    mut handler: OscHandler,
+    mut broadcast_rx: BroadcastReceiver<PatchEvent>,
+    mut shutdown: Stop,
+) {
+    let mut buffer = [0; rosc::decoder::MTU];
+    let mut expire = time::interval(Duration::from_secs(10));
+
+    loop {
+        select! {
+            Ok((size, address)) = socket.recv_from(&mut buffer) => {
+                if !handler.is_permitted(address) {
+                    debug!("[OSC] Ignoring Packet from {}, Authentication is enabled", address);
+                    continue;
+                }
+                match rosc::decoder::decode_udp(&buffer[..size]) {
+                    Ok((_, packet)) => {
+                        if let Err(e) = handler.handle_packet(&socket, packet, address).await {
+                            warn!("[OSC] Error Handling Message from {}: {}", address, e);
+                        }
+                    }
+                    Err(e) => debug!("[OSC] Invalid Packet from {}: {:?}", address, e),
+                }
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) = broadcast_rx.recv() => {
+                handler.status_changed(&socket).await;
+            }
+            _ = expire.tick() => {
+                handler.expire_subscribers(Instant::now());
+            }
+            _ = shutdown.recv() => {
+                info!("[OSC] Stopped");
+                break;
+            }
+        }
+    }
+}
+
+fn is_loopback(address: &str) -> bool {
+    address == "localhost" || address.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+struct OscHandler {
+    messenger: Messenger,
+    settings: SettingsHandle,
+
+    // Subscribers, and when their subscription expires
+    subscribers: HashMap<SocketAddr, Instant>,
+
+    // The profile as of the last patch, so incoming messages don't need to fetch it
+    profile: Option<Profile>,
+
+    // The last values sent to subscribers, keyed by address
+    state: HashMap<String, f32>,
+}
+
+impl OscHandler {
+    fn new(messenger: Messenger, settings: SettingsHandle) -> Self {
+        Self {
+            messenger,
+            settings,
+            subscribers: HashMap::new(),
+            profile: None,
+            state: HashMap::new(),
+        }
+    }
+
+    fn is_permitted(&self, from: SocketAddr) -> bool {
+        from.ip().is_loopback() || !self.settings.auth_enabled()
+    }
+
+    fn expire_subscribers(&mut self, now: Instant) {
+        self.subscribers.retain(|address, expires| {
+            if *expires <= now {
+                debug!("[OSC] Subscription from {} Expired", address);
+            }
+            *expires > now
+        });
+    }
+
+    async fn handle_packet(
+        &mut self,
+        socket: &UdpSocket,
+        packet: OscPacket,
+        from: SocketAddr,
+    ) -> Result<()> {
+        match packet {
+            OscPacket::Message(message) => self.handle_message(socket, message, from).await,
+            OscPacket::Bundle(bundle) => {
+                for packet in bundle.content {
+                    Box::pin(self.handle_packet(socket, packet, from)).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn handle_message(
+        &mut self,
+        socket: &UdpSocket,
+        message: OscMessage,
+        from: SocketAddr,
+    ) -> Result<()> {
+        let parts: Vec<&str> = message.addr.trim_start_matches('/').split('/').collect();
+        if self.profile.is_none() {
+            self.profile = Some(self.get_status().await?.audio.profile);
+        }
+        let profile = self.profile.as_ref().ok_or(anyhow!("Profile not Available"))?;
+
+        match parts.as_slice() {
+            [PREFIX, "subscribe"] => {
+                // A renewal doesn't need sending everything again
+                let expires = Instant::now() + SUBSCRIPTION_TIMEOUT;
+                if self.subscribers.insert(from, expires).is_some() {
+                    return Ok(());
+                }
+
+                // Send the new subscriber everything we know
+                debug!("[OSC] Subscribing {}", from);
+                for (address, value) in get_state(profile) {
+                    self.state.insert(address.clone(), value);
+                    send_value(socket, from, address, value).await;
+                }
+                Ok(())
+            }
+            [PREFIX, "unsubscribe"] => {
+                debug!("[OSC] Unsubscribing {}", from);
+                self.subscribers.remove(&from);
+                Ok(())
+            }
+            _ => {
+                let command = parse_command(profile, &parts, &message.args)?;
+                self.run_command(command).await
+            }
+        }
+    }
+
+    async fn status_changed(&mut self, socket: &UdpSocket) {
+        let profile = match self.get_status().await {
+            Ok(status) => status.audio.profile,
+            Err(e) => {
+                warn!("[OSC] Unable to fetch Status: {}", e);
+                return;
+            }
+        };
+
+        if !self.subscribers.is_empty() {
+            for (address, value) in get_state(&profile) {
+                if self.state.get(&address) == Some(&value) {
+                    continue;
+                }
+                self.state.insert(address.clone(), value);
+                for subscriber in self.subscribers.keys() {
+                    send_value(socket, *subscriber, address.clone(), value).await;
+                }
+            }
+        }
+        self.profile = Some(profile);
+    }
+
+    async fn run_command(&self, command: APICommand) -> Result<()> {
+        let request = DaemonRequest::Pipewire(command);
+        match handle_packet(request, self.messenger.clone()).await? {
+            DaemonResponse::Pipewire(APICommandResponse::Err(e)) => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_status(&self) -> Result<DaemonStatus> {
+        let result = handle_packet(DaemonRequest::GetStatus, self.messenger.clone()).await?;
+        match result {
+            DaemonResponse::Status(status) => Ok(status),
+            _ => Err(anyhow!("Unexpected Daemon Status Result: {:?}", result)),
+        }
+    }
+}
+
+fn parse_command(profile: &Profile, parts: &[&str], args: &[OscType]) -> Result<APICommand> {
+    match parts {
+        [PREFIX, "source", node, "volume", mix] => {
+            let id = find_node(&sources(profile), node)?;
+            let mix = mix.parse::<Mix>().map_err(|_| anyhow!("Unknown Mix: {}", mix))?;
+            Ok(APICommand::SetSourceVolume(id, mix, get_volume(args)?))
+        }
+        [PREFIX, "source", node, "mute", target] => {
+            let id = find_node(&sources(profile), node)?;
+            let err = |_| anyhow!("Unknown Mute Target: {}", target);
+            let target = target.parse::<MuteTarget>().map_err(err)?;
+
+            let (_, mute_states) = profile.get_source_state(id).ok_or(anyhow!("Not a Source"))?;
+            let muted = mute_states.mute_state.contains(&target);
+            match get_bool(args)?.unwrap_or(!muted) {
+                true => Ok(APICommand::AddSourceMuteTarget(id, target)),
+                false => Ok(APICommand::DelSourceMuteTarget(id, target)),
+            }
+        }
+        [PREFIX, "target", node, "volume"] => {
+            let id = find_node(&targets(profile), node)?;
+            Ok(APICommand::SetTargetVolume(id, get_volume(args)?))
+        }
+        [PREFIX, "target", node, "mute"] => {
+            let id = find_node(&targets(profile), node)?;
+
+            let (_, state) = profile.get_target_state(id).ok_or(anyhow!("Not a Target"))?;
+            let muted = state == MuteState::Muted;
+            match get_bool(args)?.unwrap_or(!muted) {
+                true => Ok(APICommand::SetTargetMuteState(id, MuteState::Muted)),
+                false => Ok(APICommand::SetTargetMuteState(id, MuteState::Unmuted)),
+            }
+        }
+        _ => bail!("Unknown Address: /{}", parts.join("/")),
+    }
+}
+
+fn sources(profile: &Profile) -> Vec<&DeviceDescription> {
+    let sources = &profile.devices.sources;
+    sources
+        .physical_devices
+        .iter()
+        .map(|d| &d.description)
+        .chain(sources.virtual_devices.iter().map(|d| &d.description))
+        .collect()
+}
+
+fn targets(profile: &Profile) -> Vec<&DeviceDescription> {
+    let targets = &profile.devices.targets;
+    targets
+        .physical_devices
+        .iter()
+        .map(|d| &d.description)
+        .chain(targets.virtual_devices.iter().map(|d| &d.description))
+        .collect()
+}
+
+/// Spaces can't be used in an OSC address, so they're swapped for underscores
+fn address_name(name: &str) -> String {
+    name.replace(' ', "_").to_lowercase()
+}
+
+fn find_node(nodes: &[&DeviceDescription], name: &str) -> Result<Ulid> {
+    let id = name.parse::<Ulid>().ok();
+    if let Some(node) = nodes.iter().find(|node| Some(node.id) == id) {
+        return Ok(node.id);
+    }
+
+    let address = address_name(name);
+    let mut found = nodes.iter().filter(|node| address_name(&node.name) == address);
+    match (found.next(), found.next()) {
+        (Some(node), None) => Ok(node.id),
+        (Some(_), Some(_)) => bail!("More than one Node is called {}, use its ID", name),
+        _ => bail!("Unknown Node: {}", name),
+    }
+}
+
+/// The name used for a node in outgoing addresses, nodes which share an address name with
+/// another node use their ID instead, so the values sent can't be confused.
+fn node_address(nodes: &[&DeviceDescription], node: &DeviceDescription) -> String {
+    let name = address_name(&node.name);
+    if nodes.iter().filter(|other| address_name(&other.name) == name).count() > 1 {
+        return node.id.to_string();
+    }
+    node.name.replace(' ', "_")
+}
+
+fn get_volume(args: &[OscType]) -> Result<u8> {
+    match args.first() {
+        Some(OscType::Float(value)) => Ok((value.clamp(0., 1.) * 100.).round() as u8),
+        Some(OscType::Double(value)) => Ok((value.clamp(0., 1.) * 100.).round() as u8),
+        Some(OscType::Int(value)) => Ok((*value).clamp(0, 100) as u8),
+        _ => bail!("Expected a Float or Int volume"),
+    }
+}
+
+fn get_bool(args: &[OscType]) -> Result<Option<bool>> {
+    match args.first() {
+        None => Ok(None),
+        Some(OscType::Bool(value)) => Ok(Some(*value)),
+        Some(OscType::Int(value)) => Ok(Some(*value != 0)),
+        Some(OscType::Float(value)) => Ok(Some(*value >= 0.5)),
+        _ => bail!("Expected a Bool, Int or Float"),
+    }
+}
+
+/// Builds the full list of addresses and values representing the current profile
+fn get_state(profile: &Profile) -> Vec<(String, f32)> {
+    let mut state = vec![];
+    let bool_value = |value: bool| if value { 1. } else { 0. };
+
+    let sources = sources(profile);
+    for description in &sources {
+        let Some((volumes, mute_states)) = profile.get_source_state(description.id) else {
+            continue;
+        };
+
+        let base = format!("/{}/source/{}", PREFIX, node_address(&sources, description));
+        for mix in Mix::iter() {
+            let volume = volumes.volume[mix] as f32 / 100.;
+            state.push((format!("{}/volume/{}", base, mix), volume));
+        }
+        for target in MuteTarget::iter() {
+            let muted = bool_value(mute_states.mute_state.contains(&target));
+            state.push((format!("{}/mute/{}", base, target), muted));
+        }
+    }
+
+    let targets = targets(profile);
+    for description in &targets {
+        let Some((volume, mute_state)) = profile.get_target_state(description.id) else {
+            continue;
+        };
+
+        let base = format!("/{}/target/{}", PREFIX, node_address(&targets, description));
+        state.push((format!("{}/volume", base), volume as f32 / 100.));
+        state.push((format!("{}/mute", base), bool_value(mute_state == MuteState::Muted)));
+    }
+    state
+}
+
+async fn send_value(socket: &UdpSocket, target: SocketAddr, addr: String, value: f32) {
+    let packet = OscPacket::Message(OscMessage { addr, args: vec![OscType::Float(value)] });
+
+    match rosc::encoder::encode(&packet) {
+        Ok(bytes) => {
+            if let Err(e) = socket.send_to(&bytes, target).await {
+                warn!("[OSC] Unable to send to {}: {}", target, e);
+            }
+        }
+        Err(e) => warn!("[OSC] Unable to encode packet: {:?}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::mock_daemon::mock_daemon;
+    use json_patch::Patch;
+    use tokio::sync::broadcast;
+
+    fn settings() -> SettingsHandle {
+        SettingsHandle::load(std::env::temp_dir().join(format!("osc-{}.json", Ulid::new())))
+    }
+
+    async fn receive(socket: &UdpSocket, addr: &str) -> OscMessage {
+        let mut buffer = [0; rosc::decoder::MTU];
+        loop {
+            let received = time::timeout(Duration::from_secs(2), socket.recv(&mut buffer));
+            let size = received.await.expect("Timed out waiting for OSC").unwrap();
+            let packet = rosc::decoder::decode_udp(&buffer[..size]);
+            if let Ok((_, OscPacket::Message(message))) = packet {
+                if message.addr == addr {
+                    return message;
+                }
+            }
+        }
+    }
+
+    async fn send(socket: &UdpSocket, addr: &str, args: Vec<OscType>) {
+        let packet = OscPacket::Message(OscMessage { addr: addr.to_string(), args });
+        socket.send(&rosc::encoder::encode(&packet).unwrap()).await.unwrap();
+    }
+
+    #[test]
+    fn shared_names_need_an_id() {
+        let mut profile = Profile::base_settings();
+        let chat_mic = profile.find_node("Chat Mic").unwrap();
+        let stream = profile.find_node("Stream Mix").unwrap();
+        assert_eq!(find_node(&targets(&profile), "chat_mic").unwrap(), chat_mic);
+        assert!(find_node(&sources(&profile), "Chat_Mic").is_err());
+
+        profile.devices.targets.virtual_devices[0].description.name = "Chat_Mic".into();
+        assert!(find_node(&targets(&profile), "Chat_Mic").is_err());
+        assert_eq!(find_node(&targets(&profile), &stream.to_string()).unwrap(), stream);
+
+        let state = get_state(&profile);
+        let address = format!("/pipeweaver/target/{}/volume", stream);
+        assert!(state.iter().any(|(addr, _)| *addr == address));
+        assert!(!state.iter().any(|(addr, _)| addr.contains("Chat_Mic")));
+    }
+
+    #[tokio::test]
+    async fn subscriptions_expire() {
+        let (messenger, _daemon) = mock_daemon(Default::default());
+        let mut handler = OscHandler::new(messenger, settings());
+        let address = "127.0.0.1:9000".parse().unwrap();
+        handler.subscribers.insert(address, Instant::now() + SUBSCRIPTION_TIMEOUT);
+
+        handler.expire_subscribers(Instant::now());
+        assert!(handler.subscribers.contains_key(&address));
+        handler.expire_subscribers(Instant::now() + SUBSCRIPTION_TIMEOUT);
+        assert!(handler.subscribers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn udp_round_trip() {
+        let mut status = DaemonStatus::default();
+        status.audio.profile = Profile::base_settings();
+        let headphones = status.audio.profile.find_node("Headphones").unwrap();
+        let (messenger, daemon) = mock_daemon(status);
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+
+        let (broadcast_tx, broadcast_rx) = broadcast::channel(4);
+        let shutdown = Stop::new();
+        let handler = OscHandler::new(messenger, settings());
+        let task = tokio::spawn(run_osc_server(server, handler, broadcast_rx, shutdown.clone()));
+
+        // Subscribing sends the current state
+        send(&client, "/pipeweaver/subscribe", vec![]).await;
+        let volume = receive(&client, "/pipeweaver/target/Headphones/volume").await;
+        assert_eq!(volume.args, vec![OscType::Float(0.99)]);
+
+        // Commands go to the daemon, without fetching the status each time
+        send(&client, "/pipeweaver/target/headphones/volume", vec![OscType::Int(50)]).await;
+        send(&client, "/pipeweaver/target/Headphones/volume", vec![OscType::Float(0.4)]).await;
+        time::timeout(Duration::from_secs(2), async {
+            while daemon.commands().len() < 2 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let command = daemon.commands()[0].clone();
+        assert!(matches!(command, APICommand::SetTargetVolume(id, 50) if id == headphones));
+        assert_eq!(daemon.status_requests(), 1);
+
+        // Once the change has been applied, subscribers are told about it
+        daemon.status().audio.profile.devices.targets.physical_devices[0].volume = 40;
+        let event = PatchEvent { data: Patch(vec![]), epoch: Ulid::nil(), revision: 1 };
+        let _ = broadcast_tx.send(event);
+        let volume = receive(&client, "/pipeweaver/target/Headphones/volume").await;
+        assert_eq!(volume.args, vec![OscType::Float(0.4)]);
+
+        shutdown.trigger();
+        task.await.unwrap();
+    }
+}
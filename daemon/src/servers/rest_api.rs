@@ -0,0 +1,470 @@
+use crate::handler::packet::{handle_packet, Messenger};
+use crate::servers::http_server::{forbidden, get_access, get_status_code, unauthorized, AppData};
+use crate::settings::SettingsHandle;
+use actix_web::web::Data;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use pipeweaver_ipc::commands::{
+    APICommand, APICommandResponse, AccessLevel, ApiError, BatchRequest, DaemonRequest,
+    DaemonResponse, DaemonStatus, ErrorCode,
+};
+use pipeweaver_profile::{NodeMetadata, NodePolicy, Profile};
+use pipeweaver_shared::{Colour, Mix, MuteState, MuteTarget, NodeType};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use ulid::Ulid;
+
+/// Resource oriented routes, these are thin wrappers which translate into APICommands, so
+/// behave identically to sending the equivalent command to /api/command.
+///
+/// GET    /api/nodes                      - All nodes
+/// GET    /api/nodes/{node}               - A single node, by ID or name
+/// PATCH  /api/nodes/{node}               - Update a node, see NodeUpdate
+/// POST   /api/nodes/{node}/mute          - Mute a node (?target=TargetA|TargetB for sources)
+/// DELETE /api/nodes/{node}/mute          - Unmute a node
+/// PUT    /api/routes/{source}/{target}   - Route a source to a target
+/// DELETE /api/routes/{source}/{target}   - Remove a route
+/// GET    /api/devices/physical           - Physical pipewire devices available for attaching
+pub(crate) fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(get_nodes)
+        .service(get_node)
+        .service(update_node)
+        .service(mute_node)
+        .service(unmute_node)
+        .service(add_route)
+        .service(remove_route)
+        .service(get_physical_devices);
+}
+
+/// Partial update of a node, only the provided fields will be changed
+#[derive(Deserialize)]
+struct NodeUpdate {
+    name: Option<String>,
+    colour: Option<Colour>,
+
+    /// Target Volume and Mix
+    volume: Option<u8>,
+    mix: Option<Mix>,
+
+    /// Source Volumes, and whether they're linked
+    volumes: Option<MixVolumes>,
+    linked: Option<bool>,
+
+    /// Session Manager Policy and Metadata for Virtual Nodes
+    policy: Option<NodePolicy>,
+    metadata: Option<NodeMetadata>,
+}
+
+#[derive(Deserialize)]
+struct MixVolumes {
+    #[serde(rename = "A")]
+    a: Option<u8>,
+
+    #[serde(rename = "B")]
+    b: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct MuteQuery {
+    #[serde(default)]
+    target: MuteTarget,
+}
+
+#[derive(Serialize)]
+struct Node {
+    #[serde(skip)]
+    id: Ulid,
+    node_type: NodeType,
+
+    #[serde(flatten)]
+    device: Value,
+
+    /// For sources, the targets this node is routed to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    routes: Option<Vec<Ulid>>,
+}
+
+#[get("/api/nodes")]
+async fn get_nodes(app_data: Data<Mutex<AppData>>, req: HttpRequest) -> HttpResponse {
+    match get_status(&app_data, &req).await {
+        Ok(status) => HttpResponse::Ok().json(get_node_list(&status.audio.profile)),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/api/nodes/{node}")]
+async fn get_node(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let status = match get_status(&app_data, &req).await {
+        Ok(status) => status,
+        Err(error) => return error_response(error),
+    };
+
+    let profile = &status.audio.profile;
+    match find_node(profile, &path).and_then(|id| get_node_by_id(profile, id)) {
+        Ok(node) => HttpResponse::Ok().json(node),
+        Err(error) => error_response(error),
+    }
+}
+
+#[patch("/api/nodes/{node}")]
+async fn update_node(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    update: web::Json<NodeUpdate>,
+) -> HttpResponse {
+    let status = match get_status(&app_data, &req).await {
+        Ok(status) => status,
+        Err(error) => return error_response(error),
+    };
+    let id = match find_node(&status.audio.profile, &path) {
+        Ok(id) => id,
+        Err(error) => return error_response(error),
+    };
+
+    let node = match get_node_by_id(&status.audio.profile, id) {
+        Ok(node) => node,
+        Err(error) => return error_response(error),
+    };
+    let profile = &status.audio.profile;
+    let commands = match update_commands(profile, node.node_type, id, update.into_inner()) {
+        Ok(commands) => commands,
+        Err(error) => return error_response(error),
+    };
+
+    // Nothing to change, so the node we already have is current
+    if commands.is_empty() {
+        return HttpResponse::Ok().json(node);
+    }
+    if let Err(error) = execute(&app_data, &req, commands).await {
+        return error_response(error);
+    }
+
+    // Send back the node as it is now
+    let result = get_status(&app_data, &req).await;
+    match result.and_then(|status| get_node_by_id(&status.audio.profile, id)) {
+        Ok(node) => HttpResponse::Ok().json(node),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Translates an update into commands, rejecting fields which don't apply to this type of node
+/// up front, rather than letting the batch fail part way through and be rolled back.
+fn update_commands(
+    profile: &Profile,
+    node_type: NodeType,
+    id: Ulid,
+    update: NodeUpdate,
+) -> Result<Vec<APICommand>, ApiError> {
+    let source = matches!(node_type, NodeType::PhysicalSource | NodeType::VirtualSource);
+    let virtual_node = matches!(node_type, NodeType::VirtualSource | NodeType::VirtualTarget);
+
+    let invalid =
+        |field, kind| ApiError::invalid(id, format!("'{}' only applies to {}", field, kind));
+    if source && update.volume.is_some() {
+        return Err(invalid("volume", "Targets"));
+    }
+    if source && update.mix.is_some() {
+        return Err(invalid("mix", "Targets"));
+    }
+    if !source && update.volumes.is_some() {
+        return Err(invalid("volumes", "Sources"));
+    }
+    if !source && update.linked.is_some() {
+        return Err(invalid("linked", "Sources"));
+    }
+    if !virtual_node && update.policy.is_some() {
+        return Err(invalid("policy", "Virtual Nodes"));
+    }
+    if !virtual_node && update.metadata.is_some() {
+        return Err(invalid("metadata", "Virtual Nodes"));
+    }
+
+    let mut commands = vec![];
+    if let Some(name) = update.name {
+        commands.push(APICommand::RenameNode(id, name));
+    }
+    if let Some(colour) = update.colour {
+        commands.push(APICommand::SetNodeColour(id, colour));
+    }
+    if let Some(volume) = update.volume {
+        commands.push(APICommand::SetTargetVolume(id, volume));
+    }
+    if let Some(mix) = update.mix {
+        commands.push(APICommand::SetTargetMix(id, mix));
+    }
+    if let Some(policy) = update.policy {
+        commands.push(APICommand::SetNodePolicy(id, policy));
+    }
+    if let Some(metadata) = update.metadata {
+        commands.push(APICommand::SetNodeMetadata(id, metadata));
+    }
+
+    // Only change the link if it's not already in the requested state
+    let state = profile.get_source_state(id);
+    let linked_now = state.map(|(volumes, _)| volumes.volumes_linked.is_some());
+    let linked = update.linked.filter(|&linked| Some(linked) != linked_now);
+
+    // Unlink first, so the volumes can be set independently
+    if linked == Some(false) {
+        commands.push(APICommand::SetSourceVolumeLinked(id, false));
+    }
+    if let Some(volumes) = update.volumes {
+        if let Some(volume) = volumes.a {
+            commands.push(APICommand::SetSourceVolume(id, Mix::A, volume));
+        }
+        if let Some(volume) = volumes.b {
+            commands.push(APICommand::SetSourceVolume(id, Mix::B, volume));
+        }
+    }
+    if linked == Some(true) {
+        commands.push(APICommand::SetSourceVolumeLinked(id, true));
+    }
+    Ok(commands)
+}
+
+#[post("/api/nodes/{node}/mute")]
+async fn mute_node(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<MuteQuery>,
+) -> HttpResponse {
+    set_mute(app_data, req, &path, query.target, true).await
+}
+
+#[delete("/api/nodes/{node}/mute")]
+async fn unmute_node(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<MuteQuery>,
+) -> HttpResponse {
+    set_mute(app_data, req, &path, query.target, false).await
+}
+
+async fn set_mute(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    node: &str,
+    target: MuteTarget,
+    muted: bool,
+) -> HttpResponse {
+    let status = match get_status(&app_data, &req).await {
+        Ok(status) => status,
+        Err(error) => return error_response(error),
+    };
+    let profile = &status.audio.profile;
+    let id = match find_node(profile, node) {
+        Ok(id) => id,
+        Err(error) => return error_response(error),
+    };
+
+    // The mute commands differ for sources and targets, so work out which this is
+    let command = if profile.get_source_state(id).is_some() {
+        match muted {
+            true => APICommand::AddSourceMuteTarget(id, target),
+            false => APICommand::DelSourceMuteTarget(id, target),
+        }
+    } else {
+        match muted {
+            true => APICommand::SetTargetMuteState(id, MuteState::Muted),
+            false => APICommand::SetTargetMuteState(id, MuteState::Unmuted),
+        }
+    };
+
+    // Like routes, muting something which is already muted isn't an error
+    match execute(&app_data, &req, vec![command]).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(error) if error.code == ErrorCode::NoChange => HttpResponse::NoContent().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[put("/api/routes/{source}/{target}")]
+async fn add_route(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    set_route(app_data, req, path.into_inner(), true).await
+}
+
+#[delete("/api/routes/{source}/{target}")]
+async fn remove_route(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> HttpResponse {
+    set_route(app_data, req, path.into_inner(), false).await
+}
+
+async fn set_route(
+    app_data: Data<Mutex<AppData>>,
+    req: HttpRequest,
+    (source, target): (String, String),
+    enabled: bool,
+) -> HttpResponse {
+    let status = match get_status(&app_data, &req).await {
+        Ok(status) => status,
+        Err(error) => return error_response(error),
+    };
+    let profile = &status.audio.profile;
+    let ids = find_node(profile, &source).and_then(|s| Ok((s, find_node(profile, &target)?)));
+    let (source, target) = match ids {
+        Ok(ids) => ids,
+        Err(error) => return error_response(error),
+    };
+
+    // PUT and DELETE are idempotent, so an already existing (or missing) route isn't an error
+    let command = APICommand::SetRoute(source, target, enabled);
+    match execute(&app_data, &req, vec![command]).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(error) if error.code == ErrorCode::NoChange => HttpResponse::NoContent().finish(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[get("/api/devices/physical")]
+async fn get_physical_devices(app_data: Data<Mutex<AppData>>, req: HttpRequest) -> HttpResponse {
+    match get_status(&app_data, &req).await {
+        Ok(status) => HttpResponse::Ok().json(status.audio.devices),
+        Err(error) => error_response(error),
+    }
+}
+
+fn error_response(error: ApiError) -> HttpResponse {
+    HttpResponse::build(get_status_code(error.code)).json(error)
+}
+
+async fn get_handles(app_data: &Data<Mutex<AppData>>) -> (Messenger, SettingsHandle) {
+    let data = app_data.lock().await;
+    (data.messenger.clone(), data.settings.clone())
+}
+
+/// Fetches the current status, any valid token is allowed to do this
+async fn get_status(
+    app_data: &Data<Mutex<AppData>>,
+    req: &HttpRequest,
+) -> Result<DaemonStatus, ApiError> {
+    let (messenger, settings) = get_handles(app_data).await;
+    if get_access(&settings, req).0.is_none() {
+        return Err(unauthorized());
+    }
+
+    match handle_packet(DaemonRequest::GetStatus, messenger).await? {
+        DaemonResponse::Status(status) => Ok(status),
+        DaemonResponse::Err(error) => Err(error),
+        _ => Err(ApiError::internal("Unexpected Daemon Status Result")),
+    }
+}
+
+/// Runs commands as a single atomic batch, so a partially applied update gets rolled back
+async fn execute(
+    app_data: &Data<Mutex<AppData>>,
+    req: &HttpRequest,
+    commands: Vec<APICommand>,
+) -> Result<(), ApiError> {
+    let (messenger, settings) = get_handles(app_data).await;
+    match get_access(&settings, req).0 {
+        Some(AccessLevel::Full) => {}
+        Some(AccessLevel::ReadOnly) => return Err(forbidden()),
+        None => return Err(unauthorized()),
+    }
+
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    let request = DaemonRequest::Batch(BatchRequest { commands, atomic: true });
+    match handle_packet(request, messenger).await? {
+        DaemonResponse::Batch(responses) => match APICommandResponse::batch_error(&responses) {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        },
+        DaemonResponse::Err(error) => Err(error),
+        _ => Err(ApiError::internal("Unexpected Batch Result")),
+    }
+}
+
+fn find_node(profile: &Profile, node: &str) -> Result<Ulid, ApiError> {
+    let error = || ApiError::not_found(None, format!("Unknown Node: {}", node));
+    profile.find_node(node).ok_or_else(error)
+}
+
+fn get_node_by_id(profile: &Profile, id: Ulid) -> Result<Node, ApiError> {
+    let node = get_node_list(profile).into_iter().find(|node| node.id == id);
+    node.ok_or_else(|| ApiError::not_found(id, "Unknown Node"))
+}
+
+fn get_node_list(profile: &Profile) -> Vec<Node> {
+    let sources = &profile.devices.sources;
+    let targets = &profile.devices.targets;
+
+    let mut nodes = vec![];
+    for device in &sources.physical_devices {
+        nodes.push(to_node(profile, NodeType::PhysicalSource, device.description.id, device));
+    }
+    for device in &sources.virtual_devices {
+        nodes.push(to_node(profile, NodeType::VirtualSource, device.description.id, device));
+    }
+    for device in &targets.physical_devices {
+        nodes.push(to_node(profile, NodeType::PhysicalTarget, device.description.id, device));
+    }
+    for device in &targets.virtual_devices {
+        nodes.push(to_node(profile, NodeType::VirtualTarget, device.description.id, device));
+    }
+    nodes
+}
+
+fn to_node<T: Serialize>(profile: &Profile, node_type: NodeType, id: Ulid, device: &T) -> Node {
+    let routes = match node_type {
+        NodeType::PhysicalSource | NodeType::VirtualSource => {
+            let routes = profile.routes.get(&id);
+            Some(routes.map(|r| r.iter().copied().collect()).unwrap_or_default())
+        }
+        NodeType::PhysicalTarget | NodeType::VirtualTarget => None,
+    };
+
+    Node { id, node_type, device: serde_json::to_value(device).unwrap_or(Value::Null), routes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(json: Value) -> NodeUpdate {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn updates_are_checked_against_the_node() {
+        let profile = Profile::base_settings();
+        let mic = profile.find_node("Microphone").unwrap();
+        let stream = profile.find_node("Stream Mix").unwrap();
+        let source = NodeType::PhysicalSource;
+
+        // Target fields on a source shouldn't get as far as the daemon
+        let body = update(serde_json::json!({ "name": "Mic", "volume": 50 }));
+        let error = update_commands(&profile, source, mic, body).err().unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+
+        let body = update(serde_json::json!({ "policy": {} }));
+        assert!(update_commands(&profile, source, mic, body).is_err());
+
+        let body = update(serde_json::json!({ "volume": 50, "mix": "B" }));
+        let commands = update_commands(&profile, NodeType::VirtualTarget, stream, body).unwrap();
+        assert_eq!(commands.len(), 2);
+
+        // Asking for the link state the source is already in shouldn't fail the batch
+        let linked = profile.get_source_state(mic).unwrap().0.volumes_linked.is_some();
+        let body = update(serde_json::json!({ "volumes": { "A": 20 }, "linked": linked }));
+        let commands = update_commands(&profile, source, mic, body).unwrap();
+        assert!(matches!(commands[..], [APICommand::SetSourceVolume(_, Mix::A, 20)]));
+    }
+}
@@ -1,4 +1,13 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use pipeweaver_ipc::commands::{AccessLevel, ApiToken, HttpSettings, OscSettings};
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use ulid::Ulid;
 
 /// Ok, this time around I'm going to use Serde's 'default' feature, rather than having to
 /// have everything as an Option<T> and fixing it later
@@ -7,8 +16,203 @@ use serde::{Deserialize, Serialize};
 struct Settings {
     #[serde(default = "default_profile")]
     profile: String,
+
+    #[serde(default = "default_http")]
+    http_settings: HttpSettings,
+
+    #[serde(default = "default_osc")]
+    osc_settings: OscSettings,
+
+    /// API Tokens, if this is empty, authentication is disabled
+    tokens: Vec<ApiToken>,
+}
+
+/// Compares two tokens in constant time (for a given length), so a caller can't discover a
+/// token one character at a time by timing failed attempts
+fn tokens_match(expected: &str, provided: &str) -> bool {
+    let (expected, provided) = (expected.as_bytes(), provided.as_bytes());
+
+    // Fold the length difference into the result rather than returning early
+    let mut difference = (expected.len() != provided.len()) as u8;
+    for (index, byte) in expected.iter().enumerate() {
+        difference |= byte ^ provided.get(index).copied().unwrap_or(0);
+    }
+    std::hint::black_box(difference) == 0
 }
 
 fn default_profile() -> String {
     String::from("default")
 }
+
+fn default_http() -> HttpSettings {
+    HttpSettings {
+        enabled: true,
+        bind_address: "127.0.0.1".to_string(),
+        cors_enabled: false,
+        port: 14565,
+    }
+}
+
+fn default_osc() -> OscSettings {
+    OscSettings { enabled: true, bind_address: "127.0.0.1".to_string(), port: 14566 }
+}
+
+#[derive(Clone)]
+pub struct SettingsHandle {
+    path: PathBuf,
+    settings: Arc<RwLock<Settings>>,
+}
+
+impl SettingsHandle {
+    pub fn load(path: PathBuf) -> Self {
+        let settings = match File::open(&path) {
+            Ok(reader) => serde_json::from_reader(reader).unwrap_or_else(|e| {
+                warn!("[Settings] Found, but unable to Load ({}), using defaults", e);
+                default_settings()
+            }),
+            Err(_) => {
+                info!("[Settings] Not Found, using defaults");
+                default_settings()
+            }
+        };
+
+        Self { path, settings: Arc::new(RwLock::new(settings)) }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent).context("Unable to create config directory")?;
+        }
+
+        let settings = self.read();
+        let temp_path = self.path.with_extension("tmp");
+
+        // The file holds the API tokens, so only we should be able to read it. The mode only
+        // applies when the file is created, so anything left over from a crash is removed first.
+        let _ = fs::remove_file(&temp_path);
+        let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp_path)?;
+        serde_json::to_writer_pretty(file, &*settings)?;
+        fs::rename(temp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Settings> {
+        // A panic while holding the lock can't leave the settings half-written, so just carry on
+        self.settings.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Settings> {
+        self.settings.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get_http_settings(&self) -> HttpSettings {
+        self.read().http_settings.clone()
+    }
+
+    pub fn get_osc_settings(&self) -> OscSettings {
+        self.read().osc_settings.clone()
+    }
+
+    /// Returns true if any tokens have been configured
+    pub fn auth_enabled(&self) -> bool {
+        !self.read().tokens.is_empty()
+    }
+
+    /// Returns the level of access a token grants, if auth is disabled, this will be Full
+    pub fn get_access(&self, token: Option<&str>) -> Option<AccessLevel> {
+        let settings = self.read();
+        if settings.tokens.is_empty() {
+            return Some(AccessLevel::Full);
+        }
+
+        // Every token is checked, so the time taken doesn't reveal which (if any) matched
+        let token = token?;
+        let mut access = None;
+        for api_token in &settings.tokens {
+            if tokens_match(&api_token.token, token) {
+                access = Some(api_token.access);
+            }
+        }
+        access
+    }
+
+    pub fn add_token(&self, name: String, access: AccessLevel) -> Result<String> {
+        let mut settings = self.write();
+        if settings.tokens.iter().any(|t| t.name == name) {
+            bail!("A Token with this name already exists");
+        }
+
+        // Ulid's random component is 80 bits, so combine two for the token
+        let token = format!("{:020x}{:020x}", Ulid::new().random(), Ulid::new().random());
+        settings.tokens.push(ApiToken { name, token: token.clone(), access });
+        drop(settings);
+
+        self.save()?;
+        Ok(token)
+    }
+
+    pub fn remove_token(&self, name: String) -> Result<()> {
+        let mut settings = self.write();
+        let count = settings.tokens.len();
+        settings.tokens.retain(|t| t.name != name);
+        if settings.tokens.len() == count {
+            bail!("Token not Found");
+        }
+        drop(settings);
+
+        self.save()
+    }
+
+    pub fn get_tokens(&self) -> Vec<ApiToken> {
+        self.read().tokens.clone()
+    }
+}
+
+fn default_settings() -> Settings {
+    Settings {
+        profile: default_profile(),
+        http_settings: default_http(),
+        osc_settings: default_osc(),
+        tokens: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(tokens_match("0123abcd", "0123abcd"));
+        assert!(!tokens_match("0123abcd", "0123abce"));
+        assert!(!tokens_match("0123abcd", "0123abc"));
+        assert!(!tokens_match("0123abcd", "0123abcd0"));
+        assert!(!tokens_match("0123abcd", ""));
+    }
+
+    #[test]
+    fn access_follows_the_token() {
+        let path = std::env::temp_dir().join(format!("settings-{}.json", Ulid::new()));
+        let settings = SettingsHandle::load(path.clone());
+        assert_eq!(settings.get_access(None), Some(AccessLevel::Full));
+
+        let token = settings.add_token(String::from("Viewer"), AccessLevel::ReadOnly).unwrap();
+        assert_eq!(settings.get_access(Some(&token)), Some(AccessLevel::ReadOnly));
+        assert_eq!(settings.get_access(Some(&token[1..])), None);
+        assert_eq!(settings.get_access(None), None);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn settings_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("settings-{}.json", Ulid::new()));
+        let settings = SettingsHandle::load(path.clone());
+        settings.add_token(String::from("Viewer"), AccessLevel::ReadOnly).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(path).unwrap();
+    }
+}
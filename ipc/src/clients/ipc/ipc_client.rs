@@ -1,8 +1,16 @@
 use crate::client::Client;
+use crate::clients::ipc::ipc_path::get_socket_path;
 use crate::clients::ipc::ipc_socket::Socket;
-use crate::commands::{APICommand, DaemonRequest, DaemonResponse, DaemonStatus, HttpSettings};
-use anyhow::{anyhow, Context, Result};
+use crate::commands::{
+    APICommand, APICommandResponse, DaemonEvent, DaemonRequest, DaemonResponse, DaemonStatus,
+    HttpSettings, Subscription,
+};
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use interprocess::local_socket::tokio::prelude::LocalSocketStream;
+use interprocess::local_socket::traits::tokio::Stream as _;
+use interprocess::local_socket::{GenericFilePath, ToFsName};
 
 #[derive(Debug)]
 pub struct IPCClient {
@@ -19,6 +27,46 @@ impl IPCClient {
             http_settings: Default::default(),
         }
     }
+
+    /// Connects to the daemon's socket, respecting any location overrides
+    pub async fn connect() -> Result<Self> {
+        let name = get_socket_path().to_fs_name::<GenericFilePath>()?;
+        let stream = LocalSocketStream::connect(name)
+            .await
+            .context("Unable to connect to the PipeWeaver daemon, is it running?")?;
+        Ok(Self::new(Socket::new(stream)))
+    }
+
+    /// Subscribes to events from the daemon, this consumes the client as the connection will
+    /// only carry events from this point on.
+    pub async fn subscribe(
+        mut self,
+        subscriptions: Vec<Subscription>,
+    ) -> Result<impl Stream<Item = Result<DaemonEvent>>> {
+        self.socket
+            .send(DaemonRequest::Subscribe(subscriptions))
+            .await
+            .context("Failed to send the subscription to the daemon process")?;
+
+        let response = self
+            .socket
+            .read()
+            .await
+            .context("Daemon closed the connection while subscribing")?
+            .context("Failed to parse the subscription result from the daemon process")?;
+
+        match response {
+            DaemonResponse::Ok => {}
+            DaemonResponse::Err(error) => bail!(error),
+            response => bail!("Unexpected Subscription Response: {:?}", response),
+        }
+
+        Ok(self.socket.into_stream().map(|message| match message? {
+            DaemonResponse::Event(event) => Ok(event),
+            DaemonResponse::Err(error) => Err(anyhow!(error)),
+            response => Err(anyhow!("Unexpected Message in Event Stream: {:?}", response)),
+        }))
+    }
 }
 
 #[async_trait]
@@ -42,14 +90,27 @@ impl Client for IPCClient {
                 Ok(())
             }
             DaemonResponse::Ok => Ok(()),
-            DaemonResponse::Err(error) => Err(anyhow!("{}", error)),
+            DaemonResponse::Err(error) => Err(anyhow!(error)),
             DaemonResponse::Patch(_patch) => {
                 Err(anyhow!("Received Patch as response, shouldn't happen!"))
             }
+            DaemonResponse::Patches(_patches) => {
+                Err(anyhow!("Received Patches as response, shouldn't happen!"))
+            }
             DaemonResponse::Pipewire(_response) => {
                 // TODO: We need a way to pass back a response to a request properly..
                 Ok(())
             }
+            DaemonResponse::Auth(_response) => Ok(()),
+            DaemonResponse::Batch(responses) => match APICommandResponse::batch_error(&responses) {
+                Some(error) => Err(anyhow!(error.clone())),
+                None => Ok(()),
+            },
+            DaemonResponse::Graph(_graph) => Ok(()),
+            DaemonResponse::GraphDot(_dot) => Ok(()),
+            DaemonResponse::Event(_event) => {
+                Err(anyhow!("Received Event as response, shouldn't happen!"))
+            }
         }
     }
 
@@ -0,0 +1,251 @@
+use crate::clients::ipc::ipc_socket::Socket;
+use crate::commands::{DaemonRequest, DaemonResponse};
+use anyhow::{bail, Result};
+use interprocess::local_socket::tokio::prelude::LocalSocketStream;
+use interprocess::local_socket::traits::tokio::Stream;
+use interprocess::local_socket::{GenericFilePath, ListenerOptions, ToFsName};
+use log::debug;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Setting this environment variable overrides the socket location for both the daemon and clients
+pub const SOCKET_ENV: &str = "PIPEWEAVER_SOCKET";
+
+const SOCKET_DIR: &str = "pipeweaver";
+const SOCKET_NAME: &str = "pipeweaver.socket";
+
+/// Returns where the IPC socket should live, in order of preference this is the $PIPEWEAVER_SOCKET
+/// override, $XDG_RUNTIME_DIR/pipeweaver/pipeweaver.socket, or a per-user file in /tmp
+pub fn get_socket_path() -> PathBuf {
+    resolve_socket_path(|key| env::var_os(key))
+}
+
+fn resolve_socket_path<F>(var: F) -> PathBuf
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let var = |key| var(key).filter(|value| !value.is_empty());
+
+    if let Some(path) = var(SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+    if let Some(runtime_dir) = var("XDG_RUNTIME_DIR") {
+        return PathBuf::from(runtime_dir).join(SOCKET_DIR).join(SOCKET_NAME);
+    }
+
+    // No runtime dir, /tmp is shared so try to at least keep users from colliding
+    match var("USER") {
+        Some(user) => {
+            let mut name = OsString::from("pipeweaver-");
+            name.push(user);
+            name.push(".socket");
+            PathBuf::from("/tmp").join(name)
+        }
+        None => PathBuf::from("/tmp").join(SOCKET_NAME),
+    }
+}
+
+/// Creates the socket's parent directory if needed, only accessible by the current user
+pub fn prepare_socket_path(path: &Path) -> Result<()> {
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+
+    // We don't touch the permissions of existing directories, as this could well be /tmp
+    if parent.exists() {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+    }
+
+    #[cfg(not(unix))]
+    fs::create_dir_all(parent)?;
+
+    Ok(())
+}
+
+/// Listener options for the socket at this path. On unix the socket is restricted to the current
+/// user as it's bound (rather than afterwards), so there's no window where others can connect.
+pub fn listener_options(path: &Path) -> Result<ListenerOptions<'static>> {
+    let name = path.to_path_buf().to_fs_name::<GenericFilePath>()?;
+    let options = ListenerOptions::new().name(name);
+
+    #[cfg(unix)]
+    let options = {
+        use interprocess::os::unix::local_socket::ListenerOptionsExt;
+        options.mode(0o600)
+    };
+
+    Ok(options)
+}
+
+/// Checks whether a socket already exists at this path, removing it if it's stale. If there's an
+/// active daemon on the other end this will return an error.
+pub async fn tidy_socket(path: &Path) -> Result<()> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+
+    // Whatever this is, it's not ours to remove
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists, but is not a socket", path.display());
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = metadata;
+
+    let socket = path.to_fs_name::<GenericFilePath>()?;
+    let connection = LocalSocketStream::connect(socket).await;
+
+    if connection.is_err() {
+        match cfg!(windows) {
+            true => {
+                debug!("Named Pipe not running, continuing..");
+            }
+            false => {
+                debug!("Connection Failed. Socket File is stale, removing..");
+                fs::remove_file(path)?;
+            }
+        }
+        return Ok(());
+    }
+
+    debug!("Connected to socket, seeing if there's a Daemon on the other side..");
+    let connection = connection?;
+
+    let mut socket: Socket<DaemonResponse, DaemonRequest> = Socket::new(connection);
+    if let Err(e) = socket.send(DaemonRequest::Ping).await {
+        match cfg!(windows) {
+            true => {
+                debug!("Our named pipe is broken, something is horribly wrong..");
+                bail!("Named Pipe Error: {}", e);
+            }
+            false => {
+                debug!("Unable to send messages, removing socket..");
+                fs::remove_file(path)?;
+            }
+        }
+        return Ok(());
+    }
+
+    // If we get here, there's an active Daemon running!
+    bail!("The Daemon is already running.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interprocess::local_socket::traits::tokio::Listener;
+    use ulid::Ulid;
+
+    fn temp_socket() -> PathBuf {
+        env::temp_dir().join(format!("pipeweaver-test-{}.socket", Ulid::new()))
+    }
+
+    #[test]
+    fn resolve_prefers_override() {
+        let path = resolve_socket_path(|key| match key {
+            SOCKET_ENV => Some("/custom/path.socket".into()),
+            "XDG_RUNTIME_DIR" => Some("/run/user/1000".into()),
+            _ => None,
+        });
+        assert_eq!(path, PathBuf::from("/custom/path.socket"));
+    }
+
+    #[test]
+    fn resolve_uses_runtime_dir() {
+        let path = resolve_socket_path(|key| match key {
+            SOCKET_ENV => Some("".into()),
+            "XDG_RUNTIME_DIR" => Some("/run/user/1000".into()),
+            _ => None,
+        });
+        assert_eq!(path, PathBuf::from("/run/user/1000/pipeweaver/pipeweaver.socket"));
+    }
+
+    #[test]
+    fn resolve_falls_back_to_tmp() {
+        let path = resolve_socket_path(|key| match key {
+            "USER" => Some("frank".into()),
+            _ => None,
+        });
+        assert_eq!(path, PathBuf::from("/tmp/pipeweaver-frank.socket"));
+    }
+
+    #[tokio::test]
+    async fn tidy_missing_socket() {
+        assert!(tidy_socket(&temp_socket()).await.is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tidy_removes_stale_socket() {
+        let path = temp_socket();
+
+        // A std listener doesn't remove its file on drop, leaving a dead socket behind
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        assert!(tidy_socket(&path).await.is_ok());
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tidy_leaves_non_socket() {
+        let path = temp_socket();
+        fs::write(&path, "").unwrap();
+
+        assert!(tidy_socket(&path).await.is_err());
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn tidy_leaves_active_socket() {
+        let path = temp_socket();
+        let listener = listener_options(&path).unwrap().create_tokio().unwrap();
+
+        let accept = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap();
+            let mut socket: Socket<DaemonRequest, DaemonResponse> = Socket::new(stream);
+            let _ = socket.read().await;
+        });
+
+        assert!(tidy_socket(&path).await.is_err());
+        assert!(path.exists());
+
+        accept.abort();
+        let _ = fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn socket_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("pipeweaver-test-{}", Ulid::new()));
+        let path = dir.join(SOCKET_NAME);
+        prepare_socket_path(&path).unwrap();
+
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let listener = listener_options(&path).unwrap().create_tokio().unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(listener);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,10 +1,12 @@
-use futures::{SinkExt, StreamExt, TryStreamExt};
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
 use interprocess::local_socket::tokio::prelude::LocalSocketStream;
 use interprocess::local_socket::tokio::{RecvHalf, SendHalf};
-use interprocess::local_socket::traits::tokio::Stream;
+use interprocess::local_socket::traits::tokio::Stream as _;
 use serde::{Deserialize, Serialize};
 use std::io::Error;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use tokio_serde::formats::SymmetricalJson;
 use tokio_serde::SymmetricallyFramed;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
@@ -54,4 +56,29 @@ where
     pub fn address(&self) -> &SocketAddr {
         &self.address
     }
+
+    /// Returns the incoming messages as a Stream. The writing half is held by the stream, as
+    /// dropping it would shut the connection down.
+    pub fn into_stream(self) -> impl Stream<Item = Result<In, Error>> {
+        SocketStream { reader: self.reader, _writer: self.writer }
+    }
+}
+
+struct SocketStream<In, Out> {
+    reader:
+        SymmetricallyFramed<FramedRead<RecvHalf, LengthDelimitedCodec>, In, SymmetricalJson<In>>,
+    _writer:
+        SymmetricallyFramed<FramedWrite<SendHalf, LengthDelimitedCodec>, Out, SymmetricalJson<Out>>,
+}
+
+impl<In, Out> Stream for SocketStream<In, Out>
+where
+    for<'a> In: Deserialize<'a> + Unpin,
+    Out: Unpin,
+{
+    type Item = Result<In, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.reader.poll_next_unpin(cx)
+    }
 }
@@ -1,2 +1,3 @@
 pub mod ipc_client;
+pub mod ipc_path;
 pub mod ipc_socket;
@@ -8,6 +8,7 @@ use async_trait::async_trait;
 #[derive(Debug)]
 pub struct WebClient {
     url: String,
+    token: Option<String>,
     status: DaemonStatus,
 }
 
@@ -19,17 +20,26 @@ impl WebClient {
     fn new(url: String) -> Self {
         Self {
             url,
+            token: None,
             status: DaemonStatus::default(),
         }
     }
+
+    /// Sets the API token sent with each request, required if the daemon has auth enabled
+    pub fn set_token(&mut self, token: Option<String>) {
+        self.token = token;
+    }
 }
 
 #[async_trait]
 impl Client for WebClient {
     async fn send(&mut self, request: DaemonRequest) -> anyhow::Result<()> {
-        let resp = reqwest::Client::new()
-            .post(&self.url)
-            .json(&request)
+        let mut builder = reqwest::Client::new().post(&self.url).json(&request);
+        if let Some(token) = &self.token {
+            builder = builder.bearer_auth(token);
+        }
+
+        let resp = builder
             .send()
             .await?
             .json::<DaemonResponse>()
@@ -42,12 +52,20 @@ impl Client for WebClient {
                 Ok(())
             }
             DaemonResponse::Ok => Ok(()),
-            DaemonResponse::Err(error) => bail!("{}", error),
+            DaemonResponse::Err(error) => bail!(error),
             DaemonResponse::Patch(_) => bail!("Received PATCH!"),
+            DaemonResponse::Patches(_) => bail!("Received PATCH!"),
             DaemonResponse::Pipewire(response) => match response {
                 APICommandResponse::Id(_) => Ok(()),
                 APICommandResponse::Ok => Ok(()),
-                APICommandResponse::Err(error) => bail!("{}", error),
+                APICommandResponse::Err(error) => bail!(error),
+            },
+            DaemonResponse::Auth(_) => bail!("Auth is only available over IPC"),
+            DaemonResponse::Event(_) => bail!("Events are only available over IPC"),
+            DaemonResponse::Graph(_) | DaemonResponse::GraphDot(_) => Ok(()),
+            DaemonResponse::Batch(responses) => match APICommandResponse::batch_error(&responses) {
+                Some(error) => bail!(error.clone()),
+                None => Ok(()),
             },
         }
     }
@@ -0,0 +1,91 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use ulid::Ulid;
+
+/// Stable error codes, clients should match on these rather than the message
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum ErrorCode {
+    /// The referenced node (or mapping / device) doesn't exist
+    NotFound,
+
+    /// The request was understood, but a value isn't valid for it
+    InvalidArgument,
+
+    /// The requested state is already the current state
+    NoChange,
+
+    /// Pipewire failed to perform the requested operation
+    Pipewire,
+
+    /// Another command in an atomic batch failed, so this one was undone or never run
+    RolledBack,
+
+    Unauthorized,
+    Forbidden,
+
+    /// Anything else
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ApiError {
+    pub code: ErrorCode,
+
+    /// The node which caused the error, if there is one
+    #[schemars(with = "Option<String>")]
+    pub id: Option<Ulid>,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, id: impl Into<Option<Ulid>>, message: impl Into<String>) -> Self {
+        Self { code, id: id.into(), message: message.into() }
+    }
+
+    pub fn not_found(id: impl Into<Option<Ulid>>, message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, id, message)
+    }
+
+    pub fn invalid(id: impl Into<Option<Ulid>>, message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidArgument, id, message)
+    }
+
+    pub fn no_change(id: impl Into<Option<Ulid>>, message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NoChange, id, message)
+    }
+
+    pub fn pipewire(error: impl Display) -> Self {
+        Self::new(ErrorCode::Pipewire, None, error.to_string())
+    }
+
+    pub fn rolled_back(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::RolledBack, None, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, None, message)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "{} ({})", self.message, id),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Errors raised inside the daemon are anyhow errors, if one of them started life as an
+/// ApiError we pull it back out, otherwise it's treated as an Internal error.
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<ApiError>() {
+            Ok(error) => error,
+            Err(error) => Self::internal(error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use pipeweaver_shared::Mix;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use ulid::Ulid;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum GraphFormat {
+    /// Returned as DaemonResponse::Graph
+    #[default]
+    Json,
+
+    /// A Graphviz digraph, returned as DaemonResponse::GraphDot
+    Dot,
+}
+
+/// What the daemon has actually built in Pipewire, as opposed to what the profile asks for
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PipewireGraph {
+    pub nodes: Vec<GraphNode>,
+    pub filters: Vec<GraphFilter>,
+    pub links: Vec<GraphLink>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GraphNode {
+    #[schemars(with = "String")]
+    pub id: Ulid,
+    pub name: Option<String>,
+
+    /// The Pipewire ID, if Pipewire has assigned one
+    pub pw_id: Option<u32>,
+
+    /// The node has been assigned an ID and all of its ports
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GraphFilter {
+    #[schemars(with = "String")]
+    pub id: Ulid,
+    pub pw_id: Option<u32>,
+    pub role: FilterRole,
+
+    /// The profile node this filter belongs to
+    #[schemars(with = "Option<String>")]
+    pub owner: Option<Ulid>,
+    pub owner_name: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum FilterRole {
+    /// The entry point of a Physical Source
+    PassThrough,
+
+    /// A Source's volume for one of the mixes
+    Volume(Mix),
+
+    /// A Target's volume, for Physical Targets this is the entry point
+    TargetVolume,
+    Meter,
+
+    /// A filter which isn't referenced by the profile, this usually means something was left
+    /// behind when a node was removed
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GraphLink {
+    pub source: GraphEndpoint,
+    pub destination: GraphEndpoint,
+
+    /// The Pipewire IDs of each port link which has been created
+    pub pw_ids: Vec<u32>,
+
+    /// Every port link has been created
+    pub ready: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum GraphEndpoint {
+    Node(#[schemars(with = "String")] Ulid),
+    Filter(#[schemars(with = "String")] Ulid),
+
+    /// A node we didn't create (such as a physical device), by its Pipewire ID
+    Unmanaged(u32),
+}
+
+impl GraphEndpoint {
+    fn dot_id(&self) -> String {
+        match self {
+            GraphEndpoint::Node(id) | GraphEndpoint::Filter(id) => format!("\"{}\"", id),
+            GraphEndpoint::Unmanaged(id) => format!("\"pw-{}\"", id),
+        }
+    }
+}
+
+impl PipewireGraph {
+    /// Renders the graph as a Graphviz digraph, anything not yet ready is drawn dashed
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph pipeweaver {\n    rankdir=LR;\n");
+
+        for node in &self.nodes {
+            let name = node.name.as_deref().unwrap_or("Unknown Node");
+            let label = Self::label(name, node.pw_id);
+            let style = if node.ready { "solid" } else { "dashed" };
+            let endpoint = GraphEndpoint::Node(node.id).dot_id();
+            let attributes = format!("label=\"{}\", shape=box, style={}", label, style);
+            let _ = writeln!(dot, "    {} [{}];", endpoint, attributes);
+        }
+
+        for filter in &self.filters {
+            let role = match filter.role {
+                FilterRole::Volume(mix) => format!("Volume {}", mix),
+                role => format!("{:?}", role),
+            };
+            let name = match &filter.owner_name {
+                Some(owner) => format!("{} ({})", owner, role),
+                None => role,
+            };
+            let label = Self::label(&name, filter.pw_id);
+            let style = if filter.pw_id.is_some() { "solid" } else { "dashed" };
+            let endpoint = GraphEndpoint::Filter(filter.id).dot_id();
+            let _ = writeln!(dot, "    {} [label=\"{}\", style={}];", endpoint, label, style);
+        }
+
+        // Unmanaged nodes only appear as link endpoints, so declare them once here
+        let mut unmanaged: Vec<u32> = vec![];
+        for link in &self.links {
+            for endpoint in [link.source, link.destination] {
+                if let GraphEndpoint::Unmanaged(id) = endpoint {
+                    if !unmanaged.contains(&id) {
+                        unmanaged.push(id);
+                    }
+                }
+            }
+        }
+        for id in unmanaged {
+            let endpoint = GraphEndpoint::Unmanaged(id).dot_id();
+            let attributes = format!("label=\"Pipewire {}\", shape=box, style=dotted", id);
+            let _ = writeln!(dot, "    {} [{}];", endpoint, attributes);
+        }
+
+        for link in &self.links {
+            let style = if link.ready { "solid" } else { "dashed" };
+            let source = link.source.dot_id();
+            let destination = link.destination.dot_id();
+            let _ = writeln!(dot, "    {} -> {} [style={}];", source, destination, style);
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn label(name: &str, pw_id: Option<u32>) -> String {
+        let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+        match pw_id {
+            Some(pw_id) => format!("{}\\n#{}", name, pw_id),
+            None => format!("{}\\n(pending)", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_marks_pending_links() {
+        let node = Ulid::new();
+        let filter = Ulid::new();
+
+        let graph = PipewireGraph {
+            nodes: vec![GraphNode {
+                id: node,
+                name: Some(String::from("Stream \"Mix\"")),
+                pw_id: Some(40),
+                ready: true,
+            }],
+            filters: vec![GraphFilter {
+                id: filter,
+                pw_id: None,
+                role: FilterRole::Volume(Mix::A),
+                owner: Some(node),
+                owner_name: Some(String::from("System")),
+            }],
+            links: vec![
+                GraphLink {
+                    source: GraphEndpoint::Filter(filter),
+                    destination: GraphEndpoint::Node(node),
+                    pw_ids: vec![],
+                    ready: false,
+                },
+                GraphLink {
+                    source: GraphEndpoint::Node(node),
+                    destination: GraphEndpoint::Unmanaged(72),
+                    pw_ids: vec![81, 82],
+                    ready: true,
+                },
+            ],
+        };
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph pipeweaver {"));
+        assert!(dot.contains(r#"label="Stream \"Mix\"\n#40""#));
+        assert!(dot.contains(r#"label="System (Volume A)\n(pending)""#));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [style=dashed];", filter, node)));
+        assert!(dot.contains(&format!("\"{}\" -> \"pw-72\" [style=solid];", node)));
+        assert!(dot.contains("\"pw-72\" [label=\"Pipewire 72\""));
+    }
+}
@@ -1,11 +1,23 @@
 use enum_map::EnumMap;
 use json_patch::Patch;
-use pipeweaver_profile::Profile;
+use pipeweaver_profile::{
+    DefaultDevices, DeviceIdentity, LiveConfiguration, MidiAction, MidiMessage, NodeMetadata,
+    NodePolicy, Profile,
+};
 use pipeweaver_shared::{Colour, DeviceType, Mix, MuteState, MuteTarget, NodeType, OrderGroup};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use ulid::Ulid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+mod error;
+mod graph;
+pub use error::{ApiError, ErrorCode};
+pub use graph::{
+    FilterRole, GraphEndpoint, GraphFilter, GraphFormat, GraphLink, GraphNode, PipewireGraph,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum DaemonRequest {
     /// Simple ping, will get an Ok / Error response
     Ping,
@@ -13,94 +25,292 @@ pub enum DaemonRequest {
     /// This fetches the full status for all devices
     GetStatus,
 
+    /// Fetches every patch made after the provided epoch and revision, returned as
+    /// DaemonResponse::Patches. If they're no longer available (or the daemon has restarted, so
+    /// the epoch differs), a full DaemonResponse::Status is returned instead.
+    GetPatchesSince(#[schemars(with = "String")] Ulid, u64),
+
+    /// Fetches the nodes, filters and links the daemon has built in Pipewire
+    GetGraph(GraphFormat),
+
     Daemon(DaemonCommand),
     Pipewire(APICommand),
+
+    /// Runs several commands in order, producing a single status update
+    Batch(BatchRequest),
+
+    /// Token management, this is only available over the IPC socket
+    Auth(AuthCommand),
+
+    /// Turns an IPC connection into a stream of DaemonResponse::Event messages, after the initial
+    /// Ok response no further requests will be handled on this connection.
+    Subscribe(Vec<Subscription>),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct WebsocketRequest {
     pub id: u64,
     pub data: DaemonRequest,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[allow(clippy::large_enum_variant)]
 pub enum DaemonResponse {
     Ok,
-    Err(String),
-    Patch(Patch),
+    Err(ApiError),
+    Patch(StatusPatch),
     Status(DaemonStatus),
     Pipewire(APICommandResponse),
+
+    /// Patches in revision order, each one moves the status forward by a single revision
+    Patches(#[schemars(with = "Vec<serde_json::Value>")] Vec<Patch>),
+    Auth(AuthResponse),
+    Event(DaemonEvent),
+
+    /// The result of each command in a Batch, in the order they were sent
+    Batch(Vec<APICommandResponse>),
+
+    Graph(PipewireGraph),
+    GraphDot(String),
+}
+
+/// A JSON Patch against the DaemonStatus, and the revision the status is at once it's applied
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct StatusPatch {
+    /// The epoch of the status this patch applies to
+    #[schemars(with = "String")]
+    pub epoch: Ulid,
+    pub revision: u64,
+
+    #[schemars(with = "serde_json::Value")]
+    pub patch: Patch,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Subscription {
+    Patches,
+    Meters,
+    Devices,
+    Applications,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[allow(clippy::large_enum_variant)]
+pub enum DaemonEvent {
+    Patch(StatusPatch),
+
+    /// Sent instead of patches when the subscriber has fallen behind and missed some
+    Status(DaemonStatus),
+
+    Meter(#[schemars(with = "String")] Ulid, u8),
+
+    DeviceAdded(PhysicalDevice),
+    DeviceRemoved(u32),
+
+    ApplicationAdded(u32, String),
+    ApplicationRemoved(u32),
+}
+
+impl DaemonEvent {
+    pub fn subscription(&self) -> Subscription {
+        match self {
+            DaemonEvent::Patch(_) | DaemonEvent::Status(_) => Subscription::Patches,
+            DaemonEvent::Meter(_, _) => Subscription::Meters,
+            DaemonEvent::DeviceAdded(_) | DaemonEvent::DeviceRemoved(_) => Subscription::Devices,
+            DaemonEvent::ApplicationAdded(_, _) | DaemonEvent::ApplicationRemoved(_) => {
+                Subscription::Applications
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct WebsocketResponse {
     pub id: u64,
     pub data: DaemonResponse,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum DaemonCommand {
     SetMetering(bool),
+
+    /// Checks every link against the profile, repairing any which are missing or unexpected
+    Reconcile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum AuthCommand {
+    /// Creates a new token with the given name, the token is returned
+    AddToken(String, AccessLevel),
+    RemoveToken(String),
+    ListTokens,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum AuthResponse {
+    Token(String),
+    Tokens(Vec<ApiToken>),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum AccessLevel {
+    /// Can fetch the status, and receive patches and meters, but not make changes
+    ReadOnly,
+    Full,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ApiToken {
+    pub name: String,
+    pub token: String,
+    pub access: AccessLevel,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum APICommand {
     CreateNode(NodeType, String),
-    RenameNode(Ulid, String),
-    SetNodeColour(Ulid, Colour),
-    RemoveNode(Ulid),
+    RenameNode(#[schemars(with = "String")] Ulid, String),
+    SetNodeColour(#[schemars(with = "String")] Ulid, Colour),
+    RemoveNode(#[schemars(with = "String")] Ulid),
 
-    SetSourceVolume(Ulid, Mix, u8),
-    SetSourceVolumeLinked(Ulid, bool),
-    SetTargetVolume(Ulid, u8),
-    SetTargetMix(Ulid, Mix),
+    // Changing the policy or metadata of a Virtual Node will cause it to be rebuilt
+    SetNodePolicy(#[schemars(with = "String")] Ulid, NodePolicy),
+    SetNodeMetadata(#[schemars(with = "String")] Ulid, NodeMetadata),
 
-    SetRoute(Ulid, Ulid, bool),
+    // These take a Virtual Source and a Virtual Target respectively
+    SetDefaultSink(#[schemars(with = "String")] Ulid),
+    SetDefaultSource(#[schemars(with = "String")] Ulid),
 
-    AddSourceMuteTarget(Ulid, MuteTarget),
-    DelSourceMuteTarget(Ulid, MuteTarget),
+    SetSourceVolume(#[schemars(with = "String")] Ulid, Mix, u8),
+    SetSourceVolumeLinked(#[schemars(with = "String")] Ulid, bool),
+    SetTargetVolume(#[schemars(with = "String")] Ulid, u8),
+    SetTargetMix(#[schemars(with = "String")] Ulid, Mix),
 
-    AddMuteTargetNode(Ulid, MuteTarget, Ulid),
-    DelMuteTargetNode(Ulid, MuteTarget, Ulid),
-    ClearMuteTargetNodes(Ulid, MuteTarget),
+    SetRoute(#[schemars(with = "String")] Ulid, #[schemars(with = "String")] Ulid, bool),
 
-    SetTargetMuteState(Ulid, MuteState),
+    AddSourceMuteTarget(#[schemars(with = "String")] Ulid, MuteTarget),
+    DelSourceMuteTarget(#[schemars(with = "String")] Ulid, MuteTarget),
+
+    AddMuteTargetNode(
+        #[schemars(with = "String")] Ulid,
+        MuteTarget,
+        #[schemars(with = "String")] Ulid,
+    ),
+    DelMuteTargetNode(
+        #[schemars(with = "String")] Ulid,
+        MuteTarget,
+        #[schemars(with = "String")] Ulid,
+    ),
+    ClearMuteTargetNodes(#[schemars(with = "String")] Ulid, MuteTarget),
+
+    SetTargetMuteState(#[schemars(with = "String")] Ulid, MuteState),
 
     // Attach or Detach physical nodes
-    AttachPhysicalNode(Ulid, u32),
-    RemovePhysicalNode(Ulid, usize),
+    AttachPhysicalNode(#[schemars(with = "String")] Ulid, u32),
+    RemovePhysicalNode(#[schemars(with = "String")] Ulid, usize),
 
     // Set the position of a node in the order tree
-    SetOrderGroup(Ulid, OrderGroup),
-    SetOrder(Ulid, u8),
+    SetOrderGroup(#[schemars(with = "String")] Ulid, OrderGroup),
+    SetOrder(#[schemars(with = "String")] Ulid, u8),
+
+    // MIDI Controller Configuration
+    AddMidiDevice(String),
+    RemoveMidiDevice(String),
+    AddMidiMapping(MidiMessage, MidiAction),
+    RemoveMidiMapping(MidiMessage),
+
+    // Binds the next incoming MIDI message to this action, None cancels
+    SetMidiLearn(Option<MidiAction>),
+
+    // Configure what happens when a source goes live
+    SetLiveConfiguration(LiveConfiguration),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BatchRequest {
+    pub commands: Vec<APICommand>,
+
+    /// If any command fails, the profile is rolled back to its state prior to the batch, and
+    /// any remaining commands will not be run.
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum APICommandResponse {
     Ok,
-    Id(Ulid),
-    Err(String),
+    Id(#[schemars(with = "String")] Ulid),
+    Err(ApiError),
+}
+
+impl APICommandResponse {
+    /// The error which failed a batch, commands which were rolled back because of it are only
+    /// reported if there's nothing else
+    pub fn batch_error(responses: &[APICommandResponse]) -> Option<&ApiError> {
+        let mut errors = responses.iter().filter_map(|response| match response {
+            APICommandResponse::Err(error) => Some(error),
+            _ => None,
+        });
+        let first = errors.next()?;
+        if first.code != ErrorCode::RolledBack {
+            return Some(first);
+        }
+        errors.find(|error| error.code != ErrorCode::RolledBack).or(Some(first))
+    }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DaemonStatus {
+    /// Changes every time the daemon starts, revisions restart with it, so patches from one
+    /// epoch can't be applied to a status from another
+    #[serde(default)]
+    #[schemars(with = "String")]
+    pub epoch: Ulid,
+
+    /// Incremented by every patch, so clients can tell whether they've missed any
+    #[serde(default)]
+    pub revision: u64,
+
+    #[serde(default)]
+    pub health: DaemonHealth,
+
     pub config: DaemonConfig,
     pub audio: AudioConfiguration,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// Whether the daemon is able to manage audio, if Pipewire couldn't be started (or has failed in
+/// a way we can't recover from) the daemon keeps running, but reports why here.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum DaemonHealth {
+    #[default]
+    Healthy,
+
+    /// Pipewire is running, but the profile couldn't be fully built
+    Degraded(String),
+    Failed(String),
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AudioConfiguration {
     pub profile: Profile,
+    #[schemars(with = "HashMap<DeviceType, Vec<PhysicalDevice>>")]
     pub devices: EnumMap<DeviceType, Vec<PhysicalDevice>>,
+
+    /// The action waiting to be bound to the next incoming MIDI message
+    pub midi_learn: Option<MidiAction>,
+
+    /// The system defaults Pipewire is currently using, these are only set if they're our nodes
+    #[serde(default)]
+    pub defaults: DefaultDevices,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DaemonConfig {
     pub http_settings: HttpSettings,
+    pub osc_settings: OscSettings,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct HttpSettings {
     pub enabled: bool,
     pub bind_address: String,
@@ -108,12 +318,23 @@ pub struct HttpSettings {
     pub port: u16,
 }
 
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OscSettings {
+    pub enabled: bool,
+    pub bind_address: String,
+    pub port: u16,
+}
+
 /// The API generally doesn't need to care about all the general minutia of how a Pipewire
 /// node actually looks, so instead we just have a very simple Device object that provides
-/// an ID to be passed back to the daemon in IPC calls, and the nodes name.
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// an ID to be passed back to the daemon in IPC calls, the nodes name, and enough of the
+/// hardware's identity to tell apart two devices with the same name.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PhysicalDevice {
     pub node_id: u32,
     pub name: Option<String>,
     pub description: Option<String>,
+
+    #[serde(default)]
+    pub identity: DeviceIdentity,
 }
\ No newline at end of file
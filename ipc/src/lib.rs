@@ -1,6 +1,7 @@
 pub mod commands;
 pub mod clients;
 mod client;
+pub mod schema;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
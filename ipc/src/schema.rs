@@ -0,0 +1,312 @@
+use crate::commands::{
+    ApiError, DaemonRequest, DaemonResponse, DaemonStatus, PhysicalDevice, WebsocketRequest,
+    WebsocketResponse,
+};
+use pipeweaver_profile::{NodeMetadata, NodePolicy};
+use pipeweaver_shared::{Colour, DeviceType, Mix, MuteTarget};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{RootSchema, Schema, SchemaObject};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Returns a JSON Schema containing definitions for every type that can be sent to, or received
+/// from, the daemon. This is served by the daemon at /api/schema.
+pub fn get_json_schema() -> RootSchema {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    add_definitions(&mut generator);
+
+    RootSchema {
+        meta_schema: generator.settings().meta_schema.clone(),
+        schema: SchemaObject::default(),
+        definitions: generator.take_definitions(),
+    }
+}
+
+/// Returns an OpenAPI description of the daemon's HTTP API, served at /api/openapi.json
+pub fn get_openapi() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    add_definitions(&mut generator);
+
+    let schema = |schema: Schema| serde_json::to_value(schema).unwrap_or(Value::Null);
+    let request = schema(generator.subschema_for::<DaemonRequest>());
+    let response = schema(generator.subschema_for::<DaemonResponse>());
+    let status = schema(generator.subschema_for::<DaemonStatus>());
+    let error = schema(generator.subschema_for::<ApiError>());
+    let colour = schema(generator.subschema_for::<Colour>());
+    let mix = schema(generator.subschema_for::<Mix>());
+    let target = schema(generator.subschema_for::<MuteTarget>());
+    let policy = schema(generator.subschema_for::<NodePolicy>());
+    let metadata = schema(generator.subschema_for::<NodeMetadata>());
+    let devices = schema(generator.subschema_for::<HashMap<DeviceType, Vec<PhysicalDevice>>>());
+
+    let json = |schema: &Value| json!({ "content": { "application/json": { "schema": schema } } });
+    let error_response = json(&error);
+    let node = json!({
+        "type": "object",
+        "description": "A node from the profile, with its node_type and (for sources) routes",
+        "additionalProperties": true,
+    });
+
+    let node_param = json!({
+        "name": "node", "in": "path", "required": true,
+        "description": "The node's ID, or its name",
+        "schema": { "type": "string" },
+    });
+    let event_id = json!({
+        "type": "string",
+        "description": "The ID of the last status or patch event received, as epoch:revision",
+    });
+    let route_params = json!([
+        { "name": "source", "in": "path", "required": true, "schema": { "type": "string" } },
+        { "name": "target", "in": "path", "required": true, "schema": { "type": "string" } },
+    ]);
+    let mute_params = json!([node_param, {
+        "name": "target", "in": "query", "required": false,
+        "description": "The Mute Target, only used for Sources",
+        "schema": target,
+    }]);
+
+    // Responses shared by every route which makes a change
+    let errors = json!({
+        "400": { "description": "Invalid Argument", "content": error_response["content"] },
+        "401": { "description": "Unauthorized", "content": error_response["content"] },
+        "403": { "description": "Forbidden", "content": error_response["content"] },
+        "404": { "description": "Not Found", "content": error_response["content"] },
+        "409": { "description": "No Change", "content": error_response["content"] },
+        "502": { "description": "Pipewire Error", "content": error_response["content"] },
+    });
+    let with_errors = |mut responses: Value| {
+        if let (Some(responses), Some(errors)) = (responses.as_object_mut(), errors.as_object()) {
+            responses.extend(errors.clone());
+        }
+        responses
+    };
+
+    // PUT and DELETE style routes succeed if the state is already what was asked for
+    let idempotent = |responses: Value| {
+        let mut responses = with_errors(responses);
+        if let Some(responses) = responses.as_object_mut() {
+            responses.remove("409");
+        }
+        responses
+    };
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "PipeWeaver",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "components": {
+            "schemas": generator.take_definitions(),
+            "securitySchemes": {
+                "token": { "type": "http", "scheme": "bearer" },
+            },
+        },
+        "security": [{ "token": [] }],
+        "paths": {
+            "/api/command": {
+                "post": {
+                    "summary": "Runs a DaemonRequest",
+                    "requestBody": { "required": true, "content": json(&request)["content"] },
+                    "responses": with_errors(json!({
+                        "200": { "description": "Success", "content": json(&response)["content"] },
+                    })),
+                },
+            },
+            "/api/get-devices": {
+                "get": {
+                    "summary": "Fetches the full Daemon Status",
+                    "responses": with_errors(json!({
+                        "200": { "description": "Success", "content": json(&status)["content"] },
+                    })),
+                },
+            },
+            "/api/nodes": {
+                "get": {
+                    "summary": "Lists all nodes",
+                    "responses": with_errors(json!({
+                        "200": {
+                            "description": "Success",
+                            "content": json(&json!({ "type": "array", "items": node }))["content"],
+                        },
+                    })),
+                },
+            },
+            "/api/nodes/{node}": {
+                "parameters": [node_param],
+                "get": {
+                    "summary": "Fetches a single node",
+                    "responses": with_errors(json!({
+                        "200": { "description": "Success", "content": json(&node)["content"] },
+                    })),
+                },
+                "patch": {
+                    "summary": "Updates a node, only the provided fields are changed",
+                    "requestBody": {
+                        "required": true,
+                        "content": json(&json!({
+                            "type": "object",
+                            "properties": {
+                                "name": { "type": "string" },
+                                "colour": colour,
+                                "volume": { "type": "integer", "minimum": 0, "maximum": 100 },
+                                "mix": mix,
+                                "volumes": {
+                                    "type": "object",
+                                    "properties": {
+                                        "A": { "type": "integer", "minimum": 0, "maximum": 100 },
+                                        "B": { "type": "integer", "minimum": 0, "maximum": 100 },
+                                    },
+                                },
+                                "linked": { "type": "boolean" },
+                                "policy": {
+                                    "description": "Virtual Nodes only, replaces the whole \
+                                        policy. Missing fields take their defaults, so nodes \
+                                        without a policy use dont_reconnect and no autoconnect",
+                                    "allOf": [policy],
+                                },
+                                "metadata": {
+                                    "description": "Virtual Nodes only, replaces the whole \
+                                        metadata. Missing fields take their defaults",
+                                    "allOf": [metadata],
+                                },
+                            },
+                        }))["content"],
+                    },
+                    "responses": with_errors(json!({
+                        "200": { "description": "Updated", "content": json(&node)["content"] },
+                    })),
+                },
+            },
+            "/api/nodes/{node}/mute": {
+                "parameters": mute_params,
+                "post": {
+                    "summary": "Mutes a node",
+                    "responses": idempotent(json!({
+                        "204": { "description": "Muted, or was already muted" },
+                    })),
+                },
+                "delete": {
+                    "summary": "Unmutes a node",
+                    "responses": idempotent(json!({
+                        "204": { "description": "Unmuted, or wasn't muted" },
+                    })),
+                },
+            },
+            "/api/routes/{source}/{target}": {
+                "parameters": route_params,
+                "put": {
+                    "summary": "Routes a source to a target",
+                    "responses": idempotent(json!({
+                        "204": { "description": "Routed, or was already routed" },
+                    })),
+                },
+                "delete": {
+                    "summary": "Removes a route",
+                    "responses": idempotent(json!({
+                        "204": { "description": "Removed, or wasn't routed" },
+                    })),
+                },
+            },
+            "/api/events": {
+                "get": {
+                    "summary": "A Server-Sent Events stream of status, patch and meter events",
+                    "parameters": [
+                        { "name": "meters", "in": "query", "schema": { "type": "boolean" } },
+                        { "name": "last_event_id", "in": "query", "schema": event_id },
+                        { "name": "Last-Event-ID", "in": "header", "schema": event_id },
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The event stream",
+                            "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                        },
+                        "401": { "description": "Unauthorized", "content": error_response["content"] },
+                    },
+                },
+            },
+            "/api/devices/physical": {
+                "get": {
+                    "summary": "Lists physical devices available to be attached to nodes",
+                    "responses": with_errors(json!({
+                        "200": { "description": "Success", "content": json(&devices)["content"] },
+                    })),
+                },
+            },
+            "/metrics": {
+                "get": {
+                    "summary": "Node volumes, mute states, meters and daemon counters for Prometheus",
+                    "responses": {
+                        "200": {
+                            "description": "Metrics in the Prometheus text format",
+                            "content": { "text/plain": { "schema": { "type": "string" } } },
+                        },
+                        "401": { "description": "Unauthorized", "content": error_response["content"] },
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn add_definitions(generator: &mut SchemaGenerator) {
+    generator.subschema_for::<DaemonRequest>();
+    generator.subschema_for::<DaemonResponse>();
+    generator.subschema_for::<WebsocketRequest>();
+    generator.subschema_for::<WebsocketResponse>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Run with UPDATE_SCHEMA=1 to regenerate this after changing any of the API types
+    fn snapshot_path() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("schema/api-schema.json")
+    }
+
+    #[test]
+    fn schema_matches_snapshot() {
+        let schema = serde_json::to_string_pretty(&get_json_schema()).unwrap() + "\n";
+        let path = snapshot_path();
+
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            fs::write(&path, &schema).unwrap();
+            return;
+        }
+
+        let snapshot = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            snapshot == schema,
+            "The API types no longer match {}, run the tests with UPDATE_SCHEMA=1 to update it",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn openapi_references_resolve() {
+        let openapi = serde_json::to_string(&get_openapi()).unwrap();
+        let schemas = get_openapi()["components"]["schemas"].clone();
+
+        for reference in openapi.split("\"$ref\":\"").skip(1) {
+            let reference = &reference[..reference.find('"').unwrap()];
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.get(name).is_some(), "Missing Schema: {}", name);
+        }
+    }
+
+    #[test]
+    fn node_updates_list_every_field() {
+        let openapi = get_openapi();
+        let body = &openapi["paths"]["/api/nodes/{node}"]["patch"]["requestBody"];
+        let properties = &body["content"]["application/json"]["schema"]["properties"];
+
+        let fields = ["name", "colour", "volume", "mix", "volumes", "linked", "policy", "metadata"];
+        for field in fields {
+            assert!(properties.get(field).is_some(), "Missing Field: {}", field);
+        }
+    }
+}
@@ -7,7 +7,9 @@ use crate::manager::run_pw_main_loop;
 use anyhow::{anyhow, bail, Result};
 use log::{debug, info, warn};
 use oneshot::TryRecvError;
-use std::sync::mpsc;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::AtomicU64;
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::thread::{sleep, JoinHandle};
 use std::time::Duration;
@@ -28,12 +30,35 @@ pub enum PipewireMessage {
     RemoveFilterNode(Ulid),
     RemoveDeviceLink(LinkType, LinkType),
 
+    /// Drops every copy of a link except one, preferring a copy which has finished coming up
+    RemoveDuplicateLinks(LinkType, LinkType),
+
     SetFilterValue(Ulid, u32, FilterValue),
 
     SetNodeVolume(Ulid, u8),
 
+    /// Sets the volume of an application's stream, by its Pipewire ID
+    SetApplicationVolume(u32, u8),
+
+    /// Updates the nick and description of a node or filter without recreating it, nodes are
+    /// updated through their Props, which Pipewire can accept without applying, so the sender
+    /// fires once the node reports the new labels back
+    UpdateNodeProperties(Ulid, NodeLabels, Option<oneshot::Sender<()>>),
+
     DestroyUnmanagedLinks(u32),
 
+    /// Destroys a single link by Pipewire ID, as long as it's not one of ours
+    DestroyUnmanagedLink(u32),
+
+    /// Takes a snapshot of every node, filter and link we've created
+    GetGraph(oneshot::Sender<GraphSnapshot>),
+
+    /// Makes one of our nodes the system default, via Pipewire's 'default' metadata
+    SetDefaultDevice(DefaultDevice, Ulid),
+
+    /// Removes our choice of default, handing it back to the session manager
+    ClearDefaultDevice(DefaultDevice),
+
     Quit,
 }
 
@@ -54,11 +79,23 @@ pub enum PipewireInternalMessage {
         LinkType,
         oneshot::Sender<Result<()>>,
     ),
+    RemoveDuplicateLinks(LinkType, LinkType, oneshot::Sender<Result<()>>),
 
     SetFilterValue(Ulid, u32, FilterValue, oneshot::Sender<Result<()>>),
     SetNodeVolume(Ulid, u8, oneshot::Sender<Result<()>>),
+    SetApplicationVolume(u32, u8, oneshot::Sender<Result<()>>),
+    UpdateNodeProperties(
+        Ulid,
+        NodeLabels,
+        Option<oneshot::Sender<()>>,
+        oneshot::Sender<Result<()>>,
+    ),
 
     DestroyUnmanagedLinks(u32, oneshot::Sender<Result<()>>),
+    DestroyUnmanagedLink(u32, oneshot::Sender<Result<()>>),
+    GetGraph(oneshot::Sender<GraphSnapshot>, oneshot::Sender<Result<()>>),
+    SetDefaultDevice(DefaultDevice, Ulid, oneshot::Sender<Result<()>>),
+    ClearDefaultDevice(DefaultDevice, oneshot::Sender<Result<()>>),
     Quit(oneshot::Sender<Result<()>>),
 }
 
@@ -73,8 +110,79 @@ pub enum PipewireReceiver {
     ApplicationRemoved(u32),
 
     NodeVolumeChanged(Ulid, u8),
+    ApplicationVolumeChanged(u32, u8),
 
     ManagedLinkDropped(LinkType, LinkType),
+
+    /// Something other than us has linked to one of our nodes, this carries the Pipewire ID of
+    /// the link, and the node it's attached to
+    UnmanagedLinkAdded(u32, Ulid),
+
+    /// The system default has changed, this will be None if it's not one of our nodes
+    DefaultDeviceChanged(DefaultDevice, Option<Ulid>),
+
+    /// The connection to Pipewire has been lost, everything we created is gone with it
+    Disconnected,
+
+    /// We've reconnected to Pipewire, the graph will need to be rebuilt
+    Reconnected,
+}
+
+/// Errors raised by the Pipewire layer, these are returned inside the anyhow errors from
+/// send_message, so can be recovered with downcast_ref if needed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipewireError {
+    /// The Pipewire thread isn't running
+    NotRunning,
+
+    /// The connection to Pipewire was lost, and hasn't been re-established yet
+    Disconnected,
+
+    NodeNotFound(Ulid),
+    FilterNotFound(Ulid),
+    UnmanagedNodeNotFound(u32),
+
+    /// The node or filter exists, but Pipewire hasn't finished setting it up yet
+    NotReady(Ulid),
+
+    /// The unmanaged node doesn't have a port we can link to
+    PortNotFound(u32),
+
+    UnknownProperty(u32),
+    InvalidPropertyValue(u32),
+
+    /// Pipewire won't allow this node's properties to be changed once it's been created
+    ReadOnly(Ulid),
+}
+
+impl Display for PipewireError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipewireError::NotRunning => write!(f, "Pipewire is not running"),
+            PipewireError::Disconnected => write!(f, "Disconnected from Pipewire"),
+            PipewireError::NodeNotFound(id) => write!(f, "Node not found: {}", id),
+            PipewireError::FilterNotFound(id) => write!(f, "Filter not found: {}", id),
+            PipewireError::UnmanagedNodeNotFound(id) => {
+                write!(f, "Unmanaged Node not found: {}", id)
+            }
+            PipewireError::NotReady(id) => write!(f, "Node is not ready: {}", id),
+            PipewireError::PortNotFound(id) => write!(f, "No usable port on Node {}", id),
+            PipewireError::UnknownProperty(id) => write!(f, "Unknown Filter Property: {}", id),
+            PipewireError::InvalidPropertyValue(id) => {
+                write!(f, "Invalid value for Filter Property: {}", id)
+            }
+            PipewireError::ReadOnly(id) => write!(f, "Node properties are read only: {}", id),
+        }
+    }
+}
+
+impl std::error::Error for PipewireError {}
+
+/// Counters updated from the Pipewire thread, these can be read at any time
+#[derive(Debug, Default)]
+pub struct PipewireStats {
+    /// Filter process callbacks which took longer than the quantum allows
+    pub filter_overruns: AtomicU64,
 }
 
 pub struct NamingScheme {
@@ -91,7 +199,10 @@ pub struct PipewireRunner {
 }
 
 impl PipewireRunner {
-    pub fn new(callback_tx: mpsc::Sender<PipewireReceiver>) -> Result<Self> {
+    pub fn new(
+        callback_tx: mpsc::Sender<PipewireReceiver>,
+        stats: Arc<PipewireStats>,
+    ) -> Result<Self> {
         // First, we need our pipewire messaging queue, so establish that here
         let (pw_tx, pw_rx) = pipewire::channel::channel();
         let (tx, rx) = mpsc::channel();
@@ -100,7 +211,8 @@ impl PipewireRunner {
         let (start_tx, start_rx) = oneshot::channel();
 
         // Next, spawn up the pipewire mainloop in a separate thread
-        let pipewire_handle = thread::spawn(|| run_pw_main_loop(pw_rx, start_tx, callback_tx));
+        let pipewire_handle =
+            thread::spawn(|| run_pw_main_loop(pw_rx, start_tx, callback_tx, stats));
 
         // Await a response from that thread to indicate we're ready to handle messages
         loop {
@@ -114,7 +226,7 @@ impl PipewireRunner {
                 }
                 Err(e) => {
                     if e == TryRecvError::Disconnected {
-                        panic!("Channel Closed before Response");
+                        bail!(PipewireError::NotRunning);
                     }
                     sleep(Duration::from_millis(5));
                 }
@@ -152,15 +264,34 @@ impl PipewireRunner {
             PipewireMessage::RemoveDeviceLink(lt, lt2) => {
                 PipewireInternalMessage::RemoveDeviceLink(lt, lt2, tx)
             }
+            PipewireMessage::RemoveDuplicateLinks(lt, lt2) => {
+                PipewireInternalMessage::RemoveDuplicateLinks(lt, lt2, tx)
+            }
             PipewireMessage::DestroyUnmanagedLinks(id) => {
                 PipewireInternalMessage::DestroyUnmanagedLinks(id, tx)
             }
+            PipewireMessage::DestroyUnmanagedLink(id) => {
+                PipewireInternalMessage::DestroyUnmanagedLink(id, tx)
+            }
             PipewireMessage::SetFilterValue(id, prop, value) => {
                 PipewireInternalMessage::SetFilterValue(id, prop, value, tx)
             }
             PipewireMessage::SetNodeVolume(id, volume) => {
                 PipewireInternalMessage::SetNodeVolume(id, volume, tx)
             }
+            PipewireMessage::SetApplicationVolume(id, volume) => {
+                PipewireInternalMessage::SetApplicationVolume(id, volume, tx)
+            }
+            PipewireMessage::UpdateNodeProperties(id, labels, cb) => {
+                PipewireInternalMessage::UpdateNodeProperties(id, labels, cb, tx)
+            }
+            PipewireMessage::GetGraph(graph) => PipewireInternalMessage::GetGraph(graph, tx),
+            PipewireMessage::SetDefaultDevice(device, id) => {
+                PipewireInternalMessage::SetDefaultDevice(device, id, tx)
+            }
+            PipewireMessage::ClearDefaultDevice(device) => {
+                PipewireInternalMessage::ClearDefaultDevice(device, tx)
+            }
             PipewireMessage::Quit => PipewireInternalMessage::Quit(tx),
         };
 
@@ -172,6 +303,18 @@ impl PipewireRunner {
     }
 }
 
+/// Something which can handle a PipewireMessage. PipewireRunner is the real implementation, but
+/// this allows the daemon to be tested against an in-memory graph instead.
+pub trait PipewireBackend: Send + Sync {
+    fn send_message(&self, message: PipewireMessage) -> Result<()>;
+}
+
+impl PipewireBackend for PipewireRunner {
+    fn send_message(&self, message: PipewireMessage) -> Result<()> {
+        PipewireRunner::send_message(self, message)
+    }
+}
+
 impl Drop for PipewireRunner {
     fn drop(&mut self) {
         info!("[PIPEWIRE] Stopping");
@@ -240,6 +383,17 @@ pub struct NodeProperties {
     pub linger: bool,
     pub class: MediaClass,
 
+    // Session Manager Policy
+    pub dont_reconnect: bool,
+    pub autoconnect: bool,
+    pub priority_session: Option<i32>,
+    pub priority_driver: Option<i32>,
+
+    // Presentation, a Source which isn't a virtual microphone is kept out of the device lists
+    pub media_role: Option<String>,
+    pub icon_name: Option<String>,
+    pub virtual_microphone: bool,
+
     // Latency Configuration
     pub buffer: u32,
 
@@ -247,6 +401,27 @@ pub struct NodeProperties {
     pub ready_sender: Option<oneshot::Sender<()>>,
 }
 
+impl NodeProperties {
+    /// The media.class to create the node with. Session managers only offer 'Audio/Sink' and
+    /// 'Audio/Source' nodes to the desktop, so a target which isn't a virtual microphone uses an
+    /// internal class rather than showing up as an output.
+    pub fn media_class(&self) -> &'static str {
+        match self.class {
+            MediaClass::Source if self.virtual_microphone => "Audio/Source/Virtual",
+            MediaClass::Source => "Audio/Sink/Internal",
+            MediaClass::Duplex => "Audio/Duplex",
+            MediaClass::Sink => "Audio/Sink",
+        }
+    }
+}
+
+/// The parts of a node's properties which are shown to the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeLabels {
+    pub nick: String,
+    pub description: String,
+}
+
 pub struct FilterProperties {
     pub filter_id: Ulid,
 
@@ -272,7 +447,34 @@ pub enum MediaClass {
     Duplex,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// The defaults held in Pipewire's 'default' metadata, named from the point of view of
+/// applications, so our Virtual Sources are Sinks, and our Virtual Targets are Sources
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DefaultDevice {
+    Sink,
+    Source,
+}
+
+impl DefaultDevice {
+    /// The key the session manager reads to pick the default, and remembers between sessions
+    pub(crate) fn configured_key(&self) -> &'static str {
+        match self {
+            DefaultDevice::Sink => "default.configured.audio.sink",
+            DefaultDevice::Source => "default.configured.audio.source",
+        }
+    }
+
+    /// The key the session manager writes with the default it's actually using
+    pub(crate) fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "default.audio.sink" => Some(DefaultDevice::Sink),
+            "default.audio.source" => Some(DefaultDevice::Source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum LinkType {
     Node(Ulid),
     Filter(Ulid),
@@ -282,8 +484,8 @@ pub enum LinkType {
 pub type FilterCallback = dyn FnMut(Vec<&mut [f32]>, Vec<&mut [f32]>) + Send;
 pub trait FilterHandler: Send + 'static {
     fn get_properties(&self) -> Vec<FilterProperty>;
-    fn get_property(&self, id: u32) -> FilterProperty;
-    fn set_property(&mut self, id: u32, value: FilterValue);
+    fn get_property(&self, id: u32) -> Result<FilterProperty, PipewireError>;
+    fn set_property(&mut self, id: u32, value: FilterValue) -> Result<(), PipewireError>;
 
     fn process_samples(&mut self, inputs: Vec<&mut [f32]>, outputs: Vec<&mut [f32]>);
 }
@@ -306,6 +508,51 @@ pub struct FilterProperty {
     pub value: FilterValue,
 }
 
+/// The nodes, filters and links we've created, and whether Pipewire has finished setting them up
+#[derive(Debug, Clone, Default)]
+pub struct GraphSnapshot {
+    pub nodes: Vec<NodeSnapshot>,
+    pub filters: Vec<FilterSnapshot>,
+    pub links: Vec<LinkSnapshot>,
+
+    /// Links made by something other than us, by Pipewire ID
+    pub unmanaged_links: Vec<UnmanagedLinkSnapshot>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeSnapshot {
+    pub id: Ulid,
+    pub pw_id: Option<u32>,
+
+    /// The node has an ID and all of its ports
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterSnapshot {
+    pub id: Ulid,
+    pub pw_id: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkSnapshot {
+    pub source: LinkType,
+    pub destination: LinkType,
+
+    /// The Pipewire IDs of the port links which make up this link
+    pub pw_ids: Vec<u32>,
+
+    /// Every port link has been created
+    pub ready: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnmanagedLinkSnapshot {
+    pub pw_id: u32,
+    pub output_node: u32,
+    pub input_node: u32,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct DeviceNode {
     pub node_id: u32,
@@ -314,6 +561,21 @@ pub struct DeviceNode {
     pub name: Option<String>,
     pub nickname: Option<String>,
     pub description: Option<String>,
+
+    pub identity: DeviceIdentity,
+}
+
+/// Hardware properties from the node and its parent device, these help to find the same device
+/// again when its node name changes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceIdentity {
+    pub bus_path: Option<String>,
+    pub serial: Option<String>,
+    pub card_name: Option<String>,
+    pub vendor_id: Option<String>,
+    pub product_id: Option<String>,
+    pub profile_name: Option<String>,
+    pub port_names: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -322,4 +584,7 @@ pub struct ApplicationNode {
     pub node_class: MediaClass,
 
     pub name: String,
+
+    /// The stream's volume, None until Pipewire has reported it
+    pub volume: Option<u8>,
 }
@@ -4,11 +4,14 @@ use crate::{
     registry, FilterHandler, FilterProperties, FilterValue, LinkType, NodeProperties,
     PipewireInternalMessage, PipewireReceiver,
 };
-use crate::{MediaClass, PWReceiver, PipewireMessage};
+use crate::{
+    DefaultDevice, GraphSnapshot, MediaClass, NodeLabels, PWReceiver, PipewireError,
+    PipewireMessage, PipewireStats,
+};
 use anyhow::Result;
 use anyhow::{anyhow, bail};
-use log::{debug, error, info};
-use pipewire::core::Core;
+use log::{debug, error, info, warn};
+use pipewire::core::{Core, Listener as CoreListener, PW_ID_CORE};
 use pipewire::filter::{Filter, FilterFlags, FilterState, PortFlags};
 use pipewire::keys::{APP_ICON_NAME, APP_ID, APP_NAME, AUDIO_CHANNEL, AUDIO_CHANNELS, DEVICE_ICON_NAME, FACTORY_NAME, FORMAT_DSP, LINK_INPUT_NODE, LINK_INPUT_PORT, LINK_OUTPUT_NODE, LINK_OUTPUT_PORT, MEDIA_CATEGORY, MEDIA_CLASS, MEDIA_ICON_NAME, MEDIA_ROLE, MEDIA_TYPE, NODE_DESCRIPTION, NODE_DRIVER, NODE_FORCE_QUANTUM, NODE_FORCE_RATE, NODE_ID, NODE_LATENCY, NODE_MAX_LATENCY, NODE_NAME, NODE_NICK, NODE_PASSIVE, NODE_VIRTUAL, OBJECT_LINGER, PORT_MONITOR, PORT_NAME};
 use pipewire::link::{Link, LinkListener, LinkState};
@@ -22,7 +25,8 @@ use pipewire::spa::pod::{object, Pod, Property, PropertyFlags, Value, ValueArray
 use pipewire::spa::sys::{
     spa_process_latency_build, spa_process_latency_info, SPA_FORMAT_AUDIO_position,
     SPA_PARAM_PORT_CONFIG_format, SPA_PARAM_PortConfig, SPA_PARAM_Props, SPA_PROP_channelVolumes,
-    SPA_TYPE_OBJECT_ParamProcessLatency, SPA_AUDIO_CHANNEL_FL, SPA_AUDIO_CHANNEL_FR,
+    SPA_PROP_params, SPA_TYPE_OBJECT_ParamProcessLatency, SPA_AUDIO_CHANNEL_FL,
+    SPA_AUDIO_CHANNEL_FR,
 };
 use pipewire::spa::utils::Direction;
 
@@ -32,48 +36,65 @@ use parking_lot::RwLock;
 use pipewire::spa::param::ParamType;
 use pipewire::spa::pod::serialize::{PodSerialize, PodSerializer};
 use pipewire::spa::utils;
-use pipewire::sys::pw_impl_link_destroy;
+use pipewire::sys::{pw_filter_update_properties, pw_impl_link_destroy};
 use pipewire::{context, main_loop};
 use std::cell::RefCell;
 use std::io::Cursor;
 use std::rc::Rc;
 use std::str::FromStr;
-use std::sync::mpsc;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 use strum::IntoEnumIterator;
 use ulid::Ulid;
 
 static SAMPLE_RATE: i32 = 48000;
 
+// Pipewire reports a lost connection as an EPIPE on the core
+const EPIPE: i32 = 32;
+
+// How often we check for a pending reconnect, and the bounds of the backoff between attempts
+const RECONNECT_CHECK: Duration = Duration::from_millis(250);
+const RECONNECT_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
 pub(crate) struct FilterData {
     pub callback: Box<dyn FilterHandler>,
 }
 
 struct PipewireManager {
     core: Core,
+    _core_listener: CoreListener,
     registry: PipewireRegistry,
 
     store: Rc<RefCell<Store>>,
     callback_tx: mpsc::Sender<PipewireReceiver>,
+    stats: Arc<PipewireStats>,
 }
 
 impl PipewireManager {
     pub fn new(
         core: Core,
+        core_listener: CoreListener,
         registry: Registry,
         callback_tx: mpsc::Sender<PipewireReceiver>,
+        stats: Arc<PipewireStats>,
     ) -> Self {
         let store = Rc::new(RefCell::new(Store::new(callback_tx.clone())));
         let registry = PipewireRegistry::new(registry, store.clone());
 
         Self {
             core,
+            _core_listener: core_listener,
             registry,
             store,
             callback_tx,
+            stats,
         }
     }
 
     pub fn create_node(&mut self, properties: NodeProperties) -> Result<()> {
+        let icon_name = properties.icon_name.as_deref().unwrap_or(&properties.app_id);
         let node_properties = &mut properties! {
             *FACTORY_NAME => "support.null-audio-sink",
             *NODE_NAME => properties.node_name.clone(),
@@ -84,19 +105,15 @@ impl PipewireManager {
             *PORT_MONITOR => "false",
 
             *APP_ICON_NAME => &*properties.app_id,
-            *MEDIA_ICON_NAME => &*properties.app_id,
-            *DEVICE_ICON_NAME => &*properties.app_id,
+            *MEDIA_ICON_NAME => icon_name,
+            *DEVICE_ICON_NAME => icon_name,
 
             //*APP_NAME => properties.app_name,
             *OBJECT_LINGER => match properties.linger {
                 true => "true",
                 false => "false"
             },
-            *MEDIA_CLASS => match properties.class {
-                MediaClass::Source => "Audio/Source/Virtual",
-                MediaClass::Duplex => "Audio/Duplex",
-                MediaClass::Sink => "Audio/Sink",
-            },
+            *MEDIA_CLASS => properties.media_class(),
 
             *AUDIO_CHANNELS => "2",
             *NODE_LATENCY => format!("{}/{}", properties.buffer, SAMPLE_RATE),
@@ -129,7 +146,27 @@ impl PipewireManager {
 
             // Keep the monitor as close to 'real-time' as possible
             "monitor.passthrough" => "true",
+
+            // Session manager policy, these stop WirePlumber from linking us to the default
+            // devices, or moving us around when things change
+            "node.dont-reconnect" => match properties.dont_reconnect {
+                true => "true",
+                false => "false"
+            },
+            "node.autoconnect" => match properties.autoconnect {
+                true => "true",
+                false => "false"
+            },
         };
+        if let Some(priority) = properties.priority_session {
+            node_properties.insert("priority.session", priority.to_string());
+        }
+        if let Some(priority) = properties.priority_driver {
+            node_properties.insert("priority.driver", priority.to_string());
+        }
+        if let Some(role) = &properties.media_role {
+            node_properties.insert("media.role", role.as_str());
+        }
 
         debug!(
             "[{}] Attempting to Create Device '{}'",
@@ -164,9 +201,9 @@ impl PipewireManager {
             .add_listener_local()
             .bound(move |id| {
                 debug!("[{}] Pipewire NodeID assigned: {}", proxy_id, id);
-                proxy_store
-                    .borrow_mut()
-                    .managed_node_set_pw_id(proxy_id, id);
+                if let Err(e) = proxy_store.borrow_mut().managed_node_set_pw_id(proxy_id, id) {
+                    warn!("[{}] Unable to set Node ID: {}", proxy_id, e);
+                }
             })
             .removed(|| {
                 debug!("Removed..");
@@ -180,6 +217,15 @@ impl PipewireManager {
         let listener = proxy
             .add_listener_local()
             .info(move |info| {
+                if info.change_mask().contains(NodeChangeMask::PROPS) {
+                    if let Some(props) = info.props() {
+                        let name = props.get(*NODE_NAME);
+                        let description = props.get(*NODE_DESCRIPTION);
+                        let mut store = listener_info_store.borrow_mut();
+                        store.managed_node_labels_changed(listener_id, name, description);
+                    }
+                }
+
                 // Check whether this is a PORT related message
                 if info.change_mask().contains(NodeChangeMask::INPUT_PORTS)
                     || info.change_mask().contains(NodeChangeMask::OUTPUT_PORTS)
@@ -190,9 +236,10 @@ impl PipewireManager {
                             "[{}] Ports have appeared, requesting configuration",
                             listener_id
                         );
-                        listener_info_store
-                            .borrow()
-                            .managed_node_request_ports(listener_id);
+                        let store = listener_info_store.borrow();
+                        if let Err(e) = store.managed_node_request_ports(listener_id) {
+                            warn!("[{}] Unable to request Ports: {}", listener_id, e);
+                        }
                     }
                 }
             })
@@ -223,19 +270,21 @@ impl PipewireManager {
                                         {
                                             let mut store = listener_param_store.borrow_mut();
                                             for (index, value) in array.iter().enumerate() {
+                                                let location = match value.0 {
+                                                    SPA_AUDIO_CHANNEL_FL => PortLocation::LEFT,
+                                                    SPA_AUDIO_CHANNEL_FR => PortLocation::RIGHT,
+                                                    _ => continue,
+                                                };
                                                 let index = index as u32;
-                                                if value.0 == SPA_AUDIO_CHANNEL_FL {
-                                                    store.managed_node_add_port(
-                                                        listener_id,
-                                                        PortLocation::LEFT,
-                                                        index,
-                                                    );
-                                                }
-                                                if value.0 == SPA_AUDIO_CHANNEL_FR {
-                                                    store.managed_node_add_port(
-                                                        listener_id,
-                                                        PortLocation::RIGHT,
-                                                        index,
+                                                let result = store.managed_node_add_port(
+                                                    listener_id,
+                                                    location,
+                                                    index,
+                                                );
+                                                if let Err(e) = result {
+                                                    warn!(
+                                                        "[{}] Unable to add Port: {}",
+                                                        listener_id, e
                                                     );
                                                 }
                                             }
@@ -253,14 +302,13 @@ impl PipewireManager {
                             if let Some(prop) = prop {
                                 if let Value::ValueArray(ValueArray::Float(value)) = &prop.value {
                                     // OK, so KDE and pwvucontrol use the highest value for their reference
-                                    let max = value
-                                        .iter()
-                                        .copied()
-                                        .max_by(|a, b| a.partial_cmp(b).unwrap())
-                                        .unwrap();
-
-                                    let volume = (max.cbrt() * 100.0).round() as u8;
-                                    listener_param_store.borrow_mut().on_volume_change(listener_id, volume);
+                                    let max = value.iter().copied().max_by(|a, b| a.total_cmp(b));
+                                    if let Some(max) = max {
+                                        let volume = (max.cbrt() * 100.0).round() as u8;
+                                        listener_param_store
+                                            .borrow_mut()
+                                            .on_volume_change(listener_id, volume);
+                                    }
                                 }
                             }
                         } else {
@@ -290,6 +338,7 @@ impl PipewireManager {
             ports_ready: false,
 
             ready_sender: Some(properties.ready_sender),
+            pending_labels: None,
         };
 
         self.store.borrow_mut().managed_node_add(store);
@@ -391,35 +440,62 @@ impl PipewireManager {
         let listener_output_ports = output_ports.clone();
         let listener_state_store = self.store.clone();
         let listener_id = props.filter_id;
+        let listener_stats = self.stats.clone();
         let listener = filter
             .add_local_listener_with_user_data(data_inner)
             .state_changed(move |filter, _data, old, _new| {
                 if old == FilterState::Connecting {
                     debug!("[{}] Filter Connected", listener_id);
-                    listener_state_store
-                        .borrow_mut()
-                        .managed_filter_set_pw_id(listener_id, filter.node_id());
+                    let mut store = listener_state_store.borrow_mut();
+                    if let Err(e) = store.managed_filter_set_pw_id(listener_id, filter.node_id()) {
+                        warn!("[{}] Unable to set Filter ID: {}", listener_id, e);
+                    }
                 }
             })
             .process(move |filter, data, position| {
+                let started = Instant::now();
                 let samples = position.clock.duration as u32;
                 //debug!("Rate: {:?}", position.clock.rate.denom);
 
+                // If a buffer isn't available (the port isn't linked yet), skip this cycle
+                let mut missing = false;
                 let mut input_list = vec![];
                 let mut output_list = vec![];
                 for input in listener_input_ports.borrow().iter() {
-                    let in_buffer = filter.get_dsp_buffer::<f32>(input, samples);
-                    input_list.push(in_buffer.unwrap());
+                    match filter.get_dsp_buffer::<f32>(input, samples) {
+                        Some(in_buffer) => input_list.push(in_buffer),
+                        None => missing = true,
+                    }
                 }
 
                 for output in listener_output_ports.borrow().iter() {
-                    let out_buffer = filter.get_dsp_buffer::<f32>(output, samples);
-                    output_list.push(out_buffer.unwrap());
+                    match filter.get_dsp_buffer::<f32>(output, samples) {
+                        Some(out_buffer) => output_list.push(out_buffer),
+                        None => missing = true,
+                    }
+                }
+
+                // Whatever was left in the outputs from the last cycle would otherwise be
+                // played again, so send silence instead
+                if missing {
+                    for output in output_list {
+                        output.fill(0.0);
+                    }
+                    return;
                 }
 
                 data.write()
                     .callback
                     .process_samples(input_list, output_list);
+
+                // If we've taken longer than the quantum, we're going to cause an xrun
+                let rate = position.clock.rate.denom as u64;
+                if rate > 0 {
+                    let budget = Duration::from_nanos(samples as u64 * 1_000_000_000 / rate);
+                    if started.elapsed() > budget {
+                        listener_stats.filter_overruns.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
             })
             .register()
             .map_err(|e| anyhow!("Unable to Register Filter: {:?}", e))?;
@@ -452,7 +528,7 @@ impl PipewireManager {
 
             id: props.filter_id,
             _listener: listener,
-            _filter: filter,
+            filter,
 
             port_map: enum_map! {
                 registry::Direction::In => input_port_map,
@@ -479,7 +555,7 @@ impl PipewireManager {
         // We need to grab the filter from the store, and pass the value set..
         self.store
             .borrow_mut()
-            .managed_filter_set_parameter(id, key, value);
+            .managed_filter_set_parameter(id, key, value)?;
         Ok(())
     }
 
@@ -537,6 +613,11 @@ impl PipewireManager {
         Ok(())
     }
 
+    pub fn dedupe_links(&mut self, source: LinkType, destination: LinkType) -> Result<()> {
+        self.store.borrow_mut().managed_link_dedupe(source, destination);
+        Ok(())
+    }
+
     pub fn remove_all_unmanaged_links(&mut self, node: u32) -> Result<()> {
         for (&id, link) in self.store.borrow().get_unmanaged_links() {
             if link.input_node == node || link.output_node == node {
@@ -547,6 +628,15 @@ impl PipewireManager {
         Ok(())
     }
 
+    pub fn remove_unmanaged_link(&mut self, id: u32) -> Result<()> {
+        // Our own links show up as unmanaged until Pipewire confirms them, so only destroy the
+        // link if it's still unmanaged now.
+        if self.store.borrow().get_unmanaged_links().contains_key(&id) {
+            self.registry.destroy_global(id);
+        }
+        Ok(())
+    }
+
     fn get_port(
         &mut self,
         link: LinkType,
@@ -557,25 +647,26 @@ impl PipewireManager {
         let mut store = self.store.borrow_mut();
         match link {
             LinkType::Node(id) => {
-                let node = store.managed_node_get(id).unwrap();
+                let node = store.managed_node_get(id).ok_or(PipewireError::NodeNotFound(id))?;
 
-                let id = node.pw_id.unwrap();
-                let port = node.port_map[location].unwrap();
+                let pw_id = node.pw_id.ok_or(PipewireError::NotReady(id))?;
+                let port = node.port_map[location].ok_or(PipewireError::NotReady(id))?;
 
-                Ok((id, port))
+                Ok((pw_id, port))
             }
             LinkType::Filter(id) => {
-                let filter = store.managed_filter_get(id).unwrap();
+                let filter =
+                    store.managed_filter_get(id).ok_or(PipewireError::FilterNotFound(id))?;
 
-                let id = filter.pw_id.unwrap();
+                let pw_id = filter.pw_id.ok_or(PipewireError::NotReady(id))?;
                 let port = filter.port_map[direction][location];
 
-                Ok((id, port))
+                Ok((pw_id, port))
             }
             LinkType::UnmanagedNode(id) => {
                 let node = store
                     .unmanaged_device_node_get(id)
-                    .ok_or_else(|| anyhow!("Unmanaged Device Node not Found"))?;
+                    .ok_or(PipewireError::UnmanagedNodeNotFound(id))?;
 
                 let ports = &node.ports[direction];
 
@@ -595,8 +686,8 @@ impl PipewireManager {
                     }
                 }
 
-                // If we get here, we didn't find anything, this shouldn't happen!
-                bail!("Requested Unmanaged Node is Neither Stereo or Mono");
+                // If we get here, the node is neither Stereo nor Mono
+                bail!(PipewireError::PortNotFound(id));
             }
         }
     }
@@ -651,53 +742,146 @@ impl PipewireManager {
     fn set_node_volume(&mut self, id: Ulid, volume: u8) -> Result<()> {
         self.store.borrow_mut().set_volume(id, volume)
     }
+
+    fn set_application_volume(&mut self, id: u32, volume: u8) -> Result<()> {
+        self.store.borrow_mut().set_application_volume(id, volume)
+    }
+
+    fn get_graph(&self, sender: Sender<GraphSnapshot>) -> Result<()> {
+        let _ = sender.send(self.store.borrow().graph_snapshot());
+        Ok(())
+    }
+
+    fn update_node_properties(
+        &mut self,
+        id: Ulid,
+        labels: NodeLabels,
+        applied: Option<Sender<()>>,
+    ) -> Result<()> {
+        let mut store = self.store.borrow_mut();
+
+        // Our nodes are created by Pipewire's adapter factory, so live in the Pipewire daemon,
+        // the only way to reach their properties is to pass them as params through Props.
+        // Filters belong to us, so can be updated directly.
+        if let Some(node) = store.managed_node_get(id) {
+            // The node's name is its nick, so that needs to follow as well
+            let params = vec![
+                Value::String(NODE_NAME.to_string()),
+                Value::String(labels.nick.clone()),
+                Value::String(NODE_NICK.to_string()),
+                Value::String(labels.nick.clone()),
+                Value::String(NODE_DESCRIPTION.to_string()),
+                Value::String(labels.description.clone()),
+            ];
+            let pod = Value::Object(object! {
+                utils::SpaTypes::ObjectParamProps,
+                ParamType::Props,
+                Property::new(SPA_PROP_params, Value::Struct(params)),
+            });
+
+            let (cursor, _) = PodSerializer::serialize(Cursor::new(Vec::new()), &pod)
+                .map_err(|_| PipewireError::ReadOnly(id))?;
+            let bytes = cursor.into_inner();
+            let pod = Pod::from_bytes(&bytes).ok_or(PipewireError::ReadOnly(id))?;
+
+            debug!("[{}] Updating Node Properties", id);
+            node.proxy.set_param(ParamType::Props, 0, pod);
+
+            // Pipewire doesn't tell us if the params were ignored, so the only confirmation is the
+            // node's info coming back with the new labels
+            store.managed_node_await_labels(id, labels, applied)?;
+            return Ok(());
+        }
+
+        let filter = store.managed_filter_get(id).ok_or(PipewireError::FilterNotFound(id))?;
+        let properties = properties! {
+            *NODE_NICK => labels.nick,
+            *NODE_DESCRIPTION => labels.description,
+        };
+
+        debug!("[{}] Updating Filter Properties", id);
+        let result = unsafe {
+            pw_filter_update_properties(
+                filter.filter.as_raw_ptr(),
+                std::ptr::null_mut(),
+                properties.dict().as_raw(),
+            )
+        };
+        if result < 0 {
+            bail!("Unable to update Filter Properties: {}", result);
+        }
+        Ok(())
+    }
+
+    fn set_default_device(&mut self, device: DefaultDevice, id: Ulid) -> Result<()> {
+        self.store.borrow_mut().default_device_set(device, id)
+    }
+
+    fn clear_default_device(&mut self, device: DefaultDevice) -> Result<()> {
+        self.store.borrow_mut().default_device_clear(device);
+        Ok(())
+    }
 }
 
 pub fn run_pw_main_loop(
     pw_rx: PWReceiver,
     start_tx: oneshot::Sender<anyhow::Result<()>>,
     callback_tx: mpsc::Sender<PipewireReceiver>,
+    stats: Arc<PipewireStats>,
 ) {
     debug!("Initialising Pipewire..");
 
     let Ok(mainloop) = main_loop::MainLoop::new(None) else {
-        start_tx
-            .send(Err(anyhow!("Unable to create MainLoop")))
-            .expect("OneShot Channel is broken!");
+        let _ = start_tx.send(Err(anyhow!("Unable to create MainLoop")));
         return;
     };
     let Ok(context) = context::Context::new(&mainloop) else {
-        start_tx
-            .send(Err(anyhow!("Unable to create Context")))
-            .expect("OneShot Channel is broken!");
+        let _ = start_tx.send(Err(anyhow!("Unable to create Context")));
         return;
     };
 
-    // Wrap the mainloop so we shuffle it around
+    // Wrap the mainloop and context so we can shuffle them around
     let mainloop = Rc::new(mainloop);
-
-    // Now we create a core, and flag it as a manager
-    let Ok(core) = context.connect(Some(properties!(
-        *MEDIA_CATEGORY => "Manager",
-    ))) else {
-        start_tx
-            .send(Err(anyhow!("Unable to Fetch Core from Context")))
-            .expect("OneShot Channel is broken!");
-        return;
+    let context = Rc::new(context);
+
+    let reconnect = Rc::new(RefCell::new(Reconnect::default()));
+    let connection = connect(&context, &callback_tx, &stats, &reconnect);
+    let manager = match connection {
+        Ok(manager) => Rc::new(RefCell::new(Some(manager))),
+        Err(e) => {
+            let _ = start_tx.send(Err(e));
+            return;
+        }
     };
 
-    let Ok(registry) = core.get_registry() else {
-        start_tx
-            .send(Err(anyhow!("Unable to Fetch Registry from Core")))
-            .expect("OneShot Channel is broken!");
-        return;
-    };
+    // If we lose Pipewire, this periodically attempts to reconnect until it comes back
+    let timer_manager = manager.clone();
+    let timer_context = context.clone();
+    let timer = mainloop.loop_().add_timer(move |_| {
+        let due = matches!(reconnect.borrow().next_attempt, Some(at) if at <= Instant::now());
+        if !due {
+            return;
+        }
 
-    let manager = Rc::new(RefCell::new(PipewireManager::new(
-        core,
-        registry,
-        callback_tx,
-    )));
+        // Drop everything attached to the old core before we try again
+        timer_manager.borrow_mut().take();
+
+        match connect(&timer_context, &callback_tx, &stats, &reconnect) {
+            Ok(manager) => {
+                info!("[PIPEWIRE] Reconnected to Pipewire");
+                timer_manager.borrow_mut().replace(manager);
+                *reconnect.borrow_mut() = Reconnect::default();
+                let _ = callback_tx.send(PipewireReceiver::Reconnected);
+            }
+            Err(e) => {
+                let mut reconnect = reconnect.borrow_mut();
+                reconnect.backoff = (reconnect.backoff * 2).min(RECONNECT_MAX);
+                reconnect.next_attempt = Some(Instant::now() + reconnect.backoff);
+                debug!("[PIPEWIRE] Reconnect Failed, retrying in {:?}: {}", reconnect.backoff, e);
+            }
+        }
+    });
+    let _ = timer.update_timer(Some(RECONNECT_CHECK), Some(RECONNECT_CHECK));
 
     let receiver_clone = mainloop.clone();
     let _receiver = pw_rx.attach(mainloop.loop_(), {
@@ -708,47 +892,142 @@ pub fn run_pw_main_loop(
                 receiver_clone.quit();
             }
             PipewireInternalMessage::CreateDeviceNode(props, result) => {
-                let _ = result.send(manager.borrow_mut().create_node(props));
+                let _ = result.send(connected(&manager, |m| m.create_node(props)));
             }
             PipewireInternalMessage::CreateFilterNode(props, result) => {
-                let _ = result.send(manager.borrow_mut().create_filter(props));
+                let _ = result.send(connected(&manager, |m| m.create_filter(props)));
             }
             PipewireInternalMessage::CreateDeviceLink(source, destination, sender, result) => {
-                let _ = result.send(
-                    manager
-                        .borrow_mut()
-                        .create_link(source, destination, sender),
-                );
+                let _ = result
+                    .send(connected(&manager, |m| m.create_link(source, destination, sender)));
             }
 
             PipewireInternalMessage::RemoveDeviceNode(id, result) => {
-                let _ = result.send(manager.borrow_mut().remove_node(id));
+                let _ = result.send(connected(&manager, |m| m.remove_node(id)));
             }
 
             PipewireInternalMessage::RemoveDeviceLink(source, destination, result) => {
-                let _ = result.send(manager.borrow_mut().remove_link(source, destination));
+                let _ = result.send(connected(&manager, |m| m.remove_link(source, destination)));
+            }
+            PipewireInternalMessage::RemoveDuplicateLinks(source, destination, result) => {
+                let _ = result.send(connected(&manager, |m| m.dedupe_links(source, destination)));
             }
             PipewireInternalMessage::RemoveFilterNode(ulid, result) => {
-                let _ = result.send(manager.borrow_mut().remove_filter(ulid));
+                let _ = result.send(connected(&manager, |m| m.remove_filter(ulid)));
             }
 
             PipewireInternalMessage::DestroyUnmanagedLinks(id, result) => {
-                let _ = result.send(manager.borrow_mut().remove_all_unmanaged_links(id));
+                let _ = result.send(connected(&manager, |m| m.remove_all_unmanaged_links(id)));
+            }
+
+            PipewireInternalMessage::DestroyUnmanagedLink(id, result) => {
+                let _ = result.send(connected(&manager, |m| m.remove_unmanaged_link(id)));
             }
 
             PipewireInternalMessage::SetFilterValue(id, key, value, result) => {
-                let _ = result.send(manager.borrow_mut().set_filter_value(id, key, value));
+                let _ = result.send(connected(&manager, |m| m.set_filter_value(id, key, value)));
             }
 
             PipewireInternalMessage::SetNodeVolume(id, volume, result) => {
-                let _ = result.send(manager.borrow_mut().set_node_volume(id, volume));
+                let _ = result.send(connected(&manager, |m| m.set_node_volume(id, volume)));
+            }
+
+            PipewireInternalMessage::SetApplicationVolume(id, volume, result) => {
+                let _ = result.send(connected(&manager, |m| m.set_application_volume(id, volume)));
+            }
+
+            PipewireInternalMessage::GetGraph(graph, result) => {
+                let _ = result.send(connected(&manager, |m| m.get_graph(graph)));
+            }
+
+            PipewireInternalMessage::UpdateNodeProperties(id, labels, applied, result) => {
+                let update =
+                    |m: &mut PipewireManager| m.update_node_properties(id, labels, applied);
+                let _ = result.send(connected(&manager, update));
+            }
+
+            PipewireInternalMessage::SetDefaultDevice(device, id, result) => {
+                let _ = result.send(connected(&manager, |m| m.set_default_device(device, id)));
+            }
+            PipewireInternalMessage::ClearDefaultDevice(device, result) => {
+                let _ = result.send(connected(&manager, |m| m.clear_default_device(device)));
             }
         }
     });
 
     debug!("Pipewire Initialised, starting mainloop");
-    start_tx.send(Ok(())).expect("OneShot Channel is broken!");
+    if start_tx.send(Ok(())).is_err() {
+        // Whoever started us has gone away, so there's nobody to run for
+        return;
+    }
     mainloop.run();
 
+    // The core needs to go before the context does
+    manager.borrow_mut().take();
+
     info!("[PIPEWIRE] Main Loop Terminated");
 }
+
+/// Tracks when we should next try to reconnect to Pipewire, next_attempt is only set while
+/// we're disconnected.
+struct Reconnect {
+    next_attempt: Option<Instant>,
+    backoff: Duration,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self { next_attempt: None, backoff: RECONNECT_MIN }
+    }
+}
+
+fn connect(
+    context: &context::Context,
+    callback_tx: &mpsc::Sender<PipewireReceiver>,
+    stats: &Arc<PipewireStats>,
+    reconnect: &Rc<RefCell<Reconnect>>,
+) -> Result<PipewireManager> {
+    // Now we create a core, and flag it as a manager
+    let core = context
+        .connect(Some(properties!(
+            *MEDIA_CATEGORY => "Manager",
+        )))
+        .map_err(|e| anyhow!("Unable to Fetch Core from Context: {}", e))?;
+
+    let registry =
+        core.get_registry().map_err(|e| anyhow!("Unable to Fetch Registry from Core: {}", e))?;
+
+    // An EPIPE on the core itself means the Pipewire daemon has gone away
+    let error_reconnect = reconnect.clone();
+    let error_tx = callback_tx.clone();
+    let core_listener = core
+        .add_listener_local()
+        .error(move |id, _seq, res, message| {
+            if id != PW_ID_CORE || res != -EPIPE {
+                debug!("[PIPEWIRE] Error on {}: {} ({})", id, message, res);
+                return;
+            }
+
+            let mut reconnect = error_reconnect.borrow_mut();
+            if reconnect.next_attempt.is_none() {
+                warn!("[PIPEWIRE] Lost connection to Pipewire: {}", message);
+                reconnect.next_attempt = Some(Instant::now() + reconnect.backoff);
+                let _ = error_tx.send(PipewireReceiver::Disconnected);
+            }
+        })
+        .register();
+
+    let callback_tx = callback_tx.clone();
+    Ok(PipewireManager::new(core, core_listener, registry, callback_tx, stats.clone()))
+}
+
+// Runs a message against the manager, as long as we're currently connected to Pipewire
+fn connected<T>(
+    manager: &RefCell<Option<PipewireManager>>,
+    f: impl FnOnce(&mut PipewireManager) -> Result<T>,
+) -> Result<T> {
+    match manager.borrow_mut().as_mut() {
+        Some(manager) => f(manager),
+        None => bail!(PipewireError::Disconnected),
+    }
+}
@@ -1,17 +1,27 @@
-use crate::store::Store;
+use crate::store::{DefaultMetadata, Store};
 use anyhow::{anyhow, bail};
 use enum_map::{Enum, EnumMap};
+use log::warn;
 use pipewire::keys::{ACCESS, APP_NAME, AUDIO_CHANNEL, CLIENT_ID, DEVICE_DESCRIPTION, DEVICE_ID, DEVICE_NAME, DEVICE_NICK, FACTORY_NAME, FACTORY_TYPE_NAME, FACTORY_TYPE_VERSION, LINK_INPUT_NODE, LINK_INPUT_PORT, LINK_OUTPUT_NODE, LINK_OUTPUT_PORT, MODULE_ID, NODE_DESCRIPTION, NODE_ID, NODE_NAME, NODE_NICK, PORT_DIRECTION, PORT_ID, PORT_MONITOR, PORT_NAME, PROTOCOL, SEC_GID, SEC_PID, SEC_UID};
+use pipewire::core::PW_ID_CORE;
+use pipewire::metadata::Metadata;
+use pipewire::node::{Node, NodeListener};
 use pipewire::registry::Listener;
 use pipewire::registry::Registry;
+use pipewire::spa::param::ParamType;
+use pipewire::spa::pod::deserialize::PodDeserializer;
+use pipewire::spa::pod::{Value, ValueArray};
+use pipewire::spa::sys::{SPA_PARAM_Props, SPA_PROP_channelVolumes};
 use pipewire::spa::utils::dict::DictRef;
 use pipewire::types::ObjectType;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
 
 pub(crate) struct PipewireRegistry {
-    registry: Registry,
+    // Shared with the listener, which needs it to bind the 'default' metadata
+    registry: Rc<Registry>,
     store: Rc<RefCell<Store>>,
 
     // These two need to exist, if the Listeners are dropped they simply stop working.
@@ -22,7 +32,7 @@ pub(crate) struct PipewireRegistry {
 impl PipewireRegistry {
     pub fn new(registry: Registry, store: Rc<RefCell<Store>>) -> Self {
         let mut registry = Self {
-            registry,
+            registry: Rc::new(registry),
             store,
             registry_listener: None,
             registry_removal_listener: None,
@@ -36,6 +46,9 @@ impl PipewireRegistry {
 
     pub fn register_listener(&self) -> Listener {
         let store = self.store.clone();
+        let metadata_store = self.store.clone();
+        let node_store = self.store.clone();
+        let registry = self.registry.clone();
         self.registry
             .add_listener_local()
             .global(
@@ -59,9 +72,19 @@ impl PipewireRegistry {
                                         store.unmanaged_device_node_add(id, node);
                                     }
                                 }
-                                if let Ok(node) = RegistryClientNode::try_from(props) {
+                                if let Ok(mut node) = RegistryClientNode::try_from(props) {
                                     if let Some(client) = store.unmanaged_client_get(node.parent_id) {
                                         client.add_node(id);
+
+                                        // Bind the node so we can follow, and change, its volume
+                                        match registry.bind::<Node, _>(global) {
+                                            Ok(proxy) => {
+                                                let store = node_store.clone();
+                                                let proxy = ClientNodeProxy::new(proxy, id, store);
+                                                node.proxy = Some(proxy);
+                                            }
+                                            Err(e) => warn!("Unable to bind Node {}: {}", id, e),
+                                        }
                                         store.unmanaged_client_node_add(id, node);
                                     }
                                 }
@@ -150,6 +173,39 @@ impl PipewireRegistry {
                                 }
                             }
                         }
+                        ObjectType::Metadata => {
+                            // The 'default' metadata holds the system default devices
+                            let name = global.props.and_then(|props| props.get("metadata.name"));
+                            if name != Some("default") {
+                                return;
+                            }
+
+                            let proxy = match registry.bind::<Metadata, _>(global) {
+                                Ok(proxy) => proxy,
+                                Err(e) => {
+                                    warn!("Unable to bind Default Metadata: {}", e);
+                                    return;
+                                }
+                            };
+
+                            let listener_store = metadata_store.clone();
+                            let listener = proxy
+                                .add_listener_local()
+                                .property(move |subject, key, _type, value| {
+                                    if subject == PW_ID_CORE {
+                                        let mut store = listener_store.borrow_mut();
+                                        store.default_metadata_changed(key, value);
+                                    }
+                                    0
+                                })
+                                .register();
+
+                            store.default_metadata_set(DefaultMetadata {
+                                pw_id: id,
+                                proxy,
+                                _listener: listener,
+                            });
+                        }
                         // ObjectType::ClientEndpoint => {}
                         // ObjectType::ClientNode => {}
                         // ObjectType::ClientSession => {}
@@ -157,7 +213,6 @@ impl PipewireRegistry {
                         // ObjectType::Endpoint => {}
                         // ObjectType::EndpointLink => {}
                         // ObjectType::EndpointStream => {}
-                        // ObjectType::Metadata => {}
                         // ObjectType::Module => {}
                         // ObjectType::Profiler => {}
                         // ObjectType::Registry => {}
@@ -221,6 +276,13 @@ pub(crate) struct RegistryDevice {
     description: Option<String>,
     name: Option<String>,
 
+    // These are used to identify the hardware, if the node name changes
+    pub(crate) bus_path: Option<String>,
+    pub(crate) serial: Option<String>,
+    pub(crate) card_name: Option<String>,
+    pub(crate) vendor_id: Option<String>,
+    pub(crate) product_id: Option<String>,
+
     pub(crate) nodes: Vec<u32>,
 }
 
@@ -230,10 +292,21 @@ impl From<&DictRef> for RegistryDevice {
         let description = value.get(*DEVICE_DESCRIPTION).map(|s| s.to_string());
         let name = value.get(*DEVICE_NAME).map(|s| s.to_string());
 
+        let bus_path = value.get("device.bus-path").map(|s| s.to_string());
+        let serial = value.get("device.serial").map(|s| s.to_string());
+        let card_name = value.get("api.alsa.card.name").map(|s| s.to_string());
+        let vendor_id = value.get("device.vendor.id").map(|s| s.to_string());
+        let product_id = value.get("device.product.id").map(|s| s.to_string());
+
         Self {
             nickname,
             description,
             name,
+            bus_path,
+            serial,
+            card_name,
+            vendor_id,
+            product_id,
             nodes: vec![],
         }
     }
@@ -259,6 +332,10 @@ pub(crate) struct RegistryDeviceNode {
     pub description: Option<String>,
     pub name: Option<String>,
 
+    // The card name is also on the device, but not every backend sets it there
+    pub card_name: Option<String>,
+    pub profile_name: Option<String>,
+
     pub ports: EnumMap<Direction, HashMap<u32, RegistryPort>>,
 }
 
@@ -270,6 +347,8 @@ impl TryFrom<&DictRef> for RegistryDeviceNode {
         let nickname = value.get(*NODE_NICK).map(|s| s.to_string());
         let description = value.get(*NODE_DESCRIPTION).map(|s| s.to_string());
         let name = value.get(*NODE_NAME).map(|s| s.to_string());
+        let card_name = value.get("api.alsa.card.name").map(|s| s.to_string());
+        let profile_name = value.get("device.profile.name").map(|s| s.to_string());
 
         if let Some(device_id) = device.and_then(|s| s.parse::<u32>().ok()) {
             return Ok(Self {
@@ -277,6 +356,8 @@ impl TryFrom<&DictRef> for RegistryDeviceNode {
                 nickname,
                 description,
                 name,
+                card_name,
+                profile_name,
                 ports: Default::default(),
             });
         }
@@ -288,6 +369,14 @@ impl RegistryDeviceNode {
     pub(crate) fn add_port(&mut self, id: u32, direction: Direction, port: RegistryPort) {
         self.ports[direction].insert(id, port);
     }
+
+    /// The names of the node's ports (excluding monitors), sorted so they compare consistently
+    pub(crate) fn port_names(&self) -> Vec<String> {
+        let ports = self.ports.values().flat_map(|ports| ports.values());
+        let mut names: Vec<_> = ports.filter(|p| !p.is_monitor).map(|p| p.name.clone()).collect();
+        names.sort();
+        names
+    }
 }
 
 #[derive(Debug)]
@@ -365,6 +454,9 @@ pub(crate) struct RegistryClientNode {
     pub(crate) node_name: String,
 
     pub ports: EnumMap<Direction, HashMap<u32, RegistryPort>>,
+
+    pub(crate) proxy: Option<ClientNodeProxy>,
+    pub(crate) volume: Option<u8>,
 }
 
 impl TryFrom<&DictRef> for RegistryClientNode {
@@ -381,6 +473,9 @@ impl TryFrom<&DictRef> for RegistryClientNode {
             node_name,
 
             ports: Default::default(),
+
+            proxy: None,
+            volume: None,
         })
     }
 }
@@ -391,6 +486,49 @@ impl RegistryClientNode {
     }
 }
 
+/// A bound application node, the listener reports volume changes back to the store
+pub(crate) struct ClientNodeProxy {
+    pub(crate) node: Node,
+    _listener: NodeListener,
+}
+
+impl ClientNodeProxy {
+    fn new(node: Node, id: u32, store: Rc<RefCell<Store>>) -> Self {
+        let listener = node
+            .add_listener_local()
+            .param(move |_seq, _type, _index, _next, param| {
+                let Some(pod) = param else {
+                    return;
+                };
+                let pod = PodDeserializer::deserialize_any_from(pod.as_bytes()).map(|(_, v)| v);
+                let Ok(Value::Object(object)) = pod else {
+                    return;
+                };
+                if object.id != SPA_PARAM_Props {
+                    return;
+                }
+
+                let prop = object.properties.iter().find(|p| p.key == SPA_PROP_channelVolumes);
+                if let Some(Value::ValueArray(ValueArray::Float(value))) = prop.map(|p| &p.value) {
+                    // As with our own nodes, the highest channel is the reference
+                    if let Some(max) = value.iter().copied().max_by(|a, b| a.total_cmp(b)) {
+                        let volume = (max.cbrt() * 100.0).round() as u8;
+                        store.borrow_mut().on_application_volume_change(id, volume);
+                    }
+                }
+            })
+            .register();
+        node.subscribe_params(&[ParamType::Props]);
+
+        Self { node, _listener: listener }
+    }
+}
+
+impl Debug for ClientNodeProxy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ClientNodeProxy")
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct RegistryLink {
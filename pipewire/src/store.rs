@@ -3,7 +3,11 @@ use crate::registry::{
     Direction, RegistryClient, RegistryClientNode, RegistryDevice, RegistryDeviceNode,
     RegistryFactory, RegistryLink,
 };
-use crate::{ApplicationNode, DeviceNode, FilterValue, LinkType, MediaClass, PipewireReceiver};
+use crate::{
+    ApplicationNode, DefaultDevice, DeviceIdentity, DeviceNode, FilterSnapshot, FilterValue,
+    GraphSnapshot, LinkSnapshot, LinkType, MediaClass, NodeLabels, NodeSnapshot, PipewireError,
+    PipewireReceiver, UnmanagedLinkSnapshot,
+};
 use anyhow::Result;
 use anyhow::{anyhow, bail};
 use enum_map::{Enum, EnumMap};
@@ -11,7 +15,9 @@ use log::{debug, error};
 use oneshot::Sender;
 use parking_lot::RwLock;
 use pipewire::filter::{Filter, FilterListener, FilterPort};
+use pipewire::keys::NODE_NAME;
 use pipewire::link::{Link, LinkListener};
+use pipewire::metadata::{Metadata, MetadataListener};
 use pipewire::node::{Node, NodeListener};
 use pipewire::properties::{properties, Properties};
 use pipewire::proxy::ProxyListener;
@@ -55,6 +61,10 @@ pub struct Store {
     usable_device_nodes: Vec<u32>,
     usable_client_nodes: Vec<u32>,
 
+    // Pipewire's 'default' metadata, and any defaults requested before it appeared
+    default_metadata: Option<DefaultMetadata>,
+    pending_defaults: HashMap<DefaultDevice, String>,
+
     callback_tx: mpsc::Sender<PipewireReceiver>,
 }
 
@@ -78,6 +88,9 @@ impl Store {
             usable_device_nodes: vec![],
             usable_client_nodes: vec![],
 
+            default_metadata: None,
+            pending_defaults: HashMap::new(),
+
             callback_tx,
         }
     }
@@ -117,44 +130,86 @@ impl Store {
         }
     }
 
-    pub fn managed_node_set_pw_id(&mut self, id: Ulid, pw_id: u32) {
-        let node = self.managed_nodes.get_mut(&id).expect("Broke");
+    // These are called from Pipewire listeners, which can fire after a node has been removed
+    fn managed_node_get_mut(&mut self, id: Ulid) -> Result<&mut NodeStore, PipewireError> {
+        self.managed_nodes.get_mut(&id).ok_or(PipewireError::NodeNotFound(id))
+    }
+
+    pub fn managed_node_set_pw_id(&mut self, id: Ulid, pw_id: u32) -> Result<(), PipewireError> {
+        let node = self.managed_node_get_mut(id)?;
         node.pw_id.replace(pw_id);
 
-        self.managed_node_check_ready(id);
+        self.managed_node_check_ready(id)
+    }
+
+    pub fn managed_node_await_labels(
+        &mut self,
+        id: Ulid,
+        labels: NodeLabels,
+        sender: Option<Sender<()>>,
+    ) -> Result<(), PipewireError> {
+        // Replacing an earlier request drops its sender, so whoever was waiting on it gives up
+        let node = self.managed_node_get_mut(id)?;
+        node.pending_labels = Some((labels, sender));
+        Ok(())
+    }
+
+    pub fn managed_node_labels_changed(
+        &mut self,
+        id: Ulid,
+        name: Option<&str>,
+        description: Option<&str>,
+    ) {
+        let Ok(node) = self.managed_node_get_mut(id) else {
+            return;
+        };
+        let applied = node.pending_labels.as_ref().is_some_and(|(labels, _)| {
+            name == Some(labels.nick.as_str()) && description == Some(labels.description.as_str())
+        });
+        if !applied {
+            return;
+        }
+
+        debug!("[{}] Node Properties Applied", id);
+        if let Some((_, Some(sender))) = node.pending_labels.take() {
+            let _ = sender.send(());
+        }
     }
 
-    pub fn managed_node_request_ports(&self, id: Ulid) {
-        let node = self.managed_nodes.get(&id).expect("Broke");
+    pub fn managed_node_request_ports(&self, id: Ulid) -> Result<(), PipewireError> {
+        let node = self.managed_nodes.get(&id).ok_or(PipewireError::NodeNotFound(id))?;
         node.proxy
             .enum_params(0, Some(ParamType::PortConfig), 0, u32::MAX);
+        Ok(())
     }
 
-    pub fn managed_node_add_port(&mut self, id: Ulid, location: PortLocation, port_id: u32) {
-        let node = self.managed_nodes.get_mut(&id).expect("Broke");
+    pub fn managed_node_add_port(
+        &mut self,
+        id: Ulid,
+        location: PortLocation,
+        port_id: u32,
+    ) -> Result<(), PipewireError> {
+        let node = self.managed_node_get_mut(id)?;
         node.port_map[location] = Some(port_id);
 
         for location in PortLocation::iter() {
             if node.port_map[location].is_none() {
-                return;
+                return Ok(());
             }
         }
 
         // If we get here, all our ports have been set, trigger the ready event
-        self.managed_node_ports_ready(id);
+        self.managed_node_ports_ready(id)
     }
 
-    pub fn managed_node_ports_ready(&mut self, id: Ulid) {
-        let node = self.managed_nodes.get_mut(&id).expect("Broke");
+    pub fn managed_node_ports_ready(&mut self, id: Ulid) -> Result<(), PipewireError> {
+        let node = self.managed_node_get_mut(id)?;
         node.ports_ready = true;
-        self.managed_node_check_ready(id);
+        self.managed_node_check_ready(id)
     }
 
-    pub fn managed_node_check_ready(&mut self, id: Ulid) {
-        let node = self
-            .managed_nodes
-            .get_mut(&id)
-            .expect("Attempted to lookup non-existing node!");
+    pub fn managed_node_check_ready(&mut self, id: Ulid) -> Result<(), PipewireError> {
+        let node = self.managed_node_get_mut(id)?;
 
         if node.ports_ready && node.pw_id.is_some() {
             if let Some(sender) = node.ready_sender.take() {
@@ -164,31 +219,35 @@ impl Store {
                 }
             }
         }
+        Ok(())
     }
 
     // ----- NODE VOLUMES -----
     pub fn set_volume(&mut self, id: Ulid, volume: u8) -> Result<()> {
-        let node = self.managed_nodes.get(&id).ok_or(anyhow!("Failed to find node"))?;
-
-        let volume = (volume as f32 / 100.0).powi(3);
-        let pod = Value::Object(object! {
-                    utils::SpaTypes::ObjectParamProps,
-                    ParamType::Props,
-                    Property::new(SPA_PROP_channelVolumes, Value::ValueArray(ValueArray::Float(vec![volume, volume]))),
-                });
-
-        let (cursor, _) = PodSerializer::serialize(Cursor::new(Vec::new()), &pod).unwrap();
-        let bytes = cursor.into_inner();
-        if let Some(bytes) = Pod::from_bytes(&bytes) {
-            node.proxy.set_param(ParamType::Props, 0, bytes);
-        }
-        Ok(())
+        let node = self.managed_nodes.get(&id).ok_or(PipewireError::NodeNotFound(id))?;
+        set_proxy_volume(&node.proxy, volume)
     }
 
     pub fn on_volume_change(&mut self, id: Ulid, volume: u8) {
         let _ = self.callback_tx.send(PipewireReceiver::NodeVolumeChanged(id, volume));
     }
 
+    pub fn set_application_volume(&mut self, id: u32, volume: u8) -> Result<()> {
+        let node = self.unmanaged_client_nodes.get(&id);
+        let proxy = node.and_then(|node| node.proxy.as_ref()).map(|proxy| &proxy.node);
+        set_proxy_volume(proxy.ok_or(PipewireError::UnmanagedNodeNotFound(id))?, volume)
+    }
+
+    pub fn on_application_volume_change(&mut self, id: u32, volume: u8) {
+        let Some(node) = self.unmanaged_client_nodes.get_mut(&id) else {
+            return;
+        };
+        node.volume = Some(volume);
+        if self.usable_client_nodes.contains(&id) {
+            let _ = self.callback_tx.send(PipewireReceiver::ApplicationVolumeChanged(id, volume));
+        }
+    }
+
     // ----- MANAGED FILTERS -----
     pub fn managed_filter_add(&mut self, filter: FilterStore) {
         debug!("[{}] Filter Added to Store", &filter.id);
@@ -204,18 +263,24 @@ impl Store {
         self.managed_filters.remove(&filter);
     }
 
-    pub fn managed_filter_set_pw_id(&mut self, id: Ulid, pw_id: u32) {
-        let filter = self.managed_filters.get_mut(&id).expect("Broke");
+    pub fn managed_filter_set_pw_id(&mut self, id: Ulid, pw_id: u32) -> Result<(), PipewireError> {
+        let filter = self.managed_filters.get_mut(&id).ok_or(PipewireError::FilterNotFound(id))?;
         filter.pw_id = Some(pw_id);
 
         if let Some(Some(sender)) = filter.ready_sender.take() {
             let _ = sender.send(());
         }
+        Ok(())
     }
 
-    pub fn managed_filter_set_parameter(&mut self, id: Ulid, key: u32, value: FilterValue) {
-        let filter = self.managed_filters.get_mut(&id).expect("Broke!");
-        filter.data.write().callback.set_property(key, value);
+    pub fn managed_filter_set_parameter(
+        &mut self,
+        id: Ulid,
+        key: u32,
+        value: FilterValue,
+    ) -> Result<(), PipewireError> {
+        let filter = self.managed_filters.get_mut(&id).ok_or(PipewireError::FilterNotFound(id))?;
+        filter.data.write().callback.set_property(key, value)
     }
 
     // ----- MANAGED LINKS -----
@@ -242,6 +307,23 @@ impl Store {
             .retain(|_, link| link.source != source || link.destination != destination)
     }
 
+    pub fn managed_link_dedupe(&mut self, source: LinkType, destination: LinkType) {
+        // Keep a copy whose ports have all come up if there is one, the rest get dropped
+        let ready = |link: &LinkStore| {
+            link.links.values().all(|port| port.as_ref().is_some_and(|p| p.pw_id.is_some()))
+        };
+        let keep = self
+            .managed_links
+            .iter()
+            .filter(|(_, link)| link.source == source && link.destination == destination)
+            .max_by_key(|(_, link)| ready(link))
+            .map(|(&id, _)| id);
+
+        self.managed_links.retain(|id, link| {
+            Some(*id) == keep || link.source != source || link.destination != destination
+        });
+    }
+
     pub fn managed_link_remove_for_type(&mut self, id: LinkType) {
         self.managed_links
             .retain(|_, link| link.source != id && link.destination != id);
@@ -285,8 +367,48 @@ impl Store {
             }
 
             // Ok, we get here, we're ready
-            let sender = link.ready_sender.take();
-            let _ = sender.unwrap().send(());
+            if let Some(sender) = link.ready_sender.take() {
+                let _ = sender.send(());
+            }
+        }
+    }
+
+    pub fn graph_snapshot(&self) -> GraphSnapshot {
+        let nodes = self.managed_nodes.values().map(|node| NodeSnapshot {
+            id: node.id,
+            pw_id: node.pw_id,
+            ready: node.ports_ready && node.pw_id.is_some(),
+        });
+
+        let filters = self
+            .managed_filters
+            .values()
+            .map(|filter| FilterSnapshot { id: filter.id, pw_id: filter.pw_id });
+
+        let links = self.managed_links.values().map(|link| {
+            let ports: Vec<Option<u32>> =
+                link.links.values().map(|port| port.as_ref().and_then(|p| p.pw_id)).collect();
+
+            LinkSnapshot {
+                source: link.source,
+                destination: link.destination,
+                pw_ids: ports.iter().flatten().copied().collect(),
+                ready: ports.iter().all(Option::is_some),
+            }
+        });
+
+        let unmanaged_links =
+            self.unmanaged_links.iter().map(|(&pw_id, link)| UnmanagedLinkSnapshot {
+                pw_id,
+                output_node: link.output_node,
+                input_node: link.input_node,
+            });
+
+        GraphSnapshot {
+            nodes: nodes.collect(),
+            filters: filters.collect(),
+            links: links.collect(),
+            unmanaged_links: unmanaged_links.collect(),
         }
     }
 
@@ -339,12 +461,27 @@ impl Store {
             if let Some(media_type) = is_usable {
                 if !self.usable_device_nodes.contains(&id) {
                     self.usable_device_nodes.push(id);
+
+                    // Most of the identifying properties are on the parent device
+                    let device = self.unmanaged_devices.get(&node.parent_id);
+                    let card_name = device.and_then(|d| d.card_name.clone());
+                    let identity = DeviceIdentity {
+                        bus_path: device.and_then(|d| d.bus_path.clone()),
+                        serial: device.and_then(|d| d.serial.clone()),
+                        card_name: node.card_name.clone().or(card_name),
+                        vendor_id: device.and_then(|d| d.vendor_id.clone()),
+                        product_id: device.and_then(|d| d.product_id.clone()),
+                        profile_name: node.profile_name.clone(),
+                        port_names: node.port_names(),
+                    };
+
                     let node = DeviceNode {
                         node_id: id,
                         node_class: media_type,
                         name: node.name.clone(),
                         nickname: node.nickname.clone(),
                         description: node.description.clone(),
+                        identity,
                     };
 
                     let _ = self.callback_tx.send(PipewireReceiver::DeviceAdded(node));
@@ -437,6 +574,7 @@ impl Store {
                         node_id: id,
                         node_class: media_type,
                         name: node.application_name.clone(),
+                        volume: node.volume,
                     };
 
                     let _ = self
@@ -495,9 +633,17 @@ impl Store {
     // ----- UNMANAGED LINKS -----
     pub fn unmanaged_link_add(&mut self, id: u32, link: RegistryLink) {
         // Check our Managed Links to see if this is actually unmanaged
-        if self.is_managed_link(id).is_none() {
-            self.unmanaged_links.insert(id, link);
+        if self.is_managed_link(id).is_some() {
+            return;
+        }
+
+        // Let the daemon know if this touches one of our nodes, so it can apply its policy
+        for node in self.managed_nodes.values() {
+            if node.pw_id.is_some_and(|pw| pw == link.input_node || pw == link.output_node) {
+                let _ = self.callback_tx.send(PipewireReceiver::UnmanagedLinkAdded(id, node.id));
+            }
         }
+        self.unmanaged_links.insert(id, link);
     }
 
     pub fn unmanaged_link_remove(&mut self, id: u32) {
@@ -508,6 +654,56 @@ impl Store {
         &self.unmanaged_links
     }
 
+    // ----- DEFAULT DEVICES -----
+    pub fn default_metadata_set(&mut self, metadata: DefaultMetadata) {
+        debug!("Default Metadata Found: {}", metadata.pw_id);
+        for (device, name) in self.pending_defaults.drain() {
+            write_default(&metadata.proxy, device, &name);
+        }
+        self.default_metadata = Some(metadata);
+    }
+
+    pub fn default_device_set(&mut self, device: DefaultDevice, id: Ulid) -> Result<()> {
+        let node = self.managed_nodes.get(&id).ok_or(PipewireError::NodeNotFound(id))?;
+        let name = node.props.get(*NODE_NAME).ok_or(PipewireError::NotReady(id))?.to_string();
+
+        // The metadata arrives with the rest of the registry, so may not be here yet on startup
+        match &self.default_metadata {
+            Some(metadata) => write_default(&metadata.proxy, device, &name),
+            None => {
+                self.pending_defaults.insert(device, name);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn default_device_clear(&mut self, device: DefaultDevice) {
+        self.pending_defaults.remove(&device);
+        if let Some(metadata) = &self.default_metadata {
+            debug!("Clearing {}", device.configured_key());
+            metadata.proxy.set_property(0, device.configured_key(), None, None);
+        }
+    }
+
+    pub fn default_metadata_changed(&mut self, key: Option<&str>, value: Option<&str>) {
+        // A missing key means every property was cleared
+        let devices = match key {
+            Some(key) => DefaultDevice::from_key(key).into_iter().collect(),
+            None => vec![DefaultDevice::Sink, DefaultDevice::Source],
+        };
+
+        let name = value.and_then(default_node_name);
+        let id = name.and_then(|name| {
+            let name = Some(name.as_str());
+            let node = self.managed_nodes.values().find(|n| n.props.get(*NODE_NAME) == name);
+            node.map(|node| node.id)
+        });
+
+        for device in devices {
+            let _ = self.callback_tx.send(PipewireReceiver::DefaultDeviceChanged(device, id));
+        }
+    }
+
     // ----- REMOVE HANDLER -----
     // PipeWire doesn't inform us of the type which is being removed, just the ID, so we need
     // to go through our stored data, find the corresponding item, and handle it.
@@ -532,6 +728,11 @@ impl Store {
             return self.unmanaged_link_remove(id);
         }
 
+        if self.default_metadata.as_ref().is_some_and(|metadata| metadata.pw_id == id) {
+            self.default_metadata = None;
+            return;
+        }
+
         // Something may be trying to mess with a managed link, if so, completely drop our links
         // and report back to whatever is calling us that it's happened, so they can action it.
         if let Some(id) = self.is_managed_link(id) {
@@ -576,6 +777,9 @@ pub(crate) struct NodeStore {
     pub(crate) ports_ready: bool,
 
     pub(crate) ready_sender: Option<Option<Sender<()>>>,
+
+    /// Labels we've asked Pipewire to apply, and who to tell once they show up in the node's info
+    pub(crate) pending_labels: Option<(NodeLabels, Option<Sender<()>>)>,
 }
 
 pub struct FilterStore {
@@ -594,9 +798,9 @@ pub struct FilterStore {
     pub(crate) input_ports: Rc<RefCell<Vec<FilterPort>>>,
     pub(crate) output_ports: Rc<RefCell<Vec<FilterPort>>>,
 
-    /// These two fields need to exist purely to prevent the filter and the listener from
-    /// being dropped, they're never directly accessed, they're just a store.
-    pub(crate) _filter: Filter,
+    /// The filter needs to exist to prevent it from being dropped, it's only accessed directly
+    /// when updating its properties.
+    pub(crate) filter: Filter,
 
     /// The 'Ready Sender' is called once the filter is setup and ready-to-go
     pub(crate) ready_sender: Option<Option<Sender<()>>>,
@@ -629,6 +833,24 @@ pub struct LinkStoreMap {
     pub(crate) destination_port_id: u32,
 }
 
+pub(crate) struct DefaultMetadata {
+    pub(crate) pw_id: u32,
+    pub(crate) proxy: Metadata,
+    pub(crate) _listener: MetadataListener,
+}
+
+fn write_default(metadata: &Metadata, device: DefaultDevice, name: &str) {
+    debug!("Setting {} to {}", device.configured_key(), name);
+    let value = serde_json::json!({ "name": name }).to_string();
+    metadata.set_property(0, device.configured_key(), Some("Spa:String:JSON"), Some(&value));
+}
+
+// Values look like { "name": "alsa_output.pci-0000_00_1f.3.analog-stereo" }
+fn default_node_name(value: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(value).ok()?;
+    value["name"].as_str().map(String::from)
+}
+
 #[derive(Debug, Enum, EnumIter, Copy, Clone, PartialEq)]
 pub(crate) enum PortLocation {
     LEFT,
@@ -655,3 +877,23 @@ impl FromStr for PortLocation {
         }
     }
 }
+
+fn set_proxy_volume(proxy: &Node, volume: u8) -> Result<()> {
+    let volume = (volume as f32 / 100.0).powi(3);
+    let pod = Value::Object(object! {
+        utils::SpaTypes::ObjectParamProps,
+        ParamType::Props,
+        Property::new(
+            SPA_PROP_channelVolumes,
+            Value::ValueArray(ValueArray::Float(vec![volume, volume]))
+        ),
+    });
+
+    let (cursor, _) = PodSerializer::serialize(Cursor::new(Vec::new()), &pod)
+        .map_err(|e| anyhow!("Unable to serialise Volume: {:?}", e))?;
+    let bytes = cursor.into_inner();
+    if let Some(bytes) = Pod::from_bytes(&bytes) {
+        proxy.set_param(ParamType::Props, 0, bytes);
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+// These run against a throwaway Pipewire instance in a temporary runtime directory, so need the
+// `pipewire` binary to be available. They're ignored by default, run them with:
+//
+//   cargo test -p pipeweaver-pipewire -- --ignored
+
+use pipeweaver_pipewire::{
+    PipewireError, PipewireMessage, PipewireReceiver, PipewireRunner, PipewireStats,
+};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::{mpsc, Arc};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+use ulid::Ulid;
+
+struct TestPipewire {
+    child: Child,
+}
+
+impl TestPipewire {
+    fn start(runtime_dir: &Path) -> Self {
+        let child = Command::new("pipewire")
+            .env("PIPEWIRE_RUNTIME_DIR", runtime_dir)
+            .env("XDG_RUNTIME_DIR", runtime_dir)
+            .spawn()
+            .expect("Unable to start pipewire");
+
+        // Wait for the socket to appear before letting anything connect
+        let socket = runtime_dir.join("pipewire-0");
+        let started = Instant::now();
+        while !socket.exists() {
+            assert!(started.elapsed() < Duration::from_secs(5), "Pipewire didn't start");
+            sleep(Duration::from_millis(50));
+        }
+
+        Self { child }
+    }
+
+    fn stop(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for TestPipewire {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+fn runtime_dir() -> PathBuf {
+    let dir = env::temp_dir().join(format!("pipeweaver-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn wait_for(rx: &mpsc::Receiver<PipewireReceiver>, event: PipewireReceiver, timeout: Duration) {
+    let started = Instant::now();
+    while let Some(remaining) = timeout.checked_sub(started.elapsed()) {
+        match rx.recv_timeout(remaining) {
+            Ok(received) if received == event => return,
+            Ok(_) => continue,
+            Err(_) => break,
+        }
+    }
+    panic!("Timed out waiting for {:?}", event);
+}
+
+// A node we've never created should be reported as missing, rather than Pipewire being gone
+fn assert_connected(runner: &PipewireRunner) {
+    let id = Ulid::new();
+    let error = runner.send_message(PipewireMessage::SetNodeVolume(id, 50)).unwrap_err();
+    assert_eq!(error.downcast_ref::<PipewireError>(), Some(&PipewireError::NodeNotFound(id)));
+}
+
+#[test]
+#[ignore]
+fn reconnects_after_pipewire_restart() {
+    let dir = runtime_dir();
+    env::set_var("PIPEWIRE_RUNTIME_DIR", &dir);
+    env::set_var("XDG_RUNTIME_DIR", &dir);
+
+    let pipewire = TestPipewire::start(&dir);
+
+    let (tx, rx) = mpsc::channel();
+    let runner = PipewireRunner::new(tx, Arc::new(PipewireStats::default())).unwrap();
+    assert_connected(&runner);
+
+    pipewire.stop();
+    wait_for(&rx, PipewireReceiver::Disconnected, Duration::from_secs(5));
+
+    let pipewire = TestPipewire::start(&dir);
+    wait_for(&rx, PipewireReceiver::Reconnected, Duration::from_secs(10));
+    assert_connected(&runner);
+
+    drop(runner);
+    pipewire.stop();
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -1,7 +1,7 @@
 use crate::{
-    DeviceDescription, Devices, Mix, MuteState, MuteStates, PhysicalDeviceDescriptor,
-    PhysicalSourceDevice, PhysicalTargetDevice, Profile, SourceDevices, TargetDevices,
-    VirtualSourceDevice, VirtualTargetDevice, Volumes,
+    DeviceDescription, Devices, Mix, MuteState, MuteStates, NodeMetadata, NodePolicy,
+    PhysicalDeviceDescriptor, PhysicalSourceDevice, PhysicalTargetDevice, Profile, SourceDevices,
+    TargetDevices, VirtualSourceDevice, VirtualTargetDevice, Volumes,
 };
 use enum_map::enum_map;
 use pipeweaver_shared::{Colour, MuteTarget, OrderGroup};
@@ -51,10 +51,12 @@ impl Profile {
                                 PhysicalDeviceDescriptor {
                                     name: None,
                                     description: Some(String::from("BEACN Mic Microphone")),
+                                    identity: Default::default(),
                                 },
                                 PhysicalDeviceDescriptor {
                                     name: None,
                                     description: Some(String::from("Elgato XLR Dock Mono")),
+                                    identity: Default::default(),
                                 },
                             ],
                         },
@@ -84,6 +86,7 @@ impl Profile {
                                     "alsa_input.pci-0000_31_00.4.analog-stereo",
                                 )),
                                 description: None,
+                                identity: Default::default(),
                             }],
                         },
                     ],
@@ -98,6 +101,8 @@ impl Profile {
                                     blue: 30,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_states: MuteStates {
                                 mute_state: HashSet::new(),
                                 mute_targets: Default::default(),
@@ -120,6 +125,8 @@ impl Profile {
                                     blue: 93,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_states: MuteStates {
                                 mute_state: HashSet::new(),
                                 mute_targets: enum_map! {
@@ -147,6 +154,8 @@ impl Profile {
                                     blue: 182,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_states: MuteStates {
                                 mute_state: HashSet::new(),
                                 mute_targets: enum_map! {
@@ -176,6 +185,8 @@ impl Profile {
                                     blue: 130,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_states: MuteStates {
                                 mute_state: HashSet::new(),
                                 mute_targets: Default::default(),
@@ -198,6 +209,8 @@ impl Profile {
                                     blue: 48,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_states: MuteStates {
                                 mute_state: HashSet::new(),
                                 mute_targets: Default::default(),
@@ -238,14 +251,17 @@ impl Profile {
                             PhysicalDeviceDescriptor {
                                 name: None,
                                 description: Some(String::from("BEACN Mic Headphones")),
+                                identity: Default::default(),
                             },
                             PhysicalDeviceDescriptor {
                                 name: None,
                                 description: Some(String::from("GoXLR System")),
+                                identity: Default::default(),
                             },
                             PhysicalDeviceDescriptor {
                                 name: None,
                                 description: Some(String::from("Elgato XLR Dock Analog Stereo")),
+                                identity: Default::default(),
                             },
                         ],
                     }],
@@ -260,6 +276,8 @@ impl Profile {
                                     blue: 116,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_state: MuteState::Unmuted,
                             volume: 99,
                             mix: Mix::B,
@@ -274,6 +292,8 @@ impl Profile {
                                     blue: 92,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_state: MuteState::Unmuted,
                             volume: 99,
                             mix: Mix::B,
@@ -288,6 +308,8 @@ impl Profile {
                                     blue: 69,
                                 },
                             },
+                            policy: NodePolicy::default(),
+                            metadata: NodeMetadata::default(),
                             mute_state: MuteState::Unmuted,
                             volume: 99,
                             mix: Mix::A,
@@ -333,6 +355,9 @@ impl Profile {
             ]
                 .into_iter()
                 .collect(),
+            midi: Default::default(),
+            live: Default::default(),
+            defaults: Default::default(),
         }
     }
 }
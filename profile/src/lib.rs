@@ -2,19 +2,33 @@ mod default;
 
 use enum_map::{enum_map, EnumMap};
 use pipeweaver_shared::{Colour, Mix, MuteState, MuteTarget, OrderGroup};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use ulid::Ulid;
 
 /// Main Profile Node
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Profile {
     /// A list of devices currently configured in this profile
     pub devices: Devices,
+    #[schemars(with = "HashMap<String, HashSet<String>>")]
     pub routes: HashMap<Ulid, HashSet<Ulid>>,
+
+    /// MIDI Controller Configuration
+    #[serde(default)]
+    pub midi: MidiConfiguration,
+
+    /// Actions to perform when a source 'goes live'
+    #[serde(default)]
+    pub live: LiveConfiguration,
+
+    /// The nodes to set as the system defaults when the profile is loaded
+    #[serde(default)]
+    pub defaults: DefaultDevices,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Devices {
     /// Source devices (Devices that bring audio into the Mixer)
     pub sources: SourceDevices,
@@ -23,7 +37,7 @@ pub struct Devices {
     pub targets: TargetDevices,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SourceDevices {
     /// Sink Devices physically attached to Pipewire
     pub physical_devices: Vec<PhysicalSourceDevice>,
@@ -32,6 +46,7 @@ pub struct SourceDevices {
     pub virtual_devices: Vec<VirtualSourceDevice>,
 
     /// Device Orders
+    #[schemars(with = "HashMap<OrderGroup, Vec<String>>")]
     pub device_order: EnumMap<OrderGroup, Vec<Ulid>>,
 }
 
@@ -41,7 +56,7 @@ pub struct DeviceGroup {
     order: Vec<Ulid>,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TargetDevices {
     /// Source Devices attached to Pipewire
     pub physical_devices: Vec<PhysicalTargetDevice>,
@@ -50,37 +65,204 @@ pub struct TargetDevices {
     pub virtual_devices: Vec<VirtualTargetDevice>,
 
     /// Device Orders
+    #[schemars(with = "HashMap<OrderGroup, Vec<String>>")]
     pub device_order: EnumMap<OrderGroup, Vec<Ulid>>,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DeviceDescription {
+    #[schemars(with = "String")]
     pub id: Ulid,
     pub name: String,
 
     pub colour: Colour,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct VirtualSourceDevice {
     pub description: DeviceDescription,
+    #[serde(default)]
+    pub policy: NodePolicy,
+    #[serde(default)]
+    pub metadata: NodeMetadata,
     pub mute_states: MuteStates,
     pub volumes: Volumes,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+/// How the session manager (normally WirePlumber) should treat a virtual node. Nodes from
+/// profiles without a policy get the default, which sets dont_reconnect and disables autoconnect.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct NodePolicy {
+    /// Don't move streams on this node elsewhere when the device they're linked to goes away
+    pub dont_reconnect: bool,
+
+    /// Allow the session manager to link this node to the default device
+    pub autoconnect: bool,
+
+    /// Used by the session manager when choosing a default device, higher wins
+    pub priority_session: Option<i32>,
+
+    /// Used by Pipewire when choosing which node drives the graph, higher wins
+    pub priority_driver: Option<i32>,
+
+    /// Remove any link to this node which wasn't created by us, this includes applications
+    pub exclusive: bool,
+}
+
+impl Default for NodePolicy {
+    fn default() -> Self {
+        Self {
+            dont_reconnect: true,
+            autoconnect: false,
+            priority_session: None,
+            priority_driver: None,
+            exclusive: false,
+        }
+    }
+}
+
+/// How a virtual node is presented to applications and desktop sound settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct NodeMetadata {
+    /// The media.role of the node, such as "Music", "Game" or "Communication"
+    pub media_role: Option<String>,
+
+    /// An icon name from the desktop's icon theme, such as "audio-headset"
+    pub icon_name: Option<String>,
+
+    /// Translated descriptions keyed by locale (eg. "de_DE" or "de"), used in place of the
+    /// generated description when the daemon's locale matches
+    pub descriptions: HashMap<String, String>,
+
+    /// Only applies to Virtual Targets. When set the node is exposed as a microphone
+    /// (Audio/Source/Virtual), otherwise it uses an internal sink class (Audio/Sink/Internal),
+    /// which keeps it out of both the input and output device lists.
+    pub virtual_microphone: bool,
+}
+
+impl Default for NodeMetadata {
+    fn default() -> Self {
+        Self {
+            media_role: None,
+            icon_name: None,
+            descriptions: HashMap::new(),
+            virtual_microphone: true,
+        }
+    }
+}
+
+impl NodeMetadata {
+    /// Finds the description for a locale such as "de_DE.UTF-8", falling back to the language
+    pub fn description_for(&self, locale: &str) -> Option<&String> {
+        let locale = locale.split(['.', '@']).next().unwrap_or(locale);
+        let language = locale.split('_').next().unwrap_or(locale);
+
+        self.descriptions.get(locale).or_else(|| self.descriptions.get(language))
+    }
+}
+
+/// The system default devices, named from the point of view of applications
+#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DefaultDevices {
+    /// A Virtual Source, which applications will play into unless told otherwise
+    #[schemars(with = "Option<String>")]
+    pub sink: Option<Ulid>,
+
+    /// A Virtual Target, which applications will record from unless told otherwise
+    #[schemars(with = "Option<String>")]
+    pub source: Option<Ulid>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MuteStates {
     pub mute_state: HashSet<MuteTarget>,
+    #[schemars(with = "HashMap<MuteTarget, HashSet<String>>")]
     pub mute_targets: EnumMap<MuteTarget, HashSet<Ulid>>,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct PhysicalDeviceDescriptor {
     pub name: Option<String>,
     pub description: Option<String>,
+
+    /// Hardware properties used to find the device again if its name changes
+    #[serde(default)]
+    pub identity: DeviceIdentity,
+}
+
+/// Properties of a physical device which don't change when it's moved between USB ports
+/// (except the bus path), or when ALSA renames it. Any of these may be missing, depending on
+/// the device and the Pipewire backend.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct DeviceIdentity {
+    /// device.bus-path, the physical location the device is attached to
+    pub bus_path: Option<String>,
+
+    /// device.serial
+    pub serial: Option<String>,
+
+    /// api.alsa.card.name
+    pub card_name: Option<String>,
+
+    /// device.vendor.id and device.product.id
+    pub vendor_id: Option<String>,
+    pub product_id: Option<String>,
+
+    /// device.profile.name, this separates the nodes of a single device (eg. 'analog-stereo')
+    pub profile_name: Option<String>,
+
+    /// The node's port names (eg. 'capture_FL'), sorted, and empty if they weren't known
+    pub port_names: Vec<String>,
+}
+
+impl PhysicalDeviceDescriptor {
+    /// Scores how likely it is that a device is the one this descriptor was saved from, higher
+    /// is better, and None means it's definitely a different device.
+    pub fn match_score(&self, device: &PhysicalDeviceDescriptor) -> Option<u32> {
+        let (saved, live) = (&self.identity, &device.identity);
+
+        // Both sides need a value to compare, otherwise we can't tell either way
+        fn compare(a: &Option<String>, b: &Option<String>) -> Option<bool> {
+            Some(a.as_ref()? == b.as_ref()?)
+        }
+
+        // These identify a specific piece of hardware, so a mismatch rules it out
+        let serial = compare(&saved.serial, &live.serial);
+        let product = compare(&saved.vendor_id, &live.vendor_id)
+            .zip(compare(&saved.product_id, &live.product_id))
+            .map(|(vendor, product)| vendor && product);
+        if serial == Some(false) || product == Some(false) {
+            return None;
+        }
+
+        // Matching hardware alone isn't enough, as a single device can have several nodes
+        let name = compare(&self.name, &device.name);
+        let description = compare(&self.description, &device.description);
+        let profile = compare(&saved.profile_name, &live.profile_name);
+        let ports = (!saved.port_names.is_empty() && !live.port_names.is_empty())
+            .then(|| saved.port_names == live.port_names);
+        if ![name, description, profile].contains(&Some(true)) {
+            return None;
+        }
+
+        let weights = [
+            (serial, 100),
+            (name, 50),
+            (compare(&saved.bus_path, &live.bus_path), 30),
+            (product, 20),
+            (profile, 15),
+            (compare(&saved.card_name, &live.card_name), 10),
+            (ports, 5),
+            (description, 5),
+        ];
+        Some(weights.iter().filter(|(hit, _)| *hit == Some(true)).map(|(_, w)| w).sum())
+    }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PhysicalSourceDevice {
     pub description: DeviceDescription,
     pub mute_states: MuteStates,
@@ -89,16 +271,20 @@ pub struct PhysicalSourceDevice {
     pub attached_devices: Vec<PhysicalDeviceDescriptor>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct VirtualTargetDevice {
     pub description: DeviceDescription,
+    #[serde(default)]
+    pub policy: NodePolicy,
+    #[serde(default)]
+    pub metadata: NodeMetadata,
 
     pub mute_state: MuteState,
     pub volume: u8,
     pub mix: Mix,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PhysicalTargetDevice {
     pub description: DeviceDescription,
 
@@ -127,6 +313,8 @@ impl Default for VirtualTargetDevice {
     fn default() -> Self {
         Self {
             description: Default::default(),
+            policy: Default::default(),
+            metadata: Default::default(),
             volume: 100,
 
             mute_state: Default::default(),
@@ -135,8 +323,9 @@ impl Default for VirtualTargetDevice {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Volumes {
+    #[schemars(with = "HashMap<Mix, u8>")]
     pub volume: EnumMap<Mix, u8>,
     pub volumes_linked: Option<f32>,
 }
@@ -153,3 +342,149 @@ impl Default for Volumes {
     }
 }
 
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MidiConfiguration {
+    /// Hardware MIDI ports to attach to, matched against the port name. The virtual
+    /// PipeWeaver port is always available regardless of this list.
+    pub devices: Vec<String>,
+
+    /// Message -> Action Mappings
+    pub mappings: Vec<MidiMapping>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MidiMapping {
+    pub message: MidiMessage,
+    pub action: MidiAction,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum MidiMessage {
+    ControlChange { channel: u8, control: u8 },
+    Note { channel: u8, note: u8 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum MidiAction {
+    SourceVolume(#[schemars(with = "String")] Ulid, Mix),
+    TargetVolume(#[schemars(with = "String")] Ulid),
+    SourceMute(#[schemars(with = "String")] Ulid, MuteTarget),
+    TargetMute(#[schemars(with = "String")] Ulid),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LiveConfiguration {
+    /// What determines whether we're live, None disables live detection
+    pub trigger: Option<LiveTrigger>,
+
+    /// The actions to perform on going live, these are reversed when no longer live
+    pub actions: Vec<LiveAction>,
+
+    /// How long a meter has to stay below the threshold before we're no longer live
+    pub release_ms: u32,
+}
+
+impl Default for LiveConfiguration {
+    fn default() -> Self {
+        Self { trigger: None, actions: vec![], release_ms: 1000 }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum LiveTrigger {
+    /// Live when the source's meter reaches the threshold (0 - 100)
+    Meter {
+        #[schemars(with = "String")]
+        source: Ulid,
+        threshold: u8,
+    },
+
+    /// Live when the source is not muted to the Mute Target
+    Unmuted {
+        #[schemars(with = "String")]
+        source: Ulid,
+        target: MuteTarget,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum LiveAction {
+    /// Pause any playing MPRIS players whose bus name contains this value, and resume them after
+    Pause { player: String },
+
+    /// Limit the volume of an MPRIS player's stream while live, restoring it after. The player
+    /// is matched against application names in the same way as Pause.
+    Duck { player: String, volume: u8 },
+}
+
+impl LiveTrigger {
+    pub fn source(&self) -> Ulid {
+        match self {
+            LiveTrigger::Meter { source, .. } | LiveTrigger::Unmuted { source, .. } => *source,
+        }
+    }
+}
+
+impl MidiAction {
+    pub fn node_id(&self) -> Ulid {
+        match self {
+            MidiAction::SourceVolume(id, _) | MidiAction::SourceMute(id, _) => *id,
+            MidiAction::TargetVolume(id) | MidiAction::TargetMute(id) => *id,
+        }
+    }
+}
+
+impl Profile {
+    /// The descriptions of every node, sources first
+    pub fn descriptions(&self) -> impl Iterator<Item = &DeviceDescription> {
+        let sources = &self.devices.sources;
+        let targets = &self.devices.targets;
+
+        sources
+            .physical_devices
+            .iter()
+            .map(|d| &d.description)
+            .chain(sources.virtual_devices.iter().map(|d| &d.description))
+            .chain(targets.physical_devices.iter().map(|d| &d.description))
+            .chain(targets.virtual_devices.iter().map(|d| &d.description))
+    }
+
+    /// Locates a node by its ID, or failing that, by a case-insensitive match on its name
+    pub fn find_node(&self, name: &str) -> Option<Ulid> {
+        let id = name.parse::<Ulid>().ok();
+        let mut found = None;
+        for description in self.descriptions() {
+            if Some(description.id) == id {
+                return id;
+            }
+            if found.is_none() && description.name.eq_ignore_ascii_case(name) {
+                found = Some(description.id);
+            }
+        }
+        found
+    }
+
+    /// Returns the Volumes and Mute States of a Source node
+    pub fn get_source_state(&self, id: Ulid) -> Option<(&Volumes, &MuteStates)> {
+        let sources = &self.devices.sources;
+        if let Some(device) = sources.physical_devices.iter().find(|d| d.description.id == id) {
+            return Some((&device.volumes, &device.mute_states));
+        }
+        if let Some(device) = sources.virtual_devices.iter().find(|d| d.description.id == id) {
+            return Some((&device.volumes, &device.mute_states));
+        }
+        None
+    }
+
+    /// Returns the Volume and Mute State of a Target node
+    pub fn get_target_state(&self, id: Ulid) -> Option<(u8, MuteState)> {
+        let targets = &self.devices.targets;
+        if let Some(device) = targets.physical_devices.iter().find(|d| d.description.id == id) {
+            return Some((device.volume, device.mute_state));
+        }
+        if let Some(device) = targets.virtual_devices.iter().find(|d| d.description.id == id) {
+            return Some((device.volume, device.mute_state));
+        }
+        None
+    }
+}
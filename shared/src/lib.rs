@@ -1,9 +1,22 @@
 use enum_map::Enum;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
-use strum_macros::{Display, EnumIter};
+use strum_macros::{Display, EnumIter, EnumString};
 
-#[derive(Debug, Display, Copy, Clone, PartialEq, Enum, EnumIter, Serialize, Deserialize)]
+#[derive(
+    Debug,
+    Display,
+    EnumString,
+    Copy,
+    Clone,
+    PartialEq,
+    Enum,
+    EnumIter,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
 pub enum NodeType {
     PhysicalSource,
     PhysicalTarget,
@@ -11,35 +24,81 @@ pub enum NodeType {
     VirtualTarget,
 }
 
-#[derive(Default, Debug, Copy, Clone, Enum, EnumIter, Serialize, Deserialize, PartialEq)]
+#[derive(
+    Default,
+    Debug,
+    Display,
+    EnumString,
+    Copy,
+    Clone,
+    Enum,
+    EnumIter,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+    PartialEq,
+)]
 pub enum Mix {
     #[default]
     A,
     B,
 }
 
-#[derive(Default, Debug, Copy, Clone, Enum, EnumIter, Serialize, Deserialize, PartialEq)]
+#[derive(
+    Default, Debug, Copy, Clone, Enum, EnumIter, Serialize, Deserialize, JsonSchema, PartialEq,
+)]
 pub enum DeviceType {
     #[default]
     Source,
     Target,
 }
 
-#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq, Enum, EnumIter)]
+#[derive(
+    Default, Debug, Copy, Clone, Serialize, Deserialize, JsonSchema, Eq, PartialEq, Enum, EnumIter,
+)]
 pub enum MuteState {
     #[default]
     Unmuted,
     Muted,
 }
 
-#[derive(Default, Debug, Copy, Clone, Hash, Enum, EnumIter, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(
+    Default,
+    Debug,
+    Display,
+    EnumString,
+    Copy,
+    Clone,
+    Hash,
+    Enum,
+    EnumIter,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+    Eq,
+    PartialEq,
+)]
 pub enum MuteTarget {
     #[default]
     TargetA,
     TargetB,
 }
 
-#[derive(Default, Debug, Copy, Clone, Hash, Enum, EnumIter, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(
+    Default,
+    Debug,
+    EnumString,
+    Copy,
+    Clone,
+    Hash,
+    Enum,
+    EnumIter,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+    Eq,
+    PartialEq,
+)]
 pub enum OrderGroup {
     #[default]
     Default,
@@ -47,7 +106,7 @@ pub enum OrderGroup {
     Hidden,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Colour {
     pub red: u8,
     pub green: u8,
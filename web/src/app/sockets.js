@@ -8,7 +8,7 @@ export class Websocket {
   #command_index = 0
 
   connect() {
-    this.#websocket = new WebSocket(getWebsocketAddress())
+    this.#websocket = new WebSocket(withToken(getWebsocketAddress()))
 
     let self = this
     self.#websocket.addEventListener('message', function (event) {
@@ -18,7 +18,12 @@ export class Websocket {
       let message_id = json.id
       let message_data = json.data
       if (message_data['Status'] !== undefined) {
-        self.#fulfill_promise(message_id, message_data, true)
+        if (self.#message_queue[message_id] === undefined) {
+          // We didn't ask for this, we've missed some patches and the daemon is resyncing us
+          store.replaceData(message_data)
+        } else {
+          self.#fulfill_promise(message_id, message_data, true)
+        }
       } else if (message_data['Patch'] !== undefined) {
         // Nothing ever requests patch data, so we can ignore this.
         store.patchData(message_data)
@@ -145,7 +150,7 @@ export class WebsocketMeter {
   #callbacks = [];
 
   connect() {
-    this.#websocket = new WebSocket(getWebsocketAddress() + "/meter");
+    this.#websocket = new WebSocket(withToken(getWebsocketAddress() + "/meter"));
 
     let self = this
     self.#websocket.addEventListener('message', function (event) {
@@ -240,11 +245,16 @@ export function sendHttpCommand(serial, command) {
 function executeHttpRequest(request) {
   let cmd_resolve, cmd_reject
 
+  let headers = {
+    'Content-Type': 'application/json'
+  }
+  if (getToken() !== null) {
+    headers['Authorization'] = 'Bearer ' + getToken()
+  }
+
   fetch(getHTTPAddress(), {
     method: 'POST',
-    headers: {
-      'Content-Type': 'application/json'
-    },
+    headers: headers,
     body: JSON.stringify(request)
   })
     .then((response) => response.json())
@@ -276,6 +286,22 @@ function getWebsocketAddress() {
   return 'ws://' + window.location.host + '/api/websocket'
 }
 
+/*
+If the daemon has API tokens configured, the UI needs to be opened with ?token=<token>, we pass that
+through to the websockets as a query parameter, and to HTTP requests as a Bearer token.
+ */
+function getToken() {
+  return new URLSearchParams(window.location.search).get('token')
+}
+
+function withToken(address) {
+  let token = getToken()
+  if (token === null) {
+    return address
+  }
+  return address + '?token=' + encodeURIComponent(token)
+}
+
 // Same as above, except for HTTP request...
 function getHTTPAddress() {
   return getBaseHTTPAddress() + 'api/command'
@@ -108,7 +108,7 @@ export const store = reactive({
 
   // eslint-disable-next-line no-unused-vars
   patchData(json) {
-    for (let patch of json.Patch) {
+    for (let patch of json.Patch.patch) {
       if (this.pausedPaths.includes(patch.path)) {
         continue
       }